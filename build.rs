@@ -0,0 +1,50 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Assets built by `cargo xtask assets`. Releases include them in the
+/// package, so installing from crates.io doesn't need the JS
+/// toolchain.
+const ASSETS: &str = "src/html/assets.rs";
+
+/// Used when the assets haven't been built, such as in a fresh
+/// checkout. The generated pages work but are unstyled and lack
+/// interactive elements.
+const UNSTYLED_ASSETS: &str = r#"
+pub const INDEX: &str = "";
+
+pub const CSS_NAME: &str = "ui.unstyled.css";
+pub const CSS: &str = "";
+pub const CSS_MAP: &str = "";
+
+pub const JS_NAME: &str = "ui.unstyled.js";
+pub const JS: &str = "";
+pub const JS_MAP: &str = "";
+"#;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={ASSETS}");
+
+    if env::var_os("CARGO_FEATURE_HTML").is_none() {
+        return;
+    }
+
+    let out_dir = env::var_os("OUT_DIR").expect("`OUT_DIR` must be set");
+    let destination = PathBuf::from(out_dir).join("assets.rs");
+
+    let result = if Path::new(ASSETS).exists() {
+        fs::copy(ASSETS, &destination).map(drop)
+    } else {
+        println!(
+            "cargo:warning=`{ASSETS}` does not exist; generated HTML will be unstyled. \
+             Run `cargo xtask assets` to build the assets."
+        );
+        fs::write(&destination, UNSTYLED_ASSETS)
+    };
+
+    if let Err(e) = result {
+        panic!("Could not write `{}`: {e}", destination.display());
+    }
+}
@@ -0,0 +1,272 @@
+//! Authentication scenarios that the shared conformance suite does
+//! not cover.
+//!
+//! ```text
+//! cargo run -- --auth-scenarios
+//! ```
+
+use base64::Engine;
+use registry_conformance::{CommandExt, Crate, Registry as _, RegistryBuilder as _, ScratchSpace};
+use snafu::{prelude::*, Whatever};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{ExitCode, Output},
+};
+use tokio::process::Command;
+
+use crate::{Margo, MargoBuilder};
+
+const USERNAME: &str = "alice";
+const PASSWORD: &str = "hunter2";
+const ROTATED_PASSWORD: &str = "correct-horse-battery-staple";
+
+const CRATE_NAME: &str = "auth-scenario";
+const CRATE_VERSION: &str = "1.0.0";
+
+pub async fn run() -> ExitCode {
+    let results = [
+        (
+            "unauthenticated requests are rejected",
+            unauthenticated_requests_are_rejected().await,
+        ),
+        (
+            "wrong credentials fail cleanly",
+            wrong_credentials_fail_cleanly().await,
+        ),
+        (
+            "rotated credentials are used",
+            rotated_credentials_are_used().await,
+        ),
+    ];
+
+    let mut failures = 0;
+    for (name, result) in results {
+        match result {
+            Ok(()) => println!("ok   {name}"),
+            Err(e) => {
+                failures += 1;
+                println!("FAIL {name}: {}", snafu::Report::from_error(e));
+            }
+        }
+    }
+
+    if failures == 0 {
+        ExitCode::SUCCESS
+    } else {
+        println!("{failures} authentication scenario(s) failed");
+        ExitCode::FAILURE
+    }
+}
+
+/// Every file in an `auth_required` registry, including the HTML
+/// page and its assets, must be refused without credentials.
+async fn unauthenticated_requests_are_rejected() -> Result<(), Whatever> {
+    let fixture = Fixture::new().await?;
+    let margo = &fixture.margo;
+
+    margo
+        .command()
+        .arg("generate-html")
+        .arg("--registry")
+        .arg(&margo.directory)
+        .expect_success()
+        .await
+        .whatever_context("Could not generate the HTML")?;
+
+    let mut paths = vec![
+        "config.json".to_owned(),
+        format!("au/th/{CRATE_NAME}"),
+        format!("crates/au/th/{CRATE_NAME}/{CRATE_VERSION}.crate"),
+        "index.html".to_owned(),
+    ];
+
+    let assets = fs::read_dir(margo.directory.join("assets"))
+        .whatever_context("Could not list the HTML assets")?;
+    for asset in assets {
+        let asset = asset.whatever_context("Could not list the HTML assets")?;
+        paths.push(format!("assets/{}", asset.file_name().to_string_lossy()));
+    }
+
+    let base = format!("http://{}/", margo.webserver_address);
+
+    let statuses = tokio::task::spawn_blocking(move || {
+        paths
+            .into_iter()
+            .map(|path| {
+                let status = match ureq::get(&format!("{base}{path}")).call() {
+                    Ok(r) => Ok(r.status()),
+                    Err(ureq::Error::Status(status, _)) => Ok(status),
+                    Err(e) => Err(e.to_string()),
+                };
+                (path, status)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .whatever_context("The HTTP task panicked")?;
+
+    for (path, status) in statuses {
+        let status = match status {
+            Ok(s) => s,
+            Err(e) => whatever!("Could not request `{path}`: {e}"),
+        };
+        ensure_whatever!(
+            status == 401,
+            "`{path}` responded with HTTP status {status} instead of 401",
+        );
+    }
+
+    fixture.shutdown().await
+}
+
+async fn wrong_credentials_fail_cleanly() -> Result<(), Whatever> {
+    let fixture = Fixture::new().await?;
+
+    let output = fixture.cargo_fetch(USERNAME, "wrong-password").await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    ensure_whatever!(
+        !output.status.success(),
+        "Cargo fetched the crate with the wrong credentials",
+    );
+    ensure_whatever!(
+        stderr.contains("401"),
+        "Cargo did not report the authentication failure:\n{stderr}",
+    );
+    ensure_whatever!(
+        !stderr.contains("panicked"),
+        "Cargo did not fail cleanly:\n{stderr}",
+    );
+
+    fixture.shutdown().await
+}
+
+async fn rotated_credentials_are_used() -> Result<(), Whatever> {
+    let fixture = Fixture::new().await?;
+
+    let output = fixture.cargo_fetch(USERNAME, PASSWORD).await?;
+    ensure_whatever!(
+        output.status.success(),
+        "Cargo could not fetch the crate before rotation:\n{}",
+        String::from_utf8_lossy(&output.stderr),
+    );
+
+    fixture.margo.rotate_basic_auth(USERNAME, ROTATED_PASSWORD);
+
+    let output = fixture.cargo_fetch(USERNAME, PASSWORD).await?;
+    ensure_whatever!(
+        !output.status.success(),
+        "Cargo fetched the crate with the credentials from before rotation",
+    );
+
+    let output = fixture.cargo_fetch(USERNAME, ROTATED_PASSWORD).await?;
+    ensure_whatever!(
+        output.status.success(),
+        "Cargo could not fetch the crate after rotation:\n{}",
+        String::from_utf8_lossy(&output.stderr),
+    );
+
+    fixture.shutdown().await
+}
+
+/// A running registry requiring authentication that contains one
+/// published crate.
+struct Fixture {
+    margo: Margo,
+    consumer: PathBuf,
+    _scratch: ScratchSpace,
+}
+
+impl Fixture {
+    async fn new() -> Result<Self, Whatever> {
+        let scratch = ScratchSpace::new()
+            .await
+            .whatever_context("Could not create the scratch space")?;
+
+        let mut margo = MargoBuilder::default()
+            .enable_basic_auth(USERNAME, PASSWORD)
+            .start(scratch.registry())
+            .await
+            .whatever_context("Could not start the registry")?;
+
+        let crate_ = Crate::new(CRATE_NAME, CRATE_VERSION)
+            .lib_rs("pub const ID: u8 = 1;")
+            .create_in(&scratch)
+            .await
+            .whatever_context("Could not create the crate")?;
+
+        margo
+            .publish_crate(&crate_)
+            .await
+            .whatever_context("Could not publish the crate")?;
+
+        let consumer = scratch.registry().with_file_name("auth-consumer");
+
+        Ok(Self {
+            margo,
+            consumer,
+            _scratch: scratch,
+        })
+    }
+
+    /// Runs `cargo fetch` in a fresh project (and a fresh Cargo home,
+    /// so nothing is served from a cache) that depends on the
+    /// published crate.
+    async fn cargo_fetch(&self, username: &str, password: &str) -> Result<Output, Whatever> {
+        let dir = &self.consumer;
+
+        match fs::remove_dir_all(dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).whatever_context("Could not clean the consumer project"),
+        }
+
+        write_consumer_project(dir).whatever_context("Could not create the consumer project")?;
+
+        let creds = format!("{username}:{password}");
+        let creds = base64::engine::general_purpose::STANDARD.encode(creds);
+        let index = self.margo.registry_url().await;
+
+        Command::new("cargo")
+            .arg("fetch")
+            .current_dir(dir)
+            .env("CARGO_HOME", dir.join("cargo-home"))
+            .env("CARGO_REGISTRIES_MARGO_INDEX", index)
+            .env("CARGO_REGISTRIES_MARGO_TOKEN", format!("Basic {creds}"))
+            .env("CARGO_REGISTRIES_MARGO_CREDENTIAL_PROVIDER", "cargo:token")
+            .kill_on_drop(true)
+            .output()
+            .await
+            .whatever_context("Could not run Cargo")
+    }
+
+    async fn shutdown(self) -> Result<(), Whatever> {
+        self.margo
+            .shutdown_()
+            .await
+            .whatever_context("Could not shut down the registry")
+    }
+}
+
+fn write_consumer_project(dir: &Path) -> io::Result<()> {
+    let src = dir.join("src");
+    fs::create_dir_all(&src)?;
+
+    fs::write(
+        dir.join("Cargo.toml"),
+        format!(
+            r#"[package]
+name = "consumer"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+{CRATE_NAME} = {{ version = "={CRATE_VERSION}", registry = "margo" }}
+"#
+        ),
+    )?;
+    fs::write(src.join("lib.rs"), "")?;
+
+    Ok(())
+}
@@ -18,21 +18,44 @@ use std::{
     net::SocketAddr,
     path::{Path, PathBuf},
     process::ExitCode,
+    sync::{Arc, RwLock},
 };
 use tokio::{net::TcpListener, process::Command, task::JoinHandle};
 use tokio_util::sync::CancellationToken;
 use tower_http::services::ServeDir;
 
+mod auth;
+mod remote;
+
 #[tokio::main]
-async fn main() -> Result<ExitCode, BuildError> {
+async fn main() -> Result<ExitCode, MainError> {
+    let args = env::args().collect::<Vec<_>>();
+    if let Some(options) = remote::Options::from_args(&args)? {
+        return Ok(remote::run(options));
+    }
+
     if env::var_os("MARGO_BINARY").is_none() {
         Margo::build().await?;
     }
 
-    Ok(registry_conformance::test_conformance::<Margo>(std::env::args()).await)
+    if args.iter().any(|a| a == "--auth-scenarios") {
+        return Ok(auth::run().await);
+    }
+
+    Ok(registry_conformance::test_conformance::<Margo>(args.into_iter()).await)
+}
+
+#[derive(Debug, Snafu)]
+pub enum MainError {
+    #[snafu(transparent)]
+    Args { source: remote::ArgsError },
+
+    #[snafu(transparent)]
+    Build { source: BuildError },
 }
 
 type BasicAuth = Option<(String, String)>;
+type SharedBasicAuth = Arc<RwLock<BasicAuth>>;
 
 #[derive(Debug, Default)]
 pub struct MargoBuilder {
@@ -55,6 +78,7 @@ impl MargoBuilder {
             webserver_basic_auth,
         } = self;
         let auth_required = webserver_basic_auth.is_some();
+        let webserver_basic_auth = Arc::new(RwLock::new(webserver_basic_auth));
 
         let directory = directory.into();
 
@@ -68,9 +92,15 @@ impl MargoBuilder {
 
         let serve_files = ServeDir::new(&directory);
 
-        let auth_middleware = middleware::from_fn(move |hdr, req, next| {
+        let auth_middleware = middleware::from_fn({
             let webserver_basic_auth = webserver_basic_auth.clone();
-            auth(webserver_basic_auth, hdr, req, next)
+            move |hdr, req, next| {
+                let webserver_basic_auth = webserver_basic_auth
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone();
+                auth(webserver_basic_auth, hdr, req, next)
+            }
         });
 
         let serve_files = Router::new()
@@ -85,6 +115,7 @@ impl MargoBuilder {
 
         let this = Margo {
             directory,
+            webserver_basic_auth,
             webserver_cancel,
             webserver_address,
             webserver,
@@ -148,6 +179,7 @@ pub enum StartError {
 
 pub struct Margo {
     directory: PathBuf,
+    webserver_basic_auth: SharedBasicAuth,
     webserver_cancel: CancellationToken,
     webserver_address: SocketAddr,
     webserver: JoinHandle<io::Result<()>>,
@@ -220,6 +252,15 @@ impl Margo {
         Ok(())
     }
 
+    /// Changes the credentials the webserver accepts, as if they had
+    /// been rotated.
+    fn rotate_basic_auth(&self, username: &str, password: &str) {
+        *self
+            .webserver_basic_auth
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Some((username.into(), password.into()));
+    }
+
     async fn shutdown_(self) -> Result<(), ShutdownError> {
         use shutdown_error::*;
 
@@ -0,0 +1,303 @@
+//! Read-only checks against an already-deployed registry.
+//!
+//! The local conformance tests spawn their own webserver, so they
+//! can't catch problems introduced by the real hosting layer (wrong
+//! content types, missing rewrites, broken authentication, etc.).
+//!
+//! ```text
+//! conformance --remote-url https://registry.example.com/ \
+//!     --crate some-crate --crate other-crate
+//! ```
+//!
+//! Credentials are read from `--username` / `--password` or the
+//! `MARGO_HTTP_USERNAME` / `MARGO_HTTP_PASSWORD` environment
+//! variables.
+
+use base64::Engine;
+use serde::Deserialize;
+use sha2::Digest;
+use snafu::prelude::*;
+use std::{env, io::Read, process::ExitCode};
+use url::Url;
+
+pub struct Options {
+    url: Url,
+    credentials: Option<(String, String)>,
+    crates: Vec<String>,
+}
+
+impl Options {
+    /// Returns `None` when `--remote-url` is absent, so the regular
+    /// conformance tests should run instead.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, ArgsError> {
+        use args_error::*;
+
+        if !args.iter().any(|a| a == "--remote-url") {
+            return Ok(None);
+        }
+
+        let mut url = None;
+        let mut username = env::var("MARGO_HTTP_USERNAME").ok();
+        let mut password = env::var("MARGO_HTTP_PASSWORD").ok();
+        let mut crates = Vec::new();
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().cloned().context(ValueSnafu { arg });
+
+            match arg.as_str() {
+                "--remote-url" => url = Some(value()?),
+                "--username" => username = Some(value()?),
+                "--password" => password = Some(value()?),
+                "--crate" => crates.push(value()?),
+                _ => return UnknownSnafu { arg }.fail(),
+            }
+        }
+
+        let url = url.unwrap_or_default();
+        let mut url = Url::parse(&url).context(UrlSnafu { url })?;
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+
+        let credentials = username.map(|u| (u, password.unwrap_or_default()));
+
+        Ok(Some(Self {
+            url,
+            credentials,
+            crates,
+        }))
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum ArgsError {
+    #[snafu(display("`{arg}` requires a value"))]
+    Value { arg: String },
+
+    #[snafu(display("`{arg}` is not supported with `--remote-url`"))]
+    Unknown { arg: String },
+
+    #[snafu(display("`{url}` is not a valid URL"))]
+    Url {
+        source: url::ParseError,
+        url: String,
+    },
+}
+
+pub fn run(options: Options) -> ExitCode {
+    let mut checker = Checker {
+        agent: ureq::agent(),
+        authorization: options.credentials.as_ref().map(|(u, p)| {
+            let creds = base64::engine::general_purpose::STANDARD.encode(format!("{u}:{p}"));
+            format!("Basic {creds}")
+        }),
+        failures: 0,
+    };
+
+    checker.run(&options);
+
+    if checker.failures == 0 {
+        println!("All remote checks passed");
+        ExitCode::SUCCESS
+    } else {
+        println!("{} remote check(s) failed", checker.failures);
+        ExitCode::FAILURE
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Config {
+    dl: String,
+    #[serde(default, rename = "auth-required")]
+    auth_required: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexEntry {
+    vers: String,
+    cksum: String,
+    yanked: bool,
+}
+
+struct Checker {
+    agent: ureq::Agent,
+    authorization: Option<String>,
+    failures: usize,
+}
+
+impl Checker {
+    fn run(&mut self, options: &Options) {
+        let config_url = join(&options.url, "config.json");
+
+        let Some(config) = self.fetch(&config_url) else {
+            return;
+        };
+        let config = match serde_json::from_slice::<Config>(&config) {
+            Ok(c) => c,
+            Err(e) => return self.fail(&config_url, format!("is not a valid config: {e}")),
+        };
+        self.pass(&config_url);
+
+        if config.auth_required {
+            self.check_requires_auth(&config_url);
+        }
+
+        for name in &options.crates {
+            // Cargo always requests the lowercased path
+            let index_path = format!("{}/{name}", prefix(name)).to_lowercase();
+            let index_url = join(&options.url, &index_path);
+            if config.auth_required {
+                self.check_requires_auth(&index_url);
+            }
+            let Some(index) = self.fetch(&index_url) else {
+                continue;
+            };
+
+            let entries = String::from_utf8_lossy(&index)
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(serde_json::from_str::<IndexEntry>)
+                .collect::<Result<Vec<_>, _>>();
+            let entries = match entries {
+                Ok(e) if !e.is_empty() => e,
+                Ok(_) => {
+                    self.fail(&index_url, "has no entries".into());
+                    continue;
+                }
+                Err(e) => {
+                    self.fail(&index_url, format!("contains an invalid entry: {e}"));
+                    continue;
+                }
+            };
+            self.pass(&index_url);
+
+            // Yanked versions must stay in the index so that existing
+            // lockfiles keep working.
+            let yanked = entries.iter().filter(|e| e.yanked).count();
+            println!(
+                "info {index_url}: {} version(s), {yanked} yanked",
+                entries.len()
+            );
+
+            for entry in &entries {
+                let dl = expand_dl(&config.dl, name, &entry.vers, &entry.cksum);
+                let dl_url = match options.url.join(&dl) {
+                    Ok(u) => u,
+                    Err(e) => {
+                        self.fail(
+                            &index_url,
+                            format!("has an invalid download URL `{dl}`: {e}"),
+                        );
+                        continue;
+                    }
+                };
+
+                if config.auth_required {
+                    self.check_requires_auth(&dl_url);
+                }
+                let Some(body) = self.fetch(&dl_url) else {
+                    continue;
+                };
+
+                let checksum = hex::encode(sha2::Sha256::digest(&body));
+                if checksum == entry.cksum {
+                    self.pass(&dl_url);
+                } else {
+                    self.fail(
+                        &dl_url,
+                        format!(
+                            "has checksum {checksum} but the index expects {}",
+                            entry.cksum
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_requires_auth(&mut self, url: &Url) {
+        match self.agent.get(url.as_str()).call() {
+            Err(ureq::Error::Status(401 | 403, _)) => self.pass(url),
+            Ok(_) => self.fail(url, "was served without authentication".into()),
+            Err(e) => self.fail(url, format!("unauthenticated request failed: {e}")),
+        }
+    }
+
+    fn fetch(&mut self, url: &Url) -> Option<Vec<u8>> {
+        let mut request = self.agent.get(url.as_str());
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+
+        let response = match request.call() {
+            Ok(r) => r,
+            Err(e) => {
+                self.fail(url, e.to_string());
+                return None;
+            }
+        };
+
+        let mut body = Vec::new();
+        if let Err(e) = response.into_reader().read_to_end(&mut body) {
+            self.fail(url, format!("could not read the body: {e}"));
+            return None;
+        }
+
+        Some(body)
+    }
+
+    fn pass(&self, url: &Url) {
+        println!("ok   {url}");
+    }
+
+    fn fail(&mut self, url: &Url, msg: String) {
+        self.failures += 1;
+        println!("FAIL {url}: {msg}");
+    }
+}
+
+fn join(base: &Url, path: &str) -> Url {
+    base.join(path)
+        .expect("paths built from crate names are valid URLs")
+}
+
+/// The prefix directories as used by the `{prefix}` marker. Names
+/// come from the server under test, so an empty or non-ASCII one
+/// must not panic; it gets whatever prefix its characters give.
+fn prefix(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let part = |range: std::ops::Range<usize>| chars[range].iter().collect::<String>();
+
+    match chars.len() {
+        0 => String::new(),
+        1 => "1".into(),
+        2 => "2".into(),
+        3 => format!("3/{}", part(0..1)),
+        _ => format!("{}/{}", part(0..2), part(2..4)),
+    }
+}
+
+fn expand_dl(template: &str, name: &str, version: &str, cksum: &str) -> String {
+    const MARKERS: [&str; 5] = [
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowerprefix}",
+        "{sha256-checksum}",
+    ];
+
+    if !MARKERS.iter().any(|m| template.contains(m)) {
+        return format!("{template}/{name}/{version}/download");
+    }
+
+    let prefix = prefix(name);
+
+    template
+        .replace("{crate}", name)
+        .replace("{version}", version)
+        .replace("{lowerprefix}", &prefix.to_lowercase())
+        .replace("{prefix}", &prefix)
+        .replace("{sha256-checksum}", cksum)
+}
@@ -80,8 +80,4 @@ RSpec.describe 'The HTML crate list', type: :feature do
       end
     end
   end
-
-  Capybara.add_selector(:section) do
-    xpath { |title| ".//section[h1[contains(., '#{title}')]]" }
-  end
 end
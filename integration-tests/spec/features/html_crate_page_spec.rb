@@ -0,0 +1,55 @@
+# frozen_string_literal: true
+
+require 'scratch_space'
+
+RSpec.describe 'The HTML crate page', type: :feature do
+  let(:scratch) { ScratchSpace.new }
+  let(:registry) { scratch.registry }
+
+  before { registry.start }
+
+  after do
+    registry.stop
+    scratch.cleanup
+  end
+
+  it 'shows install snippets for the selected version' do
+    name = 'awesome'
+    versions = %w[1.0.0 2.0.0]
+
+    versions.each do |version|
+      scratch
+        .crate(name:, version:)
+        .lib_rs(%(pub const ID: &str = "#{version}";))
+        .publish_to(registry)
+    end
+
+    visit registry.url
+    click_link name
+
+    aggregate_failures do
+      within(:section, name) do
+        expect(page).to have_select('version', selected: '2.0.0')
+        expect(page).to have_content("#{name}@2.0.0")
+        expect(page).to have_no_content("#{name}@1.0.0")
+
+        select '1.0.0', from: 'version'
+
+        expect(page).to have_content("#{name}@1.0.0")
+        expect(page).to have_no_content("#{name}@2.0.0")
+      end
+    end
+  end
+
+  it 'skips to the content without leaving the page' do
+    name = 'awesome'
+
+    scratch.crate(name:, version: '1.0.0').publish_to(registry)
+
+    visit registry.url
+    click_link name
+
+    skip_link = find_link('Skip to content', visible: :all)
+    expect(skip_link[:href]).to eq("#{page.current_url}#main")
+  end
+end
@@ -44,3 +44,7 @@ MARGO_BINARY = ENV.fetch('MARGO_BINARY', '../target/debug/margo')
 
 Capybara.default_driver = Capybara.javascript_driver = :selenium_headless
 Capybara.run_server = false
+
+Capybara.add_selector(:section) do
+  xpath { |title| ".//section[h1[contains(., '#{title}')]]" }
+end
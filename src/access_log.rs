@@ -0,0 +1,236 @@
+use semver::Version;
+use snafu::prelude::*;
+use std::str::FromStr;
+
+use crate::common::CrateName;
+
+/// The web server access log formats that can be ingested.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// The nginx (and Apache) "combined" log format.
+    Nginx,
+    /// CloudFront standard logs (tab-separated, W3C extended).
+    Cloudfront,
+    /// Caddy's default JSON access logs.
+    Caddy,
+}
+
+impl FromStr for Format {
+    type Err = FormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nginx" => Ok(Self::Nginx),
+            "cloudfront" => Ok(Self::Cloudfront),
+            "caddy" => Ok(Self::Caddy),
+            _ => FormatSnafu { format: s }.fail(),
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("Unknown log format `{format}`; expected `nginx`, `cloudfront` or `caddy`"))]
+pub struct FormatError {
+    format: String,
+}
+
+/// A successful download of a crate file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Download {
+    pub name: CrateName,
+    pub version: Version,
+}
+
+/// Tracks per-format state while reading a log file.
+#[derive(Debug)]
+pub struct Parser {
+    format: Format,
+    cloudfront_fields: Option<CloudfrontFields>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct CloudfrontFields {
+    method: usize,
+    path: usize,
+    status: usize,
+}
+
+impl CloudfrontFields {
+    // The field order documented for CloudFront standard logs.
+    const DEFAULT: Self = Self {
+        method: 5,
+        path: 7,
+        status: 8,
+    };
+}
+
+impl Parser {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            cloudfront_fields: None,
+        }
+    }
+
+    /// Returns the download described by the line, if the line is a
+    /// successful GET of a crate file.
+    pub fn parse_line(&mut self, line: &str) -> Option<Download> {
+        let (method, path, status) = match self.format {
+            Format::Nginx => parse_nginx(line)?,
+            Format::Cloudfront => self.parse_cloudfront(line)?,
+            Format::Caddy => parse_caddy(line)?,
+        };
+
+        if method != "GET" || status != 200 {
+            return None;
+        }
+
+        download_from_path(&path)
+    }
+
+    fn parse_cloudfront(&mut self, line: &str) -> Option<(String, String, u16)> {
+        if let Some(fields) = line.strip_prefix("#Fields:") {
+            let fields = fields.split_whitespace().collect::<Vec<_>>();
+            let pos = |name| fields.iter().position(|&f| f == name);
+
+            self.cloudfront_fields = Some(CloudfrontFields {
+                method: pos("cs-method")?,
+                path: pos("cs-uri-stem")?,
+                status: pos("sc-status")?,
+            });
+            return None;
+        }
+
+        if line.starts_with('#') {
+            return None;
+        }
+
+        let fields = self.cloudfront_fields.unwrap_or(CloudfrontFields::DEFAULT);
+        let columns = line.split('\t').collect::<Vec<_>>();
+
+        let method = columns.get(fields.method)?;
+        let path = columns.get(fields.path)?;
+        let status = columns.get(fields.status)?.parse().ok()?;
+
+        Some((method.to_string(), path.to_string(), status))
+    }
+}
+
+fn parse_nginx(line: &str) -> Option<(String, String, u16)> {
+    // ... [time] "GET /path HTTP/1.1" 200 ...
+    let (_, rest) = line.split_once('"')?;
+    let (request, rest) = rest.split_once('"')?;
+
+    let mut request = request.split_whitespace();
+    let method = request.next()?;
+    let path = request.next()?;
+
+    let status = rest.split_whitespace().next()?.parse().ok()?;
+
+    Some((method.to_owned(), path.to_owned(), status))
+}
+
+fn parse_caddy(line: &str) -> Option<(String, String, u16)> {
+    #[derive(serde::Deserialize)]
+    struct Entry {
+        request: Request,
+        status: u16,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Request {
+        method: String,
+        uri: String,
+    }
+
+    let entry = serde_json::from_str::<Entry>(line).ok()?;
+
+    Some((entry.request.method, entry.request.uri, entry.status))
+}
+
+/// Extracts the crate name and version from a path ending in
+/// `{crate}/{version}.crate`.
+fn download_from_path(path: &str) -> Option<Download> {
+    let path = path.split(['?', '#']).next()?;
+    let path = percent_decode(path)?;
+
+    let mut segments = path.rsplit('/');
+    let file = segments.next()?;
+    let name = segments.next()?;
+
+    let version = file.strip_suffix(".crate")?;
+
+    Some(Download {
+        name: name.parse().ok()?,
+        version: version.parse().ok()?,
+    })
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut input = s.bytes();
+
+    while let Some(b) = input.next() {
+        if b == b'%' {
+            let hi = input.next()?;
+            let lo = input.next()?;
+            let hex = [hi, lo];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn download(name: &str, version: &str) -> Option<Download> {
+        Some(Download {
+            name: name.parse().unwrap(),
+            version: version.parse().unwrap(),
+        })
+    }
+
+    #[test]
+    fn nginx() {
+        let mut p = Parser::new(Format::Nginx);
+
+        let line = r#"10.0.0.1 - alice [10/Jun/2024:13:55:36 +0000] "GET /crates/my/cr/my-crate/1.2.3.crate HTTP/1.1" 200 5120 "-" "cargo/1.78.0""#;
+        assert_eq!(p.parse_line(line), download("my-crate", "1.2.3"));
+
+        let line = r#"10.0.0.1 - - [10/Jun/2024:13:55:36 +0000] "GET /crates/my/cr/my-crate/1.2.3.crate HTTP/1.1" 404 0 "-" "cargo/1.78.0""#;
+        assert_eq!(p.parse_line(line), None);
+
+        let line = r#"10.0.0.1 - - [10/Jun/2024:13:55:36 +0000] "GET /my/cr/my-crate HTTP/1.1" 200 512 "-" "cargo/1.78.0""#;
+        assert_eq!(p.parse_line(line), None);
+    }
+
+    #[test]
+    fn cloudfront() {
+        let mut p = Parser::new(Format::Cloudfront);
+
+        assert_eq!(p.parse_line("#Version: 1.0"), None);
+        assert_eq!(
+            p.parse_line("#Fields: date time x-edge-location sc-bytes c-ip cs-method cs(Host) cs-uri-stem sc-status"),
+            None,
+        );
+
+        let line = "2024-06-10\t13:55:36\tIAD89-C1\t5120\t10.0.0.1\tGET\td111.cloudfront.net\t/crates/3/a/abc/0.1.0-alpha.1%2Bbuild5.crate\t200";
+        assert_eq!(p.parse_line(line), download("abc", "0.1.0-alpha.1+build5"));
+    }
+
+    #[test]
+    fn caddy() {
+        let mut p = Parser::new(Format::Caddy);
+
+        let line = r#"{"level":"info","msg":"handled request","request":{"method":"GET","uri":"/crates/1/a/1.0.0.crate?x=y"},"status":200}"#;
+        assert_eq!(p.parse_line(line), download("a", "1.0.0"));
+
+        assert_eq!(p.parse_line("not json"), None);
+    }
+}
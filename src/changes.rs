@@ -0,0 +1,488 @@
+//! A log of every change made to the registry's crates, so that
+//! mirrors can fetch only the index files and crate files that
+//! changed instead of crawling the whole registry.
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::prelude::*;
+use std::{
+    collections::BTreeSet,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+use url::Url;
+
+use crate::{
+    common::{CrateName, Timestamp},
+    ensure_last_segment_empty, expand_dl_template, http, index_entry, Global, Index, OpenError,
+    ParseIndexError, Registry, RemoveError, WriteIndexError,
+};
+
+/// Written at the root of the registry, so mirrors can fetch it
+/// alongside the index.
+pub const CHANGES_FILE_NAME: &str = "changes.jsonl";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    #[serde(rename = "crate")]
+    pub name: CrateName,
+    pub version: Version,
+    pub action: Action,
+    pub time: Timestamp,
+}
+
+impl Change {
+    pub fn now(name: &CrateName, version: &Version, action: Action) -> Self {
+        Self {
+            name: name.clone(),
+            version: version.clone(),
+            action,
+            time: Timestamp::now(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Publish,
+    Yank,
+    Unyank,
+    Remove,
+}
+
+/// Appends changes to the registry's log.
+pub fn record(r: &Registry, changes: &[Change]) -> Result<(), RecordError> {
+    use record_error::*;
+
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let mut lines = Vec::new();
+    for change in changes {
+        serde_json::to_writer(&mut lines, change).context(SerializeSnafu)?;
+        lines.push(b'\n');
+    }
+
+    let path = r.path.join(CHANGES_FILE_NAME);
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(&lines))
+        .context(WriteSnafu { path })
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum RecordError {
+    #[snafu(display("Could not serialize the change"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not append to the change log {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+}
+
+/// Where a mirror copies changes from.
+pub enum Source {
+    /// A registry on this machine.
+    Local(Box<Registry>),
+    /// A registry served over HTTP, with its `dl` template.
+    Remote {
+        base_url: Url,
+        dl: String,
+        client: Box<http::Client>,
+    },
+}
+
+impl Source {
+    pub fn new(global: &Global, from: &str) -> Result<Self, ApplyError> {
+        use apply_error::*;
+
+        #[derive(Deserialize)]
+        struct ConfigJson {
+            dl: String,
+        }
+
+        let mut base_url = match from.parse::<Url>() {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+            _ => {
+                let r = Registry::open(from).context(OpenSourceSnafu)?;
+                return Ok(Self::Local(Box::new(r)));
+            }
+        };
+        ensure_last_segment_empty(&mut base_url);
+
+        let client = http::Client::from_env(global, None)?;
+
+        let url = base_url.join("config.json").context(UrlSnafu)?;
+        let config_json = fetch_url(&client, url)?.context(NotARegistrySnafu { from })?;
+        let ConfigJson { dl } =
+            serde_json::from_slice(&config_json).context(ConfigJsonSnafu { from })?;
+
+        Ok(Self::Remote {
+            base_url,
+            dl,
+            client: Box::new(client),
+        })
+    }
+
+    /// An identifier used to notice when a mirror switches sources.
+    fn id(&self) -> String {
+        match self {
+            Self::Local(r) => r.path.display().to_string(),
+            Self::Remote { base_url, .. } => base_url.to_string(),
+        }
+    }
+
+    /// A file relative to the source registry's root, or `None` if
+    /// it doesn't exist.
+    fn fetch(&self, relative: &str) -> Result<Option<Vec<u8>>, ApplyError> {
+        use apply_error::*;
+
+        match self {
+            Self::Local(r) => {
+                let path = r.path.join(relative);
+                match fs::read(&path) {
+                    Ok(data) => Ok(Some(data)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e).context(ReadSourceSnafu { path }),
+                }
+            }
+            Self::Remote {
+                base_url, client, ..
+            } => {
+                let url = base_url.join(relative).context(UrlSnafu)?;
+                fetch_url(client, url)
+            }
+        }
+    }
+
+    pub fn index_file(&self, name: &CrateName) -> Result<Index, ApplyError> {
+        use apply_error::*;
+
+        // Cargo always requests the lowercased path
+        let relative = format!("{}/{}", name.prefix(), name).to_lowercase();
+        let Some(data) = self.fetch(&relative)? else {
+            return Ok(Index::new());
+        };
+
+        let mut index = Index::new();
+        for line in data.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+            let entry = serde_json::from_slice::<index_entry::Root>(line)
+                .context(IndexEntrySnafu { name: name.clone() })?;
+            index.insert(entry.vers.clone(), entry);
+        }
+        Ok(index)
+    }
+
+    pub fn crate_file(
+        &self,
+        name: &CrateName,
+        version: &Version,
+        cksum: &str,
+    ) -> Result<Vec<u8>, ApplyError> {
+        use apply_error::*;
+
+        let data = match self {
+            Self::Local(r) => {
+                let path = r.crate_file_path_for(name, version, cksum);
+                fs::read(&path).context(ReadSourceSnafu { path })?
+            }
+            Self::Remote { dl, client, .. } => {
+                let url = expand_dl_template(dl, name, version, cksum);
+                let url = url.parse::<Url>().context(UrlSnafu)?;
+                fetch_url(client, url)?.context(CrateMissingSnafu {
+                    name: name.clone(),
+                    version: version.clone(),
+                })?
+            }
+        };
+
+        let actual = hex::encode(Sha256::digest(&data));
+        ensure!(
+            actual == cksum,
+            ChecksumSnafu {
+                name: name.clone(),
+                version: version.clone(),
+            }
+        );
+
+        Ok(data)
+    }
+}
+
+fn fetch_url(client: &http::Client, url: Url) -> Result<Option<Vec<u8>>, ApplyError> {
+    use apply_error::*;
+
+    let fetched = client.get(&url, true)?;
+    match fetched.status {
+        200 => Ok(Some(fetched.body)),
+        404 | 410 => Ok(None),
+        status => StatusSnafu { url, status }.fail(),
+    }
+}
+
+/// How far a mirror has read its source's change log.
+pub const MIRROR_STATE_FILE_NAME: &str = "margo-mirror.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Applied {
+    source: String,
+    lines: usize,
+}
+
+/// Copies the crates named in the source's change log since the last
+/// time this was run, returning how many changes were applied. Each
+/// crate is made to match the source as it is now, so applying the
+/// same changes twice is harmless.
+pub fn apply(mirror: &Registry, source: &Source) -> Result<usize, ApplyError> {
+    use apply_error::*;
+
+    let state_path = mirror.path.join(MIRROR_STATE_FILE_NAME);
+    let applied = match fs::read(&state_path) {
+        Ok(data) => serde_json::from_slice(&data).context(StateParseSnafu { path: &state_path })?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Applied::default(),
+        Err(e) => return Err(e).context(StateReadSnafu { path: &state_path }),
+    };
+
+    let id = source.id();
+    let skip = if applied.source == id {
+        applied.lines
+    } else {
+        0
+    };
+
+    let log = source.fetch(CHANGES_FILE_NAME)?.unwrap_or_default();
+    let lines = log
+        .split(|&b| b == b'\n')
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+    ensure!(lines.len() >= skip, TruncatedSnafu { path: &state_path });
+
+    let mut names = BTreeSet::new();
+    for line in &lines[skip..] {
+        let change = serde_json::from_slice::<Change>(line).context(ChangeParseSnafu)?;
+        names.insert(change.name);
+    }
+
+    for name in &names {
+        let changes = sync_crate(mirror, source, name)?;
+        record(mirror, &changes)?;
+    }
+
+    let state = Applied {
+        source: id,
+        lines: lines.len(),
+    };
+    let state = serde_json::to_vec_pretty(&state).context(StateSerializeSnafu)?;
+    fs::write(&state_path, state).context(StateWriteSnafu { path: &state_path })?;
+
+    Ok(lines.len() - skip)
+}
+
+/// Makes the mirror's copy of a crate match the source.
+fn sync_crate(
+    mirror: &Registry,
+    source: &Source,
+    name: &CrateName,
+) -> Result<Vec<Change>, ApplyError> {
+    use apply_error::*;
+
+    let upstream = source.index_file(name)?;
+
+    let index_path = mirror.index_file_path_for(name);
+    let local =
+        Registry::parse_index_file(&index_path).context(IndexReadSnafu { path: &index_path })?;
+
+    let mut changes = Vec::new();
+
+    // Stale crate files are removed first, as a replacement may be
+    // written to the same path.
+    for (version, entry) in &local {
+        let kept = upstream
+            .get(version)
+            .is_some_and(|e| e.cksum == entry.cksum);
+        if kept {
+            continue;
+        }
+
+        let path = mirror.crate_file_path_for(name, version, &entry.cksum);
+        mirror.remove_crate_file(&path, &entry.cksum)?;
+
+        if !upstream.contains_key(version) {
+            println!("Removed `{name}` version {version}");
+            changes.push(Change::now(name, version, Action::Remove));
+        }
+    }
+
+    for (version, entry) in &upstream {
+        match local.get(version) {
+            Some(existing) if existing.cksum == entry.cksum => {
+                if existing.yanked != entry.yanked {
+                    let action = if entry.yanked {
+                        Action::Yank
+                    } else {
+                        Action::Unyank
+                    };
+                    changes.push(Change::now(name, version, action));
+                }
+            }
+            _ => {
+                let data = source.crate_file(name, version, &entry.cksum)?;
+                let path = mirror.crate_file_path_for(name, version, &entry.cksum);
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).context(CrateWriteSnafu { path: dir })?;
+                }
+                mirror
+                    .write_crate_file(&path, &data, &entry.cksum)
+                    .context(CrateWriteSnafu { path: &path })?;
+                println!("Copied `{name}` version {version}");
+                changes.push(Change::now(name, version, Action::Publish));
+            }
+        }
+    }
+
+    if upstream.is_empty() {
+        match fs::remove_file(&index_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(IndexRemoveSnafu { path: index_path }),
+        }
+    } else {
+        if let Some(dir) = index_path.parent() {
+            fs::create_dir_all(dir).context(IndexDirSnafu { path: dir })?;
+        }
+        Registry::write_index_file(upstream, &index_path, mirror.config.durability)
+            .context(IndexWriteSnafu { path: &index_path })?;
+    }
+
+    Ok(changes)
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum ApplyError {
+    #[snafu(display("Could not open the source registry"))]
+    OpenSource {
+        #[snafu(source(from(OpenError, Box::new)))]
+        source: Box<OpenError>,
+    },
+
+    #[snafu(transparent)]
+    Client { source: http::Error },
+
+    #[snafu(transparent)]
+    Request { source: http::RequestError },
+
+    #[snafu(display("Could not construct a URL in the source registry"))]
+    Url { source: url::ParseError },
+
+    #[snafu(display("{url} responded with HTTP status {status}"))]
+    Status { url: Url, status: u16 },
+
+    #[snafu(display("`{from}` does not have a `config.json`, so it is not a registry"))]
+    NotARegistry { from: String },
+
+    #[snafu(display("The `config.json` of `{from}` is malformed"))]
+    ConfigJson {
+        source: serde_json::Error,
+        from: String,
+    },
+
+    #[snafu(display("Could not read {}", path.display()))]
+    ReadSource { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not read the mirror state {}", path.display()))]
+    StateRead { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The mirror state {} is malformed", path.display()))]
+    StateParse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not serialize the mirror state"))]
+    StateSerialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the mirror state {}", path.display()))]
+    StateWrite { source: io::Error, path: PathBuf },
+
+    #[snafu(display(
+        "The source's change log is shorter than what has already been applied; delete {} to start over",
+        path.display(),
+    ))]
+    Truncated { path: PathBuf },
+
+    #[snafu(display("The source's change log is malformed"))]
+    ChangeParse { source: serde_json::Error },
+
+    #[snafu(display("The source's index file for `{name}` is malformed"))]
+    IndexEntry {
+        source: serde_json::Error,
+        name: CrateName,
+    },
+
+    #[snafu(display("The source does not have the crate file for `{name}` version {version}"))]
+    CrateMissing { name: CrateName, version: Version },
+
+    #[snafu(display(
+        "The source's crate file for `{name}` version {version} does not match its checksum"
+    ))]
+    Checksum { name: CrateName, version: Version },
+
+    #[snafu(display("Could not read the crate's index file {}", path.display()))]
+    IndexRead {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not create the crate's index directory {}", path.display()))]
+    IndexDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the crate's index file {}", path.display()))]
+    IndexWrite {
+        source: WriteIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not delete {}", path.display()))]
+    IndexRemove { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the crate {}", path.display()))]
+    CrateWrite { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    Remove {
+        #[snafu(source(from(RemoveError, Box::new)))]
+        source: Box<RemoveError>,
+    },
+
+    #[snafu(transparent)]
+    Record { source: RecordError },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn changes_are_one_line_of_json() {
+        let change = Change {
+            name: "logged".parse().unwrap(),
+            version: "1.2.3".parse().unwrap(),
+            action: Action::Unyank,
+            time: Timestamp::from_secs(1_700_000_000),
+        };
+
+        let line = serde_json::to_string(&change).unwrap();
+        assert_eq!(
+            line,
+            r#"{"crate":"logged","version":"1.2.3","action":"unyank","time":1700000000}"#,
+        );
+        assert_eq!(serde_json::from_str::<Change>(&line).unwrap(), change);
+    }
+}
@@ -0,0 +1,253 @@
+use sha2::Digest;
+use snafu::prelude::*;
+use std::{fs, panic, path::PathBuf, thread};
+use url::Url;
+
+use crate::{
+    common::CrateName,
+    expand_dl_template,
+    http::{self, Response},
+    latest_version, ErrorKind, Index, Registry,
+};
+
+pub struct Options<'a> {
+    pub sample: usize,
+    /// How many crates to check at the same time.
+    pub jobs: usize,
+    pub client: &'a http::Client,
+}
+
+pub fn check(registry: &Registry, options: Options<'_>) -> Result<(), Error> {
+    use error::*;
+
+    let Options {
+        sample,
+        jobs,
+        client,
+    } = options;
+
+    let config = &registry.config;
+
+    ensure!(
+        !config.auth_required || client.has_credentials_for(&config.base_url),
+        CredentialsMissingSnafu
+    );
+
+    let mut checker = Checker {
+        client,
+        failures: 0,
+    };
+
+    let base_url = &config.base_url;
+
+    let config_json_url = base_url.join("config.json").context(UrlSnafu)?;
+    let config_json_path = registry.config_json_path();
+    let local_config_json = fs::read(&config_json_path).context(ReadLocalSnafu {
+        path: config_json_path,
+    })?;
+
+    if config.auth_required {
+        checker.check_requires_auth(&config_json_url)?;
+    }
+
+    checker.check(&config_json_url, |fetched| {
+        if !fetched.content_type.contains("json") {
+            return Outcome::Warn(format!(
+                "served as `{}` instead of `application/json`",
+                fetched.content_type,
+            ));
+        }
+        if !json_eq(&fetched.body, &local_config_json) {
+            return Outcome::Fail("does not match the local `config.json`".into());
+        }
+        Outcome::Ok
+    })?;
+
+    let crates = registry.list_all()?;
+    let step = usize::max(1, crates.len() / usize::max(1, sample));
+    let sampled = crates.iter().step_by(step).take(sample).collect::<Vec<_>>();
+
+    let dl_template = &config.dl_template();
+    let chunk_size = usize::max(1, sampled.len().div_ceil(usize::max(1, jobs)));
+
+    let sampled_failures = thread::scope(|s| {
+        let workers = sampled
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    let mut checker = Checker {
+                        client,
+                        failures: 0,
+                    };
+
+                    for (name, index) in chunk {
+                        checker.check_index(registry, name)?;
+                        checker.check_download(base_url, dl_template, name, index)?;
+                    }
+
+                    Ok::<_, Error>(checker.failures)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .map(|w| w.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+            .sum::<Result<usize, Error>>()
+    })?;
+
+    let failures = checker.failures + sampled_failures;
+    ensure!(failures == 0, FailedSnafu { failures });
+
+    println!("The deployed registry matches the local registry");
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display(
+        "The registry requires authentication; add it to your netrc file or set `MARGO_HTTP_USERNAME` and `MARGO_HTTP_PASSWORD`"
+    ))]
+    CredentialsMissing,
+
+    #[snafu(display("Could not construct a URL to check"))]
+    Url { source: url::ParseError },
+
+    #[snafu(display("Could not read the local file {}", path.display()))]
+    ReadLocal {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not list the crates"))]
+    #[snafu(context(false))]
+    ListAll { source: crate::ListAllError },
+
+    #[snafu(transparent)]
+    Request { source: http::RequestError },
+
+    #[snafu(display("{failures} check(s) failed"))]
+    Failed { failures: usize },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Failed { .. } => Some(ErrorKind::Validation),
+            _ => None,
+        }
+    }
+}
+
+struct Checker<'a> {
+    client: &'a http::Client,
+    failures: usize,
+}
+
+enum Outcome {
+    Ok,
+    Warn(String),
+    Fail(String),
+}
+
+impl Checker<'_> {
+    fn check(&mut self, url: &Url, f: impl FnOnce(&Response) -> Outcome) -> Result<(), Error> {
+        let fetched = self.client.get(url, true)?;
+
+        let outcome = if fetched.status == 200 {
+            f(&fetched)
+        } else {
+            Outcome::Fail(format!("responded with HTTP status {}", fetched.status))
+        };
+
+        self.report(url, outcome);
+
+        Ok(())
+    }
+
+    fn check_requires_auth(&mut self, url: &Url) -> Result<(), Error> {
+        let fetched = self.client.get(url, false)?;
+
+        let outcome = match fetched.status {
+            401 | 403 => Outcome::Ok,
+            status => Outcome::Fail(format!(
+                "responded with HTTP status {status} to an unauthenticated request",
+            )),
+        };
+
+        self.report(url, outcome);
+
+        Ok(())
+    }
+
+    fn check_index(&mut self, registry: &Registry, name: &CrateName) -> Result<(), Error> {
+        use error::*;
+
+        // Cargo always requests the lowercased path
+        let path = format!("{}/{}", name.prefix(), name).to_lowercase();
+        let url = registry.config.base_url.join(&path).context(UrlSnafu)?;
+
+        let local_path = registry.index_file_path_for(name);
+        let local = fs::read(&local_path).context(ReadLocalSnafu { path: local_path })?;
+
+        self.check(&url, |fetched| {
+            if fetched.body == local {
+                Outcome::Ok
+            } else {
+                Outcome::Fail("does not match the local index file".into())
+            }
+        })
+    }
+
+    fn check_download(
+        &mut self,
+        base_url: &Url,
+        dl_template: &str,
+        name: &CrateName,
+        index: &Index,
+    ) -> Result<(), Error> {
+        use error::*;
+
+        let newest = latest_version(index)
+            .and_then(|v| index.get_key_value(v))
+            .or_else(|| index.iter().next_back());
+        let Some((version, entry)) = newest else {
+            return Ok(());
+        };
+
+        let url = expand_dl_template(dl_template, name, version, &entry.cksum);
+        let url = base_url.join(&url).context(UrlSnafu)?;
+
+        self.check(&url, |fetched| {
+            let checksum = hex::encode(sha2::Sha256::digest(&fetched.body));
+            if checksum == entry.cksum {
+                Outcome::Ok
+            } else {
+                Outcome::Fail(format!(
+                    "has checksum {checksum} but the index expects {}",
+                    entry.cksum,
+                ))
+            }
+        })
+    }
+
+    fn report(&mut self, url: &Url, outcome: Outcome) {
+        match outcome {
+            Outcome::Ok => println!("ok   {url}"),
+            Outcome::Warn(msg) => println!("warn {url}: {msg}"),
+            Outcome::Fail(msg) => {
+                self.failures += 1;
+                println!("FAIL {url}: {msg}");
+            }
+        }
+    }
+}
+
+fn json_eq(a: &[u8], b: &[u8]) -> bool {
+    let a = serde_json::from_slice::<serde_json::Value>(a);
+    let b = serde_json::from_slice::<serde_json::Value>(b);
+
+    matches!((a, b), (Ok(a), Ok(b)) if a == b)
+}
@@ -0,0 +1,279 @@
+//! Walking the dependency requirements recorded in the index, to find
+//! out whether a version can be built from this registry alone,
+//! without running Cargo.
+
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use snafu::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+    path::PathBuf,
+};
+use url::Url;
+
+use crate::{
+    common::{CrateName, CrateVersion},
+    find_version,
+    index_entry::DependencyKind,
+    public_names, same_index_url, ErrorKind, Global, Index, ParseIndexError, Registry,
+};
+
+/// One dependency requirement of a version in the closure.
+#[derive(Debug, Serialize)]
+pub struct Requirement {
+    #[serde(rename = "crate")]
+    pub dependent: CrateName,
+    pub version: Version,
+    /// The package name, even when the dependency is renamed.
+    pub dependency: String,
+    pub req: VersionReq,
+    pub kind: &'static str,
+    pub optional: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(flatten)]
+    pub resolution: Resolution,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Resolution {
+    /// The newest version here that Cargo could select.
+    Satisfied { resolved: Version },
+    /// Only yanked versions here match, which Cargo won't select
+    /// without an existing lockfile.
+    Yanked { resolved: Version },
+    /// No version here matches.
+    Unsatisfied,
+    /// The dependency comes from crates.io.
+    CratesIo,
+    /// The dependency comes from another registry.
+    External { registry: Url },
+}
+
+impl Resolution {
+    /// Whether the requirement can't be met from any registry.
+    pub fn is_broken(&self) -> bool {
+        matches!(self, Self::Yanked { .. } | Self::Unsatisfied)
+    }
+
+    pub fn is_elsewhere(&self) -> bool {
+        matches!(self, Self::CratesIo | Self::External { .. })
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Satisfied { resolved } => write!(f, "{resolved}"),
+            Self::Yanked { resolved } => write!(f, "only yanked {resolved}"),
+            Self::Unsatisfied => f.write_str("missing"),
+            Self::CratesIo => f.write_str("crates.io"),
+            Self::External { registry } => write!(f, "from {registry}"),
+        }
+    }
+}
+
+/// Every requirement of the version and of the versions here that
+/// satisfy them, transitively, in the order they were reached.
+/// Optional dependencies are followed too, as a feature could enable
+/// them. Cargo never builds the dev-dependencies of a dependency, so
+/// only the root's are reported, after everything else, and they
+/// aren't followed.
+pub fn walk(global: &Global, r: &Registry, root: &CrateVersion) -> Result<Vec<Requirement>, Error> {
+    use error::*;
+
+    let mut indexes = Indexes::new(r);
+
+    let index = indexes.get(&root.name)?;
+    let version = find_version(index, &root.version)
+        .cloned()
+        .context(VersionNotFoundSnafu {
+            target: root.clone(),
+        })?;
+
+    let mut queue = VecDeque::from([(root.name.clone(), version)]);
+    let mut visited = BTreeSet::new();
+    let mut requirements = Vec::new();
+    let mut dev_requirements = Vec::new();
+
+    while let Some((name, version)) = queue.pop_front() {
+        let is_root = visited.is_empty();
+        if !visited.insert((name.clone(), version.clone())) {
+            continue;
+        }
+
+        let Some(entry) = indexes.get(&name)?.get(&version) else {
+            continue;
+        };
+
+        let mut found = entry
+            .deps
+            .iter()
+            .filter(|dep| is_root || !is_dev(&dep.kind))
+            .map(|dep| {
+                let requirement = Requirement {
+                    dependent: name.clone(),
+                    version: version.clone(),
+                    dependency: dep.package.as_deref().unwrap_or(&dep.name).to_owned(),
+                    req: dep.req.clone(),
+                    kind: kind_name(&dep.kind),
+                    optional: dep.optional,
+                    target: dep.target.clone(),
+                    resolution: Resolution::Unsatisfied,
+                };
+                (dep.registry.clone(), is_dev(&dep.kind), requirement)
+            })
+            .collect::<Vec<_>>();
+
+        for (registry, dev, requirement) in &mut found {
+            requirement.resolution = match registry.take() {
+                Some(registry) if is_crates_io(global, &registry) => Resolution::CratesIo,
+                Some(registry) if !is_here(r, &registry) => Resolution::External { registry },
+                _ => match requirement.dependency.parse::<CrateName>() {
+                    Ok(dep_name) => {
+                        let resolution = resolve(indexes.get(&dep_name)?, &requirement.req);
+                        if let Resolution::Satisfied { resolved } = &resolution {
+                            if !*dev {
+                                queue.push_back((dep_name, resolved.clone()));
+                            }
+                        }
+                        resolution
+                    }
+                    Err(_) => Resolution::Unsatisfied,
+                },
+            };
+        }
+        for (_, dev, requirement) in found {
+            if dev {
+                dev_requirements.push(requirement);
+            } else {
+                requirements.push(requirement);
+            }
+        }
+    }
+
+    requirements.extend(dev_requirements);
+    Ok(requirements)
+}
+
+/// Fails when a requirement can't be met, or, for a self-contained
+/// closure, when one needs another registry.
+pub fn ensure_complete(requirements: &[Requirement], self_contained: bool) -> Result<(), Error> {
+    use error::*;
+
+    let unmet = requirements
+        .iter()
+        .filter(|req| {
+            req.resolution.is_broken() || (self_contained && req.resolution.is_elsewhere())
+        })
+        .count();
+    ensure!(unmet == 0, IncompleteSnafu { unmet });
+
+    Ok(())
+}
+
+pub fn to_json(requirements: &[Requirement]) -> Result<String, Error> {
+    use error::*;
+
+    let mut json = serde_json::to_string_pretty(requirements).context(SerializeSnafu)?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// The version Cargo would select, preferring versions that aren't
+/// yanked.
+fn resolve(index: &Index, req: &VersionReq) -> Resolution {
+    let mut matching = index.iter().filter(|(v, _)| req.matches(v)).rev();
+
+    if let Some((v, _)) = matching.clone().find(|(_, e)| !e.yanked) {
+        return Resolution::Satisfied {
+            resolved: v.clone(),
+        };
+    }
+
+    match matching.next() {
+        Some((v, _)) => Resolution::Yanked {
+            resolved: v.clone(),
+        },
+        None => Resolution::Unsatisfied,
+    }
+}
+
+pub fn is_here(r: &Registry, registry: &Url) -> bool {
+    same_index_url(registry, &r.config.base_url)
+}
+
+fn is_crates_io(global: &Global, registry: &Url) -> bool {
+    let sparse = public_names::CRATES_IO_INDEX.parse::<Url>();
+    same_index_url(registry, &global.crates_io_index_url)
+        || sparse.is_ok_and(|s| same_index_url(registry, &s))
+}
+
+fn is_dev(kind: &DependencyKind) -> bool {
+    matches!(kind, DependencyKind::Dev)
+}
+
+pub fn kind_name(kind: &DependencyKind) -> &'static str {
+    match kind {
+        DependencyKind::Dev => "dev",
+        DependencyKind::Build => "build",
+        DependencyKind::Normal => "normal",
+    }
+}
+
+/// Index files, each read once.
+struct Indexes<'a> {
+    r: &'a Registry,
+    read: BTreeMap<CrateName, Index>,
+}
+
+impl<'a> Indexes<'a> {
+    fn new(r: &'a Registry) -> Self {
+        Self {
+            r,
+            read: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, name: &CrateName) -> Result<&Index, Error> {
+        use error::*;
+
+        if !self.read.contains_key(name) {
+            let path = self.r.index_file_path_for(name);
+            let index = Registry::parse_index_file(&path).context(IndexSnafu { path })?;
+            self.read.insert(name.clone(), index);
+        }
+        Ok(&self.read[name])
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not read the index file {}", path.display()))]
+    Index {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("`{target}` is not in the registry"))]
+    VersionNotFound { target: CrateVersion },
+
+    #[snafu(display("{unmet} requirement(s) can't be met from this registry"))]
+    Incomplete { unmet: usize },
+
+    #[snafu(display("Could not serialize the requirements"))]
+    Serialize { source: serde_json::Error },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::VersionNotFound { .. } => Some(ErrorKind::NotFound),
+            Self::Incomplete { .. } => Some(ErrorKind::Validation),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Comparing two versions of a crate as recorded in the index, and
+//! optionally the files in their packages, such as when reviewing a
+//! dependency bump.
+
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use snafu::prelude::*;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, Read},
+    path::PathBuf,
+};
+use url::Url;
+
+use crate::{
+    closure, common::CrateName, find_version, index_entry, ErrorKind, ParseIndexError, Registry,
+};
+
+/// The differences from the old version to the new one. Anything
+/// unchanged is left out.
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    #[serde(rename = "crate")]
+    pub name: CrateName,
+    pub old: Version,
+    pub new: Version,
+    pub dependencies: Vec<DependencyDifference>,
+    pub features: Vec<FeatureDifference>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rust_version: Option<Change<String>>,
+    /// Only when the package files were compared.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<FileDifference>>,
+}
+
+impl Comparison {
+    pub fn is_empty(&self) -> bool {
+        self.dependencies.is_empty()
+            && self.features.is_empty()
+            && self.rust_version.is_none()
+            && self.files.as_ref().map_or(true, Vec::is_empty)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change<T> {
+    Added { new: T },
+    Removed { old: T },
+    Changed { old: T, new: T },
+}
+
+/// A dependency is identified by the name the crate uses for it, its
+/// kind, and its target, as the same package may be depended on
+/// several times.
+#[derive(Debug, Serialize)]
+pub struct DependencyDifference {
+    pub name: String,
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(flatten)]
+    pub change: Change<DependencySpec>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct DependencySpec {
+    pub req: VersionReq,
+    pub optional: bool,
+    pub default_features: bool,
+    pub features: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry: Option<Url>,
+}
+
+impl fmt::Display for DependencySpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.req)?;
+
+        let mut notes = Vec::new();
+        if self.optional {
+            notes.push("optional".to_owned());
+        }
+        if !self.default_features {
+            notes.push("no default features".to_owned());
+        }
+        if !self.features.is_empty() {
+            notes.push(format!("features {}", self.features.join(" ")));
+        }
+        if let Some(package) = &self.package {
+            notes.push(format!("package {package}"));
+        }
+        if let Some(registry) = &self.registry {
+            notes.push(format!("from {registry}"));
+        }
+
+        if !notes.is_empty() {
+            write!(f, " ({})", notes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Features from both `features` and `features2`, as Cargo merges
+/// them.
+#[derive(Debug, Serialize)]
+pub struct FeatureDifference {
+    pub name: String,
+    #[serde(flatten)]
+    pub change: Change<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileDifference {
+    /// Relative to the package's root directory.
+    pub path: String,
+    #[serde(flatten)]
+    pub change: Change<FileSummary>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct FileSummary {
+    pub size: u64,
+    pub sha256: String,
+}
+
+pub fn compare(
+    r: &Registry,
+    name: &CrateName,
+    old: &Version,
+    new: &Version,
+    files: bool,
+) -> Result<Comparison, Error> {
+    use error::*;
+
+    let path = r.index_file_path_for(name);
+    let index = Registry::parse_index_file(&path).context(IndexSnafu { path })?;
+
+    let entry = |version: &Version| {
+        find_version(&index, version)
+            .and_then(|v| index.get(v))
+            .context(VersionNotFoundSnafu {
+                name: name.clone(),
+                version: version.clone(),
+            })
+    };
+    let old_entry = entry(old)?;
+    let new_entry = entry(new)?;
+
+    let dependencies = diff_maps(dependencies(old_entry), dependencies(new_entry))
+        .into_iter()
+        .map(|((name, kind, target), change)| DependencyDifference {
+            name,
+            kind,
+            target,
+            change,
+        })
+        .collect();
+
+    let features = diff_maps(features(old_entry), features(new_entry))
+        .into_iter()
+        .map(|(name, change)| FeatureDifference { name, change })
+        .collect();
+
+    let rust_version = |e: &index_entry::Root| e.rust_version.as_ref().map(ToString::to_string);
+    let rust_version = match (rust_version(old_entry), rust_version(new_entry)) {
+        (None, None) => None,
+        (None, Some(new)) => Some(Change::Added { new }),
+        (Some(old), None) => Some(Change::Removed { old }),
+        (Some(old), Some(new)) if old != new => Some(Change::Changed { old, new }),
+        (Some(_), Some(_)) => None,
+    };
+
+    let files = if files {
+        let old_files = package_files(r, old_entry)?;
+        let new_files = package_files(r, new_entry)?;
+        let differences = diff_maps(old_files, new_files)
+            .into_iter()
+            .map(|(path, change)| FileDifference { path, change })
+            .collect();
+        Some(differences)
+    } else {
+        None
+    };
+
+    Ok(Comparison {
+        name: old_entry.name.clone(),
+        old: old_entry.vers.clone(),
+        new: new_entry.vers.clone(),
+        dependencies,
+        features,
+        rust_version,
+        files,
+    })
+}
+
+pub fn to_json(comparison: &Comparison) -> Result<String, Error> {
+    use error::*;
+
+    let mut json = serde_json::to_string_pretty(comparison).context(SerializeSnafu)?;
+    json.push('\n');
+    Ok(json)
+}
+
+type DependencyKey = (String, &'static str, Option<String>);
+
+fn dependencies(entry: &index_entry::Root) -> BTreeMap<DependencyKey, DependencySpec> {
+    entry
+        .deps
+        .iter()
+        .map(|dep| {
+            let key = (
+                dep.name.clone(),
+                closure::kind_name(&dep.kind),
+                dep.target.clone(),
+            );
+
+            // The order features are listed in doesn't matter
+            let mut features = dep.features.clone();
+            features.sort();
+
+            let spec = DependencySpec {
+                req: dep.req.clone(),
+                optional: dep.optional,
+                default_features: dep.default_features,
+                features,
+                package: dep.package.clone(),
+                registry: dep.registry.clone(),
+            };
+            (key, spec)
+        })
+        .collect()
+}
+
+fn features(entry: &index_entry::Root) -> BTreeMap<String, Vec<String>> {
+    let mut features = entry.features.clone();
+    for (name, values) in &entry.features2 {
+        features
+            .entry(name.clone())
+            .or_default()
+            .extend(values.iter().cloned());
+    }
+    for values in features.values_mut() {
+        values.sort();
+        values.dedup();
+    }
+    features
+}
+
+fn package_files(
+    r: &Registry,
+    entry: &index_entry::Root,
+) -> Result<BTreeMap<String, FileSummary>, Error> {
+    use error::*;
+
+    let path = r.crate_file_path_for(&entry.name, &entry.vers, &entry.cksum);
+    let crate_data = fs::read(&path).context(ReadCrateSnafu { path: &path })?;
+
+    list_package(&crate_data).context(UnpackSnafu { path })
+}
+
+/// Every file in the package, without the `name-version` directory
+/// they are archived in.
+fn list_package(crate_data: &[u8]) -> io::Result<BTreeMap<String, FileSummary>> {
+    let crate_data = flate2::read::GzDecoder::new(crate_data);
+    let mut crate_data = tar::Archive::new(crate_data);
+
+    let mut files = BTreeMap::new();
+
+    for entry in crate_data.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path()?;
+        let relative = path
+            .components()
+            .skip(1)
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if relative.is_empty() {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+
+        let summary = FileSummary {
+            size: data.len() as u64,
+            sha256: hex::encode(Sha256::digest(&data)),
+        };
+        files.insert(relative, summary);
+    }
+
+    Ok(files)
+}
+
+fn diff_maps<K: Ord, T: PartialEq>(
+    mut old: BTreeMap<K, T>,
+    new: BTreeMap<K, T>,
+) -> Vec<(K, Change<T>)> {
+    let mut changes = Vec::new();
+
+    for (key, new) in new {
+        match old.remove(&key) {
+            None => changes.push((key, Change::Added { new })),
+            Some(old) if old != new => changes.push((key, Change::Changed { old, new })),
+            Some(_) => {}
+        }
+    }
+    changes.extend(
+        old.into_iter()
+            .map(|(key, old)| (key, Change::Removed { old })),
+    );
+
+    changes.sort_by(|(a, _), (b, _)| a.cmp(b));
+    changes
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not read the index file {}", path.display()))]
+    Index {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("`{name}` version {version} is not in the registry"))]
+    VersionNotFound { name: CrateName, version: Version },
+
+    #[snafu(display("Could not read the crate file {}", path.display()))]
+    ReadCrate { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not list the files in the crate file {}", path.display()))]
+    Unpack { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not serialize the comparison"))]
+    Serialize { source: serde_json::Error },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::VersionNotFound { .. } => Some(ErrorKind::NotFound),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,374 @@
+//! Checks `margo-config.toml` more thoroughly than deserializing it,
+//! explaining problems that would otherwise be silently ignored.
+
+use std::fmt;
+
+use crate::{Config, ConfigV1, PublicNameCheck};
+
+/// Keys of the top-level table.
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "version",
+    "base_url",
+    "auth_required",
+    "html",
+    "crate_path",
+    "dependency_registries",
+    "forbid_external_dependencies",
+    "allowed_dependency_registries",
+    "package_limits",
+    "allow_names",
+    "version_floors",
+    "public_name_check",
+    "public_index",
+    "content_addressed_crates",
+    "host",
+    "content_types",
+    "precompressed",
+    "manifest",
+    "durability",
+    "trash",
+];
+
+/// Keys of the `[html]` table.
+const HTML_KEYS: &[&str] = &[
+    "enabled",
+    "suggested_registry_name",
+    "locale",
+    "strings",
+    "page_size",
+    "recently_published",
+    "analytics_snippet",
+    "include_sourcemaps",
+];
+
+/// Keys of the `[package_limits]` table.
+const PACKAGE_LIMITS_KEYS: &[&str] = &["max_entries", "max_entry_bytes", "max_total_bytes"];
+
+/// Keys of the `[content_types]` table.
+const CONTENT_TYPES_KEYS: &[&str] = &["index", "config", "crate"];
+
+/// Keys of the `[manifest]` table.
+const MANIFEST_KEYS: &[&str] = &["enabled", "merkle_tree", "signing_key"];
+
+/// Keys of the `[trash]` table.
+const TRASH_KEYS: &[&str] = &["retention_days", "dir"];
+
+/// The tables whose keys are checked.
+const TABLES: [(&str, &[&str]); 5] = [
+    ("html", HTML_KEYS),
+    ("package_limits", PACKAGE_LIMITS_KEYS),
+    ("content_types", CONTENT_TYPES_KEYS),
+    ("manifest", MANIFEST_KEYS),
+    ("trash", TRASH_KEYS),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    /// Margo cannot use the configuration.
+    Error,
+    /// Margo can use the configuration, but probably not as intended.
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl Problem {
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    fn suggest(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}: {}", self.message)?;
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n  help: {suggestion}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the problems in the contents of a `margo-config.toml` file.
+pub fn validate(config: &str) -> Vec<Problem> {
+    let table = match toml::from_str::<toml::Table>(config) {
+        Ok(table) => table,
+        Err(e) => return vec![Problem::error(e.to_string())],
+    };
+
+    let mut problems = Vec::new();
+
+    unknown_keys(&mut problems, "", &table, TOP_LEVEL_KEYS);
+    for (name, known) in TABLES {
+        if let Some(toml::Value::Table(t)) = table.get(name) {
+            unknown_keys(&mut problems, &format!("{name}."), t, known);
+        }
+    }
+
+    match toml::Value::Table(table).try_into::<Config>() {
+        Ok(Config::V1(config)) => inconsistencies(&mut problems, &config),
+        Err(e) => problems.push(Problem::error(e.message().to_owned())),
+    }
+
+    problems
+}
+
+fn unknown_keys(problems: &mut Vec<Problem>, prefix: &str, table: &toml::Table, known: &[&str]) {
+    for key in table.keys() {
+        if known.contains(&key.as_str()) {
+            continue;
+        }
+
+        let problem = Problem::warning(format!("unknown key `{prefix}{key}` is ignored"));
+        let problem = match closest(key, known) {
+            Some(k) => problem.suggest(format!("did you mean `{prefix}{k}`?")),
+            None => problem,
+        };
+        problems.push(problem);
+    }
+}
+
+fn inconsistencies(problems: &mut Vec<Problem>, config: &ConfigV1) {
+    let base_url = &config.base_url;
+
+    if !matches!(base_url.scheme(), "http" | "https") {
+        problems.push(Problem::error(format!(
+            "`base_url` must be an HTTP or HTTPS URL, not `{base_url}`"
+        )));
+    }
+
+    if !base_url.path().ends_with('/') {
+        problems.push(
+            Problem::warning(format!(
+                "`base_url` does not end with a slash, so Cargo will look for files beside `{base_url}` instead of inside it"
+            ))
+            .suggest(format!("use `{base_url}/`")),
+        );
+    }
+
+    let html = &config.html;
+    if !html.enabled {
+        let set = [
+            (
+                "suggested_registry_name",
+                html.suggested_registry_name.is_some(),
+            ),
+            ("strings", html.strings.is_some()),
+            ("page_size", html.page_size.is_some()),
+            ("recently_published", html.recently_published.is_some()),
+            ("analytics_snippet", html.analytics_snippet.is_some()),
+            ("include_sourcemaps", html.include_sourcemaps.is_some()),
+        ];
+
+        for (key, _) in set.into_iter().filter(|(_, set)| *set) {
+            problems.push(
+                Problem::warning(format!(
+                    "`html.{key}` has no effect because the HTML pages are disabled"
+                ))
+                .suggest("set `html.enabled = true` or remove the option"),
+            );
+        }
+    }
+
+    if !config.forbid_external_dependencies && !config.allowed_dependency_registries.is_empty() {
+        problems.push(
+            Problem::warning(
+                "`allowed_dependency_registries` has no effect unless `forbid_external_dependencies` is true",
+            )
+            .suggest("set `forbid_external_dependencies = true` or pass `--forbid-external-deps` to `margo add`"),
+        );
+    }
+
+    if config.host.is_none() && !config.content_types.is_default() {
+        problems.push(
+            Problem::warning("`content_types` has no effect unless `host` is set")
+                .suggest("pass `--host` to `margo init --reconfigure`"),
+        );
+    }
+
+    if config.public_name_check == PublicNameCheck::Off && config.public_index.is_some() {
+        problems.push(
+            Problem::warning("`public_index` has no effect because `public_name_check` is off")
+                .suggest("set `public_name_check` to `warn` or `deny`"),
+        );
+    }
+}
+
+/// The known key most similar to `key`, if any is similar enough to
+/// be a likely typo.
+fn closest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|k| (edit_distance(key, k), *k))
+        .filter(|(d, _)| *d <= 2)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != *cb);
+            current.push(substitute.min(prev[j + 1] + 1).min(current[j] + 1));
+        }
+        prev = current;
+    }
+
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn messages(config: &str) -> Vec<String> {
+        validate(config).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn valid_configuration_has_no_problems() {
+        let config = r#"
+            version = "1"
+            base_url = "https://example.com/registry/"
+
+            [html]
+            enabled = true
+            page_size = 50
+        "#;
+
+        assert_eq!(messages(config), Vec::<String>::new());
+    }
+
+    #[test]
+    fn problems_are_explained() {
+        let config = r#"
+            version = "1"
+            base_url = "https://example.com/registry"
+            allowed_dependency_registries = ["https://other.example.com/"]
+            forbid_external_dependecies = true
+
+            [html]
+            enabled = false
+            page_size = 50
+            loacle = "de"
+        "#;
+
+        assert_eq!(
+            messages(config),
+            [
+                "warning: unknown key `forbid_external_dependecies` is ignored\n  help: did you mean `forbid_external_dependencies`?",
+                "warning: unknown key `html.loacle` is ignored\n  help: did you mean `html.locale`?",
+                "warning: `base_url` does not end with a slash, so Cargo will look for files beside `https://example.com/registry` instead of inside it\n  help: use `https://example.com/registry/`",
+                "warning: `html.page_size` has no effect because the HTML pages are disabled\n  help: set `html.enabled = true` or remove the option",
+                "warning: `allowed_dependency_registries` has no effect unless `forbid_external_dependencies` is true\n  help: set `forbid_external_dependencies = true` or pass `--forbid-external-deps` to `margo add`",
+            ],
+        );
+    }
+
+    #[test]
+    fn unusable_configuration_is_an_error() {
+        let problems = validate("version = \"1\"\nbase_url = 5\n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, Severity::Error);
+
+        let problems = validate("base_url = ");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn known_keys_match_the_configuration() {
+        let mut config = ConfigV1::new("https://example.com/".parse().unwrap());
+        config.forbid_external_dependencies = true;
+        config.content_addressed_crates = true;
+        config.allow_names = vec!["a".into()];
+        config
+            .version_floors
+            .insert("a".parse().unwrap(), "1.0.0".parse().unwrap());
+        config.public_index = Some("https://example.com/".parse().unwrap());
+        config.public_name_check = PublicNameCheck::Warn;
+        config.dependency_registries.insert(
+            "https://a.example.com/".parse().unwrap(),
+            "https://b.example.com/".parse().unwrap(),
+        );
+        config.allowed_dependency_registries = vec!["https://a.example.com/".parse().unwrap()];
+        config.package_limits.max_entries = 1;
+        config.crate_path = "crates/{sha256-checksum}".parse().unwrap();
+        config.html.suggested_registry_name = Some("a".into());
+        config.html.locale = crate::HtmlLocale::De;
+        config.html.strings = Some("strings.toml".into());
+        config.html.page_size = std::num::NonZeroUsize::new(1);
+        config.html.recently_published = Some(1);
+        config.html.analytics_snippet = Some("a".into());
+        config.html.include_sourcemaps = Some(true);
+        config.host = Some(crate::hosting::Host::S3);
+        config.content_types.index = "text/plain; charset=utf-8".into();
+        config.precompressed = vec![crate::precompress::Encoding::Gzip];
+        config.manifest.enabled = true;
+        config.manifest.signing_key = Some("key".into());
+        config.durability = crate::Durability::Full;
+        config.trash.retention_days = 1;
+        config.trash.dir = Some("trash".into());
+
+        let table = toml::Table::try_from(Config::V1(config)).unwrap();
+
+        let mut keys = table.keys().map(String::as_str).collect::<Vec<_>>();
+        keys.sort_unstable();
+        let mut expected = TOP_LEVEL_KEYS.to_vec();
+        expected.sort_unstable();
+        assert_eq!(keys, expected);
+
+        for (name, known) in TABLES {
+            let mut keys = table[name]
+                .as_table()
+                .unwrap()
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            keys.sort_unstable();
+            let mut expected = known.to_vec();
+            expected.sort_unstable();
+            assert_eq!(keys, expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("locale", "loacle"), 2);
+        assert_eq!(closest("page_sise", HTML_KEYS), Some("page_size"));
+        assert_eq!(closest("something", HTML_KEYS), None);
+    }
+}
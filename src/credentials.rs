@@ -0,0 +1,198 @@
+use base64::Engine;
+use snafu::prelude::*;
+use std::{collections::BTreeMap, env, fs, io, path::PathBuf};
+use url::{Origin, Url};
+
+use crate::{env_fallback, EnvFallbackError, ENV_HTTP_PASSWORD, ENV_HTTP_USERNAME};
+
+const ENV_NETRC: &str = "MARGO_NETRC";
+
+/// Credentials used when Margo makes HTTP requests.
+///
+/// Credentials from the `MARGO_HTTP_USERNAME` and
+/// `MARGO_HTTP_PASSWORD` environment variables apply only to the
+/// registry's own URL, so they are never sent to other registries or
+/// crates.io, and take precedence over the netrc file there. The netrc
+/// file is read from `MARGO_NETRC`, falling back to `~/.netrc`.
+#[derive(Debug, Default)]
+pub struct Credentials {
+    from_env: Option<(Origin, Credential)>,
+    netrc: Netrc,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Credential {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credential {
+    pub fn authorization(&self) -> String {
+        let Self { username, password } = self;
+        let creds = format!("{username}:{password}");
+        let creds = base64::engine::general_purpose::STANDARD.encode(creds);
+        format!("Basic {creds}")
+    }
+}
+
+impl Credentials {
+    /// Loads the credentials, using those from the environment for
+    /// the registry at `registry`, if any.
+    pub fn load(registry: Option<&Url>) -> Result<Self, Error> {
+        use error::*;
+
+        let username = env_fallback::<String>(None, ENV_HTTP_USERNAME)?;
+        let password = env_fallback::<String>(None, ENV_HTTP_PASSWORD)?;
+        let from_env = registry.zip(username).map(|(registry, username)| {
+            let credential = Credential {
+                username,
+                password: password.unwrap_or_default(),
+            };
+            (registry.origin(), credential)
+        });
+
+        let explicit_path = env::var_os(ENV_NETRC).map(PathBuf::from);
+        let netrc_path = explicit_path.clone().or_else(|| {
+            let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+            Some(PathBuf::from(home).join(".netrc"))
+        });
+
+        let netrc = match netrc_path {
+            Some(path) => match fs::read_to_string(&path) {
+                Ok(contents) => Netrc::parse(&contents),
+                Err(e) if e.kind() == io::ErrorKind::NotFound && explicit_path.is_none() => {
+                    Netrc::default()
+                }
+                Err(e) => return Err(e).context(NetrcReadSnafu { path }),
+            },
+            None => Netrc::default(),
+        };
+
+        Ok(Self { from_env, netrc })
+    }
+
+    pub fn for_url(&self, url: &Url) -> Option<&Credential> {
+        if let Some((origin, c)) = &self.from_env {
+            if *origin == url.origin() {
+                return Some(c);
+            }
+        }
+
+        url.host_str()
+            .and_then(|host| self.netrc.machines.get(host))
+            .or(self.netrc.default.as_ref())
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(transparent)]
+    EnvFallback { source: EnvFallbackError },
+
+    #[snafu(display("Could not read the netrc file {}", path.display()))]
+    NetrcRead { source: io::Error, path: PathBuf },
+}
+
+#[derive(Debug, Default)]
+struct Netrc {
+    machines: BTreeMap<String, Credential>,
+    default: Option<Credential>,
+}
+
+impl Netrc {
+    fn parse(contents: &str) -> Self {
+        let mut this = Self::default();
+
+        let mut tokens = contents.split_whitespace();
+        let mut current = None;
+
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" => {
+                    this.finish(current.take());
+                    current = tokens
+                        .next()
+                        .map(|m| (Some(m.to_owned()), Credential::default()));
+                }
+                "default" => {
+                    this.finish(current.take());
+                    current = Some((None, Credential::default()));
+                }
+                "login" => {
+                    if let (Some((_, c)), Some(v)) = (&mut current, tokens.next()) {
+                        c.username = v.to_owned();
+                    }
+                }
+                "password" => {
+                    if let (Some((_, c)), Some(v)) = (&mut current, tokens.next()) {
+                        c.password = v.to_owned();
+                    }
+                }
+                "account" => {
+                    tokens.next();
+                }
+                // Macro definitions run until a blank line, which we
+                // can't see after splitting on whitespace. They are
+                // only used by FTP clients, so stop here.
+                "macdef" => break,
+                _ => {}
+            }
+        }
+
+        this.finish(current);
+
+        this
+    }
+
+    fn finish(&mut self, entry: Option<(Option<String>, Credential)>) {
+        match entry {
+            Some((Some(machine), c)) => {
+                self.machines.insert(machine, c);
+            }
+            Some((None, c)) => self.default = Some(c),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn netrc_parsing() {
+        let netrc = Netrc::parse(
+            "machine registry.example.com login alice password hunter2\n\
+             machine other.example.com\n  login bob\n  account ignored\n  password swordfish\n\
+             default login anonymous password guest\n",
+        );
+
+        let c = &netrc.machines["registry.example.com"];
+        assert_eq!(c.username, "alice");
+        assert_eq!(c.password, "hunter2");
+
+        let c = &netrc.machines["other.example.com"];
+        assert_eq!(c.username, "bob");
+        assert_eq!(c.password, "swordfish");
+
+        let c = netrc.default.as_ref().unwrap();
+        assert_eq!(c.username, "anonymous");
+
+        let registry = "https://registry.example.com/".parse::<Url>().unwrap();
+        let from_env = Credential {
+            username: "carol".to_owned(),
+            password: "secret".to_owned(),
+        };
+        let credentials = Credentials {
+            from_env: Some((registry.origin(), from_env)),
+            netrc,
+        };
+        let url = "https://registry.example.com/config.json".parse().unwrap();
+        assert_eq!(credentials.for_url(&url).unwrap().username, "carol");
+        let url = "https://other.example.com/config.json".parse().unwrap();
+        assert_eq!(credentials.for_url(&url).unwrap().username, "bob");
+        let url = "https://unknown.example.com/config.json".parse().unwrap();
+        assert_eq!(credentials.for_url(&url).unwrap().username, "anonymous");
+    }
+}
@@ -0,0 +1,179 @@
+//! Stable codes for the errors that users run into most often, each
+//! with a hint toward the usual fix. The codes are printed with the
+//! error so that reports can be triaged without reading the whole
+//! message, and `margo explain` prints a longer explanation of each
+//! from the `explanations` directory.
+
+use std::{fmt, str};
+
+use crate::http;
+
+/// Codes are never renumbered or reused; new codes are added at the
+/// end.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Code {
+    RegistryNotFound,
+    ConfigMalformed,
+    NotAPackage,
+    UnversionedDependencies,
+    ExternalDependencies,
+    NameNotAllowed,
+    PublicNameConflict,
+    DirtyPackage,
+    NameCaseConflict,
+    CrateNotFound,
+    Offline,
+    LockfileVersionMissing,
+}
+
+impl Code {
+    pub const ALL: [Self; 12] = [
+        Self::RegistryNotFound,
+        Self::ConfigMalformed,
+        Self::NotAPackage,
+        Self::UnversionedDependencies,
+        Self::ExternalDependencies,
+        Self::NameNotAllowed,
+        Self::PublicNameConflict,
+        Self::DirtyPackage,
+        Self::NameCaseConflict,
+        Self::CrateNotFound,
+        Self::Offline,
+        Self::LockfileVersionMissing,
+    ];
+
+    pub fn number(self) -> u16 {
+        match self {
+            Self::RegistryNotFound => 1,
+            Self::ConfigMalformed => 2,
+            Self::NotAPackage => 3,
+            Self::UnversionedDependencies => 4,
+            Self::ExternalDependencies => 5,
+            Self::NameNotAllowed => 6,
+            Self::PublicNameConflict => 7,
+            Self::DirtyPackage => 8,
+            Self::NameCaseConflict => 9,
+            Self::CrateNotFound => 10,
+            Self::Offline => 11,
+            Self::LockfileVersionMissing => 12,
+        }
+    }
+
+    pub fn hint(self) -> &'static str {
+        match self {
+            Self::RegistryNotFound => {
+                "are you pointing at the registry root? It is the directory containing `margo-config.toml`"
+            }
+            Self::ConfigMalformed => {
+                "run `margo config validate` to list the problems in `margo-config.toml`"
+            }
+            Self::NotAPackage => {
+                "was this file produced by `cargo package`? Pass the `.crate` file from `target/package/`"
+            }
+            Self::UnversionedDependencies => {
+                "add a `version` to each `path` and `git` dependency in `Cargo.toml`, then run `cargo package` again"
+            }
+            Self::ExternalDependencies => {
+                "add the dependencies to this registry first, or list their registries in `allowed_dependency_registries`"
+            }
+            Self::NameNotAllowed => {
+                "add a matching pattern to `allow_names` in `margo-config.toml`, or pass `--allow-any-name` for a one-off exception"
+            }
+            Self::PublicNameConflict => {
+                "rename the crate so Cargo can't choose the public one, or set `public_name_check = \"warn\"`"
+            }
+            Self::DirtyPackage => {
+                "commit the changes and run `cargo package` again, or add the crate without `--deny-dirty`"
+            }
+            Self::NameCaseConflict => "add the crate using the existing crate's capitalization",
+            Self::CrateNotFound => "check the spelling of the name with `margo list`",
+            Self::Offline => "run without `--offline` and with `MARGO_OFFLINE` unset",
+            Self::LockfileVersionMissing => {
+                "the lockfile was resolved against a version this registry doesn't have; add that version or run `cargo update`"
+            }
+        }
+    }
+
+    /// The causes and fixes, as Markdown.
+    pub fn explanation(self) -> &'static str {
+        match self {
+            Self::RegistryNotFound => include_str!("explanations/E001.md"),
+            Self::ConfigMalformed => include_str!("explanations/E002.md"),
+            Self::NotAPackage => include_str!("explanations/E003.md"),
+            Self::UnversionedDependencies => include_str!("explanations/E004.md"),
+            Self::ExternalDependencies => include_str!("explanations/E005.md"),
+            Self::NameNotAllowed => include_str!("explanations/E006.md"),
+            Self::PublicNameConflict => include_str!("explanations/E007.md"),
+            Self::DirtyPackage => include_str!("explanations/E008.md"),
+            Self::NameCaseConflict => include_str!("explanations/E009.md"),
+            Self::CrateNotFound => include_str!("explanations/E010.md"),
+            Self::Offline => include_str!("explanations/E011.md"),
+            Self::LockfileVersionMissing => include_str!("explanations/E012.md"),
+        }
+    }
+
+    /// Finds a code from the first underlying cause we recognize.
+    pub fn of_cause(e: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        let mut cause = Some(e);
+
+        while let Some(e) = cause {
+            if let Some(http::Error::Offline) = e.downcast_ref::<http::Error>() {
+                return Some(Self::Offline);
+            }
+
+            cause = e.source();
+        }
+
+        None
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MARGO-E{:03}", self.number())
+    }
+}
+
+/// Accepts `MARGO-E012`, `E012`, or `12`, ignoring case.
+impl str::FromStr for Code {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        let digits = upper.strip_prefix("MARGO-").unwrap_or(&upper);
+        let digits = digits.strip_prefix('E').unwrap_or(digits);
+
+        digits
+            .parse::<u16>()
+            .ok()
+            .and_then(|n| Self::ALL.into_iter().find(|c| c.number() == n))
+            .ok_or_else(|| format!("unknown error code `{s}`"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn codes_are_unique_and_formatted() {
+        let numbers = Code::ALL.map(Code::number);
+        assert_eq!(numbers.iter().collect::<BTreeSet<_>>().len(), numbers.len());
+
+        assert_eq!(Code::RegistryNotFound.to_string(), "MARGO-E001");
+        assert_eq!(Code::LockfileVersionMissing.to_string(), "MARGO-E012");
+    }
+
+    #[test]
+    fn explanations_start_with_their_code() {
+        for code in Code::ALL {
+            let heading = format!("# {code}: ");
+            assert!(code.explanation().starts_with(&heading), "{code}");
+            assert_eq!(code.to_string().parse::<Code>(), Ok(code));
+        }
+
+        assert_eq!("e12".parse::<Code>(), Ok(Code::LockfileVersionMissing));
+        assert!("MARGO-E999".parse::<Code>().is_err());
+    }
+}
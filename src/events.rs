@@ -0,0 +1,103 @@
+use semver::Version;
+use serde::Serialize;
+use std::{env, fmt, io::Write, path::PathBuf, sync::Arc};
+
+use crate::common::CrateName;
+
+/// Something that happened while modifying the registry, reported to
+/// applications that drive Margo and want to show precise progress.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A crate file's `Cargo.toml` was read.
+    CrateParsed { name: CrateName, version: Version },
+
+    /// The checksum recorded in the index was computed.
+    ChecksumComputed {
+        name: CrateName,
+        version: Version,
+        checksum: String,
+    },
+
+    /// A crate's index file was written.
+    IndexWritten {
+        name: CrateName,
+        version: Version,
+        path: PathBuf,
+    },
+
+    /// A crate file was copied into the registry.
+    CrateWritten {
+        name: CrateName,
+        version: Version,
+        path: PathBuf,
+    },
+
+    /// The HTML pages were regenerated.
+    #[cfg(feature = "html")]
+    HtmlRegenerated { path: PathBuf },
+}
+
+type Handler = dyn Fn(&Event) + Send + Sync;
+
+/// Where events are delivered. By default, events are discarded.
+#[derive(Clone, Default)]
+pub struct Sink(Option<Arc<Handler>>);
+
+impl Sink {
+    pub fn new(f: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(f)))
+    }
+
+    /// Writes each event as a line of JSON to stderr when requested
+    /// by the environment, otherwise discards them.
+    pub fn from_env() -> Self {
+        match env::var(ENV_EVENTS).as_deref() {
+            Ok("json") => Self::new(|event| {
+                if let Ok(event) = serde_json::to_string(event) {
+                    let _ = writeln!(std::io::stderr(), "{event}");
+                }
+            }),
+            _ => Self::default(),
+        }
+    }
+
+    pub fn emit(&self, event: Event) {
+        if let Some(f) = &self.0 {
+            f(&event);
+        }
+    }
+}
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Sink").field(&self.0.is_some()).finish()
+    }
+}
+
+const ENV_EVENTS: &str = "MARGO_EVENTS";
+
+#[cfg(all(test, feature = "html"))]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn events_are_delivered() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        let sink = Sink::new({
+            let seen = seen.clone();
+            move |e| seen.lock().unwrap().push(serde_json::to_string(e).unwrap())
+        });
+
+        sink.emit(Event::HtmlRegenerated {
+            path: PathBuf::from("registry"),
+        });
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [r#"{"event":"html_regenerated","path":"registry"}"#],
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Every file that is part of the registry, for tools that copy the
+//! registry somewhere else.
+
+use sha2::Digest;
+use snafu::prelude::*;
+use std::{
+    collections::BTreeSet,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    changes, common::CrateName, hosting, manifest, merkle, snapshot, ParseIndexError, Registry,
+    BLOB_DIR_NAME, CONFIG_FILE_NAME, CRATE_DIR_NAME, GENERATED_FILE_NAME, METADATA_DIR_NAME,
+    VERIFIED_FILE_NAME,
+};
+
+/// Where the HTML pages for each crate are written, matching the
+/// `html` module.
+const CRATE_PAGES_DIR: &str = "crate";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// The public `config.json`.
+    Config,
+    /// Margo's own configuration and state.
+    Margo,
+    Index,
+    Crate,
+    /// The data of content-addressed crate files.
+    Blob,
+    Metadata,
+    Html,
+    /// A gzip or brotli copy of another file.
+    Compressed,
+    Changes,
+    Snapshot,
+    Hosting,
+    /// The manifest for consumers, its signature, and the merkle tree.
+    Manifest,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Config => "config",
+            Self::Margo => "margo",
+            Self::Index => "index",
+            Self::Crate => "crate",
+            Self::Blob => "blob",
+            Self::Metadata => "metadata",
+            Self::Html => "html",
+            Self::Compressed => "compressed",
+            Self::Changes => "changes",
+            Self::Snapshot => "snapshot",
+            Self::Hosting => "hosting",
+            Self::Manifest => "manifest",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    /// Relative to the registry root.
+    pub path: PathBuf,
+    pub kind: Kind,
+    pub sha256: String,
+}
+
+/// The registry's files, ordered by path. When `name` is given, only
+/// the files for that crate are listed.
+pub fn list(r: &Registry, name: Option<&CrateName>) -> Result<Vec<File>, Error> {
+    use error::*;
+
+    let wanted = name.map(|name| paths_for_crate(r, name)).transpose()?;
+
+    let walker = walkdir::WalkDir::new(&r.path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            // Skip version control and other unrelated directories
+            e.depth() != 1
+                || !e.file_name().to_string_lossy().starts_with('.')
+                || e.file_type().is_file()
+        });
+
+    let mut files = Vec::new();
+
+    for entry in walker {
+        let entry = entry.context(WalkdirSnafu { path: &r.path })?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path();
+        let Ok(relative) = path.strip_prefix(&r.path) else {
+            continue;
+        };
+        let Some(kind) = classify(r, relative) else {
+            continue;
+        };
+
+        if let Some(wanted) = &wanted {
+            if !wanted.contains(&without_compression(relative)) {
+                continue;
+            }
+        }
+
+        let data = fs::read(path).context(ReadSnafu { path })?;
+
+        files.push(File {
+            path: relative.to_owned(),
+            kind,
+            sha256: hex::encode(sha2::Sha256::digest(data)),
+        });
+    }
+
+    Ok(files)
+}
+
+fn classify(r: &Registry, relative: &Path) -> Option<Kind> {
+    if without_compression(relative) != relative {
+        return classify(r, &without_compression(relative)).map(|_| Kind::Compressed);
+    }
+
+    let mut components = relative.components().map(|c| c.as_os_str().to_str());
+    let first = components.next()??;
+    let top = components.next().is_none();
+
+    let kind = match first {
+        "config.json" if top => Kind::Config,
+        CONFIG_FILE_NAME
+        | GENERATED_FILE_NAME
+        | VERIFIED_FILE_NAME
+        | changes::MIRROR_STATE_FILE_NAME
+            if top =>
+        {
+            Kind::Margo
+        }
+        changes::CHANGES_FILE_NAME if top => Kind::Changes,
+        manifest::MANIFEST_FILE_NAME | manifest::SIGNATURE_FILE_NAME | merkle::TREE_FILE_NAME
+            if top =>
+        {
+            Kind::Manifest
+        }
+        name if top && hosting::FILE_NAMES.contains(&name) => Kind::Hosting,
+        "index.html" | "health.html" | "health.json" if top => Kind::Html,
+        "assets" | CRATE_PAGES_DIR | "page" if !top => Kind::Html,
+        METADATA_DIR_NAME if !top => Kind::Metadata,
+        snapshot::SNAPSHOT_DIR_NAME if !top => Kind::Snapshot,
+        CRATE_DIR_NAME if !top => {
+            let second = relative.components().nth(1)?.as_os_str();
+            if second == BLOB_DIR_NAME {
+                Kind::Blob
+            } else if relative.extension().is_some_and(|ext| ext == "crate") {
+                Kind::Crate
+            } else {
+                return None;
+            }
+        }
+        _ => {
+            let name = relative.file_name()?.to_str()?.parse::<CrateName>().ok()?;
+            if r.index_file_path_for(&name) != r.path.join(relative) {
+                return None;
+            }
+            Kind::Index
+        }
+    };
+
+    Some(kind)
+}
+
+fn without_compression(path: &Path) -> PathBuf {
+    if path
+        .extension()
+        .is_some_and(|ext| ext == "gz" || ext == "br")
+    {
+        path.with_extension("")
+    } else {
+        path.to_owned()
+    }
+}
+
+/// The relative paths of the files that belong to the crate.
+fn paths_for_crate(r: &Registry, name: &CrateName) -> Result<BTreeSet<PathBuf>, Error> {
+    use error::*;
+
+    let index_path = r.index_file_path_for(name);
+    let index =
+        Registry::parse_index_file(&index_path).context(IndexSnafu { path: &index_path })?;
+
+    let mut paths = vec![
+        index_path,
+        r.metadata_file_path_for(name),
+        r.path
+            .join(CRATE_PAGES_DIR)
+            .join(name.as_str())
+            .join("index.html"),
+    ];
+
+    for (version, entry) in &index {
+        paths.push(r.crate_file_path_for(&entry.name, version, &entry.cksum));
+        if r.config.content_addressed_crates {
+            paths.push(r.blob_path_for(&entry.cksum));
+        }
+    }
+
+    Ok(paths
+        .into_iter()
+        .filter_map(|p| Some(p.strip_prefix(&r.path).ok()?.to_owned()))
+        .collect())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not list the files in `{}`", path.display()))]
+    Walkdir {
+        source: walkdir::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not read `{}`", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not parse the index file `{}`", path.display()))]
+    Index {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+}
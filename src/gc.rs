@@ -0,0 +1,162 @@
+//! Finding crate files that no index entry refers to, and index
+//! entries whose crate file is missing, such as after a crate file
+//! was deleted by hand or an index file was restored from a backup.
+
+use semver::Version;
+use snafu::prelude::*;
+use std::{collections::BTreeSet, fs, io, path::PathBuf};
+
+use crate::{
+    common::CrateName,
+    trash::{self, Disposal},
+    ListAllError, Registry, RemoveError, BLOB_DIR_NAME,
+};
+
+#[derive(Debug, Default)]
+pub struct Orphans {
+    /// Crate files, and content-addressed data, that no index entry
+    /// refers to.
+    pub files: Vec<PathBuf>,
+    /// Index entries whose crate file is missing.
+    pub entries: Vec<Entry>,
+}
+
+impl Orphans {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.entries.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: CrateName,
+    pub version: Version,
+    pub cksum: String,
+    /// The content-addressed data for the checksum still exists, so
+    /// the crate file can be linked to it again.
+    pub restorable: bool,
+}
+
+/// Compares the crate files on disk with the index.
+pub fn find(r: &Registry) -> Result<Orphans, Error> {
+    use error::*;
+
+    let crates = r.list_all()?;
+
+    let mut orphans = Orphans::default();
+    let mut crate_files = BTreeSet::new();
+    let mut checksums = BTreeSet::new();
+
+    for (name, index) in &crates {
+        for (version, entry) in index {
+            let path = r.crate_file_path_for(name, version, &entry.cksum);
+            checksums.insert(entry.cksum.clone());
+
+            if !path.is_file() {
+                orphans.entries.push(Entry {
+                    name: name.clone(),
+                    version: version.clone(),
+                    cksum: entry.cksum.clone(),
+                    restorable: r.blob_path_for(&entry.cksum).is_file(),
+                });
+            }
+
+            crate_files.insert(path);
+        }
+    }
+
+    let crate_dir = r.crate_dir();
+    let blob_dir = crate_dir.join(BLOB_DIR_NAME);
+
+    for entry in walkdir::WalkDir::new(&crate_dir) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if e.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound) => break,
+            Err(e) => return Err(e).context(WalkSnafu { path: &crate_dir }),
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry.into_path();
+        let orphaned = if path.starts_with(&blob_dir) {
+            let cksum = path.file_name().and_then(|n| n.to_str());
+            !cksum.is_some_and(|c| checksums.contains(c))
+        } else {
+            !crate_files.contains(&path)
+        };
+
+        if orphaned {
+            orphans.files.push(path);
+        }
+    }
+
+    Ok(orphans)
+}
+
+/// Moves the orphaned files to the trash or deletes them, and either
+/// links each crate file that is missing to its content-addressed
+/// data again or removes its version from the index.
+pub fn collect(r: &Registry, orphans: &Orphans, disposal: Disposal) -> Result<(), Error> {
+    for path in &orphans.files {
+        if disposal == Disposal::Trash {
+            trash::put(r, path, None)?;
+        }
+        r.remove_file_and_empty_dirs(path)?;
+    }
+
+    for entry in &orphans.entries {
+        if entry.restorable {
+            restore(r, entry)?;
+        } else {
+            let (name, version) = (entry.name.clone(), entry.version.clone());
+            r.remove(name, version, None, disposal)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn restore(r: &Registry, entry: &Entry) -> Result<(), Error> {
+    use error::*;
+
+    let blob = r.blob_path_for(&entry.cksum);
+    let data = fs::read(&blob).context(ReadSnafu { path: blob })?;
+
+    let path = r.crate_file_path_for(&entry.name, &entry.version, &entry.cksum);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(RestoreSnafu { path: dir })?;
+    }
+    r.write_crate_file(&path, &data, &entry.cksum)
+        .context(RestoreSnafu { path: &path })?;
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(transparent)]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not list the crate files in {}", path.display()))]
+    Walk {
+        source: walkdir::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(transparent)]
+    Remove {
+        #[snafu(source(from(RemoveError, Box::new)))]
+        source: Box<RemoveError>,
+    },
+
+    #[snafu(transparent)]
+    Trash { source: trash::Error },
+
+    #[snafu(display("Could not read the content-addressed data {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not restore the crate file {}", path.display()))]
+    Restore { source: io::Error, path: PathBuf },
+}
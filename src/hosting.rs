@@ -0,0 +1,243 @@
+//! Files that static hosts need beside the registry for it to be
+//! served correctly on the first deploy.
+
+use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+use std::{collections::BTreeMap, fs, io, path::PathBuf, str};
+
+use crate::{Registry, CRATE_DIR_NAME};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Host {
+    #[serde(rename = "github-pages")]
+    GitHubPages,
+    #[serde(rename = "gitlab-pages")]
+    GitLabPages,
+    S3,
+    Netlify,
+    CloudflarePages,
+}
+
+impl str::FromStr for Host {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "github-pages" => Self::GitHubPages,
+            "gitlab-pages" => Self::GitLabPages,
+            "s3" => Self::S3,
+            "netlify" => Self::Netlify,
+            "cloudflare-pages" => Self::CloudflarePages,
+            _ => {
+                return Err(format!(
+                    "unknown host `{s}`; expected `github-pages`, `gitlab-pages`, `s3`, `netlify`, or `cloudflare-pages`"
+                ))
+            }
+        })
+    }
+}
+
+/// The content types that registry files are served with. Cargo's
+/// sparse index support fails in confusing ways when a host guesses
+/// these wrongly.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContentTypes {
+    pub index: String,
+    pub config: String,
+    #[serde(rename = "crate")]
+    pub crate_file: String,
+}
+
+impl ContentTypes {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for ContentTypes {
+    fn default() -> Self {
+        Self {
+            index: "text/plain".into(),
+            config: "application/json".into(),
+            crate_file: "application/gzip".into(),
+        }
+    }
+}
+
+/// The content type each kind of registry file should be served with,
+/// as `(path pattern, content type)`. Patterns are absolute paths on
+/// the host and may end with `*` or use `:placeholder` segments.
+pub fn content_types(r: &Registry) -> Vec<(String, &str)> {
+    let base = r.config.base_url.path();
+    let types = &r.config.content_types;
+    let index = types.index.as_str();
+
+    vec![
+        (format!("{base}config.json"), types.config.as_str()),
+        (format!("{base}1/*"), index),
+        (format!("{base}2/*"), index),
+        (format!("{base}3/*"), index),
+        (format!("{base}:prefix1/:prefix2/:name"), index),
+        (
+            format!("{base}{CRATE_DIR_NAME}/*"),
+            types.crate_file.as_str(),
+        ),
+    ]
+}
+
+/// Writes the files `host` needs into the registry, returning their
+/// paths.
+pub fn write_files(r: &Registry, host: Host) -> Result<Vec<PathBuf>, Error> {
+    use error::*;
+
+    let files = match host {
+        // Jekyll skips files and directories starting with `_` or
+        // `.`, and isn't needed to serve static files.
+        Host::GitHubPages => vec![(".nojekyll", String::new())],
+        Host::GitLabPages => vec![("gitlab-pages.yml", gitlab_pages_job(r))],
+        Host::S3 => vec![("s3-bucket-policy.json", S3_BUCKET_POLICY.to_owned())],
+        Host::Netlify => vec![("netlify.toml", NETLIFY_TOML.to_owned())],
+        Host::CloudflarePages => vec![],
+    };
+
+    // Hosts that can't read `_headers` get the same rules as JSON,
+    // for deploy scripts to apply when uploading.
+    let hints = match host {
+        Host::Netlify | Host::CloudflarePages => ("_headers", headers_file(r)),
+        Host::GitHubPages | Host::GitLabPages | Host::S3 => (HEADERS_JSON, headers_json(r)?),
+    };
+
+    files
+        .into_iter()
+        .chain([hints])
+        .map(|(name, contents)| {
+            let path = r.path.join(name);
+            fs::write(&path, contents).context(WriteSnafu { path: &path })?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// The `_headers` file understood by Netlify and Cloudflare Pages.
+fn headers_file(r: &Registry) -> String {
+    content_types(r)
+        .into_iter()
+        .map(|(path, content_type)| format!("{path}\n  Content-Type: {content_type}\n"))
+        .collect()
+}
+
+const HEADERS_JSON: &str = "headers.json";
+
+/// Every file that [`write_files`] may write.
+pub const FILE_NAMES: &[&str] = &[
+    ".nojekyll",
+    "gitlab-pages.yml",
+    "s3-bucket-policy.json",
+    "netlify.toml",
+    "_headers",
+    HEADERS_JSON,
+];
+
+fn headers_json(r: &Registry) -> Result<String, Error> {
+    use error::*;
+
+    #[derive(Serialize)]
+    struct Rule<'a> {
+        path: String,
+        headers: BTreeMap<&'static str, &'a str>,
+    }
+
+    let rules = content_types(r)
+        .into_iter()
+        .map(|(path, content_type)| Rule {
+            path,
+            headers: [("Content-Type", content_type)].into(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut json = serde_json::to_string_pretty(&rules).context(JsonSnafu)?;
+    json.push('\n');
+    Ok(json)
+}
+
+const NETLIFY_TOML: &str = r#"# Generated by Margo. Content types are set in `_headers`.
+[build]
+publish = "."
+"#;
+
+/// GitLab only publishes Pages from a job named `pages`; this one
+/// can be copied into the project's `.gitlab-ci.yml`.
+fn gitlab_pages_job(r: &Registry) -> String {
+    let dir = r
+        .path
+        .file_name()
+        .map_or_else(|| ".".into(), |n| n.to_string_lossy());
+
+    format!(
+        "# Generated by Margo. Copy this job into `.gitlab-ci.yml`.\n\
+         pages:\n  \
+           script:\n    \
+             - echo \"Publishing the registry\"\n  \
+           publish: {dir}\n  \
+           artifacts:\n    \
+             paths:\n      \
+               - {dir}\n  \
+           rules:\n    \
+             - if: $CI_COMMIT_BRANCH == $CI_DEFAULT_BRANCH\n"
+    )
+}
+
+/// Grants anonymous read access to the bucket's objects. Margo
+/// doesn't know the bucket name, so it must be filled in.
+const S3_BUCKET_POLICY: &str = r#"{
+  "Version": "2012-10-17",
+  "Statement": [
+    {
+      "Sid": "PublicReadForMargoRegistry",
+      "Effect": "Allow",
+      "Principal": "*",
+      "Action": "s3:GetObject",
+      "Resource": "arn:aws:s3:::REPLACE-WITH-BUCKET-NAME/*"
+    }
+  ]
+}
+"#;
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not serialize `{HEADERS_JSON}`"))]
+    Json { source: serde_json::Error },
+
+    #[snafu(display("Could not write the hosting file `{}`", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hosts_are_parsed() {
+        assert_eq!("github-pages".parse(), Ok(Host::GitHubPages));
+        assert_eq!("s3".parse(), Ok(Host::S3));
+        assert_eq!("cloudflare-pages".parse(), Ok(Host::CloudflarePages));
+        assert!("geocities".parse::<Host>().is_err());
+    }
+
+    #[test]
+    fn host_names_round_trip() {
+        for host in [
+            Host::GitHubPages,
+            Host::GitLabPages,
+            Host::S3,
+            Host::Netlify,
+            Host::CloudflarePages,
+        ] {
+            let name = serde_json::to_value(host).unwrap();
+            assert_eq!(name.as_str().unwrap().parse(), Ok(host));
+        }
+    }
+}
@@ -1,50 +1,400 @@
 use indoc::formatdoc;
 use maud::{html, Markup, PreEscaped, DOCTYPE};
+use notify::{RecursiveMode, Watcher};
 use semver::Version;
+use serde::Serialize;
 use snafu::prelude::*;
-use std::{fs, io, path::PathBuf};
+use std::{
+    cmp,
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
 
-use crate::{index_entry, ConfigV1, Index, ListAll, Registry};
+use crate::{
+    common::{CrateName, GeneratedBy, Timestamp},
+    index_entry, latest_version, lock, metadata, temp_path_beside, ConfigV1, Index, ListAll,
+    Metrics, OpenError, Registry, Verification, METADATA_DIR_NAME,
+};
 
-#[rustfmt::skip]
-mod assets;
+mod assets {
+    include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+}
+mod strings;
+
+use strings::Strings;
+
+/// Where the per-crate pages are written, relative to the registry
+/// root. Index directories never have names this long.
+const CRATE_PAGES_DIR: &str = "crate";
+
+/// Where the second and later pages of the crate list are written
+/// when the list is split into pages.
+const LISTING_PAGES_DIR: &str = "page";
+
+const HEALTH_HTML: &str = "health.html";
+pub const HEALTH_JSON: &str = "health.json";
+
+/// Choices for a single generation of the HTML, as opposed to the
+/// registry's configuration.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Options {
+    /// Don't remove asset files written by previous versions of the
+    /// UI.
+    pub keep_old_assets: bool,
+}
 
-pub fn write(registry: &Registry) -> Result<(), Error> {
+pub fn write(registry: &Registry, options: Options) -> Result<(), Error> {
     use error::*;
 
+    let Options { keep_old_assets } = options;
+
+    // Reading the index while another process changes it could
+    // describe a version without its crate file, or lose an entry
+    let _lock = lock::shared(registry)?;
+
+    let html = &registry.config.html;
+    let strings_path = html.strings.as_ref().map(|p| registry.path.join(p));
+    let strings = Strings::load(html.locale, strings_path.as_deref())?;
+
+    let generated = GeneratedBy::now();
+    let site = Site {
+        config: &registry.config,
+        strings: &strings,
+        generated: &generated,
+    };
+
     let crates = registry.list_all()?;
-    let index = index(&registry.config, &crates).into_string();
+    let metadata = registry.list_all_metadata()?;
+
+    let recent = recently_published(&crates, &metadata, html.recently_published());
+
+    let listed = crates.iter().collect::<Vec<_>>();
+    let page_size = html.page_size.map_or(listed.len(), NonZeroUsize::get);
+    let mut listings = listed.chunks(page_size.max(1));
+    let first = listings.next().unwrap_or_default();
+    let total = listings.len() + 1;
+
+    let index = index(
+        &site,
+        first,
+        &metadata,
+        &recent,
+        Pagination { current: 1, total },
+    )
+    .into_string();
     let index_path = registry.path.join("index.html");
-    fs::write(&index_path, index).context(WriteIndexSnafu { path: index_path })?;
+    write_atomically(&index_path, index).context(WriteIndexSnafu { path: index_path })?;
 
-    let assets_dir = registry.path.join("assets");
-    fs::create_dir_all(&assets_dir).context(AssetDirSnafu { path: &assets_dir })?;
+    let listing_dir = registry.path.join(LISTING_PAGES_DIR);
+    clean_dir(&listing_dir)?;
 
-    let css_path = assets_dir.join(assets::CSS_NAME);
-    fs::write(&css_path, assets::CSS).context(CssSnafu { path: &css_path })?;
+    for (current, listed) in (2..).zip(listings) {
+        let page_dir = listing_dir.join(current.to_string());
+        fs::create_dir_all(&page_dir).context(ListingPageDirSnafu { path: &page_dir })?;
+
+        let pagination = Pagination { current, total };
+        let page = listing_page(&site, listed, &metadata, pagination);
+        let page_path = page_dir.join("index.html");
+        write_atomically(&page_path, page.into_string())
+            .context(ListingPageSnafu { path: page_path })?;
+    }
+
+    let pages_dir = registry.path.join(CRATE_PAGES_DIR);
+    clean_dir(&pages_dir)?;
 
-    let css_map_path = {
-        let mut css_map_path = css_path;
-        css_map_path.as_mut_os_string().push(".map");
-        css_map_path
+    let dependents = reverse_dependencies(&crates);
+
+    for (name, index) in &crates {
+        let page_dir = pages_dir.join(name.as_str());
+        fs::create_dir_all(&page_dir).context(CratePageDirSnafu { path: &page_dir })?;
+
+        let dependents = dependents.get(name);
+        let page =
+            crate_page(&site, &crates, metadata.get(name), dependents, name, index).into_string();
+        let page_path = page_dir.join("index.html");
+        write_atomically(&page_path, page).context(CratePageSnafu { path: page_path })?;
+    }
+
+    let metrics = registry.metrics()?;
+    let last_verification = registry.last_verification()?;
+    let health = Health {
+        generated: &generated,
+        metrics: &metrics,
+        last_verification: last_verification.as_ref(),
     };
-    fs::write(&css_map_path, assets::CSS_MAP).context(CssMapSnafu {
-        path: &css_map_path,
+
+    let mut health_json =
+        serde_json::to_string_pretty(&health).context(HealthJsonSerializeSnafu)?;
+    health_json.push('\n');
+    let health_json_path = registry.path.join(HEALTH_JSON);
+    write_atomically(&health_json_path, health_json).context(HealthJsonSnafu {
+        path: health_json_path,
     })?;
 
-    let js_path = assets_dir.join(assets::JS_NAME);
-    fs::write(&js_path, assets::JS).context(JsSnafu { path: &js_path })?;
+    let health_page = health_page(&site, &health).into_string();
+    let health_page_path = registry.path.join(HEALTH_HTML);
+    write_atomically(&health_page_path, health_page).context(HealthPageSnafu {
+        path: health_page_path,
+    })?;
+
+    write_assets(&registry.path, html.include_sourcemaps(), keep_old_assets)
+}
+
+/// Writes a page to `root` linking to each of the registries in its
+/// subdirectories. The page uses the first registry's HTML settings,
+/// such as its locale.
+pub fn write_landing_page(
+    root: &Path,
+    registries: &[(String, Registry)],
+    options: Options,
+) -> Result<(), Error> {
+    use error::*;
+
+    let Options { keep_old_assets } = options;
+
+    let Some((_, first)) = registries.first() else {
+        return Ok(());
+    };
+
+    let html = &first.config.html;
+    let strings_path = html.strings.as_ref().map(|p| first.path.join(p));
+    let strings = Strings::load(html.locale, strings_path.as_deref())?;
+
+    let generated = GeneratedBy::now();
+    let site = Site {
+        config: &first.config,
+        strings: &strings,
+        generated: &generated,
+    };
+
+    let mut listed = Vec::new();
+    for (name, registry) in registries {
+        listed.push((name.as_str(), registry.list_all()?.len()));
+    }
 
-    let js_map_path = {
-        let mut js_map_path = js_path;
-        js_map_path.as_mut_os_string().push(".map");
-        js_map_path
+    let page = landing_page(&site, &listed).into_string();
+    let page_path = root.join("index.html");
+    write_atomically(&page_path, page).context(WriteIndexSnafu { path: page_path })?;
+
+    write_assets(root, html.include_sourcemaps(), keep_old_assets)
+}
+
+fn write_assets(root: &Path, include_sourcemaps: bool, keep_old_assets: bool) -> Result<(), Error> {
+    use error::*;
+
+    let assets_dir = root.join("assets");
+    fs::create_dir_all(&assets_dir).context(AssetDirSnafu { path: &assets_dir })?;
+
+    let asset = |content: &str| {
+        if include_sourcemaps {
+            content.to_owned()
+        } else {
+            without_sourcemap_url(content)
+        }
     };
-    fs::write(&js_map_path, assets::JS_MAP).context(JsMapSnafu { path: &js_map_path })?;
+
+    let css_path = assets_dir.join(assets::CSS_NAME);
+    fs::write(&css_path, asset(assets::CSS)).context(CssSnafu { path: &css_path })?;
+
+    if include_sourcemaps {
+        let css_map_path = {
+            let mut css_map_path = css_path;
+            css_map_path.as_mut_os_string().push(".map");
+            css_map_path
+        };
+        fs::write(&css_map_path, assets::CSS_MAP).context(CssMapSnafu {
+            path: &css_map_path,
+        })?;
+    }
+
+    let js_path = assets_dir.join(assets::JS_NAME);
+    fs::write(&js_path, asset(assets::JS)).context(JsSnafu { path: &js_path })?;
+
+    if include_sourcemaps {
+        let js_map_path = {
+            let mut js_map_path = js_path;
+            js_map_path.as_mut_os_string().push(".map");
+            js_map_path
+        };
+        fs::write(&js_map_path, assets::JS_MAP).context(JsMapSnafu { path: &js_map_path })?;
+    }
+
+    if !keep_old_assets {
+        clean_assets(&assets_dir, include_sourcemaps)?;
+    }
+
+    Ok(())
+}
+
+/// Removes assets written by previous versions of the UI. Asset names
+/// change with their content, so these would otherwise accumulate.
+/// Only files named like our assets are considered.
+fn clean_assets(assets_dir: &Path, include_sourcemaps: bool) -> Result<(), Error> {
+    use error::*;
+
+    let current = [assets::CSS_NAME, assets::JS_NAME];
+
+    let entries = fs::read_dir(assets_dir).context(AssetDirReadSnafu { path: assets_dir })?;
+
+    for entry in entries {
+        let entry = entry.context(AssetDirReadSnafu { path: assets_dir })?;
+        let name = entry.file_name();
+
+        let Some(name) = name.to_str() else { continue };
+        if !name.starts_with("ui.") {
+            continue;
+        }
+
+        let (asset, is_sourcemap) = match name.strip_suffix(".map") {
+            Some(asset) => (asset, true),
+            None => (name, false),
+        };
+        if current.contains(&asset) && (include_sourcemaps || !is_sourcemap) {
+            continue;
+        }
+
+        let path = entry.path();
+        fs::remove_file(&path).context(AssetRemoveSnafu { path })?;
+    }
 
     Ok(())
 }
 
+/// Removes the comment pointing browsers at an asset's sourcemap, for
+/// when the sourcemap isn't published.
+fn without_sourcemap_url(asset: &str) -> String {
+    asset
+        .lines()
+        .filter(|l| !l.trim_start().starts_with("//# sourceMappingURL="))
+        .filter(|l| !l.trim_start().starts_with("/*# sourceMappingURL="))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Writes to a temporary file beside `path` and renames it into place,
+/// so that a web server never serves a partially-written page.
+fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let tmp = temp_path_beside(path);
+
+    let result = fs::write(&tmp, contents).and_then(|()| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Removes previously generated pages so that pages for removed
+/// crates don't linger.
+fn clean_dir(path: &Path) -> Result<(), Error> {
+    match fs::remove_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).context(error::PagesCleanSnafu { path }),
+    }
+}
+
+/// Regenerates the HTML whenever the registry changes on disk, such
+/// as after a `git pull` or an edit by other tooling. Runs until an
+/// error occurs.
+pub fn watch(registry: &Registry, options: Options) -> Result<(), Error> {
+    use error::*;
+
+    let root = registry.path.clone();
+    let (tx, rx) = mpsc::channel();
+
+    let mut watcher = notify::recommended_watcher({
+        let root = root.clone();
+        move |evt: notify::Result<notify::Event>| {
+            if let Ok(evt) = evt {
+                // Regenerating reads every index file; don't let
+                // that trigger another regeneration.
+                if evt.kind.is_access() {
+                    return;
+                }
+                if evt.paths.iter().any(|p| is_registry_file(&root, p)) {
+                    let _ = tx.send(());
+                }
+            }
+        }
+    })
+    .context(WatcherCreateSnafu)?;
+
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .context(WatcherWatchSnafu { path: &root })?;
+
+    println!("Watching {} for changes", root.display());
+
+    loop {
+        recv_debounced(&rx)?;
+
+        // The configuration may have changed as well.
+        let registry = Registry::open(&root)?;
+        write(&registry, options)?;
+        registry.maybe_write_manifest()?;
+        registry.maybe_precompress()?;
+
+        println!("Regenerated the HTML");
+    }
+}
+
+/// Whether the path is part of the registry data, as opposed to our
+/// own output or unrelated files like version control metadata.
+fn is_registry_file(root: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    let Some(first) = relative.components().next() else {
+        return false;
+    };
+    let first = first.as_os_str();
+
+    // Compressed copies are written after regenerating
+    if path
+        .extension()
+        .is_some_and(|ext| ext == "gz" || ext == "br")
+    {
+        return false;
+    }
+
+    if first == "index.html"
+        || first == HEALTH_HTML
+        || first == HEALTH_JSON
+        || first == "assets"
+        || first == CRATE_PAGES_DIR
+        || first == LISTING_PAGES_DIR
+        || first == crate::manifest::MANIFEST_FILE_NAME
+        || first == crate::manifest::SIGNATURE_FILE_NAME
+        || first == crate::merkle::TREE_FILE_NAME
+    {
+        return false;
+    }
+
+    if first == METADATA_DIR_NAME {
+        return true;
+    }
+
+    !first.to_string_lossy().starts_with('.')
+}
+
+fn recv_debounced(rx: &mpsc::Receiver<()>) -> Result<(), mpsc::RecvError> {
+    // Wait for an initial event
+    rx.recv()?;
+
+    loop {
+        // Wait for subsequent events to stop coming in
+        match rx.recv_timeout(Duration::from_millis(250)) {
+            Ok(()) => continue,
+            Err(mpsc::RecvTimeoutError::Timeout) => return Ok(()),
+            _ => return Err(mpsc::RecvError),
+        };
+    }
+}
+
 #[derive(Debug, Snafu)]
 #[snafu(module)]
 pub enum Error {
@@ -52,9 +402,56 @@ pub enum Error {
     #[snafu(context(false))]
     ListAll { source: crate::ListAllError },
 
+    #[snafu(display("Could not load the HTML strings"))]
+    #[snafu(context(false))]
+    Strings { source: strings::Error },
+
+    #[snafu(display("Could not list the crate metadata"))]
+    #[snafu(context(false))]
+    Metadata { source: crate::MetadataError },
+
+    #[snafu(display("Could not lock the registry"))]
+    #[snafu(context(false))]
+    Lock { source: lock::Error },
+
     #[snafu(display("Could not write the HTML index page to {}", path.display()))]
     WriteIndex { source: io::Error, path: PathBuf },
 
+    #[snafu(display("Could not remove the old pages at {}", path.display()))]
+    PagesClean { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not create the crate list page directory at {}", path.display()))]
+    ListingPageDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the crate list page to {}", path.display()))]
+    ListingPage { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not create the crate page directory at {}", path.display()))]
+    CratePageDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the crate page to {}", path.display()))]
+    CratePage { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not gather the registry health"))]
+    #[snafu(context(false))]
+    Metrics {
+        #[snafu(source(from(crate::MetricsError, Box::new)))]
+        source: Box<crate::MetricsError>,
+    },
+
+    #[snafu(display("Could not read the last verification"))]
+    #[snafu(context(false))]
+    Verification { source: crate::VerifyError },
+
+    #[snafu(display("Could not serialize the registry health"))]
+    HealthJsonSerialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the registry health to {}", path.display()))]
+    HealthJson { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the registry health page to {}", path.display()))]
+    HealthPage { source: io::Error, path: PathBuf },
+
     #[snafu(display("Could not create the HTML asset directory at {}", path.display()))]
     AssetDir { source: io::Error, path: PathBuf },
 
@@ -69,148 +466,921 @@ pub enum Error {
 
     #[snafu(display("Could not write the JS sourcemap file to {}", path.display()))]
     JsMap { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not read the HTML asset directory at {}", path.display()))]
+    AssetDirRead { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not remove the old HTML asset at {}", path.display()))]
+    AssetRemove { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not create the filesystem watcher"))]
+    WatcherCreate { source: notify::Error },
+
+    #[snafu(display("Could not watch the registry at {}", path.display()))]
+    WatcherWatch {
+        source: notify::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Event channel receiver closed unexpectedly"))]
+    #[snafu(context(false))]
+    RxClosed { source: mpsc::RecvError },
+
+    #[snafu(display("Could not reopen the registry"))]
+    #[snafu(context(false))]
+    Reopen {
+        #[snafu(source(from(OpenError, Box::new)))]
+        source: Box<OpenError>,
+    },
+
+    #[snafu(display("Could not update the registry manifest"))]
+    #[snafu(context(false))]
+    Manifest {
+        #[snafu(source(from(crate::manifest::Error, Box::new)))]
+        source: Box<crate::manifest::Error>,
+    },
+
+    #[snafu(display("Could not update the compressed files"))]
+    #[snafu(context(false))]
+    Precompress {
+        #[snafu(source(from(crate::precompress::Error, Box::new)))]
+        source: Box<crate::precompress::Error>,
+    },
 }
 
 const CARGO_DOCS: &str =
     "https://doc.rust-lang.org/cargo/reference/registries.html#using-an-alternate-registry";
 
-fn index(config: &ConfigV1, crates: &ListAll) -> Markup {
-    let base_url = &config.base_url;
-    let suggested_name = config.html.suggested_registry_name();
+fn link(href: &str, content: &str) -> Markup {
+    html! {
+        a href=(href) class="underline text-blue-800 hover:text-blue-900 visited:text-purple-800" {
+            (content)
+        }
+    }
+}
 
-    let asset_head_elements = PreEscaped(assets::INDEX);
+/// Renders `template` as text, replacing `{placeholder}` with `value`.
+fn fill(template: &str, placeholder: &str, value: Markup) -> Markup {
+    let placeholder = format!("{{{placeholder}}}");
 
-    fn link(href: &str, content: &str) -> Markup {
-        html! {
-            a href=(href) class="underline text-blue-600 hover:text-blue-800 visited:text-purple-600" {
-                (content)
-            }
-        }
+    match template.split_once(&placeholder) {
+        Some((before, after)) => html! { (before) (value) (after) },
+        None => html! { (template) },
     }
+}
 
-    fn section(name: &str, id: &str, content: Markup) -> Markup {
-        html! {
-            section class="p-1" {
-                h1 class="text-2xl" {
-                    a class="hover:after:content-['_§']" id=(id) href={"#" (id)} {
-                        (name)
-                    }
+fn section(name: &str, id: &str, content: Markup) -> Markup {
+    html! {
+        section class="p-1" {
+            h1 class="text-2xl" {
+                a class="hover:after:content-['_§']" id=(id) href={"#" (id)} {
+                    (name)
                 }
-
-                (content)
             }
+
+            (content)
         }
     }
+}
 
-    fn code_block(content: impl AsRef<str>) -> Markup {
-        let content = content.as_ref();
+fn source_link(strings: &Strings, source: &metadata::Source) -> Markup {
+    let rev = source.rev.as_deref().map(|r| r.get(..10).unwrap_or(r));
 
-        let span_class = "col-start-1 row-start-1 leading-none p-1";
+    let link = match (&source.url, rev) {
+        (Some(_), Some(rev)) => match source.rev_url() {
+            Some(url) => link(&url, rev),
+            None => html! { code { (rev) } },
+        },
+        (Some(url), None) => link(url, url),
+        (None, Some(rev)) => html! { code { (rev) } },
+        (None, None) => html! {},
+    };
 
-        html! {
-            mg-copy {
-                pre class="relative border border-black bg-theme-rose-light m-1 p-1 overflow-x-auto" {
-                    button class="hidden absolute top-0 right-0 grid" data-target="copy" {
-                        span class=(span_class) data-target="state0" { "Copy" }
-                        span class={(span_class) " invisible"} data-target="state1" { "Copied" }
-                    }
-                    code data-target="content" { (content) }
+    html! {
+        (link)
+        @if source.dirty { " " (strings.dirty) }
+    }
+}
+
+fn code_block(strings: &Strings, content: impl AsRef<str>) -> Markup {
+    let content = content.as_ref();
+
+    let span_class = "col-start-1 row-start-1 leading-none p-1";
+
+    html! {
+        mg-copy {
+            pre class="relative border border-black bg-theme-rose-light m-1 p-1 overflow-x-auto" {
+                button type="button" class="hidden absolute top-0 right-0 grid" data-target="copy" aria-live="polite" {
+                    span class=(span_class) data-target="state0" { (strings.copy) }
+                    span class={(span_class) " invisible"} data-target="state1" { (strings.copied) }
                 }
+                code data-target="content" { (content) }
             }
         }
     }
+}
 
-    let config_stanza = formatdoc! {r#"
-        [registries]
-        {suggested_name} = {{ index = "sparse+{base_url}" }}
-    "#};
+/// The path of a crate's page, relative to the registry root.
+fn crate_page_path(name: &CrateName) -> String {
+    format!("{CRATE_PAGES_DIR}/{name}/")
+}
 
-    let cargo_add_stanza = formatdoc! {"
-        cargo add --registry {suggested_name} some-crate-name
-    "};
+/// Details shared by every page.
+#[derive(Debug, Copy, Clone)]
+struct Site<'a> {
+    config: &'a ConfigV1,
+    strings: &'a Strings,
+    generated: &'a GeneratedBy,
+}
+
+/// Tags that let chat tools and search engines describe a page when
+/// a link to it is shared.
+fn link_preview(site: &Site<'_>, title: &str, description: Option<&str>, path: &str) -> Markup {
+    let Site {
+        config, strings, ..
+    } = *site;
+    let url = format!("{}{path}", config.base_url);
+
+    html! {
+        link rel="canonical" href=(url);
+        meta property="og:type" content="website";
+        meta property="og:site_name" content=(strings.title);
+        meta property="og:title" content=(title);
+        meta property="og:url" content=(url);
+        @if let Some(description) = description {
+            meta name="description" content=(description);
+            meta property="og:description" content=(description);
+        }
+    }
+}
+
+/// The layout shared by every page. `path` is the page's directory
+/// relative to the registry root; links are made relative to the root
+/// so that they work wherever the registry is hosted. `head` is added
+/// to the page's `head` element.
+fn page(site: &Site<'_>, title: &str, path: &str, head: Markup, content: Markup) -> Markup {
+    let Site {
+        config,
+        strings,
+        generated,
+    } = *site;
+    let asset_head_elements = PreEscaped(assets::INDEX);
+    let analytics = config.html.analytics_snippet.as_deref().map(PreEscaped);
+    let root = "../".repeat(path.matches('/').count());
 
     html! {
         (DOCTYPE)
-        html lang="en-US" {
+        html lang=(strings::lang(config.html.locale)) {
             head {
                 meta charset="utf-8";
                 meta name="viewport" content="width=device-width, initial-scale=1";
-                title { "Margo Crate Registry" };
+                @if !root.is_empty() {
+                    base href=(root);
+                }
+                title { (title) };
+                (head)
                 (asset_head_elements);
+                @if let Some(analytics) = analytics {
+                    (analytics)
+                }
             }
 
             body class="flex flex-col min-h-screen bg-theme-salmon-light" {
+                // The `base` element would otherwise send this to the
+                // registry root.
+                a href={(path) "#main"} class="sr-only focus:not-sr-only focus:p-2" {
+                    (strings.skip_to_content)
+                }
+
                 header {
                     h1 class="text-3xl font-bold bg-theme-purple text-theme-salmon-light p-2 drop-shadow-xl" {
-                        "Margo Crate Registry"
+                        a href="./" { (strings.title) }
                     }
                 }
 
-                (section("Getting started", "getting-started", html! {
-                    ol class="list-inside list-decimal" {
-                        li {
-                            "Add the registry definition to your "
-                            code { ".cargo/config.toml" }
-                            ":"
+                main id="main" {
+                    (content)
+                }
 
-                            (code_block(config_stanza))
-                        }
+                footer class="grow place-content-end text-center" {
+                    span class="border-t border-dashed border-theme-purple" {
+                        (fill(&strings.powered_by, "margo", link("https://github.com/integer32llc/margo", "Margo")))
+                    }
+                    span class="block text-sm" {
+                        (strings.generated_by
+                            .replace("{version}", &generated.margo_version)
+                            .replace("{time}", &generated.at.to_string()))
+                    }
+                }
+            }
+        }
+    }
+}
 
-                        li {
-                            "Add your dependency to your project:"
+/// Which page of the crate list is being rendered, counting from 1.
+#[derive(Debug, Copy, Clone)]
+struct Pagination {
+    current: usize,
+    total: usize,
+}
+
+/// The path of a page of the crate list, relative to the registry
+/// root. The first page is the landing page.
+fn listing_page_path(n: usize) -> String {
+    match n {
+        1 => String::new(),
+        n => format!("{LISTING_PAGES_DIR}/{n}/"),
+    }
+}
+
+fn index(
+    site: &Site<'_>,
+    crates: &[(&CrateName, &Index)],
+    metadata: &BTreeMap<CrateName, metadata::Root>,
+    recent: &[Published<'_>],
+    pagination: Pagination,
+) -> Markup {
+    let Site {
+        config, strings, ..
+    } = *site;
+    let suggested_name = config.html.suggested_registry_name();
+
+    let config_stanza = config.registries_stanza(suggested_name);
 
-                            (code_block(cargo_add_stanza))
+    let cargo_add_stanza = formatdoc! {"
+        cargo add --registry {suggested_name} some-crate-name
+    "};
+
+    page(
+        site,
+        &strings.title,
+        "",
+        link_preview(site, &strings.title, None, ""),
+        html! {
+            @if !recent.is_empty() {
+                (section(&strings.recently_published, "recent", html! {
+                    ul class="list-inside list-disc" {
+                        @for p in recent {
+                            li {
+                                (link(&crate_page_path(p.name), &format!("{} {}", p.name, p.version)))
+                                " "
+                                mg-relative-time {
+                                    time datetime=(p.at) title=(p.at) { (p.at) }
+                                }
+                            }
                         }
                     }
-
-                    "For complete details, check the "
-                    (link(CARGO_DOCS, "Cargo documentation"))
-                    "."
                 }))
+            }
+
+            (section(&strings.getting_started, "getting-started", html! {
+                ol class="list-inside list-decimal" {
+                    li {
+                        (fill(&strings.add_registry_definition, "file", html! { code { ".cargo/config.toml" } }))
+
+                        (code_block(strings, config_stanza))
+                    }
+
+                    li {
+                        (strings.add_dependency)
+
+                        (code_block(strings, cargo_add_stanza))
+                    }
+                }
+
+                (fill(&strings.complete_details, "docs", link(CARGO_DOCS, &strings.cargo_documentation)))
+            }))
+
+            (crate_list(strings, crates, metadata, pagination))
+
+            @let tombstones = metadata
+                .iter()
+                .flat_map(|(c, m)| m.tombstones.iter().map(move |(v, t)| (c, v, t)))
+                .collect::<Vec<_>>();
+
+            @if !tombstones.is_empty() {
+                (section(&strings.removed_versions, "removed", html! {
+                    p { (strings.removed_versions_description) }
 
-                (section("Available crates", "crates", html! {
                     table class="table-fixed w-full" {
                         thead {
                             tr {
-                                th class="w-4/5 text-left" { "Name" }
-                                th { "Versions" }
+                                th scope="col" class="text-left" { (strings.name) }
+                                th scope="col" class="text-left" { (strings.version) }
+                                th scope="col" class="text-left" { (strings.removed) }
+                                th scope="col" class="text-left" { (strings.reason) }
                             }
                         }
 
                         tbody {
-                            @for (c, v) in crates {
+                            @for (c, v, t) in tombstones {
                                 tr class="hover:bg-theme-orange" {
-                                    td {
-                                        span class="truncate" { (c.as_str()) }
-                                    }
-                                    td {
-                                        select class="w-full" name="version" {
-                                            @for (v, c, select) in most_interesting(v) {
-                                                @let suffix = if c.yanked { " (yanked)" } else { "" };
-                                                option selected[select] { (v) (suffix) }
-                                            }
+                                    td { (c.as_str()) }
+                                    td { (v) }
+                                    td { (t.removed_at) }
+                                    td { (t.reason.as_deref().unwrap_or("")) }
+                                }
+                            }
+                        }
+                    }
+                }))
+            }
+        },
+    )
+}
+
+fn landing_page(site: &Site<'_>, registries: &[(&str, usize)]) -> Markup {
+    let strings = site.strings;
+
+    page(
+        site,
+        &strings.title,
+        "",
+        html! {},
+        html! {
+            (section(&strings.registries, "registries", html! {
+                table class="table-fixed w-full" {
+                    thead {
+                        tr {
+                            th scope="col" class="text-left" { (strings.name) }
+                            th scope="col" class="text-left" { (strings.health_crates) }
+                        }
+                    }
+
+                    tbody {
+                        @for (name, crates) in registries {
+                            tr class="hover:bg-theme-orange" {
+                                td { (link(&format!("{name}/"), name)) }
+                                td { (crates) }
+                            }
+                        }
+                    }
+                }
+            }))
+        },
+    )
+}
+
+/// A version that was added to the registry.
+struct Published<'a> {
+    name: &'a CrateName,
+    version: &'a Version,
+    at: Timestamp,
+}
+
+/// The `count` most recently added versions that are still in the
+/// registry, newest first.
+fn recently_published<'a>(
+    crates: &'a ListAll,
+    metadata: &'a BTreeMap<CrateName, metadata::Root>,
+    count: usize,
+) -> Vec<Published<'a>> {
+    let mut published = metadata
+        .iter()
+        .filter_map(|(name, m)| Some((name, crates.get(name)?, m)))
+        .flat_map(|(name, index, m)| {
+            m.published
+                .iter()
+                .filter(|(version, _)| index.contains_key(version))
+                .map(move |(version, &at)| Published { name, version, at })
+        })
+        .collect::<Vec<_>>();
+
+    published.sort_by_key(|p| cmp::Reverse(p.at));
+    published.truncate(count);
+    published
+}
+
+/// The crate list shared by the landing page and the later pages of
+/// the list.
+fn crate_list(
+    strings: &Strings,
+    crates: &[(&CrateName, &Index)],
+    metadata: &BTreeMap<CrateName, metadata::Root>,
+    pagination: Pagination,
+) -> Markup {
+    html! {
+        (section(&strings.available_crates, "crates", html! {
+            table class="table-fixed w-full" {
+                thead {
+                    tr {
+                        th scope="col" class="w-2/5 text-left" { (strings.name) }
+                        th scope="col" class="w-1/5 text-right" { (strings.downloads) }
+                        th scope="col" class="w-1/5 text-left" { (strings.source) }
+                        th scope="col" { (strings.versions) }
+                    }
+                }
+
+                tbody {
+                    @for &(c, v) in crates {
+                        tr class="hover:bg-theme-orange" {
+                            td {
+                                span class="truncate" { (link(&crate_page_path(c), c.as_str())) }
+                            }
+                            td class="text-right pr-2" {
+                                (metadata.get(c).map_or(0, |m| m.downloads.values().sum::<u64>()))
+                            }
+                            td class="truncate" {
+                                @if let Some(source) = latest_version(v).and_then(|l| metadata.get(c)?.sources.get(l)) {
+                                    (source_link(strings, source))
+                                }
+                            }
+                            td {
+                                select class="w-full" name="version" aria-label=(strings.version_of.replace("{crate}", c.as_str())) {
+                                    @for (v, c, select) in most_interesting(v) {
+                                        option selected[select] {
+                                            (v)
+                                            @if c.yanked { " " (strings.yanked) }
                                         }
                                     }
                                 }
                             }
                         }
                     }
-                }))
+                }
+            }
 
-                footer class="grow place-content-end text-center" {
-                    span class="border-t border-dashed border-theme-purple" {
-                        "Powered by "
-                        (link("https://github.com/integer32llc/margo", "Margo"))
+            (pagination_nav(strings, pagination))
+        }))
+    }
+}
+
+fn pagination_nav(strings: &Strings, pagination: Pagination) -> Markup {
+    let Pagination { current, total } = pagination;
+
+    html! {
+        @if total > 1 {
+            nav class="p-1" aria-label=(strings.pages) {
+                ol class="flex flex-wrap gap-2" {
+                    @for n in 1..=total {
+                        li {
+                            @if n == current {
+                                span class="font-bold" aria-current="page" { (n) }
+                            } @else {
+                                // The landing page's path is empty, which
+                                // would not be a link at all.
+                                @let href = match n {
+                                    1 => "./".to_owned(),
+                                    n => listing_page_path(n),
+                                };
+                                (link(&href, &n.to_string()))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A later page of the crate list.
+fn listing_page(
+    site: &Site<'_>,
+    crates: &[(&CrateName, &Index)],
+    metadata: &BTreeMap<CrateName, metadata::Root>,
+    pagination: Pagination,
+) -> Markup {
+    let Site { strings, .. } = *site;
+    let title = strings
+        .page_number
+        .replace("{page}", &pagination.current.to_string());
+    let title = format!("{title} - {}", strings.title);
+    let path = listing_page_path(pagination.current);
+
+    page(
+        site,
+        &title,
+        &path,
+        link_preview(site, &title, None, &path),
+        crate_list(strings, crates, metadata, pagination),
+    )
+}
+
+/// A summary that external monitoring can use to check that the
+/// deployed registry is fresh and complete.
+#[derive(Debug, Serialize)]
+struct Health<'a> {
+    #[serde(flatten)]
+    generated: &'a GeneratedBy,
+    #[serde(flatten)]
+    metrics: &'a Metrics,
+    last_verification: Option<&'a Verification>,
+}
+
+fn health_page(site: &Site<'_>, health: &Health<'_>) -> Markup {
+    let Site { strings, .. } = *site;
+    let title = format!("{} - {}", strings.health, strings.title);
+    let last_publish = health
+        .metrics
+        .last_publish_timestamp_seconds
+        .map(Timestamp::from_secs);
+
+    page(
+        site,
+        &title,
+        "",
+        link_preview(site, &title, None, HEALTH_HTML),
+        html! {
+            (section(&strings.health, "health", html! {
+                table class="table-auto" {
+                    tbody {
+                        tr {
+                            th scope="row" class="text-left pr-2" { (strings.health_generated_at) }
+                            td { (health.generated.at) }
+                        }
+                        tr {
+                            th scope="row" class="text-left pr-2" { (strings.health_last_published) }
+                            td {
+                                @if let Some(last_publish) = last_publish { (last_publish) }
+                            }
+                        }
+                        tr {
+                            th scope="row" class="text-left pr-2" { (strings.health_crates) }
+                            td { (health.metrics.crates) }
+                        }
+                        tr {
+                            th scope="row" class="text-left pr-2" { (strings.versions) }
+                            td { (health.metrics.versions) }
+                        }
+                        tr {
+                            th scope="row" class="text-left pr-2" { (strings.health_yanked_versions) }
+                            td { (health.metrics.yanked_versions) }
+                        }
+                        tr {
+                            th scope="row" class="text-left pr-2" { (strings.health_last_verified) }
+                            td {
+                                @if let Some(v) = health.last_verification {
+                                    (v.at)
+                                    @if v.deep { " (" (strings.health_deep) ")" }
+                                }
+                            }
+                        }
+                        tr {
+                            th scope="row" class="text-left pr-2" { (strings.health_verification_problems) }
+                            td {
+                                @if let Some(v) = health.last_verification { (v.problems) }
+                            }
+                        }
+                        tr {
+                            th scope="row" class="text-left pr-2" { (strings.health_margo_version) }
+                            td { (health.generated.margo_version) }
+                        }
+                    }
+                }
+
+                p { (link(HEALTH_JSON, HEALTH_JSON)) }
+            }))
+        },
+    )
+}
+
+/// A page for one crate. Each version's details are rendered up
+/// front; the version selector shows only the chosen version's
+/// details when JavaScript is available.
+fn crate_page(
+    site: &Site<'_>,
+    crates: &ListAll,
+    metadata: Option<&metadata::Root>,
+    dependents: Option<&BTreeSet<&CrateName>>,
+    name: &CrateName,
+    index: &Index,
+) -> Markup {
+    let Site {
+        config, strings, ..
+    } = *site;
+    let registry_name = config.html.suggested_registry_name();
+    let path = crate_page_path(name);
+
+    let latest = latest_version(index);
+    let preview_title = match latest {
+        Some(v) => format!("{name} {v}"),
+        None => name.to_string(),
+    };
+    let description = latest
+        .and_then(|v| metadata?.descriptions.get(v))
+        .map(String::as_str);
+
+    page(
+        site,
+        &format!("{name} - {}", strings.title),
+        &path,
+        link_preview(site, &preview_title, description, &path),
+        html! {
+            (section(name.as_str(), "crate", html! {
+                mg-versions {
+                    label class="m-1" for="version" { (strings.version) }
+                    select class="m-1" id="version" name="version" {
+                        @for (channel, versions) in versions_by_channel(index, metadata) {
+                            @if let Some(channel) = channel {
+                                optgroup label=(channel) {
+                                    @for (v, c, select) in versions {
+                                        (version_option(strings, v, c, select))
+                                    }
+                                }
+                            } @else {
+                                @for (v, c, select) in versions {
+                                    (version_option(strings, v, c, select))
+                                }
+                            }
+                        }
+                    }
+
+                    @for (v, c) in index.iter().rev() {
+                        div class="p-1" data-version=(v) {
+                            h2 class="text-xl" {
+                                (v)
+                                @if c.yanked { " " (strings.yanked) }
+                                @if let Some(channel) = metadata.and_then(|m| m.channels.get(v)) {
+                                    " (" (channel) ")"
+                                }
+                            }
+
+                            p { (strings.add_this_version) }
+                            (code_block(strings, cargo_add_snippet(registry_name, name, v)))
+
+                            p { (fill(&strings.add_to_cargo_toml, "file", html! { code { "Cargo.toml" } })) }
+                            (code_block(strings, cargo_toml_snippet(registry_name, name, v, c)))
+
+                            (feature_list(strings, c))
+
+                            (dependency_table(strings, crates, c))
+                        }
+                    }
+                }
+            }))
+
+            (dependent_list(strings, dependents))
+        },
+    )
+}
+
+fn version_option(
+    strings: &Strings,
+    version: &Version,
+    entry: &index_entry::Root,
+    select: bool,
+) -> Markup {
+    html! {
+        option value=(version) selected[select] {
+            (version)
+            @if entry.yanked { " " (strings.yanked) }
+        }
+    }
+}
+
+/// The versions of a crate, newest first, grouped by the channel they
+/// were published to. Versions without a channel come first so that
+/// ordinary releases aren't lost among nightly builds.
+fn versions_by_channel<'a>(
+    index: &'a Index,
+    metadata: Option<&'a metadata::Root>,
+) -> BTreeMap<Option<&'a str>, Vec<(&'a Version, &'a index_entry::Root, bool)>> {
+    let mut groups = BTreeMap::<_, Vec<_>>::new();
+
+    for (v, c, select) in most_interesting(index).rev() {
+        let channel = metadata.and_then(|m| m.channels.get(v)).map(String::as_str);
+        groups.entry(channel).or_default().push((v, c, select));
+    }
+
+    groups
+}
+
+fn feature_list(strings: &Strings, entry: &index_entry::Root) -> Markup {
+    let features = all_features(entry);
+    let defaults = default_features(&features);
+
+    html! {
+        @if features.keys().any(|f| *f != "default") {
+            h3 class="text-lg" { (strings.features) }
+
+            ul class="list-inside list-disc" {
+                @for (feature, enables) in &features {
+                    @if *feature != "default" {
+                        @let default = defaults.contains(feature);
+                        li class=[default.then_some("font-bold")] {
+                            code { (feature) }
+                            @if default { " " (strings.default_feature) }
+                            @if !enables.is_empty() {
+                                " "
+                                (fill(&strings.enables, "features", html! { code { (enables.join(", ")) } }))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Dependencies that are in this registry link to their own pages.
+fn dependency_table(strings: &Strings, crates: &ListAll, entry: &index_entry::Root) -> Markup {
+    use index_entry::DependencyKind;
+
+    let local_page =
+        |dep: &index_entry::Dependency| local_dependency(crates, dep).map(crate_page_path);
+
+    html! {
+        @if !entry.deps.is_empty() {
+            h3 class="text-lg" { (strings.dependencies) }
+
+            table class="table-auto" {
+                thead {
+                    tr {
+                        th scope="col" class="text-left pr-2" { (strings.name) }
+                        th scope="col" class="text-left pr-2" { (strings.requirement) }
+                        th scope="col" class="text-left pr-2" { (strings.kind) }
+                        th scope="col" class="text-left pr-2" { (strings.target) }
+                    }
+                }
+
+                tbody {
+                    @for dep in &entry.deps {
+                        tr class="hover:bg-theme-orange" {
+                            td class="pr-2" {
+                                @match local_page(dep) {
+                                    Some(href) => (link(&href, &dep.name)),
+                                    None => (dep.name),
+                                }
+                                @if let Some(package) = &dep.package {
+                                    " (" (package) ")"
+                                }
+                                @if dep.optional { " " (strings.optional) }
+                            }
+                            td class="pr-2" { code { (dep.req) } }
+                            td class="pr-2" {
+                                @match dep.kind {
+                                    DependencyKind::Normal => (strings.kind_normal),
+                                    DependencyKind::Build => (strings.kind_build),
+                                    DependencyKind::Dev => (strings.kind_dev),
+                                }
+                            }
+                            td class="pr-2" {
+                                @if let Some(target) = &dep.target { code { (target) } }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Crates in this registry that depend on this one in any version,
+/// so that yanking can be weighed against who would notice.
+fn dependent_list(strings: &Strings, dependents: Option<&BTreeSet<&CrateName>>) -> Markup {
+    let count = dependents.map_or(0, BTreeSet::len);
+    let summary = match count {
+        1 => strings.used_by_one.clone(),
+        _ => strings.used_by_other.replace("{count}", &count.to_string()),
+    };
+
+    html! {
+        (section(&strings.used_by, "used-by", html! {
+            p { (summary) }
+
+            @if let Some(dependents) = dependents {
+                ul class="list-inside list-disc" {
+                    @for d in dependents {
+                        li { (link(&crate_page_path(d), d.as_str())) }
                     }
                 }
             }
+        }))
+    }
+}
+
+/// The crate in this registry that a dependency refers to, if any.
+fn local_dependency<'a>(
+    crates: &'a ListAll,
+    dep: &index_entry::Dependency,
+) -> Option<&'a CrateName> {
+    if dep.registry.is_some() {
+        return None;
+    }
+    let package = dep.package.as_deref().unwrap_or(&dep.name);
+    let package = package.parse::<CrateName>().ok()?;
+    crates.get_key_value(&package).map(|(k, _)| k)
+}
+
+/// Maps each crate to the crates in this registry that depend on it.
+fn reverse_dependencies(crates: &ListAll) -> BTreeMap<&CrateName, BTreeSet<&CrateName>> {
+    let mut dependents = BTreeMap::<_, BTreeSet<_>>::new();
+
+    for (name, index) in crates {
+        for dep in index.values().flat_map(|c| &c.deps) {
+            if let Some(dep) = local_dependency(crates, dep) {
+                if dep != name {
+                    dependents.entry(dep).or_default().insert(name);
+                }
+            }
         }
     }
+
+    dependents
 }
 
-fn most_interesting(i: &Index) -> impl Iterator<Item = (&Version, &index_entry::Root, bool)> {
-    let last_non_yanked = i.iter().rfind(|(_, c)| !c.yanked).map(|(v, _)| v);
+/// `features` and `features2` together, as Cargo sees them.
+fn all_features(entry: &index_entry::Root) -> BTreeMap<&str, &[String]> {
+    entry
+        .features
+        .iter()
+        .chain(&entry.features2)
+        .map(|(f, enables)| (f.as_str(), enables.as_slice()))
+        .collect()
+}
+
+/// The features enabled by `default`, including those enabled
+/// indirectly by other default features.
+fn default_features<'a>(features: &BTreeMap<&'a str, &'a [String]>) -> BTreeSet<&'a str> {
+    let mut enabled = BTreeSet::new();
+    let mut pending = vec!["default"];
+
+    while let Some(feature) = pending.pop() {
+        let Some(enables) = features.get(feature) else {
+            continue;
+        };
 
-    i.iter()
-        .map(move |(v, c)| (v, c, Some(v) == last_non_yanked))
+        for enable in enables.iter() {
+            // Skip `dep:foo` and `foo/bar` entries
+            let enable = enable.as_str();
+            if enable.contains([':', '/']) {
+                continue;
+            }
+            if enabled.insert(enable) {
+                pending.push(enable);
+            }
+        }
+    }
+
+    enabled
+}
+
+fn cargo_add_snippet(registry_name: &str, name: &CrateName, version: &Version) -> String {
+    format!("cargo add --registry {registry_name} {name}@{version}\n")
+}
+
+fn cargo_toml_snippet(
+    registry_name: &str,
+    name: &CrateName,
+    version: &Version,
+    entry: &index_entry::Root,
+) -> String {
+    let features = all_features(entry)
+        .into_keys()
+        .filter(|f| *f != "default")
+        .collect::<Vec<_>>();
+
+    let mut snippet = String::from("[dependencies]\n");
+    if !features.is_empty() {
+        snippet.push_str(&format!("# Available features: {}\n", features.join(", ")));
+    }
+    snippet.push_str(&format!(
+        "{name} = {{ version = \"{version}\", registry = \"{registry_name}\" }}\n"
+    ));
+
+    snippet
+}
+
+fn most_interesting(
+    i: &Index,
+) -> impl DoubleEndedIterator<Item = (&Version, &index_entry::Root, bool)> {
+    let latest = latest_version(i);
+
+    i.iter().map(move |(v, c)| (v, c, Some(v) == latest))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sourcemap_urls_are_removed() {
+        let css = "a{color:red}\n/*# sourceMappingURL=ui.abc.css.map */";
+        let js = "let a=1;\n//# sourceMappingURL=ui.abc.js.map";
+
+        assert_eq!(without_sourcemap_url(css), "a{color:red}");
+        assert_eq!(without_sourcemap_url(js), "let a=1;");
+    }
+
+    #[test]
+    fn default_features_are_followed_transitively() {
+        let list = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let default = list(&["std", "dep:log"]);
+        let std = list(&["alloc", "serde?/std"]);
+        let alloc = list(&[]);
+        let extra = list(&["alloc"]);
+
+        let features = BTreeMap::from([
+            ("default", &default[..]),
+            ("std", &std[..]),
+            ("alloc", &alloc[..]),
+            ("extra", &extra[..]),
+        ]);
+
+        assert_eq!(
+            default_features(&features),
+            BTreeSet::from(["std", "alloc"]),
+        );
+    }
 }
@@ -0,0 +1,163 @@
+use serde::Deserialize;
+use snafu::prelude::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::HtmlLocale;
+
+/// The text shown on the generated pages.
+///
+/// Values may contain `{placeholders}` which are replaced when the
+/// page is rendered. A registry may replace any of the selected
+/// locale's values with its own strings file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Strings {
+    pub title: String,
+    pub skip_to_content: String,
+    pub powered_by: String,
+    pub generated_by: String,
+    pub copy: String,
+    pub copied: String,
+
+    pub recently_published: String,
+    pub registries: String,
+
+    pub getting_started: String,
+    pub add_registry_definition: String,
+    pub add_dependency: String,
+    pub complete_details: String,
+    pub cargo_documentation: String,
+
+    pub available_crates: String,
+    pub name: String,
+    pub downloads: String,
+    pub source: String,
+    pub versions: String,
+    pub version_of: String,
+    pub yanked: String,
+    pub dirty: String,
+    pub pages: String,
+    pub page_number: String,
+
+    pub removed_versions: String,
+    pub removed_versions_description: String,
+    pub version: String,
+    pub removed: String,
+    pub reason: String,
+
+    pub add_this_version: String,
+    pub add_to_cargo_toml: String,
+
+    pub features: String,
+    pub default_feature: String,
+    pub enables: String,
+
+    pub dependencies: String,
+    pub requirement: String,
+    pub kind: String,
+    pub target: String,
+    pub optional: String,
+    pub kind_normal: String,
+    pub kind_build: String,
+    pub kind_dev: String,
+
+    pub used_by: String,
+    pub used_by_one: String,
+    pub used_by_other: String,
+
+    pub health: String,
+    pub health_generated_at: String,
+    pub health_last_published: String,
+    pub health_crates: String,
+    pub health_yanked_versions: String,
+    pub health_last_verified: String,
+    pub health_deep: String,
+    pub health_verification_problems: String,
+    pub health_margo_version: String,
+}
+
+impl Strings {
+    /// The built-in strings for `locale`, with any values from the
+    /// file at `overrides` taking precedence.
+    pub fn load(locale: HtmlLocale, overrides: Option<&Path>) -> Result<Self, Error> {
+        use error::*;
+
+        let mut strings = toml::from_str::<toml::Table>(builtin(locale)).context(BuiltinSnafu {
+            locale: lang(locale),
+        })?;
+
+        if let Some(path) = overrides {
+            let user = fs::read_to_string(path).context(ReadSnafu { path })?;
+            let user = toml::from_str::<toml::Table>(&user).context(ParseSnafu { path })?;
+            strings.extend(user);
+        }
+
+        strings.try_into().context(InvalidSnafu)
+    }
+}
+
+/// The value for the `lang` attribute of pages in this locale.
+pub fn lang(locale: HtmlLocale) -> &'static str {
+    match locale {
+        HtmlLocale::En => "en",
+        HtmlLocale::De => "de",
+        HtmlLocale::Es => "es",
+        HtmlLocale::Fr => "fr",
+    }
+}
+
+fn builtin(locale: HtmlLocale) -> &'static str {
+    match locale {
+        HtmlLocale::En => include_str!("locales/en.toml"),
+        HtmlLocale::De => include_str!("locales/de.toml"),
+        HtmlLocale::Es => include_str!("locales/es.toml"),
+        HtmlLocale::Fr => include_str!("locales/fr.toml"),
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("The built-in `{locale}` strings are malformed"))]
+    Builtin {
+        source: toml::de::Error,
+        locale: &'static str,
+    },
+
+    #[snafu(display("Could not read the HTML strings file at {}", path.display()))]
+    Read {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not parse the HTML strings file at {}", path.display()))]
+    Parse {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("The HTML strings are invalid"))]
+    Invalid { source: toml::de::Error },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builtin_locales_are_complete() {
+        for locale in [
+            HtmlLocale::En,
+            HtmlLocale::De,
+            HtmlLocale::Es,
+            HtmlLocale::Fr,
+        ] {
+            if let Err(e) = Strings::load(locale, None) {
+                panic!("{}: {e:?}", lang(locale));
+            }
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use snafu::prelude::*;
+use std::{
+    env, fs,
+    io::{self, BufReader, Read},
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use url::Url;
+
+use crate::{credentials::Credentials, env_fallback, EnvFallbackError, Global};
+
+const ENV_PROXY: &str = "MARGO_HTTP_PROXY";
+const ENV_CA_BUNDLE: &str = "MARGO_CA_BUNDLE";
+const ENV_CONNECT_TIMEOUT: &str = "MARGO_HTTP_CONNECT_TIMEOUT";
+const ENV_TIMEOUT: &str = "MARGO_HTTP_TIMEOUT";
+const ENV_RETRIES: &str = "MARGO_HTTP_RETRIES";
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_RETRIES: u32 = 3;
+
+/// The wait before the first retry, doubling for each one after up
+/// to the maximum.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The HTTP client shared by every networked subcommand.
+///
+/// Configured from the environment:
+///
+/// - `MARGO_HTTP_PROXY`, `HTTPS_PROXY`, `HTTP_PROXY`, or `ALL_PROXY`
+///   (in that order) select a proxy, bypassed for hosts in `NO_PROXY`.
+/// - `MARGO_CA_BUNDLE` or `SSL_CERT_FILE` add PEM-encoded certificate
+///   authorities to the built-in roots.
+/// - `MARGO_HTTP_CONNECT_TIMEOUT` and `MARGO_HTTP_TIMEOUT` set the
+///   timeouts, in seconds.
+/// - `MARGO_HTTP_RETRIES` sets how many times a failed request is
+///   retried.
+///
+/// No client can be created when Margo is running offline.
+pub struct Client {
+    direct: ureq::Agent,
+    proxied: Option<ureq::Agent>,
+    no_proxy: NoProxy,
+    credentials: Credentials,
+    retries: u32,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl Client {
+    /// Creates a client for requests to `registry`, the registry
+    /// being managed, or only to other hosts when there is none.
+    pub fn from_env(global: &Global, registry: Option<&Url>) -> Result<Self, Error> {
+        use error::*;
+
+        ensure!(!global.offline, OfflineSnafu);
+
+        let connect_timeout =
+            env_fallback(None, ENV_CONNECT_TIMEOUT)?.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS);
+        let timeout = env_fallback(None, ENV_TIMEOUT)?.unwrap_or(DEFAULT_TIMEOUT_SECS);
+        let retries = env_fallback(None, ENV_RETRIES)?.unwrap_or(DEFAULT_RETRIES);
+
+        let ca_bundle = [ENV_CA_BUNDLE, "SSL_CERT_FILE"]
+            .into_iter()
+            .find_map(env::var_os)
+            .map(PathBuf::from);
+        let tls = ca_bundle.map(tls_config).transpose()?;
+
+        let builder = || {
+            let builder = ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(connect_timeout))
+                .timeout(Duration::from_secs(timeout));
+
+            match &tls {
+                Some(tls) => builder.tls_config(tls.clone()),
+                None => builder,
+            }
+        };
+
+        let proxy = [
+            ENV_PROXY,
+            "HTTPS_PROXY",
+            "https_proxy",
+            "HTTP_PROXY",
+            "http_proxy",
+            "ALL_PROXY",
+            "all_proxy",
+        ]
+        .into_iter()
+        .find_map(|key| env::var(key).ok().filter(|v| !v.is_empty()));
+
+        let direct = builder().build();
+        let proxied = match proxy {
+            Some(proxy) => {
+                let parsed = ureq::Proxy::new(&proxy)
+                    .map_err(Box::new)
+                    .context(ProxySnafu { proxy })?;
+                Some(builder().proxy(parsed).build())
+            }
+            None => None,
+        };
+
+        let no_proxy = ["NO_PROXY", "no_proxy"]
+            .into_iter()
+            .find_map(|key| env::var(key).ok())
+            .map(|v| NoProxy::parse(&v))
+            .unwrap_or_default();
+
+        let credentials = Credentials::load(registry)?;
+
+        Ok(Self {
+            direct,
+            proxied,
+            no_proxy,
+            credentials,
+            retries,
+        })
+    }
+
+    pub fn has_credentials_for(&self, url: &Url) -> bool {
+        self.credentials.for_url(url).is_some()
+    }
+
+    /// Performs a GET request. Responses with an error status code
+    /// are returned successfully with an empty body.
+    ///
+    /// Requests that fail in transit or with a status indicating a
+    /// temporary problem are retried, waiting longer each time.
+    pub fn get(&self, url: &Url, authenticate: bool) -> Result<Response, RequestError> {
+        let mut attempt = 0;
+
+        loop {
+            let result = self.get_once(url, authenticate);
+
+            let temporary = match &result {
+                Ok(response) => is_temporary_status(response.status),
+                Err(_) => true,
+            };
+
+            if !temporary || attempt >= self.retries {
+                return result;
+            }
+
+            attempt += 1;
+            thread::sleep(retry_delay(attempt));
+        }
+    }
+
+    fn get_once(&self, url: &Url, authenticate: bool) -> Result<Response, RequestError> {
+        use request_error::*;
+
+        let agent = match &self.proxied {
+            Some(proxied) if !self.no_proxy.matches(url) => proxied,
+            _ => &self.direct,
+        };
+
+        let mut request = agent.get(url.as_str());
+        if let (true, Some(credential)) = (authenticate, self.credentials.for_url(url)) {
+            request = request.set("Authorization", &credential.authorization());
+        }
+
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, response)) => {
+                return Ok(Response {
+                    status,
+                    content_type: response.content_type().to_owned(),
+                    body: Vec::new(),
+                })
+            }
+            Err(ureq::Error::Transport(e)) => {
+                return Err(e).context(TransportSnafu { url: url.clone() })
+            }
+        };
+
+        let status = response.status();
+        let content_type = response.content_type().to_owned();
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut body)
+            .context(BodySnafu { url: url.clone() })?;
+
+        Ok(Response {
+            status,
+            content_type,
+            body,
+        })
+    }
+}
+
+fn is_temporary_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+/// How long to wait before retry number `attempt`, counting from 1.
+fn retry_delay(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(RETRY_MAX_DELAY)
+}
+
+fn tls_config(path: PathBuf) -> Result<Arc<rustls::ClientConfig>, Error> {
+    use error::*;
+
+    let mut roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+    };
+
+    let file = fs::File::open(&path).context(CaBundleReadSnafu { path: &path })?;
+    let mut file = BufReader::new(file);
+    for cert in rustls_pemfile::certs(&mut file) {
+        let cert = cert.context(CaBundleReadSnafu { path: &path })?;
+        roots
+            .add(cert)
+            .context(CaBundleCertificateSnafu { path: &path })?;
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context(TlsSnafu)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(Arc::new(config))
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display(
+        "This operation needs network access, which is disabled by `--offline` or `MARGO_OFFLINE`"
+    ))]
+    Offline,
+
+    #[snafu(transparent)]
+    EnvFallback { source: EnvFallbackError },
+
+    #[snafu(transparent)]
+    Credentials { source: crate::credentials::Error },
+
+    #[snafu(display("Could not read the CA bundle {}", path.display()))]
+    CaBundleRead { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The CA bundle {} contains an invalid certificate", path.display()))]
+    CaBundleCertificate {
+        source: rustls::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not configure TLS"))]
+    Tls { source: rustls::Error },
+
+    #[snafu(display("The proxy `{proxy}` is invalid"))]
+    Proxy {
+        source: Box<ureq::Error>,
+        proxy: String,
+    },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum RequestError {
+    #[snafu(display("Could not fetch {url}"))]
+    Transport {
+        #[snafu(source(from(ureq::Transport, Box::new)))]
+        source: Box<ureq::Transport>,
+        url: Url,
+    },
+
+    #[snafu(display("Could not read the response body of {url}"))]
+    Body { source: io::Error, url: Url },
+}
+
+/// Hosts that should be contacted without the proxy.
+#[derive(Debug, Default)]
+struct NoProxy {
+    all: bool,
+    hosts: Vec<String>,
+}
+
+impl NoProxy {
+    fn parse(value: &str) -> Self {
+        let mut this = Self::default();
+
+        for host in value.split(',').map(str::trim).filter(|h| !h.is_empty()) {
+            if host == "*" {
+                this.all = true;
+            } else {
+                this.hosts
+                    .push(host.trim_start_matches('.').to_ascii_lowercase());
+            }
+        }
+
+        this
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        if self.all {
+            return true;
+        }
+
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+
+        self.hosts.iter().any(|h| {
+            host == *h
+                || host
+                    .strip_suffix(h.as_str())
+                    .is_some_and(|rest| rest.ends_with('.'))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn no_proxy_matching() {
+        let no_proxy = NoProxy::parse("localhost, .internal.example.com,registry.corp");
+
+        let matches = |u: &str| no_proxy.matches(&u.parse().unwrap());
+
+        assert!(matches("http://localhost:8080/"));
+        assert!(matches("https://internal.example.com/"));
+        assert!(matches("https://a.internal.example.com/"));
+        assert!(matches("https://registry.corp/config.json"));
+        assert!(!matches("https://notregistry.corp/"));
+        assert!(!matches("https://crates.io/"));
+
+        assert!(NoProxy::parse("*").matches(&"https://crates.io/".parse().unwrap()));
+    }
+
+    #[test]
+    fn retries_back_off() {
+        assert_eq!(retry_delay(1), Duration::from_millis(500));
+        assert_eq!(retry_delay(2), Duration::from_secs(1));
+        assert_eq!(retry_delay(3), Duration::from_secs(2));
+        assert_eq!(retry_delay(100), RETRY_MAX_DELAY);
+
+        assert!(is_temporary_status(503));
+        assert!(!is_temporary_status(404));
+    }
+
+    #[test]
+    fn offline_clients_are_refused() {
+        let global = Global {
+            offline: true,
+            ..Global::new().unwrap()
+        };
+
+        assert!(matches!(
+            Client::from_env(&global, None),
+            Err(Error::Offline)
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+//! Finding the versions whose requirements on a version are so narrow
+//! that yanking or removing it would leave them unresolvable, before
+//! downstream builds find out.
+
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use snafu::prelude::*;
+
+use crate::{
+    closure,
+    common::{CrateName, CrateVersion},
+    find_version, ErrorKind, ListAllError, Registry,
+};
+
+/// A version with a requirement that only the target satisfies.
+#[derive(Debug, Serialize)]
+pub struct Dependent {
+    #[serde(rename = "crate")]
+    pub name: CrateName,
+    pub version: Version,
+    /// Existing lockfiles can still use yanked versions, so they are
+    /// reported too.
+    pub yanked: bool,
+    pub req: VersionReq,
+    pub kind: &'static str,
+    pub optional: bool,
+}
+
+pub fn dependents(r: &Registry, target: &CrateVersion) -> Result<Vec<Dependent>, Error> {
+    use error::*;
+
+    let crates = r.list_all()?;
+
+    let index = crates
+        .iter()
+        .find(|(n, _)| n.as_str().eq_ignore_ascii_case(target.name.as_str()))
+        .map(|(_, index)| index);
+    let version = index
+        .and_then(|index| find_version(index, &target.version))
+        .context(VersionNotFoundSnafu {
+            target: target.clone(),
+        })?;
+    let index = index.into_iter().flatten();
+
+    // The versions Cargo would still select from
+    let others = index
+        .filter(|(v, e)| *v != version && !e.yanked)
+        .map(|(v, _)| v)
+        .collect::<Vec<_>>();
+
+    let mut dependents = Vec::new();
+    for (name, dependent_index) in &crates {
+        for (dependent_version, entry) in dependent_index {
+            for dep in &entry.deps {
+                let package = dep.package.as_deref().unwrap_or(&dep.name);
+                let local = dep
+                    .registry
+                    .as_ref()
+                    .map_or(true, |u| closure::is_here(r, u));
+                if !local || !package.eq_ignore_ascii_case(target.name.as_str()) {
+                    continue;
+                }
+
+                if !dep.req.matches(version) || others.iter().any(|v| dep.req.matches(v)) {
+                    continue;
+                }
+
+                dependents.push(Dependent {
+                    name: name.clone(),
+                    version: dependent_version.clone(),
+                    yanked: entry.yanked,
+                    req: dep.req.clone(),
+                    kind: closure::kind_name(&dep.kind),
+                    optional: dep.optional,
+                });
+            }
+        }
+    }
+
+    Ok(dependents)
+}
+
+pub fn to_json(dependents: &[Dependent]) -> Result<String, Error> {
+    use error::*;
+
+    let mut json = serde_json::to_string_pretty(dependents).context(SerializeSnafu)?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Fails when anything depends on the target alone.
+pub fn ensure_unused(dependents: &[Dependent], target: &CrateVersion) -> Result<(), Error> {
+    use error::*;
+
+    ensure!(
+        dependents.is_empty(),
+        ImpactedSnafu {
+            target: target.clone(),
+            count: dependents.len(),
+        }
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(transparent)]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("`{target}` is not in the registry"))]
+    VersionNotFound { target: CrateVersion },
+
+    #[snafu(display(
+        "Yanking or removing `{target}` would break {count} requirement(s) that no other version satisfies"
+    ))]
+    Impacted { target: CrateVersion, count: usize },
+
+    #[snafu(display("Could not serialize the dependents"))]
+    Serialize { source: serde_json::Error },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::VersionNotFound { .. } => Some(ErrorKind::NotFound),
+            Self::Impacted { .. } => Some(ErrorKind::Validation),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,534 @@
+//! Converting registries run by other implementations into Margo
+//! registries. Every implementation keeps a crates.io-style index,
+//! so each version's index entry is copied as-is, including whether
+//! it is yanked, along with its crate file.
+
+use semver::Version;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use snafu::prelude::*;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    str,
+};
+use url::Url;
+
+use crate::{
+    changes::{self, Action, Change},
+    common::CrateName,
+    ensure_last_segment_empty, expand_dl_template, http, index_entry, ErrorKind, Global, Index,
+    ParseIndexError, Registry, WriteIndexError,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Implementation {
+    Kellnr,
+    Alexandrie,
+    Ktra,
+    Estuary,
+}
+
+impl str::FromStr for Implementation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "kellnr" => Self::Kellnr,
+            "alexandrie" => Self::Alexandrie,
+            "ktra" => Self::Ktra,
+            "estuary" => Self::Estuary,
+            _ => {
+                return Err(format!(
+                    "unknown registry implementation `{s}`; expected `kellnr`, `alexandrie`, `ktra`, or `estuary`"
+                ))
+            }
+        })
+    }
+}
+
+impl Implementation {
+    const ALL: [Self; 4] = [Self::Kellnr, Self::Alexandrie, Self::Ktra, Self::Estuary];
+
+    /// Where the implementation stores a crate file, relative to its
+    /// crate storage directory.
+    fn crate_file_path(self, name: &str, version: &Version) -> PathBuf {
+        let file_name = format!("{name}-{version}.crate");
+        let version = version.to_string();
+
+        match self {
+            Self::Kellnr | Self::Alexandrie => file_name.into(),
+            Self::Ktra => [name, &version, "download"].iter().collect(),
+            Self::Estuary => [name, &version, &file_name].iter().collect(),
+        }
+    }
+
+    /// The layouts to try, starting with this implementation's own.
+    /// Storage directories may have been written by older versions
+    /// or moved between implementations, and the checksum is verified
+    /// either way.
+    fn crate_file_candidates(self, name: &CrateName, version: &Version) -> Vec<PathBuf> {
+        let names = [name.as_str().to_owned(), name.as_str().to_lowercase()];
+
+        let implementations = [self]
+            .into_iter()
+            .chain(Self::ALL.into_iter().filter(|&i| i != self));
+
+        let mut candidates = Vec::new();
+        for implementation in implementations {
+            for name in &names {
+                let path = implementation.crate_file_path(name, version);
+                if !candidates.contains(&path) {
+                    candidates.push(path);
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// Where the other registry's index is read from.
+enum Source {
+    /// A checkout of the index on this machine.
+    Local(PathBuf),
+    /// A sparse index served over HTTP.
+    Remote(Url),
+}
+
+#[derive(Deserialize)]
+struct ConfigJson {
+    dl: String,
+    api: Option<Url>,
+}
+
+struct Importer<'a> {
+    global: &'a Global,
+    implementation: Implementation,
+    source: Source,
+    crate_dir: Option<PathBuf>,
+    client: Option<http::Client>,
+}
+
+impl Importer<'_> {
+    fn client(&mut self) -> Result<&http::Client, Error> {
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => http::Client::from_env(self.global, None)?,
+        };
+        Ok(self.client.insert(client))
+    }
+
+    fn fetch(&mut self, url: &Url) -> Result<Option<Vec<u8>>, Error> {
+        use error::*;
+
+        let fetched = self.client()?.get(url, true)?;
+        match fetched.status {
+            200 => Ok(Some(fetched.body)),
+            404 | 410 => Ok(None),
+            status => StatusSnafu {
+                url: url.clone(),
+                status,
+            }
+            .fail(),
+        }
+    }
+
+    fn config_json(&mut self) -> Result<ConfigJson, Error> {
+        use error::*;
+
+        let data = match &self.source {
+            Source::Local(root) => {
+                let path = root.join("config.json");
+                fs::read(&path).context(ReadSnafu { path })?
+            }
+            Source::Remote(base_url) => {
+                let from = base_url.to_string();
+                let url = base_url.join("config.json").context(UrlSnafu)?;
+                self.fetch(&url)?.context(NotAnIndexSnafu { from })?
+            }
+        };
+
+        serde_json::from_slice(&data).context(ConfigJsonSnafu)
+    }
+
+    /// Every crate in the index. Sparse indexes can't be listed, so
+    /// the names come from the registry's search API instead.
+    fn names(&mut self, config: &ConfigJson) -> Result<Vec<CrateName>, Error> {
+        use error::*;
+
+        #[derive(Deserialize)]
+        struct Search {
+            crates: Vec<Found>,
+        }
+
+        #[derive(Deserialize)]
+        struct Found {
+            name: CrateName,
+        }
+
+        const PER_PAGE: usize = 100;
+
+        let base_url = match &self.source {
+            Source::Local(root) => return local_names(root),
+            Source::Remote(base_url) => base_url.clone(),
+        };
+        let api = config.api.clone().context(NoApiSnafu {
+            from: base_url.as_str(),
+        })?;
+
+        let mut names = Vec::new();
+        for page in 1.. {
+            let mut url = api.join("api/v1/crates").context(UrlSnafu)?;
+            url.query_pairs_mut()
+                .append_pair("q", "")
+                .append_pair("per_page", &PER_PAGE.to_string())
+                .append_pair("page", &page.to_string());
+
+            let data = self.fetch(&url)?.context(NoApiSnafu {
+                from: base_url.as_str(),
+            })?;
+            let Search { crates } = serde_json::from_slice(&data).context(SearchSnafu)?;
+
+            let found = crates.len();
+            let before = names.len();
+            for Found { name } in crates {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+
+            // Some registries ignore `page`, returning the same crates
+            if found < PER_PAGE || names.len() == before {
+                break;
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn index_file(&mut self, name: &CrateName) -> Result<Index, Error> {
+        use error::*;
+
+        // Cargo always requests the lowercased path
+        let relative = format!("{}/{}", name.prefix(), name).to_lowercase();
+
+        let data = match &self.source {
+            Source::Local(root) => {
+                let path = root.join(&relative);
+                fs::read(&path).context(ReadSnafu { path })?
+            }
+            Source::Remote(base_url) => {
+                let url = base_url.join(&relative).context(UrlSnafu)?;
+                self.fetch(&url)?.unwrap_or_default()
+            }
+        };
+
+        let mut index = Index::new();
+        for line in data.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+            let entry = serde_json::from_slice::<index_entry::Root>(line)
+                .context(IndexEntrySnafu { name: name.clone() })?;
+            index.insert(entry.vers.clone(), entry);
+        }
+        Ok(index)
+    }
+
+    fn crate_file(
+        &mut self,
+        config: &ConfigJson,
+        entry: &index_entry::Root,
+    ) -> Result<Vec<u8>, Error> {
+        use error::*;
+
+        let index_entry::Root {
+            name, vers, cksum, ..
+        } = entry;
+
+        let data = match &self.crate_dir {
+            Some(dir) => {
+                let candidates = self.implementation.crate_file_candidates(name, vers);
+                let path = candidates
+                    .into_iter()
+                    .map(|c| dir.join(c))
+                    .find(|p| p.is_file())
+                    .context(CrateMissingSnafu {
+                        name: name.clone(),
+                        version: vers.clone(),
+                    })?;
+                fs::read(&path).context(ReadSnafu { path })?
+            }
+            None => {
+                let url = expand_dl_template(&config.dl, name, vers, cksum);
+                let url = url.parse::<Url>().context(UrlSnafu)?;
+                self.fetch(&url)?.context(CrateMissingSnafu {
+                    name: name.clone(),
+                    version: vers.clone(),
+                })?
+            }
+        };
+
+        let actual = hex::encode(Sha256::digest(&data));
+        ensure!(
+            actual == *cksum,
+            ChecksumSnafu {
+                name: name.clone(),
+                version: vers.clone(),
+            }
+        );
+
+        Ok(data)
+    }
+}
+
+/// The crates in a checkout of an index, skipping `.git` and any
+/// files that aren't where Cargo would look for them.
+fn local_names(root: &Path) -> Result<Vec<CrateName>, Error> {
+    use error::*;
+
+    let walker = walkdir::WalkDir::new(root)
+        .sort_by_file_name()
+        .max_depth(3)
+        .into_iter()
+        .filter_entry(|e| e.depth() != 1 || e.file_name().len() <= 2);
+
+    let mut names = Vec::new();
+    for entry in walker {
+        let entry = entry.context(WalkdirSnafu { path: root })?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let Some(name) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<CrateName>().ok())
+        else {
+            continue;
+        };
+
+        let expected = format!("{}/{}", name.prefix(), name).to_lowercase();
+        if entry.path().strip_prefix(root).ok() == Some(Path::new(&expected)) {
+            names.push(name);
+        }
+    }
+
+    Ok(names)
+}
+
+/// Copies every crate version from the other registry, returning how
+/// many were imported. Versions that were already imported have their
+/// yank state updated; versions that exist with different contents
+/// are refused.
+///
+/// `from` is a checkout of the other registry's index or the URL of
+/// its sparse index. Crate files are read from `crate_dir` when it's
+/// given, and are otherwise downloaded.
+pub fn import(
+    global: &Global,
+    r: &Registry,
+    implementation: Implementation,
+    from: &str,
+    crate_dir: Option<PathBuf>,
+) -> Result<usize, Error> {
+    use error::*;
+
+    // Cargo's configuration spells sparse indexes with a prefix
+    let url = from.strip_prefix("sparse+").unwrap_or(from);
+    let source = match url.parse::<Url>() {
+        Ok(mut url) if matches!(url.scheme(), "http" | "https") => {
+            ensure_last_segment_empty(&mut url);
+            Source::Remote(url)
+        }
+        _ => Source::Local(from.into()),
+    };
+
+    let mut importer = Importer {
+        global,
+        implementation,
+        source,
+        crate_dir,
+        client: None,
+    };
+
+    let config = importer.config_json()?;
+    let names = importer.names(&config)?;
+
+    let mut imported = 0;
+
+    for name in &names {
+        let upstream = importer.index_file(name)?;
+        if upstream.is_empty() {
+            continue;
+        }
+
+        let index_path = r.index_file_path_for(name);
+        let mut local = Registry::parse_index_file(&index_path)
+            .context(IndexReadSnafu { path: &index_path })?;
+
+        let mut changes = Vec::new();
+
+        for (version, entry) in upstream {
+            match local.get_mut(&version) {
+                Some(existing) => {
+                    ensure!(
+                        existing.cksum == entry.cksum,
+                        ConflictSnafu {
+                            name: name.clone(),
+                            version,
+                        }
+                    );
+
+                    if existing.yanked != entry.yanked {
+                        existing.yanked = entry.yanked;
+                        let action = if entry.yanked {
+                            Action::Yank
+                        } else {
+                            Action::Unyank
+                        };
+                        changes.push(Change::now(&entry.name, &version, action));
+                    }
+                }
+                None => {
+                    let data = importer.crate_file(&config, &entry)?;
+                    let path = r.crate_file_path_for(&entry.name, &version, &entry.cksum);
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir).context(CrateWriteSnafu { path: dir })?;
+                    }
+                    r.write_crate_file(&path, &data, &entry.cksum)
+                        .context(CrateWriteSnafu { path: &path })?;
+
+                    println!("Imported `{}` version {version}", entry.name);
+                    changes.push(Change::now(&entry.name, &version, Action::Publish));
+                    local.insert(version, entry);
+                    imported += 1;
+                }
+            }
+        }
+
+        if let Some(dir) = index_path.parent() {
+            fs::create_dir_all(dir).context(IndexDirSnafu { path: dir })?;
+        }
+        Registry::write_index_file(local, &index_path, r.config.durability)
+            .context(IndexWriteSnafu { path: &index_path })?;
+
+        changes::record(r, &changes)?;
+    }
+
+    Ok(imported)
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(transparent)]
+    Client { source: http::Error },
+
+    #[snafu(transparent)]
+    Request { source: http::RequestError },
+
+    #[snafu(display("Could not construct a URL in the other registry"))]
+    Url { source: url::ParseError },
+
+    #[snafu(display("{url} responded with HTTP status {status}"))]
+    Status { url: Url, status: u16 },
+
+    #[snafu(display("`{from}` does not have a `config.json`, so it is not an index"))]
+    NotAnIndex { from: String },
+
+    #[snafu(display("The index's `config.json` is malformed"))]
+    ConfigJson { source: serde_json::Error },
+
+    #[snafu(display(
+        "`{from}` does not provide a search API, which is needed to list its crates; import from a checkout of its index instead"
+    ))]
+    NoApi { from: String },
+
+    #[snafu(display("The search API's response is malformed"))]
+    Search { source: serde_json::Error },
+
+    #[snafu(display("Could not list the files in {}", path.display()))]
+    Walkdir {
+        source: walkdir::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not read {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("An index entry for the crate `{name}` is malformed"))]
+    IndexEntry {
+        source: serde_json::Error,
+        name: CrateName,
+    },
+
+    #[snafu(display("Could not find the crate file for `{name}` version {version}"))]
+    CrateMissing { name: CrateName, version: Version },
+
+    #[snafu(display("The crate file for `{name}` version {version} does not match its checksum"))]
+    Checksum { name: CrateName, version: Version },
+
+    #[snafu(display(
+        "`{name}` version {version} is already in the registry with different contents"
+    ))]
+    Conflict { name: CrateName, version: Version },
+
+    #[snafu(display("Could not read the index file {}", path.display()))]
+    IndexRead {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not create the index directory {}", path.display()))]
+    IndexDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the index file {}", path.display()))]
+    IndexWrite {
+        source: WriteIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not write the crate file {}", path.display()))]
+    CrateWrite { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    Changes { source: changes::RecordError },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::CrateMissing { .. } | Self::NotAnIndex { .. } => Some(ErrorKind::NotFound),
+            Self::Conflict { .. } => Some(ErrorKind::Conflict),
+            Self::Checksum { .. } => Some(ErrorKind::Malformed),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crate_files_are_looked_up_in_each_layout() {
+        let name = "My_Crate".parse().unwrap();
+        let version = "1.2.3".parse().unwrap();
+
+        let candidates = Implementation::Ktra.crate_file_candidates(&name, &version);
+        let candidates = candidates
+            .iter()
+            .map(|p| p.to_str().unwrap().replace('\\', "/"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            candidates,
+            [
+                "My_Crate/1.2.3/download",
+                "my_crate/1.2.3/download",
+                "My_Crate-1.2.3.crate",
+                "my_crate-1.2.3.crate",
+                "My_Crate/1.2.3/My_Crate-1.2.3.crate",
+                "my_crate/1.2.3/my_crate-1.2.3.crate",
+            ],
+        );
+    }
+}
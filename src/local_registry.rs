@@ -0,0 +1,205 @@
+//! Copies of the registry in the layout of Cargo's `local-registry`
+//! sources, for builds that must not touch the network.
+
+use sha2::{Digest, Sha256};
+use snafu::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    common::CrateName, lockfile, public_names, ListAll, ListAllError, ParseIndexError, Registry,
+    WriteIndexError, CRATES_IO_INDEX_URL,
+};
+
+const INDEX_DIR_NAME: &str = "index";
+
+/// Where the local registry is written inside a Docker build context.
+const DOCKER_REGISTRY_DIR_NAME: &str = "local-registry";
+
+/// The Cargo configuration that replaces each source, as written in a
+/// lockfile, with the local registry at `dir`.
+pub fn cargo_config(r: &Registry, sources: &BTreeSet<String>, dir: &str) -> String {
+    use std::fmt::Write;
+
+    let ours = format!("sparse+{}", r.config.base_url);
+    let crates_io = [
+        format!("registry+{CRATES_IO_INDEX_URL}"),
+        format!("sparse+{}", public_names::CRATES_IO_INDEX),
+    ];
+
+    let mut config = String::new();
+    let mut others = 0;
+
+    for source in sources {
+        // Cargo names crates.io's source itself
+        if crates_io.contains(source) {
+            config.push_str("[source.crates-io]\n");
+        } else {
+            let name = if *source == ours {
+                r.config.html.suggested_registry_name().to_owned()
+            } else {
+                others += 1;
+                format!("replaced-{others}")
+            };
+            let url = source.strip_prefix("registry+").unwrap_or(source);
+            _ = write!(config, "[source.{name}]\nregistry = \"{url}\"\n");
+        }
+        config.push_str("replace-with = \"local-registry\"\n\n");
+    }
+
+    _ = write!(
+        config,
+        "[source.local-registry]\nlocal-registry = \"{dir}\"\n"
+    );
+    config
+}
+
+/// Writes a directory to copy into container builds, holding a local
+/// registry of the locked versions and a `.cargo/config.toml` that
+/// uses it without going to the network. Returns how many versions
+/// were exported.
+pub fn docker_context(r: &Registry, locked: &lockfile::Locked, out: &Path) -> Result<usize, Error> {
+    use error::*;
+
+    let exported = export(r, Some(&locked.crates), &out.join(DOCKER_REGISTRY_DIR_NAME))?;
+
+    // Relative paths are resolved from the directory holding `.cargo`
+    let mut config = cargo_config(r, &locked.sources, DOCKER_REGISTRY_DIR_NAME);
+    config.push_str("\n[net]\noffline = true\n");
+
+    let dir = out.join(".cargo");
+    fs::create_dir_all(&dir).context(CreateSnafu { path: &dir })?;
+    let path = dir.join("config.toml");
+    fs::write(&path, config).context(WriteSnafu { path })?;
+
+    Ok(exported)
+}
+
+/// Writes the index and crate files into `out`, returning how many
+/// versions were exported. When `only` is given, just those versions
+/// are exported. The index is rewritten each time, while crate files
+/// that were already exported with the right contents are kept.
+pub fn export(r: &Registry, only: Option<&[lockfile::Crate]>, out: &Path) -> Result<usize, Error> {
+    use error::*;
+
+    let crates = match only {
+        Some(only) => selected(r, only)?,
+        None => r.list_all()?,
+    };
+
+    fs::create_dir_all(out).context(CreateSnafu { path: out })?;
+
+    let index_dir = out.join(INDEX_DIR_NAME);
+    match fs::remove_dir_all(&index_dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).context(RemoveSnafu { path: index_dir }),
+    }
+
+    let mut crate_files = BTreeSet::new();
+    let mut exported = 0;
+
+    for (name, index) in crates {
+        for (version, entry) in &index {
+            let file_name = format!("{}-{version}.crate", entry.name);
+            let path = out.join(&file_name);
+            crate_files.insert(file_name);
+            exported += 1;
+
+            if is_exported(&path, &entry.cksum) {
+                continue;
+            }
+
+            let from = r.crate_file_path_for(&entry.name, version, &entry.cksum);
+            fs::copy(&from, &path).context(CopySnafu { from, path: &path })?;
+        }
+
+        let mut path = index_dir.clone();
+        name.append_prefix_directories(&mut path);
+        fs::create_dir_all(&path).context(CreateSnafu { path: &path })?;
+        path.push(name.as_str().to_ascii_lowercase());
+        Registry::write_index_file(index, &path, r.config.durability)
+            .context(IndexWriteSnafu { path: &path })?;
+    }
+
+    for entry in fs::read_dir(out).context(ListSnafu { path: out })? {
+        let entry = entry.context(ListSnafu { path: out })?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        if file_name.ends_with(".crate") && !crate_files.contains(&*file_name) {
+            let path = entry.path();
+            fs::remove_file(&path).context(RemoveSnafu { path })?;
+        }
+    }
+
+    Ok(exported)
+}
+
+/// Whether the crate file at `path` was already exported, rather than
+/// missing, truncated, or left over from a different package with the
+/// same name and version.
+fn is_exported(path: &Path, cksum: &str) -> bool {
+    fs::read(path).is_ok_and(|data| hex::encode(Sha256::digest(data)) == cksum)
+}
+
+/// The index entries for just the given versions.
+fn selected(r: &Registry, only: &[lockfile::Crate]) -> Result<ListAll, Error> {
+    use error::*;
+
+    let mut wanted = BTreeMap::<&CrateName, BTreeSet<_>>::new();
+    for c in only {
+        wanted.entry(&c.name).or_default().insert(&c.version);
+    }
+
+    let mut crates = ListAll::new();
+    for (name, versions) in wanted {
+        let path = r.index_file_path_for(name);
+        let mut index = Registry::parse_index_file(&path).context(IndexReadSnafu { path })?;
+        index.retain(|v, _| versions.contains(v));
+        crates.insert(name.clone(), index);
+    }
+
+    Ok(crates)
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(transparent)]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not read the index file {}", path.display()))]
+    IndexRead {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not create the directory {}", path.display()))]
+    Create { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the index file {}", path.display()))]
+    IndexWrite {
+        source: WriteIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not copy {} to {}", from.display(), path.display()))]
+    Copy {
+        source: io::Error,
+        from: PathBuf,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not write {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not list the directory {}", path.display()))]
+    List { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not remove {}", path.display()))]
+    Remove { source: io::Error, path: PathBuf },
+}
@@ -0,0 +1,81 @@
+//! Advisory locking of the registry, so that one process doesn't read
+//! the index while another is partway through changing it. Commands
+//! that change the registry hold the lock exclusively; generating the
+//! HTML, manifest, and compressed copies shares it.
+
+use fs4::fs_std::FileExt;
+use snafu::prelude::*;
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::PathBuf,
+};
+
+use crate::Registry;
+
+/// Appended to the registry's directory name to name the lock file,
+/// which is kept next to the registry so that it isn't published. It
+/// is never removed, as a process waiting on a removed file would hold
+/// a lock that nobody else sees.
+const LOCK_FILE_SUFFIX: &str = ".margo-lock";
+
+/// Releases the lock when dropped.
+#[derive(Debug)]
+#[must_use]
+pub struct Guard {
+    _file: File,
+}
+
+/// Waits until no other process is changing the registry.
+pub fn shared(r: &Registry) -> Result<Guard, Error> {
+    lock(r, false)
+}
+
+/// Waits until no other process is reading or changing the registry.
+pub fn exclusive(r: &Registry) -> Result<Guard, Error> {
+    lock(r, true)
+}
+
+fn lock(r: &Registry, exclusive: bool) -> Result<Guard, Error> {
+    use error::*;
+
+    let path = r
+        .path_beside(LOCK_FILE_SUFFIX)
+        .context(LocateSnafu { path: &r.path })?
+        .context(NoParentSnafu { path: &r.path })?;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .context(OpenSnafu { path: &path })?;
+
+    let locked = if exclusive {
+        FileExt::lock_exclusive(&file)
+    } else {
+        FileExt::lock_shared(&file)
+    };
+    locked.context(LockSnafu { path })?;
+
+    Ok(Guard { _file: file })
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not find the registry {}", path.display()))]
+    Locate { source: io::Error, path: PathBuf },
+
+    #[snafu(display(
+        "The registry {} has no parent directory to keep its lock file in",
+        path.display(),
+    ))]
+    NoParent { path: PathBuf },
+
+    #[snafu(display("Could not open the lock file {}", path.display()))]
+    Open { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not lock the registry with {}", path.display()))]
+    Lock { source: io::Error, path: PathBuf },
+}
@@ -0,0 +1,152 @@
+//! Reading which crate versions a project uses from its `Cargo.lock`.
+
+use semver::Version;
+use serde::Deserialize;
+use snafu::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{common::CrateName, diagnostics, ErrorKind, Index, ParseIndexError, Registry};
+
+#[derive(Debug, Deserialize)]
+struct Root {
+    #[serde(default)]
+    package: Vec<Package>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Package {
+    pub name: CrateName,
+    pub version: Version,
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+}
+
+/// The locked packages found in the registry, and those from other
+/// sources that it doesn't have.
+#[derive(Debug)]
+pub struct Locked {
+    pub crates: Vec<Crate>,
+    pub skipped: Vec<Package>,
+    /// The sources, as written in the lockfile, that the crates were
+    /// locked from.
+    pub sources: BTreeSet<String>,
+}
+
+/// A crate version in the registry, named as it was published.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crate {
+    pub name: CrateName,
+    pub version: Version,
+    pub cksum: String,
+}
+
+/// Finds each package in the lockfile that was downloaded from a
+/// registry. Packages from this registry must be present with the
+/// locked checksum; packages from other registries are used when this
+/// registry has an identical copy, such as when it mirrors them.
+pub fn read(r: &Registry, path: &Path) -> Result<Locked, Error> {
+    use error::*;
+
+    let data = fs::read_to_string(path).context(ReadSnafu { path })?;
+    let Root { package } = toml::from_str(&data).context(ParseSnafu { path })?;
+
+    let ours = format!("sparse+{}", r.config.base_url);
+    let mut indexes = BTreeMap::<CrateName, Index>::new();
+
+    let mut locked = Locked {
+        crates: Vec::new(),
+        skipped: Vec::new(),
+        sources: BTreeSet::new(),
+    };
+
+    for package in package {
+        // Workspace members and path dependencies have no source, and
+        // git dependencies aren't in any registry.
+        let Some(source) = &package.source else {
+            continue;
+        };
+        if !source.starts_with("registry+") && !source.starts_with("sparse+") {
+            locked.skipped.push(package);
+            continue;
+        }
+
+        let index = match indexes.get(&package.name) {
+            Some(index) => index,
+            None => {
+                let index_path = r.index_file_path_for(&package.name);
+                let index = Registry::parse_index_file(&index_path)
+                    .context(IndexSnafu { path: index_path })?;
+                indexes.entry(package.name.clone()).or_insert(index)
+            }
+        };
+
+        let entry = index
+            .get(&package.version)
+            .filter(|e| package.checksum.as_ref().map_or(true, |c| *c == e.cksum));
+
+        match entry {
+            Some(entry) => {
+                locked.sources.insert(source.clone());
+                locked.crates.push(Crate {
+                    name: entry.name.clone(),
+                    version: entry.vers.clone(),
+                    cksum: entry.cksum.clone(),
+                });
+            }
+            None if *source == ours => {
+                return MissingSnafu {
+                    name: package.name,
+                    version: package.version,
+                }
+                .fail()
+            }
+            None => locked.skipped.push(package),
+        }
+    }
+
+    Ok(locked)
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not read the lockfile {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The lockfile {} is malformed", path.display()))]
+    Parse {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not read the index file {}", path.display()))]
+    Index {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display(
+        "The lockfile uses `{name}` version {version}, which is not in the registry with the locked checksum"
+    ))]
+    Missing { name: CrateName, version: Version },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Missing { .. } => Some(ErrorKind::NotFound),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> Option<diagnostics::Code> {
+        match self {
+            Self::Missing { .. } => Some(diagnostics::Code::LockfileVersionMissing),
+            _ => None,
+        }
+    }
+}
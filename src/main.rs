@@ -1,23 +1,68 @@
-use common::CrateName;
-use semver::Version;
+use changes::{Action, Change};
+use common::{CrateName, GeneratedBy, Timestamp};
+use events::Event;
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use snafu::prelude::*;
 use std::{
     collections::{BTreeMap, BTreeSet},
-    env, fmt,
+    env,
+    ffi::{OsStr, OsString},
+    fmt,
     fs::{self, File},
     io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    num::NonZeroUsize,
     path::{Component, Path, PathBuf},
+    process::{self, ExitCode},
     str,
+    sync::atomic::{AtomicU64, Ordering},
 };
+use trash::Disposal;
 use url::Url;
 
+mod access_log;
+mod changes;
+mod check_remote;
+mod closure;
+mod compare;
+mod config_check;
+mod credentials;
+mod diagnostics;
+mod events;
+mod files;
+mod gc;
+mod hosting;
 #[cfg(feature = "html")]
 mod html;
+mod http;
+mod impact;
+mod import;
+mod local_registry;
+mod lock;
+mod lockfile;
+mod manifest;
+mod merkle;
+mod plan;
+mod precompress;
+mod public_names;
+mod reconcile;
+mod repair;
+mod smoke_test;
+mod snapshot;
+mod subset;
+mod trash;
+#[cfg(feature = "tui")]
+mod tui;
+mod vendor;
 
 #[derive(Debug, argh::FromArgs)]
 /// Manage a static crate registry
 struct Args {
+    /// never access the network; operations that need it fail instead
+    /// [env: MARGO_OFFLINE]
+    #[argh(switch)]
+    offline: bool,
+
     #[argh(subcommand)]
     subcommand: Subcommand,
 }
@@ -29,8 +74,53 @@ enum Subcommand {
     Add(AddArgs),
     Remove(RemoveArgs),
     Yank(YankArgs),
+    Plan(PlanArgs),
+    Apply(ApplyArgs),
+    Reconcile(ReconcileArgs),
     List(ListArgs),
+    LsFiles(LsFilesArgs),
     GenerateHtml(GenerateHtmlArgs),
+    GenerateLandingPage(GenerateLandingPageArgs),
+    CheckRemote(CheckRemoteArgs),
+    SmokeTest(SmokeTestArgs),
+    ApplyChanges(ApplyChangesArgs),
+    Import(ImportArgs),
+    IngestLogs(IngestLogsArgs),
+    Stats(StatsArgs),
+    Metrics(MetricsArgs),
+    FixCase(FixCaseArgs),
+    Gc(GcArgs),
+    Trash(TrashArgs),
+    Repair(RepairArgs),
+    Verify(VerifyArgs),
+    InclusionProof(InclusionProofArgs),
+    VerifyInclusion(VerifyInclusionArgs),
+    Vendor(VendorArgs),
+    ExportLocalRegistry(ExportLocalRegistryArgs),
+    DockerContext(DockerContextArgs),
+    Subset(SubsetArgs),
+    Tui(TuiArgs),
+    Latest(LatestArgs),
+    CatIndex(CatIndexArgs),
+    Closure(ClosureArgs),
+    Impact(ImpactArgs),
+    Compare(CompareArgs),
+    NextVersion(NextVersionArgs),
+    ConfigStanza(ConfigStanzaArgs),
+    Config(ConfigArgs),
+    Snapshot(SnapshotArgs),
+    Version(VersionArgs),
+    Explain(ExplainArgs),
+}
+
+/// Explain an error code, such as `MARGO-E001`, with its causes and fixes
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "explain")]
+struct ExplainArgs {
+    /// the code printed with the error
+    #[argh(positional)]
+    code: diagnostics::Code,
 }
 
 /// Initialize a new registry
@@ -38,7 +128,7 @@ enum Subcommand {
 #[argh(subcommand)]
 #[argh(name = "init")]
 struct InitArgs {
-    /// the URL that the registry is hosted at
+    /// the URL that the registry is hosted at [env: MARGO_BASE_URL]
     #[argh(option)]
     base_url: Option<Url>,
 
@@ -46,18 +136,40 @@ struct InitArgs {
     #[argh(switch)]
     defaults: bool,
 
-    /// require HTTP authentication to access crates
+    /// never prompt; implies `--defaults` [env: MARGO_NON_INTERACTIVE]
+    #[argh(switch)]
+    non_interactive: bool,
+
+    /// require HTTP authentication to access crates [env: MARGO_AUTH_REQUIRED]
     #[argh(option)]
     auth_required: Option<bool>,
 
-    /// generate an HTML file showing crates in the index
+    /// generate an HTML file showing crates in the index [env: MARGO_HTML]
     #[argh(option)]
     html: Option<bool>,
 
-    /// name you'd like to suggest other people call your registry
+    /// name you'd like to suggest other people call your registry [env:
+    /// MARGO_HTML_SUGGESTED_REGISTRY_NAME]
     #[argh(option)]
     html_suggested_registry_name: Option<String>,
 
+    /// where crate files are stored, relative to the registry, using
+    /// the `dl` markers `{crate}`, `{version}`, `{prefix}`,
+    /// `{lowerprefix}`, and `{sha256-checksum}` [env: MARGO_CRATE_PATH]
+    #[argh(option)]
+    crate_path: Option<CratePath>,
+
+    /// update the configuration of an existing registry, keeping any
+    /// settings that are not provided
+    #[argh(switch)]
+    reconfigure: bool,
+
+    /// write the files needed to serve the registry from
+    /// `github-pages`, `gitlab-pages`, `s3`, `netlify`, or
+    /// `cloudflare-pages`
+    #[argh(option)]
+    host: Option<hosting::Host>,
+
     #[argh(positional)]
     path: PathBuf,
 }
@@ -67,10 +179,45 @@ struct InitArgs {
 #[argh(subcommand)]
 #[argh(name = "add")]
 struct AddArgs {
-    /// path to the registry to modify
+    /// path to the registry to modify [env: MARGO_REGISTRY]
     #[argh(option)]
     registry: Option<PathBuf>,
 
+    /// the repository the packages were built from, such as a git
+    /// URL; defaults to the package's `repository`
+    #[argh(option)]
+    source_url: Option<String>,
+
+    /// the revision the packages were built from; defaults to the
+    /// commit recorded by `cargo package`
+    #[argh(option)]
+    source_rev: Option<String>,
+
+    /// refuse packages created from a working directory with
+    /// uncommitted changes instead of warning about them
+    #[argh(switch)]
+    deny_dirty: bool,
+
+    /// refuse packages that depend on crates from other registries,
+    /// except those allowed in the registry's configuration
+    #[argh(switch)]
+    forbid_external_deps: bool,
+
+    /// point dependencies on other registries at this registry when
+    /// it contains a matching version, such as when mirroring
+    #[argh(switch)]
+    rewrite_deps_to_self: bool,
+
+    /// add packages even when their names don't match the registry's
+    /// `allow_names` patterns
+    #[argh(switch)]
+    allow_any_name: bool,
+
+    /// publish the packages to a channel, such as `nightly`, which
+    /// lists them apart from the crate's ordinary releases
+    #[argh(option)]
+    channel: Option<String>,
+
     #[argh(positional)]
     path: Vec<PathBuf>,
 }
@@ -80,17 +227,52 @@ struct AddArgs {
 #[argh(subcommand)]
 #[argh(name = "rm")]
 struct RemoveArgs {
-    /// path to the registry to modify
+    /// path to the registry to modify [env: MARGO_REGISTRY]
     #[argh(option)]
     registry: Option<PathBuf>,
 
-    // FUTURE: Allow removing all versions at once?
-    /// the version of the crate
+    /// the version of the crate; when no versions are selected, every
+    /// version is removed
     #[argh(option)]
-    version: Version,
+    version: Option<Version>,
+
+    /// remove the versions matching this requirement, such as `<2.0.0`
+    #[argh(option)]
+    versions: Option<VersionReq>,
+
+    /// remove every pre-release version of the crate
+    #[argh(switch)]
+    all_prereleases: bool,
+
+    /// remove every yanked version of every crate
+    #[argh(switch)]
+    all_yanked: bool,
+
+    /// only remove versions published to this channel; on its own,
+    /// every version in the channel is removed
+    #[argh(option)]
+    channel: Option<String>,
+
+    /// show what would be removed without removing anything
+    #[argh(switch)]
+    dry_run: bool,
+
+    /// record that the removed versions existed so that users can
+    /// learn why they disappeared
+    #[argh(switch)]
+    tombstone: bool,
+
+    /// the reason the versions were removed; implies `--tombstone`
+    #[argh(option)]
+    reason: Option<String>,
+
+    /// delete the crate files instead of moving them to the trash
+    #[argh(switch)]
+    permanent: bool,
 
+    /// the name of the crate
     #[argh(positional)]
-    name: CrateName,
+    name: Option<CrateName>,
 }
 
 /// Generate an HTML index for the registry
@@ -98,17 +280,35 @@ struct RemoveArgs {
 #[argh(subcommand)]
 #[argh(name = "generate-html")]
 struct GenerateHtmlArgs {
-    /// path to the registry to modify
+    /// path to the registry to modify [env: MARGO_REGISTRY]
     #[argh(option)]
     registry: Option<PathBuf>,
+
+    /// keep running, regenerating the HTML whenever the registry changes
+    #[argh(switch)]
+    watch: bool,
+
+    /// don't remove asset files written by previous versions of Margo
+    #[argh(switch)]
+    keep_old_assets: bool,
+}
+
+/// Generate a page linking to each registry in a directory
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "generate-landing-page")]
+struct GenerateLandingPageArgs {
+    /// the directory containing the registries
+    #[argh(positional)]
+    path: PathBuf,
 }
 
-/// Yank a version of a crate from the registry
+/// Yank versions of a crate from the registry
 #[derive(Debug, argh::FromArgs)]
 #[argh(subcommand)]
 #[argh(name = "yank")]
 struct YankArgs {
-    /// path to the registry to modify
+    /// path to the registry to modify [env: MARGO_REGISTRY]
     #[argh(option)]
     registry: Option<PathBuf>,
 
@@ -118,1610 +318,9623 @@ struct YankArgs {
 
     /// the version of the crate
     #[argh(option)]
-    version: Version,
+    version: Option<Version>,
+
+    /// yank the versions matching this requirement, such as `<2.0.0`
+    #[argh(option)]
+    versions: Option<VersionReq>,
+
+    /// yank every pre-release version of the crate
+    #[argh(switch)]
+    all_prereleases: bool,
+
+    /// yank every version of the crate
+    #[argh(switch)]
+    all: bool,
+
+    /// only yank versions published to this channel; on its own,
+    /// every version in the channel is yanked
+    #[argh(option)]
+    channel: Option<String>,
+
+    /// show what would be yanked without changing anything
+    #[argh(switch)]
+    dry_run: bool,
 
     /// the name of the crate
     #[argh(positional)]
     name: CrateName,
 }
 
+/// Show what a task file of adds, yanks, and removals would do,
+/// without changing the registry
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "plan")]
+struct PlanArgs {
+    /// path to the registry to plan against [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
+
+    /// where to write the plan, for use with `margo apply`
+    #[argh(option)]
+    out: Option<PathBuf>,
+
+    /// the JSON task file
+    #[argh(positional)]
+    tasks: PathBuf,
+}
+
+/// Carry out a plan written by `margo plan`, undoing every step if
+/// any of them fails
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "apply")]
+struct ApplyArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
+
+    /// the plan file
+    #[argh(positional)]
+    plan: PathBuf,
+}
+
+/// Add, yank, unyank, and remove versions until the registry matches
+/// a state file
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "reconcile")]
+struct ReconcileArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
+
+    /// the TOML file declaring the versions that should exist
+    #[argh(option)]
+    state: PathBuf,
+
+    /// print the changes without making them
+    #[argh(switch)]
+    dry_run: bool,
+}
+
 /// List all crates and their versions in the registry
 #[derive(Debug, argh::FromArgs)]
 #[argh(subcommand)]
 #[argh(name = "list")]
 struct ListArgs {
-    /// path to the registry to list
+    /// path to the registry to list [env: MARGO_REGISTRY]
     #[argh(option)]
     registry: Option<PathBuf>,
 }
 
-#[snafu::report]
-fn main() -> Result<(), Error> {
-    let args: Args = argh::from_env();
-
-    let global = Global::new()?;
-    let global = Box::leak(Box::new(global));
-
-    match args.subcommand {
-        Subcommand::Init(init) => do_init(global, init)?,
-        Subcommand::Add(add) => do_add(global, add)?,
-        Subcommand::Remove(rm) => do_remove(global, rm)?,
-        Subcommand::Yank(yank) => do_yank(global, yank)?,
-        Subcommand::List(list) => do_list(global, list)?,
-        Subcommand::GenerateHtml(html) => do_generate_html(global, html)?,
-    }
+/// List every file that is part of the registry, with its type and
+/// SHA-256 checksum
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "ls-files")]
+struct LsFilesArgs {
+    /// path to the registry to list [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    Ok(())
+    /// only list the files for this crate
+    #[argh(option, long = "crate")]
+    crate_: Option<CrateName>,
 }
 
-#[derive(Debug, Snafu)]
-enum Error {
-    #[snafu(display("Could not initialize global variables"))]
-    #[snafu(context(false))]
-    Global {
-        #[snafu(source(from(GlobalError, Box::new)))]
-        source: Box<GlobalError>,
-    },
+/// Print the newest non-yanked version of a crate
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "latest")]
+struct LatestArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    #[snafu(transparent)]
-    Initialize {
-        #[snafu(source(from(DoInitializeError, Box::new)))]
-        source: Box<DoInitializeError>,
-    },
+    /// only consider versions matching this requirement, such as `^1`
+    #[argh(option)]
+    req: Option<VersionReq>,
 
-    #[snafu(transparent)]
-    Open {
-        #[snafu(source(from(DiscoverRegistryError, Box::new)))]
-        source: Box<DiscoverRegistryError>,
-    },
+    /// the name of the crate
+    #[argh(positional)]
+    name: CrateName,
+}
 
-    #[snafu(transparent)]
-    Add {
-        #[snafu(source(from(AddError, Box::new)))]
-        source: Box<AddError>,
-    },
+/// Print a crate's index file as it is stored
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "cat-index")]
+struct CatIndexArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    #[snafu(transparent)]
-    Remove {
-        #[snafu(source(from(RemoveError, Box::new)))]
-        source: Box<RemoveError>,
-    },
+    /// format each entry as indented JSON
+    #[argh(switch)]
+    pretty: bool,
 
-    #[snafu(transparent)]
-    Html {
-        #[snafu(source(from(HtmlError, Box::new)))]
-        source: Box<HtmlError>,
-    },
+    /// the name of the crate
+    #[argh(positional)]
+    name: CrateName,
 
-    #[snafu(transparent)]
-    Yank {
-        #[snafu(source(from(YankError, Box::new)))]
-        source: Box<YankError>,
-    },
+    /// only print the entry for this version
+    #[argh(positional)]
+    version: Option<Version>,
 }
 
-trait UnwrapOrDialog<T> {
-    fn apply_default(self, use_default: bool, value: impl Into<T>) -> Self;
+/// Report which of a version's dependency requirements, and theirs in
+/// turn, this registry can satisfy
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "closure")]
+struct ClosureArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    fn unwrap_or_dialog(self, f: impl FnOnce() -> dialoguer::Result<T>) -> dialoguer::Result<T>;
-}
+    /// the output format: `table` or `json`
+    #[argh(option, default = "ReportFormat::Table")]
+    output: ReportFormat,
 
-impl<T> UnwrapOrDialog<T> for Option<T> {
-    fn apply_default(self, use_default: bool, value: impl Into<T>) -> Self {
-        if self.is_none() && use_default {
-            Some(value.into())
-        } else {
-            self
-        }
-    }
+    /// also fail when a requirement is met by crates.io or another
+    /// registry, such as for air-gapped builds
+    #[argh(switch)]
+    self_contained: bool,
 
-    fn unwrap_or_dialog(self, f: impl FnOnce() -> dialoguer::Result<T>) -> dialoguer::Result<T> {
-        match self {
-            Some(v) => Ok(v),
-            None => f(),
-        }
-    }
+    /// the version to start from, as `name@version`
+    #[argh(positional)]
+    krate: common::CrateVersion,
 }
 
-fn do_init(_global: &Global, init: InitArgs) -> Result<(), DoInitializeError> {
-    use do_initialize_error::*;
+/// List the versions with requirements that only this version
+/// satisfies, which yanking or removing it would break
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "impact")]
+struct ImpactArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    let base_url = init
-        .base_url
-        .unwrap_or_dialog(|| {
-            dialoguer::Input::new()
-                .with_prompt("What URL will the registry be served from")
-                .interact()
-        })
-        .context(BaseUrlSnafu)?;
+    /// the output format: `table` or `json`
+    #[argh(option, default = "ReportFormat::Table")]
+    output: ReportFormat,
 
-    let auth_required = init
-        .auth_required
-        .apply_default(init.defaults, ConfigV1::USER_DEFAULT_AUTH_REQUIRED)
-        .unwrap_or_dialog(|| {
-            dialoguer::Confirm::new()
-                .default(ConfigV1::USER_DEFAULT_AUTH_REQUIRED)
-                .show_default(true)
-                .with_prompt("Require HTTP authentication to access crates?")
-                .interact()
-        })
-        .context(AuthRequiredSnafu)?;
+    /// the version to check, as `name@version`
+    #[argh(positional)]
+    krate: common::CrateVersion,
+}
 
-    let enabled = init
-        .html
-        .apply_default(init.defaults, ConfigV1Html::USER_DEFAULT_ENABLED)
-        .unwrap_or_dialog(|| {
-            dialoguer::Confirm::new()
-                .default(ConfigV1Html::USER_DEFAULT_ENABLED)
-                .show_default(true)
-                .with_prompt("Enable HTML index generation?")
-                .interact()
-        })
-        .context(HtmlEnabledSnafu)?;
+/// Show how the dependencies, features, and minimum Rust version of
+/// two versions of a crate differ
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "compare")]
+struct CompareArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    let suggested_registry_name = if enabled {
-        let name = init
-            .html_suggested_registry_name
-            .apply_default(
-                init.defaults,
-                ConfigV1Html::USER_DEFAULT_SUGGESTED_REGISTRY_NAME,
-            )
-            .unwrap_or_dialog(|| {
-                dialoguer::Input::new()
-                    .default(ConfigV1Html::USER_DEFAULT_SUGGESTED_REGISTRY_NAME.to_owned())
-                    .show_default(true)
-                    .with_prompt("Name you'd like to suggest other people call your registry")
-                    .interact()
-            })
-            .context(HtmlSuggestedRegistryNameSnafu)?;
+    /// the output format: `table` or `json`
+    #[argh(option, default = "ReportFormat::Table")]
+    output: ReportFormat,
 
-        Some(name)
-    } else {
-        None
-    };
+    /// also compare the files in the two crate packages
+    #[argh(switch)]
+    files: bool,
 
-    let config = ConfigV1 {
-        base_url,
-        auth_required,
-        html: ConfigV1Html {
-            enabled,
-            suggested_registry_name,
-        },
-    };
+    /// the name of the crate
+    #[argh(positional)]
+    name: CrateName,
 
-    let r = Registry::initialize(config, &init.path)?;
+    /// the version to compare from
+    #[argh(positional)]
+    old: Version,
 
-    if r.config.html.enabled {
-        let res = r.generate_html();
+    /// the version to compare to
+    #[argh(positional)]
+    new: Version,
+}
 
-        if cfg!(feature = "html") {
-            res?;
-        } else if let Err(e) = res {
-            eprintln!("Warning: {e}");
+#[derive(Debug, Copy, Clone)]
+enum ReportFormat {
+    Table,
+    Json,
+}
+
+impl str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown output format `{s}`; expected `table` or `json`"
+            )),
         }
     }
-
-    Ok(())
 }
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum DoInitializeError {
-    #[snafu(display("Could not determine the base URL"))]
-    BaseUrl { source: dialoguer::Error },
-
-    #[snafu(display("Could not determine if HTTP authorization is required"))]
-    AuthRequired { source: dialoguer::Error },
-
-    #[snafu(display("Could not determine if HTML generation is enabled"))]
-    HtmlEnabled { source: dialoguer::Error },
+/// Print the version that follows the newest version of a crate
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "next-version")]
+struct NextVersionArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    #[snafu(display("Could not determine the suggested registry name"))]
-    HtmlSuggestedRegistryName { source: dialoguer::Error },
+    /// the part of the version to increment: `major`, `minor`,
+    /// `patch`, or `pre`
+    #[argh(option)]
+    bump: Bump,
 
-    #[snafu(transparent)]
-    Initialize { source: InitializeError },
+    /// produce a pre-release with this identifier, such as `rc`
+    #[argh(option)]
+    pre: Option<String>,
 
-    #[snafu(transparent)]
-    Html { source: HtmlError },
+    /// the name of the crate
+    #[argh(positional)]
+    name: CrateName,
 }
 
-fn do_add(global: &Global, add: AddArgs) -> Result<(), Error> {
-    let r = discover_registry(add.registry)?;
-
-    for i in add.path {
-        r.add(global, i)?;
-    }
-    r.maybe_generate_html()?;
-
-    Ok(())
+/// Print the version of Margo and the versions that wrote a registry
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "version")]
+struct VersionArgs {
+    /// path to a registry to report on [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 }
 
-fn do_remove(_global: &Global, rm: RemoveArgs) -> Result<(), Error> {
-    let r = discover_registry(rm.registry)?;
+/// Work with the registry's configuration file
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "config")]
+struct ConfigArgs {
+    #[argh(subcommand)]
+    subcommand: ConfigSubcommand,
+}
 
-    r.remove(rm.name, rm.version)?;
-    r.maybe_generate_html()?;
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+enum ConfigSubcommand {
+    Validate(ConfigValidateArgs),
+}
 
-    Ok(())
+/// Check the configuration file for mistakes
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "validate")]
+struct ConfigValidateArgs {
+    /// path to the registry to check [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 }
 
-fn do_generate_html(_global: &Global, html: GenerateHtmlArgs) -> Result<(), Error> {
-    let r = discover_registry(html.registry)?;
-    r.generate_html()?;
-    Ok(())
+/// Record and restore the exact set of crate versions in the registry
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "snapshot")]
+struct SnapshotArgs {
+    #[argh(subcommand)]
+    subcommand: SnapshotSubcommand,
 }
 
-fn do_yank(_global: &Global, yank: YankArgs) -> Result<(), Error> {
-    let r = discover_registry(yank.registry)?;
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+enum SnapshotSubcommand {
+    Create(SnapshotCreateArgs),
+    Restore(SnapshotRestoreArgs),
+    Diff(SnapshotDiffArgs),
+    Export(SnapshotExportArgs),
+}
 
-    r.yank(yank.name, yank.version, !yank.undo)?;
-    r.maybe_generate_html()?;
+/// Record the crate versions currently in the registry
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "create")]
+struct SnapshotCreateArgs {
+    /// path to the registry to snapshot [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    Ok(())
+    /// the name of the snapshot, such as `2024.06`
+    #[argh(positional)]
+    name: String,
 }
 
-fn do_list(_global: &Global, list: ListArgs) -> Result<(), Error> {
-    let r = discover_registry(list.registry)?;
+/// Remove versions published since a snapshot and restore its yanked versions
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "restore")]
+struct SnapshotRestoreArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    let crates = r.list_all().unwrap();
+    /// show what would change without changing anything
+    #[argh(switch)]
+    dry_run: bool,
 
-    #[derive(Default)]
-    struct Max(usize, String);
+    /// the name of the snapshot
+    #[argh(positional)]
+    name: String,
+}
 
-    impl Max {
-        fn push(&mut self, v: impl fmt::Display) {
-            use std::fmt::Write;
+/// Show how the registry has changed since a snapshot
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "diff")]
+struct SnapshotDiffArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-            let Self(m, s) = self;
+    /// the name of the snapshot
+    #[argh(positional)]
+    name: String,
 
-            s.clear();
-            _ = write!(s, "{v}");
-            *m = usize::max(*m, s.len());
-        }
+    /// another snapshot to compare against, instead of the registry's
+    /// current contents
+    #[argh(positional)]
+    other: Option<String>,
+}
 
-        fn max(&self) -> usize {
-            self.0
-        }
-    }
+/// Print the Cargo configuration and `Cargo.lock` entries that pin consumers
+/// to the versions in a snapshot
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "export")]
+struct SnapshotExportArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    let mut max_c = Max::default();
-    let mut max_v = Max::default();
+    /// the name Cargo should use for the registry; defaults to the
+    /// name suggested on the HTML page
+    #[argh(option)]
+    name: Option<String>,
 
-    for (crate_, versions) in &crates {
-        max_c.push(crate_);
-        for version in versions.keys() {
-            max_v.push(version);
-        }
-    }
+    /// the name of the snapshot; defaults to the registry's current
+    /// contents
+    #[argh(positional)]
+    snapshot: Option<String>,
+}
 
-    let max_c = max_c.max();
-    let max_v = max_v.max();
+/// Print the Cargo configuration needed to use the registry
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "config-stanza")]
+struct ConfigStanzaArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    for (crate_, versions) in crates {
-        for version in versions.keys() {
-            println!("{crate_:<max_c$} {version:<max_v$}");
-        }
-    }
+    /// the name Cargo should use for the registry; defaults to the
+    /// name suggested on the HTML page
+    #[argh(option)]
+    name: Option<String>,
 
-    Ok(())
+    /// also replace crates.io with this registry, such as when it
+    /// mirrors crates.io
+    #[argh(switch)]
+    replace_crates_io: bool,
+
+    /// add the configuration to `.cargo/config.toml` in this project
+    /// directory instead of printing it
+    #[argh(option)]
+    write: Option<PathBuf>,
 }
 
-fn discover_registry(path: Option<PathBuf>) -> Result<Registry, DiscoverRegistryError> {
-    use discover_registry_error::*;
+/// Check that the deployed registry serves the same files as the local registry
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "check-remote")]
+struct CheckRemoteArgs {
+    /// path to the registry to check [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    match path {
-        Some(p) => Registry::open(p).context(OpenSnafu),
-        None => {
-            let cwd = env::current_dir().context(CurrentDirSnafu)?;
+    /// how many crates to check
+    #[argh(option, default = "3")]
+    sample: usize,
 
-            match Registry::open(cwd) {
-                Ok(r) => Ok(r),
-                Err(e) if e.is_not_found() => FallbackNotFoundSnafu.fail(),
-                Err(e) => Err(e).context(FallbackOpenSnafu)?,
-            }
-        }
-    }
+    /// how many crates to check at the same time
+    #[argh(option, default = "1")]
+    jobs: usize,
 }
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum DiscoverRegistryError {
-    #[snafu(display("Could not open the specified registry"))]
-    Open { source: OpenError },
+/// Check that Cargo can fetch and build a version from the registry,
+/// using a throwaway project that depends on it
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "smoke-test")]
+struct SmokeTestArgs {
+    /// path to the registry to read [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    #[snafu(display("Could not determine the current directory, {}", Self::TRY_THIS))]
-    CurrentDir { source: io::Error },
+    /// the version to depend on, as `name@version`
+    #[argh(option, long = "crate")]
+    krate: common::CrateVersion,
 
-    #[snafu(display(
-        "The current directory does not contain a registry, {}",
-        Self::TRY_THIS,
-    ))]
-    FallbackNotFound,
+    /// use a copy of the registry on disk instead of its URL, such as
+    /// before deploying it
+    #[argh(switch)]
+    local: bool,
 
-    #[snafu(display("Could not open the registry in the current directory"))]
-    FallbackOpen { source: OpenError },
-}
+    /// run only `cargo fetch`, skipping `cargo check`
+    #[argh(switch)]
+    fetch_only: bool,
 
-impl DiscoverRegistryError {
-    const TRY_THIS: &'static str = "please use the `--registry` command line option";
+    /// keep the project afterwards, printing where it is
+    #[argh(switch)]
+    keep: bool,
 }
 
-#[derive(Debug)]
-struct Registry {
-    path: PathBuf,
-    config: ConfigV1,
+/// Copy the changes made to another registry into this one, as a mirror
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "apply-changes")]
+struct ApplyChangesArgs {
+    /// path to the mirror registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
+
+    /// the URL or path of the registry to copy changes from
+    #[argh(option)]
+    from: String,
 }
 
-type Index = BTreeMap<Version, index_entry::Root>;
-type ListAll = BTreeMap<CrateName, Index>;
+/// Convert a registry run by another implementation into this one
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "import")]
+struct ImportArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-impl Registry {
-    fn initialize(config: ConfigV1, path: impl Into<PathBuf>) -> Result<Self, InitializeError> {
-        use initialize_error::*;
+    /// the implementation running the other registry: `kellnr`,
+    /// `alexandrie`, `ktra`, or `estuary`
+    #[argh(option)]
+    from: import::Implementation,
 
-        let config = config.normalize();
-        let path = path.into();
+    /// the directory where the other registry stores crate files;
+    /// defaults to downloading them
+    #[argh(option)]
+    crates: Option<PathBuf>,
 
-        println!("Initializing registry in `{}`", path.display());
+    /// a checkout of the other registry's index, or the URL of its
+    /// sparse index
+    #[argh(positional)]
+    index: String,
+}
 
-        fs::create_dir_all(&path).context(RegistryCreateSnafu)?;
+/// Count crate downloads from web server access logs
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "ingest-logs")]
+struct IngestLogsArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-        let config_toml_path = path.join(CONFIG_FILE_NAME);
-        let config = Config::V1(config);
-        let config_toml = toml::to_string(&config).context(ConfigTomlSerializeSnafu)?;
-        fs::write(&config_toml_path, config_toml).context(ConfigTomlWriteSnafu {
-            path: &config_toml_path,
-        })?;
+    /// the log format: `nginx`, `cloudfront`, or `caddy`
+    #[argh(option)]
+    format: access_log::Format,
 
-        let Config::V1(config) = config;
+    /// the access log files to read
+    #[argh(positional)]
+    path: Vec<PathBuf>,
+}
 
-        let dl = format!(
-            "{base_url}crates/{{lowerprefix}}/{{crate}}/{{version}}.crate",
-            base_url = config.base_url,
-        );
-        let auth_required = config.auth_required;
+/// Show statistics about the crates in the registry
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "stats")]
+struct StatsArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
+}
 
-        let this = Self { path, config };
+/// Move index and crate files to the lowercase paths Cargo requests
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "fix-case")]
+struct FixCaseArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-        let config_json_path = this.config_json_path();
-        let config_json = config_json::Root {
-            dl,
-            api: None,
-            auth_required,
-        };
-        let config_json = serde_json::to_string(&config_json).context(ConfigJsonSerializeSnafu)?;
-        fs::write(&config_json_path, config_json).context(ConfigJsonWriteSnafu {
-            path: &config_json_path,
-        })?;
+    /// show what would be moved without moving anything
+    #[argh(switch)]
+    dry_run: bool,
+}
 
-        Ok(this)
-    }
+/// Remove files the index doesn't refer to and repair the index
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "gc")]
+struct GcArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    fn open(path: impl Into<PathBuf>) -> Result<Self, OpenError> {
-        use open_error::*;
+    /// remove crate files without an index entry, and relink or
+    /// remove index entries without a crate file
+    #[argh(switch)]
+    orphans: bool,
 
-        let path = path.into();
+    /// show what would be changed without changing anything
+    #[argh(switch)]
+    dry_run: bool,
 
-        let config_path = path.join(CONFIG_FILE_NAME);
-        let config = fs::read_to_string(&config_path).context(ReadSnafu { path: &config_path })?;
-        let Config::V1(config) =
-            toml::from_str(&config).context(DeserializeSnafu { path: &config_path })?;
-
-        Ok(Self { path, config })
-    }
-
-    fn add(&self, global: &Global, crate_path: impl AsRef<Path>) -> Result<(), AddError> {
-        use add_error::*;
+    /// delete orphaned files instead of moving them to the trash
+    #[argh(switch)]
+    permanent: bool,
+}
 
-        let crate_path = crate_path.as_ref();
+/// List and restore the crate files that were removed
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "trash")]
+struct TrashArgs {
+    #[argh(subcommand)]
+    subcommand: TrashSubcommand,
+}
 
-        println!("Adding crate `{}` to registry", crate_path.display());
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+enum TrashSubcommand {
+    List(TrashListArgs),
+    Restore(TrashRestoreArgs),
+}
 
-        let crate_file = fs::read(crate_path).context(ReadCrateSnafu)?;
+/// List the removed files that can be restored
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "list")]
+struct TrashListArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
+}
 
-        use sha2::Digest;
-        let checksum = sha2::Sha256::digest(&crate_file);
-        let checksum_hex = hex::encode(checksum);
+/// Put a removed file back, along with its version in the index
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "restore")]
+struct TrashRestoreArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-        let cargo_toml = extract_root_cargo_toml(&crate_file)?.context(CargoTomlMissingSnafu)?;
+    /// the ID of the item, as shown by `margo trash list`
+    #[argh(positional)]
+    id: String,
+}
 
-        let cargo_toml = String::from_utf8(cargo_toml).context(CargoTomlUtf8Snafu)?;
-        let cargo_toml = toml::from_str(&cargo_toml).context(CargoTomlMalformedSnafu)?;
+/// Recover the registry from damaged files
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "repair")]
+struct RepairArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-        let index_entry =
-            adapt_cargo_toml_to_index_entry(global, &self.config, cargo_toml, checksum_hex);
+    /// write every index file again from the crate files, keeping
+    /// yanked versions yanked
+    #[argh(switch)]
+    rebuild_index: bool,
 
-        let index_path = self.index_file_path_for(&index_entry.name);
-        if let Some(path) = index_path.parent() {
-            fs::create_dir_all(path).context(IndexDirSnafu { path })?;
-        }
+    /// show what would be changed without changing anything
+    #[argh(switch)]
+    dry_run: bool,
+}
 
-        let crate_file_path = self.crate_file_path_for(&index_entry.name, &index_entry.vers);
-        if let Some(path) = crate_file_path.parent() {
-            fs::create_dir_all(path).context(CrateDirSnafu { path })?;
-        }
+/// Check that every version in the index has an intact crate file
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "verify")]
+struct VerifyArgs {
+    /// path to the registry to verify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-        // FUTURE: Stronger file system consistency (atomic file overwrites, rollbacks on error)
-        // FUTURE: "transactional" adding of multiple crates
+    /// also decompress every crate file and check that its
+    /// `Cargo.toml` matches the index
+    #[argh(switch)]
+    deep: bool,
 
-        self.read_modify_write(&index_entry.name.clone(), |index_file| {
-            index_file.insert(index_entry.vers.clone(), index_entry);
-            Ok::<_, AddError>(())
-        })?;
+    /// compare the index with the merkle tree and check that the
+    /// crate files exist, without reading them
+    #[argh(switch)]
+    fast: bool,
+}
 
-        println!("Wrote crate index to `{}`", index_path.display());
+/// Print a proof that a version is in the registry's merkle tree
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "inclusion-proof")]
+struct InclusionProofArgs {
+    /// path to the registry [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-        fs::write(&crate_file_path, &crate_file).context(CrateWriteSnafu {
-            path: &crate_file_path,
-        })?;
-        println!("Wrote crate to `{}`", crate_file_path.display());
+    /// the name of the crate
+    #[argh(positional)]
+    name: CrateName,
 
-        Ok(())
-    }
+    /// the version of the crate
+    #[argh(positional)]
+    version: Version,
+}
 
-    fn remove(&self, name: CrateName, version: Version) -> Result<(), RemoveError> {
-        use remove_error::*;
+/// Check a proof printed by `inclusion-proof` against a merkle root
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "verify-inclusion")]
+struct VerifyInclusionArgs {
+    /// the trusted root, such as `merkle_root` from `manifest.json`
+    #[argh(option)]
+    root: String,
 
-        self.read_modify_write(&name, |index| {
-            index.remove(&version);
-            Ok::<_, RemoveError>(())
-        })?;
+    /// path to the proof
+    #[argh(positional)]
+    proof: PathBuf,
+}
 
-        let crate_file = self.crate_file_path_for(&name, &version);
-        match fs::remove_file(&crate_file) {
-            Ok(()) => Ok(()),
-            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
-            Err(e) => Err(e).context(DeleteSnafu { path: crate_file }),
-        }
-    }
+/// Unpack the crates used by a project like `cargo vendor` does
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "vendor")]
+struct VendorArgs {
+    /// path to the registry to read [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    #[cfg(feature = "html")]
-    fn generate_html(&self) -> Result<(), HtmlError> {
-        html::write(self)
-    }
+    /// the project's `Cargo.lock`, listing the versions to vendor
+    #[argh(option)]
+    lockfile: PathBuf,
 
-    #[cfg(not(feature = "html"))]
-    fn generate_html(&self) -> Result<(), HtmlError> {
-        Err(HtmlError)
-    }
+    /// the directory to unpack the crates into; defaults to `vendor`
+    #[argh(option, default = "PathBuf::from(\"vendor\")")]
+    out: PathBuf,
+}
 
-    fn maybe_generate_html(&self) -> Result<(), HtmlError> {
-        if self.config.html.enabled {
-            self.generate_html()
-        } else {
-            Ok(())
-        }
-    }
+/// Copy the registry into a directory that Cargo can use as a
+/// `local-registry` source
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "export-local-registry")]
+struct ExportLocalRegistryArgs {
+    /// path to the registry to read [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-    fn yank(&self, name: CrateName, version: Version, yanked: bool) -> Result<(), YankError> {
-        use yank_error::*;
+    /// only export the versions locked in this `Cargo.lock`
+    #[argh(option)]
+    for_lockfile: Option<PathBuf>,
 
-        self.read_modify_write(&name, |index| {
-            let entry = index.get_mut(&version).context(VersionSnafu)?;
-            entry.yanked = yanked;
-            Ok(())
-        })
-    }
+    /// the directory to write the local registry to
+    #[argh(positional)]
+    out: PathBuf,
+}
 
-    fn read_modify_write<T, E>(
-        &self,
-        name: &CrateName,
-        modify: impl FnOnce(&mut Index) -> Result<T, E>,
-    ) -> Result<T, E>
-    where
-        E: From<ReadModifyWriteError>,
-    {
-        use read_modify_write_error::*;
+/// Assemble a directory for container builds holding the crates a
+/// project uses and the Cargo configuration to build offline
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "docker-context")]
+struct DockerContextArgs {
+    /// path to the registry to read [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-        let path = self.index_file_path_for(name);
-        let mut index = Self::parse_index_file(&path).context(IndexParseSnafu { path: &path })?;
+    /// the project's `Cargo.lock`, listing the versions to include
+    #[argh(option)]
+    lockfile: PathBuf,
 
-        let val = modify(&mut index)?;
+    /// the directory to write
+    #[argh(option)]
+    out: PathBuf,
+}
 
-        Self::write_index_file(index, &path).context(IndexWriteSnafu { path })?;
+/// Create a registry holding only the versions a project uses
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "subset")]
+struct SubsetArgs {
+    /// path to the registry to copy from [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-        Ok(val)
-    }
+    /// the project's `Cargo.lock`, listing the versions to include
+    #[argh(option)]
+    lockfile: PathBuf,
 
-    fn list_crate_files(
-        crate_dir: &Path,
-    ) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> {
-        walkdir::WalkDir::new(crate_dir)
-            .into_iter()
-            .flat_map(|entry| {
-                let Ok(entry) = entry else { return Some(entry) };
+    /// the directory to create the registry in; must be empty
+    #[argh(option)]
+    out: PathBuf,
 
-                let fname = entry.path().file_name()?;
-                let fname = Path::new(fname);
+    /// the URL that the new registry is hosted at; defaults to the
+    /// URL of the registry being copied
+    #[argh(option)]
+    base_url: Option<Url>,
+}
 
-                let extension = fname.extension()?;
-                if extension == "crate" {
-                    Some(Ok(entry))
-                } else {
-                    None
-                }
-            })
-    }
+/// Browse and manage the registry interactively
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "tui")]
+struct TuiArgs {
+    /// path to the registry to modify [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
+}
 
-    fn list_index_files(&self) -> Result<BTreeSet<PathBuf>, ListIndexFilesError> {
-        use list_index_files_error::*;
+/// Export registry metrics for monitoring
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "metrics")]
+struct MetricsArgs {
+    /// path to the registry to inspect [env: MARGO_REGISTRY]
+    #[argh(option)]
+    registry: Option<PathBuf>,
 
-        let crate_dir = self.crate_dir();
+    /// the output format: `prom` (Prometheus text exposition) or `json`
+    #[argh(option, default = "MetricsFormat::Prom")]
+    output: MetricsFormat,
 
-        let index_files = Self::list_crate_files(&crate_dir)
-            .map(|entry| {
-                let entry = entry.context(WalkdirSnafu { path: &crate_dir })?;
+    /// write the metrics to this file (atomically) instead of stdout
+    #[argh(option)]
+    out: Option<PathBuf>,
+}
 
-                let mut path = entry.into_path();
-                path.pop();
+#[derive(Debug, Copy, Clone)]
+enum MetricsFormat {
+    Prom,
+    Json,
+}
 
-                let subdir = path.strip_prefix(&crate_dir).context(PrefixSnafu {
-                    path: &path,
-                    prefix: &crate_dir,
-                })?;
-                let index_path = self.path.join(subdir);
-                Ok(index_path)
-            })
-            .collect::<Result<BTreeSet<_>, ListIndexFilesError>>();
+impl str::FromStr for MetricsFormat {
+    type Err = String;
 
-        match index_files {
-            Err(e) if e.is_not_found() => Ok(Default::default()),
-            r => r,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prom" => Ok(Self::Prom),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown metrics format `{s}`; expected `prom` or `json`"
+            )),
         }
     }
+}
 
-    fn list_all(&self) -> Result<ListAll, ListAllError> {
-        use list_all_error::*;
-
-        let mut crates = BTreeMap::new();
-
-        for path in self.list_index_files()? {
-            let index = Self::parse_index_file(&path).context(ParseSnafu { path })?;
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Bump {
+    Major,
+    Minor,
+    Patch,
+    Pre,
+}
 
-            if let Some(entry) = index.values().next() {
-                crates.insert(entry.name.clone(), index);
-            }
+impl str::FromStr for Bump {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "major" => Ok(Self::Major),
+            "minor" => Ok(Self::Minor),
+            "patch" => Ok(Self::Patch),
+            "pre" => Ok(Self::Pre),
+            _ => Err(format!(
+                "unknown bump `{s}`; expected `major`, `minor`, `patch`, or `pre`"
+            )),
         }
-
-        Ok(crates)
     }
+}
 
-    fn parse_index_file(path: &Path) -> Result<Index, ParseIndexError> {
-        use parse_index_error::*;
-
-        let index_file = match File::open(path) {
-            Ok(f) => f,
-            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Default::default()),
-            Err(e) => Err(e).context(OpenSnafu)?,
-        };
-        let index_file = BufReader::new(index_file);
-
-        let mut index = BTreeMap::new();
-
-        for (i, line) in index_file.lines().enumerate() {
-            let line = line.context(ReadSnafu { line: i })?;
-            let entry =
-                serde_json::from_str::<index_entry::Root>(&line).context(ParseSnafu { line: i })?;
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(code) => return code,
+    };
 
-            index.insert(entry.vers.clone(), entry);
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let exit_code = e.kind().exit_code();
+            match e.code() {
+                Some(code) => {
+                    let hint = code.hint();
+                    eprintln!("Error [{code}]: {}", snafu::Report::from_error(e));
+                    eprintln!("hint: {hint}");
+                }
+                None => eprintln!("Error: {}", snafu::Report::from_error(e)),
+            }
+            ExitCode::from(exit_code)
         }
-
-        Ok(index)
     }
+}
 
-    fn write_index_file(index_file: Index, path: &Path) -> Result<(), WriteIndexError> {
-        use write_index_error::*;
+/// Like `argh::from_env`, but exits with the usage error code when
+/// the arguments can't be understood.
+fn parse_args() -> Result<Args, ExitCode> {
+    let args = env::args_os()
+        .map(|a| a.into_string())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|a| {
+            eprintln!("Invalid UTF-8: {}", a.to_string_lossy());
+            ExitCode::from(ErrorKind::Usage.exit_code())
+        })?;
 
-        let file = File::create(path).context(OpenSnafu)?;
-        let mut file = BufWriter::new(file);
+    let command = args
+        .first()
+        .and_then(|a| Path::new(a).file_name()?.to_str())
+        .unwrap_or("margo");
+    let rest = args.iter().skip(1).map(String::as_str).collect::<Vec<_>>();
 
-        for entry in index_file.values() {
-            serde_json::to_writer(&mut file, entry).context(EntrySerializeSnafu)?;
-            file.write_all(b"\n").context(EntryNewlineSnafu)?;
+    <Args as argh::FromArgs>::from_args(&[command], &rest).map_err(|early| match early.status {
+        Ok(()) => {
+            println!("{}", early.output);
+            ExitCode::SUCCESS
         }
+        Err(()) => {
+            eprintln!(
+                "{}\nRun {command} --help for more information.",
+                early.output
+            );
+            ExitCode::from(ErrorKind::Usage.exit_code())
+        }
+    })
+}
 
-        Ok(())
-    }
-
-    fn crate_dir(&self) -> PathBuf {
-        self.path.join(CRATE_DIR_NAME)
-    }
+fn run(args: Args) -> Result<(), Error> {
+    let global = Global::new()?.with_args(&args)?;
+    let global = Box::leak(Box::new(global));
 
-    #[cfg(test)]
-    fn margo_config_toml_path(&self) -> PathBuf {
-        self.path.join(CONFIG_FILE_NAME)
+    match args.subcommand {
+        Subcommand::Init(init) => do_init(global, init)?,
+        Subcommand::Add(add) => do_add(global, add)?,
+        Subcommand::Remove(rm) => do_remove(global, rm)?,
+        Subcommand::Yank(yank) => do_yank(global, yank)?,
+        Subcommand::Plan(plan) => do_plan(global, plan)?,
+        Subcommand::Apply(apply) => do_apply(global, apply)?,
+        Subcommand::Reconcile(reconcile) => do_reconcile(global, reconcile)?,
+        Subcommand::List(list) => do_list(global, list)?,
+        Subcommand::LsFiles(ls_files) => do_ls_files(global, ls_files)?,
+        Subcommand::GenerateHtml(html) => do_generate_html(global, html)?,
+        Subcommand::GenerateLandingPage(landing) => do_generate_landing_page(global, landing)?,
+        Subcommand::SmokeTest(smoke) => do_smoke_test(global, smoke)?,
+        Subcommand::CheckRemote(check) => do_check_remote(global, check)?,
+        Subcommand::ApplyChanges(apply) => do_apply_changes(global, apply)?,
+        Subcommand::Import(import) => do_import(global, import)?,
+        Subcommand::IngestLogs(ingest) => do_ingest_logs(global, ingest)?,
+        Subcommand::Stats(stats) => do_stats(global, stats)?,
+        Subcommand::Metrics(metrics) => do_metrics(global, metrics)?,
+        Subcommand::FixCase(fix_case) => do_fix_case(global, fix_case)?,
+        Subcommand::Gc(gc) => do_gc(global, gc)?,
+        Subcommand::Trash(trash) => do_trash(global, trash)?,
+        Subcommand::Repair(repair) => do_repair(global, repair)?,
+        Subcommand::Verify(verify) => do_verify(global, verify)?,
+        Subcommand::InclusionProof(proof) => do_inclusion_proof(global, proof)?,
+        Subcommand::VerifyInclusion(verify) => do_verify_inclusion(global, verify)?,
+        Subcommand::Vendor(vendor) => do_vendor(global, vendor)?,
+        Subcommand::ExportLocalRegistry(export) => do_export_local_registry(global, export)?,
+        Subcommand::DockerContext(docker) => do_docker_context(global, docker)?,
+        Subcommand::Subset(subset) => do_subset(global, subset)?,
+        Subcommand::Tui(tui) => do_tui(global, tui)?,
+        Subcommand::Latest(latest) => do_latest(global, latest)?,
+        Subcommand::CatIndex(cat) => do_cat_index(global, cat)?,
+        Subcommand::Closure(closure) => do_closure(global, closure)?,
+        Subcommand::Impact(impact) => do_impact(global, impact)?,
+        Subcommand::Compare(compare) => do_compare(global, compare)?,
+        Subcommand::NextVersion(next) => do_next_version(global, next)?,
+        Subcommand::ConfigStanza(stanza) => do_config_stanza(global, stanza)?,
+        Subcommand::Config(config) => do_config(global, config)?,
+        Subcommand::Snapshot(snapshot) => do_snapshot(global, snapshot)?,
+        Subcommand::Version(version) => do_version(global, version)?,
+        Subcommand::Explain(explain) => do_explain(global, explain)?,
     }
 
-    fn config_json_path(&self) -> PathBuf {
-        self.path.join("config.json")
-    }
+    Ok(())
+}
 
-    fn index_file_path_for(&self, name: &CrateName) -> PathBuf {
-        let mut index_path = self.path.clone();
-        name.append_prefix_directories(&mut index_path);
-        index_path.push(name);
-        index_path
-    }
+#[derive(Debug, Snafu)]
+enum Error {
+    #[snafu(display("Could not initialize global variables"))]
+    #[snafu(context(false))]
+    Global {
+        #[snafu(source(from(GlobalError, Box::new)))]
+        source: Box<GlobalError>,
+    },
 
-    fn crate_dir_for(&self, name: &CrateName) -> PathBuf {
-        let mut crate_dir = self.crate_dir();
-        name.append_prefix_directories(&mut crate_dir);
-        crate_dir.push(name);
-        crate_dir
-    }
+    #[snafu(transparent)]
+    Initialize {
+        #[snafu(source(from(DoInitializeError, Box::new)))]
+        source: Box<DoInitializeError>,
+    },
 
-    fn crate_file_path_for(&self, name: &CrateName, version: &Version) -> PathBuf {
-        let mut crate_file_path = self.crate_dir_for(name);
-        crate_file_path.push(format!("{}.crate", version));
-        crate_file_path
-    }
-}
+    #[snafu(transparent)]
+    Open {
+        #[snafu(source(from(DiscoverRegistryError, Box::new)))]
+        source: Box<DiscoverRegistryError>,
+    },
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum InitializeError {
-    #[snafu(display("Could not create the registry directory"))]
-    RegistryCreate { source: io::Error },
+    #[snafu(transparent)]
+    Add {
+        #[snafu(source(from(AddError, Box::new)))]
+        source: Box<AddError>,
+    },
 
-    #[snafu(display("Could not serialize the registry's internal configuration"))]
-    ConfigTomlSerialize { source: toml::ser::Error },
+    #[snafu(transparent)]
+    Remove {
+        #[snafu(source(from(RemoveError, Box::new)))]
+        source: Box<RemoveError>,
+    },
 
-    #[snafu(display("Could not write the registry's internal configuration to {}", path.display()))]
-    ConfigTomlWrite { source: io::Error, path: PathBuf },
+    #[snafu(transparent)]
+    Html {
+        #[snafu(source(from(HtmlError, Box::new)))]
+        source: Box<HtmlError>,
+    },
 
-    #[snafu(display("Could not serialize the registry's public configuration"))]
-    ConfigJsonSerialize { source: serde_json::Error },
+    #[snafu(transparent)]
+    Lock {
+        #[snafu(source(from(lock::Error, Box::new)))]
+        source: Box<lock::Error>,
+    },
 
-    #[snafu(display("Could not write the registry's public configuration to {}", path.display()))]
-    ConfigJsonWrite { source: io::Error, path: PathBuf },
-}
+    #[snafu(transparent)]
+    Manifest {
+        #[snafu(source(from(manifest::Error, Box::new)))]
+        source: Box<manifest::Error>,
+    },
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum OpenError {
-    #[snafu(display("Could not open the registry's internal configuration at {}", path.display()))]
-    Read { source: io::Error, path: PathBuf },
+    #[snafu(transparent)]
+    Precompress {
+        #[snafu(source(from(precompress::Error, Box::new)))]
+        source: Box<precompress::Error>,
+    },
 
-    #[snafu(display("Could not deserialize the registry's internal configuration at {}", path.display()))]
-    Deserialize {
-        source: toml::de::Error,
-        path: PathBuf,
+    #[snafu(transparent)]
+    Yank {
+        #[snafu(source(from(YankError, Box::new)))]
+        source: Box<YankError>,
     },
-}
 
-impl OpenError {
-    fn is_not_found(&self) -> bool {
-        match self {
-            Self::Read { source, .. } => source.kind() == io::ErrorKind::NotFound,
-            Self::Deserialize { .. } => false,
-        }
-    }
-}
+    #[snafu(transparent)]
+    Plan {
+        #[snafu(source(from(plan::Error, Box::new)))]
+        source: Box<plan::Error>,
+    },
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum AddError {
-    #[snafu(display("Could not read the crate package"))]
-    ReadCrate { source: io::Error },
+    #[snafu(transparent)]
+    Reconcile {
+        #[snafu(source(from(reconcile::Error, Box::new)))]
+        source: Box<reconcile::Error>,
+    },
 
     #[snafu(transparent)]
-    CargoTomlExtract { source: ExtractRootCargoTomlError },
+    CheckRemote {
+        #[snafu(source(from(check_remote::Error, Box::new)))]
+        source: Box<check_remote::Error>,
+    },
 
-    #[snafu(display("The crate package does not contain a Cargo.toml file"))]
-    CargoTomlMissing,
+    #[snafu(transparent)]
+    SmokeTest {
+        #[snafu(source(from(smoke_test::Error, Box::new)))]
+        source: Box<smoke_test::Error>,
+    },
 
-    #[snafu(display("The crate's Cargo.toml is not valid UTF-8"))]
-    CargoTomlUtf8 { source: std::string::FromUtf8Error },
+    #[snafu(transparent)]
+    ApplyChanges {
+        #[snafu(source(from(changes::ApplyError, Box::new)))]
+        source: Box<changes::ApplyError>,
+    },
 
-    #[snafu(display("The crate's Cargo.toml is malformed"))]
-    CargoTomlMalformed { source: toml::de::Error },
+    #[snafu(transparent)]
+    Lockfile {
+        #[snafu(source(from(lockfile::Error, Box::new)))]
+        source: Box<lockfile::Error>,
+    },
 
-    #[snafu(display("Could not create the crate's index directory {}", path.display()))]
-    IndexDir { source: io::Error, path: PathBuf },
+    #[snafu(transparent)]
+    Vendor {
+        #[snafu(source(from(vendor::Error, Box::new)))]
+        source: Box<vendor::Error>,
+    },
 
     #[snafu(transparent)]
-    IndexModify { source: ReadModifyWriteError },
+    Subset {
+        #[snafu(source(from(subset::Error, Box::new)))]
+        source: Box<subset::Error>,
+    },
 
-    #[snafu(display("Could not create the crate directory {}", path.display()))]
-    CrateDir { source: io::Error, path: PathBuf },
+    #[snafu(transparent)]
+    Merkle {
+        #[snafu(source(from(merkle::Error, Box::new)))]
+        source: Box<merkle::Error>,
+    },
 
-    #[snafu(display("Could not write the crate {}", path.display()))]
-    CrateWrite { source: io::Error, path: PathBuf },
-}
+    #[snafu(transparent)]
+    Gc {
+        #[snafu(source(from(gc::Error, Box::new)))]
+        source: Box<gc::Error>,
+    },
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum RemoveError {
     #[snafu(transparent)]
-    IndexModify { source: ReadModifyWriteError },
+    DoGc { source: DoGcError },
 
-    #[snafu(display("Could not delete the crate file {}", path.display()))]
-    Delete { source: io::Error, path: PathBuf },
-}
+    #[snafu(transparent)]
+    Trash {
+        #[snafu(source(from(trash::Error, Box::new)))]
+        source: Box<trash::Error>,
+    },
 
-#[cfg(feature = "html")]
-use html::Error as HtmlError;
+    #[snafu(transparent)]
+    Repair {
+        #[snafu(source(from(repair::Error, Box::new)))]
+        source: Box<repair::Error>,
+    },
 
-#[cfg(not(feature = "html"))]
-#[derive(Debug, Snafu)]
-#[snafu(display("Margo was not compiled with the HTML feature enabled. This binary will not be able to generate HTML files"))]
-struct HtmlError;
+    #[snafu(transparent)]
+    DoRepair { source: DoRepairError },
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum YankError {
-    #[snafu(display("The version does not exist in the index"))]
-    Version,
+    #[snafu(transparent)]
+    VerifyInclusion {
+        #[snafu(source(from(VerifyInclusionError, Box::new)))]
+        source: Box<VerifyInclusionError>,
+    },
 
     #[snafu(transparent)]
-    Modify { source: ReadModifyWriteError },
-}
+    LocalRegistry {
+        #[snafu(source(from(local_registry::Error, Box::new)))]
+        source: Box<local_registry::Error>,
+    },
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum ReadModifyWriteError {
-    #[snafu(display("Could not parse the crate's index file {}", path.display()))]
-    IndexParse {
-        source: ParseIndexError,
-        path: PathBuf,
+    #[snafu(transparent)]
+    Import {
+        #[snafu(source(from(import::Error, Box::new)))]
+        source: Box<import::Error>,
     },
 
-    #[snafu(display("Could not write the crate's index file {}", path.display()))]
-    IndexWrite {
-        source: WriteIndexError,
-        path: PathBuf,
+    #[snafu(transparent)]
+    IngestLogs {
+        #[snafu(source(from(IngestLogsError, Box::new)))]
+        source: Box<IngestLogsError>,
     },
-}
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum ListIndexFilesError {
-    #[snafu(display("Could not enumerate the crate directory `{}`", path.display()))]
-    Walkdir {
-        source: walkdir::Error,
-        path: PathBuf,
+    #[snafu(transparent)]
+    Stats {
+        #[snafu(source(from(StatsError, Box::new)))]
+        source: Box<StatsError>,
     },
 
-    #[snafu(display(
-        "Could not remove the path prefix `{prefix}` from the crate package entry `{path}`",
-        prefix = prefix.display(),
-        path = path.display(),
-    ))]
-    Prefix {
-        source: std::path::StripPrefixError,
-        path: PathBuf,
-        prefix: PathBuf,
+    #[snafu(transparent)]
+    Metrics {
+        #[snafu(source(from(MetricsError, Box::new)))]
+        source: Box<MetricsError>,
     },
-}
 
-impl ListIndexFilesError {
-    fn is_not_found(&self) -> bool {
-        if let Self::Walkdir { source, .. } = self {
-            if let Some(e) = source.io_error() {
-                if e.kind() == io::ErrorKind::NotFound {
-                    return true;
-                }
-            }
-        }
+    #[snafu(transparent)]
+    FixCase {
+        #[snafu(source(from(FixCaseError, Box::new)))]
+        source: Box<FixCaseError>,
+    },
 
-        false
-    }
-}
+    #[snafu(transparent)]
+    Verify {
+        #[snafu(source(from(VerifyError, Box::new)))]
+        source: Box<VerifyError>,
+    },
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum ListAllError {
-    #[snafu(display("Unable to list the crate index files"))]
-    #[snafu(context(false))]
-    ListIndex { source: ListIndexFilesError },
+    #[snafu(transparent)]
+    Namespaces {
+        #[snafu(source(from(NamespacesError, Box::new)))]
+        source: Box<NamespacesError>,
+    },
 
-    #[snafu(display("Unable to parse the crate index file at `{}`", path.display()))]
-    Parse {
-        source: ParseIndexError,
-        path: PathBuf,
+    #[snafu(transparent)]
+    Latest {
+        #[snafu(source(from(LatestError, Box::new)))]
+        source: Box<LatestError>,
     },
-}
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum ParseIndexError {
-    #[snafu(display("Could not open the file"))]
-    Open { source: io::Error },
+    #[snafu(transparent)]
+    NextVersion {
+        #[snafu(source(from(NextVersionError, Box::new)))]
+        source: Box<NextVersionError>,
+    },
 
-    #[snafu(display("Could not read line {line}"))]
-    Read { source: io::Error, line: usize },
+    #[snafu(transparent)]
+    ConfigValidate {
+        #[snafu(source(from(ConfigValidateError, Box::new)))]
+        source: Box<ConfigValidateError>,
+    },
 
-    #[snafu(display("Could not parse line {line}"))]
-    Parse {
-        source: serde_json::Error,
-        line: usize,
+    #[snafu(transparent)]
+    ConfigStanza {
+        #[snafu(source(from(ConfigStanzaError, Box::new)))]
+        source: Box<ConfigStanzaError>,
     },
-}
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum WriteIndexError {
-    #[snafu(display("Could not open the file"))]
-    Open { source: io::Error },
+    #[snafu(transparent)]
+    CatIndex {
+        #[snafu(source(from(CatIndexError, Box::new)))]
+        source: Box<CatIndexError>,
+    },
 
-    #[snafu(display("Could not serialize the entry"))]
-    EntrySerialize { source: serde_json::Error },
+    #[snafu(transparent)]
+    Closure {
+        #[snafu(source(from(closure::Error, Box::new)))]
+        source: Box<closure::Error>,
+    },
 
-    #[snafu(display("Could not write the entry's newline"))]
-    EntryNewline { source: io::Error },
-}
+    #[snafu(transparent)]
+    Impact {
+        #[snafu(source(from(impact::Error, Box::new)))]
+        source: Box<impact::Error>,
+    },
 
-fn extract_root_cargo_toml(
-    crate_data: &[u8],
-) -> Result<Option<Vec<u8>>, ExtractRootCargoTomlError> {
-    use extract_root_cargo_toml_error::*;
+    #[snafu(transparent)]
+    Compare {
+        #[snafu(source(from(compare::Error, Box::new)))]
+        source: Box<compare::Error>,
+    },
 
-    let crate_data = flate2::read::GzDecoder::new(crate_data);
-    let mut crate_data = tar::Archive::new(crate_data);
+    #[snafu(transparent)]
+    Files {
+        #[snafu(source(from(files::Error, Box::new)))]
+        source: Box<files::Error>,
+    },
 
-    let entries = crate_data.entries().context(EntriesSnafu)?;
+    #[snafu(transparent)]
+    Snapshot {
+        #[snafu(source(from(snapshot::Error, Box::new)))]
+        source: Box<snapshot::Error>,
+    },
 
-    let mut dirname = None;
+    #[snafu(transparent)]
+    Version {
+        #[snafu(source(from(VersionError, Box::new)))]
+        source: Box<VersionError>,
+    },
 
-    for entry in entries {
-        let mut entry = entry.context(EntrySnafu)?;
-        let path = entry.path().context(PathSnafu)?;
+    #[snafu(transparent)]
+    Tui {
+        #[snafu(source(from(TuiError, Box::new)))]
+        source: Box<TuiError>,
+    },
 
-        let dirname = match &mut dirname {
-            Some(v) => v,
-            None => {
-                let Some(Component::Normal(first)) = path.components().next() else {
-                    return MalformedSnafu.fail();
-                };
-
-                dirname.insert(first.to_owned())
-            }
-        };
-
-        let fname = path.strip_prefix(dirname).context(PrefixSnafu)?;
-
-        if fname == Path::new("Cargo.toml") {
-            let mut data = vec![];
-            entry.read_to_end(&mut data).context(ReadSnafu)?;
-            return Ok(Some(data));
-        }
-    }
-
-    Ok(None)
+    #[snafu(transparent)]
+    Http {
+        #[snafu(source(from(http::Error, Box::new)))]
+        source: Box<http::Error>,
+    },
 }
 
-#[derive(Debug, Snafu)]
-#[snafu(module)]
-enum ExtractRootCargoTomlError {
-    #[snafu(display("Could not get the entries of the crate package"))]
-    Entries { source: io::Error },
-
-    #[snafu(display("Could not get the next crate package entry"))]
-    Entry { source: io::Error },
-
-    #[snafu(display("Could not get the path of the crate package entry"))]
-    Path { source: io::Error },
-
-    #[snafu(display("The crate package was malformed"))]
+/// A broad category of failure, allowing scripts to react to a
+/// failure without parsing its message. Margo exits with the kind's
+/// code; these codes will not change.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ErrorKind {
+    /// Anything not covered by another kind.
+    Other,
+    /// The registry, crate, version, or file does not exist.
+    NotFound,
+    /// The operation conflicts with the registry's contents or
+    /// configuration.
+    Conflict,
+    /// A file or package could not be understood.
     Malformed,
-
-    #[snafu(display("Could not remove the path prefix from the crate package entry"))]
-    Prefix { source: std::path::StripPrefixError },
-
-    #[snafu(display("Could not read the crate package entry for Cargo.toml"))]
-    Read { source: io::Error },
+    /// Reading or writing a file failed.
+    Io,
+    /// The command line could not be understood.
+    Usage,
+    /// A check, such as `verify`, ran and found problems.
+    Validation,
 }
 
-fn adapt_cargo_toml_to_index_entry(
-    global: &Global,
-    config: &ConfigV1,
-    mut cargo_toml: cargo_toml::Root,
-    checksum_hex: String,
-) -> index_entry::Root {
-    // Remove features that refer to dev-dependencies as we don't
-    // track those anyway.
-    {
-        // Ignore dependencies that also occur as a regular or build
-        // dependency, as we *do* track those.
-        let reg_dep_names = cargo_toml.dependencies.keys();
-        let build_dep_names = cargo_toml.build_dependencies.keys();
-        let mut only_dev_dep_names = cargo_toml.dev_dependencies.keys().collect::<BTreeSet<_>>();
-        for name in reg_dep_names.chain(build_dep_names) {
-            only_dev_dep_names.remove(name);
+impl ErrorKind {
+    fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::Usage => 2,
+            Self::NotFound => 3,
+            Self::Conflict => 4,
+            Self::Validation => 5,
+            Self::Io => 6,
+            Self::Malformed => 7,
         }
+    }
 
-        for name in only_dev_dep_names {
-            // We don't care about the official package name here as the
-            // feature syntax has to match the user-specified dependency
-            // name.
-            let prefix = format!("{name}/");
+    /// Categorizes an error by the first underlying cause we
+    /// recognize.
+    fn of_cause(e: &(dyn std::error::Error + 'static)) -> Self {
+        let mut cause = Some(e);
 
-            for enabled in cargo_toml.features.values_mut() {
-                enabled.retain(|enable| !enable.starts_with(&prefix));
+        while let Some(e) = cause {
+            if let Some(e) = e.downcast_ref::<io::Error>() {
+                return match e.kind() {
+                    io::ErrorKind::NotFound => Self::NotFound,
+                    _ => Self::Io,
+                };
             }
-        }
-    }
 
-    let mut deps: Vec<_> = cargo_toml
-        .dependencies
-        .into_iter()
-        .map(|(name, dep)| adapt_dependency(global, config, dep, name))
-        .collect();
+            if e.is::<toml::de::Error>()
+                || e.is::<serde_json::Error>()
+                || e.is::<semver::Error>()
+                || e.is::<str::Utf8Error>()
+                || e.is::<std::string::FromUtf8Error>()
+            {
+                return Self::Malformed;
+            }
 
-    let build_deps = cargo_toml
-        .build_dependencies
-        .into_iter()
-        .map(|(name, dep)| {
-            let mut dep = adapt_dependency(global, config, dep, name);
-            dep.kind = index_entry::DependencyKind::Build;
-            dep
-        });
-    deps.extend(build_deps);
+            cause = e.source();
+        }
 
-    for (target, defn) in cargo_toml.target {
-        let target_deps = defn.dependencies.into_iter().map(|(name, dep)| {
-            let mut dep = adapt_dependency(global, config, dep, name);
-            dep.target = Some(target.clone());
-            dep
-        });
-        deps.extend(target_deps);
+        Self::Other
     }
+}
 
-    // FUTURE: Opt-in to checking that all dependencies already exist
+impl Error {
+    fn kind(&self) -> ErrorKind {
+        let kind = match self {
+            Self::Open { source } => source.kind(),
+            Self::Add { source } => source.kind(),
+            Self::Remove { source } => source.kind(),
+            Self::Yank { source } => source.kind(),
+            Self::Plan { source } => source.kind(),
+            Self::Reconcile { source } => source.kind(),
+            Self::Namespaces { source } => source.kind(),
+            Self::Snapshot { source } => source.kind(),
+            Self::CatIndex { source } => source.kind(),
+            Self::Closure { source } => source.kind(),
+            Self::Impact { source } => source.kind(),
+            Self::Compare { source } => source.kind(),
+            Self::Import { source } => source.kind(),
+            Self::Lockfile { source } => source.kind(),
+            Self::Subset { source } => source.kind(),
+            Self::Merkle { source } => source.kind(),
+            Self::Trash { source } => source.kind(),
+            Self::VerifyInclusion { source } => source.kind(),
+            Self::Verify { source } => source.kind(),
+            Self::CheckRemote { source } => source.kind(),
+            Self::SmokeTest { source } => source.kind(),
+            Self::ConfigValidate { source } => source.kind(),
+            Self::ConfigStanza { source } => source.kind(),
+            Self::DoGc { .. } | Self::DoRepair { .. } => Some(ErrorKind::Usage),
+            _ => None,
+        };
 
-    index_entry::Root {
-        name: cargo_toml.package.name,
-        vers: cargo_toml.package.version,
-        deps,
-        cksum: checksum_hex,
-        features: cargo_toml.features,
-        yanked: false,
-        links: cargo_toml.package.links,
-        v: 2,
-        features2: Default::default(),
-        rust_version: cargo_toml.package.rust_version,
+        kind.unwrap_or_else(|| ErrorKind::of_cause(self))
     }
-}
 
-fn adapt_dependency(
-    global: &Global,
-    config: &ConfigV1,
-    dep: cargo_toml::Dependency,
-    name: String,
-) -> index_entry::Dependency {
-    let cargo_toml::Dependency {
-        version,
-        features,
-        optional,
-        default_features,
-        registry_index,
-        package,
-    } = dep;
+    fn code(&self) -> Option<diagnostics::Code> {
+        let code = match self {
+            Self::Open { source } => source.code(),
+            Self::Add { source } => source.code(),
+            Self::Remove { source } => source.code(),
+            Self::CatIndex { source } => source.code(),
+            Self::NextVersion { source } => source.code(),
+            Self::Lockfile { source } => source.code(),
+            _ => None,
+        };
 
-    index_entry::Dependency {
-        name,
-        req: version,
-        features,
-        optional,
-        default_features,
-        target: None,
-        kind: index_entry::DependencyKind::Normal,
-        registry: adapt_index(global, config, registry_index),
-        package,
+        code.or_else(|| diagnostics::Code::of_cause(self))
     }
 }
 
-fn adapt_index(global: &Global, config: &ConfigV1, registry_index: Option<Url>) -> Option<Url> {
-    // The dependency is in...
-    match registry_index {
-        // ...crates.io
-        None => Some(global.crates_io_index_url.clone()),
+trait UnwrapOrDialog<T> {
+    fn apply_default(self, use_default: bool, value: impl Into<T>) -> Self;
 
-        // ...this registry
-        Some(url) if url == config.base_url => None,
+    fn unwrap_or_dialog(self, f: impl FnOnce() -> dialoguer::Result<T>) -> dialoguer::Result<T>;
+}
 
-        // ...another registry
-        r => r,
+impl<T> UnwrapOrDialog<T> for Option<T> {
+    fn apply_default(self, use_default: bool, value: impl Into<T>) -> Self {
+        if self.is_none() && use_default {
+            Some(value.into())
+        } else {
+            self
+        }
     }
-}
 
-/// Only intended for the normalized Cargo.toml created for the
-/// packaged crate.
-mod cargo_toml {
-    use semver::{Version, VersionReq};
-    use serde::Deserialize;
-    use std::collections::BTreeMap;
-    use url::Url;
+    fn unwrap_or_dialog(self, f: impl FnOnce() -> dialoguer::Result<T>) -> dialoguer::Result<T> {
+        match self {
+            Some(v) => Ok(v),
+            None => f(),
+        }
+    }
+}
 
-    use crate::common::{CrateName, RustVersion};
+fn env_fallback<T>(value: Option<T>, key: &'static str) -> Result<Option<T>, EnvFallbackError>
+where
+    T: str::FromStr,
+    BoxError: From<T::Err>,
+{
+    use env_fallback_error::*;
 
-    pub type Dependencies = BTreeMap<String, Dependency>;
+    if value.is_some() {
+        return Ok(value);
+    }
 
-    #[derive(Debug, Deserialize)]
-    #[serde(rename_all = "kebab-case")]
-    pub struct Root {
-        pub package: Package,
+    let value = match env::var(key) {
+        Ok(v) if v.is_empty() => return Ok(None),
+        Ok(v) => v,
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(_)) => return NotUnicodeSnafu { key }.fail(),
+    };
 
-        #[serde(default)]
-        pub features: BTreeMap<String, Vec<String>>,
+    value
+        .parse()
+        .map(Some)
+        .map_err(BoxError::from)
+        .context(ParseSnafu { key, value })
+}
 
-        #[serde(default)]
-        pub dependencies: Dependencies,
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
-        #[serde(default)]
-        pub build_dependencies: Dependencies,
+const ENV_REGISTRY: &str = "MARGO_REGISTRY";
+const ENV_BASE_URL: &str = "MARGO_BASE_URL";
+const ENV_NON_INTERACTIVE: &str = "MARGO_NON_INTERACTIVE";
+const ENV_OFFLINE: &str = "MARGO_OFFLINE";
+const ENV_AUTH_REQUIRED: &str = "MARGO_AUTH_REQUIRED";
+const ENV_HTML: &str = "MARGO_HTML";
+const ENV_HTML_SUGGESTED_REGISTRY_NAME: &str = "MARGO_HTML_SUGGESTED_REGISTRY_NAME";
+const ENV_CRATE_PATH: &str = "MARGO_CRATE_PATH";
+const ENV_HTTP_USERNAME: &str = "MARGO_HTTP_USERNAME";
+const ENV_HTTP_PASSWORD: &str = "MARGO_HTTP_PASSWORD";
 
-        #[serde(default)]
-        pub dev_dependencies: Dependencies,
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum EnvFallbackError {
+    #[snafu(display("The environment variable `{key}` is not valid UTF-8"))]
+    NotUnicode { key: &'static str },
 
-        #[serde(default)]
-        pub target: BTreeMap<String, Target>,
-    }
+    #[snafu(display("Could not parse the environment variable `{key}` (value `{value}`)"))]
+    Parse {
+        source: BoxError,
+        key: &'static str,
+        value: String,
+    },
+}
 
-    #[derive(Debug, Deserialize)]
-    #[serde(rename_all = "kebab-case")]
-    pub struct Package {
-        pub name: CrateName,
+fn do_init(_global: &Global, init: InitArgs) -> Result<(), DoInitializeError> {
+    use do_initialize_error::*;
 
-        pub version: Version,
+    let non_interactive =
+        init.non_interactive || env_fallback(None, ENV_NON_INTERACTIVE)?.unwrap_or(false);
+    let defaults = init.defaults || non_interactive;
 
-        #[serde(default)]
-        pub links: Option<String>,
+    let existing = match Registry::open(&init.path) {
+        Ok(r) => Some(r),
+        Err(e) if e.is_not_found() => None,
+        Err(e) => return Err(e).context(ExistingOpenSnafu),
+    };
 
-        #[serde(default)]
-        pub rust_version: Option<RustVersion>,
+    let crate_path = env_fallback(init.crate_path, ENV_CRATE_PATH)?;
+    if let (Some(r), Some(crate_path)) = (&existing, &crate_path) {
+        // Existing crate files would no longer be found
+        let has_crates = !r.list_all().context(ExistingListSnafu)?.is_empty();
+        ensure!(
+            !has_crates || *crate_path == r.config.crate_path,
+            CratePathChangedSnafu
+        );
     }
+    let existing = existing.map(|r| r.config);
 
-    #[derive(Debug, Deserialize)]
-    #[serde(rename_all = "kebab-case")]
-    pub struct Dependency {
-        pub version: VersionReq,
+    ensure!(
+        existing.is_none() || init.reconfigure,
+        AlreadyInitializedSnafu { path: &init.path },
+    );
 
-        #[serde(default)]
-        pub features: Vec<String>,
+    let base_url = env_fallback(init.base_url, ENV_BASE_URL)?
+        .or_else(|| existing.as_ref().map(|c| c.base_url.clone()));
+    ensure!(base_url.is_some() || !non_interactive, BaseUrlMissingSnafu);
+    let base_url = base_url
+        .unwrap_or_dialog(|| {
+            dialoguer::Input::new()
+                .with_prompt("What URL will the registry be served from")
+                .interact()
+        })
+        .context(BaseUrlSnafu)?;
 
-        #[serde(default)]
-        pub optional: bool,
+    let auth_required = env_fallback(init.auth_required, ENV_AUTH_REQUIRED)?
+        .or_else(|| existing.as_ref().map(|c| c.auth_required))
+        .apply_default(defaults, ConfigV1::USER_DEFAULT_AUTH_REQUIRED)
+        .unwrap_or_dialog(|| {
+            dialoguer::Confirm::new()
+                .default(ConfigV1::USER_DEFAULT_AUTH_REQUIRED)
+                .show_default(true)
+                .with_prompt("Require HTTP authentication to access crates?")
+                .interact()
+        })
+        .context(AuthRequiredSnafu)?;
 
-        #[serde(default = "true_def")]
-        pub default_features: bool,
+    let enabled = env_fallback(init.html, ENV_HTML)?
+        .or_else(|| existing.as_ref().map(|c| c.html.enabled))
+        .apply_default(defaults, ConfigV1Html::USER_DEFAULT_ENABLED)
+        .unwrap_or_dialog(|| {
+            dialoguer::Confirm::new()
+                .default(ConfigV1Html::USER_DEFAULT_ENABLED)
+                .show_default(true)
+                .with_prompt("Enable HTML index generation?")
+                .interact()
+        })
+        .context(HtmlEnabledSnafu)?;
 
-        #[serde(default)]
-        pub registry_index: Option<Url>,
+    let suggested_registry_name = if enabled {
+        let name = env_fallback(
+            init.html_suggested_registry_name,
+            ENV_HTML_SUGGESTED_REGISTRY_NAME,
+        )?
+        .or_else(|| {
+            existing
+                .as_ref()
+                .and_then(|c| c.html.suggested_registry_name.clone())
+        })
+        .apply_default(defaults, ConfigV1Html::USER_DEFAULT_SUGGESTED_REGISTRY_NAME)
+        .unwrap_or_dialog(|| {
+            dialoguer::Input::new()
+                .default(ConfigV1Html::USER_DEFAULT_SUGGESTED_REGISTRY_NAME.to_owned())
+                .show_default(true)
+                .with_prompt("Name you'd like to suggest other people call your registry")
+                .interact()
+        })
+        .context(HtmlSuggestedRegistryNameSnafu)?;
 
-        #[serde(default)]
-        pub package: Option<String>,
-    }
+        Some(name)
+    } else {
+        None
+    };
 
-    #[derive(Debug, Deserialize)]
-    pub struct Target {
-        #[serde(default)]
-        pub dependencies: Dependencies,
+    let mut config = existing.unwrap_or_else(|| ConfigV1::new(base_url.clone()));
+    config.base_url = base_url;
+    config.auth_required = auth_required;
+    config.html.enabled = enabled;
+    if suggested_registry_name.is_some() {
+        config.html.suggested_registry_name = suggested_registry_name;
     }
-
-    fn true_def() -> bool {
-        true
+    if let Some(crate_path) = crate_path {
+        config.crate_path = crate_path;
+    }
+    if init.host.is_some() {
+        config.host = init.host;
     }
-}
 
-const CONFIG_FILE_NAME: &str = "margo-config.toml";
-const CRATE_DIR_NAME: &str = "crates";
+    let r = Registry::initialize(config, &init.path)?;
 
-const CRATES_IO_INDEX_URL: &str = "https://github.com/rust-lang/crates.io-index";
+    // Rewritten when reconfiguring, as they depend on the base URL
+    if let Some(host) = r.config.host {
+        for path in hosting::write_files(&r, host)? {
+            println!("Wrote `{}`", path.display());
+        }
 
-#[derive(Debug)]
-struct Global {
-    crates_io_index_url: Url,
-}
+        if host == hosting::Host::S3 {
+            println!(
+                "S3 serves files with the content type they were uploaded with; \
+                 set the content types listed in `headers.json` when uploading"
+            );
+        }
+    }
 
-impl Global {
-    fn new() -> Result<Self, GlobalError> {
-        use global_error::*;
+    if r.config.html.enabled {
+        let res = r.generate_html();
 
-        Ok(Self {
-            crates_io_index_url: CRATES_IO_INDEX_URL.parse().context(CratesIoIndexUrlSnafu)?,
-        })
+        if cfg!(feature = "html") {
+            res?;
+        } else if let Err(e) = res {
+            eprintln!("Warning: {e}");
+        }
     }
+
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
+
+    Ok(())
 }
 
 #[derive(Debug, Snafu)]
 #[snafu(module)]
-enum GlobalError {
-    #[snafu(display("Could not parse the crates.io index URL"))]
-    CratesIoIndexUrl { source: url::ParseError },
-}
+enum DoInitializeError {
+    #[snafu(transparent)]
+    EnvFallback { source: EnvFallbackError },
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "version")]
-enum Config {
-    #[serde(rename = "1")]
-    V1(ConfigV1),
-}
+    #[snafu(display(
+        "The base URL must be provided via `--base-url` or `{ENV_BASE_URL}` when running non-interactively"
+    ))]
+    BaseUrlMissing,
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ConfigV1 {
-    base_url: Url,
+    #[snafu(display("Could not check for an existing registry"))]
+    ExistingOpen { source: OpenError },
 
-    #[serde(default)]
-    auth_required: bool,
+    #[snafu(display(
+        "A registry already exists at {}; use `--reconfigure` to change its configuration",
+        path.display(),
+    ))]
+    AlreadyInitialized { path: PathBuf },
 
-    #[serde(default)]
-    html: ConfigV1Html,
+    #[snafu(display("Could not list the crates in the existing registry"))]
+    ExistingList { source: ListAllError },
+
+    #[snafu(display("The crate path cannot be changed once the registry contains crates"))]
+    CratePathChanged,
+
+    #[snafu(display("Could not determine the base URL"))]
+    BaseUrl { source: dialoguer::Error },
+
+    #[snafu(display("Could not determine if HTTP authorization is required"))]
+    AuthRequired { source: dialoguer::Error },
+
+    #[snafu(display("Could not determine if HTML generation is enabled"))]
+    HtmlEnabled { source: dialoguer::Error },
+
+    #[snafu(display("Could not determine the suggested registry name"))]
+    HtmlSuggestedRegistryName { source: dialoguer::Error },
+
+    #[snafu(transparent)]
+    Initialize { source: InitializeError },
+
+    #[snafu(transparent)]
+    Hosting { source: hosting::Error },
+
+    #[snafu(transparent)]
+    Manifest { source: manifest::Error },
+
+    #[snafu(transparent)]
+    Precompress { source: precompress::Error },
+
+    #[snafu(transparent)]
+    Html { source: HtmlError },
 }
 
-impl ConfigV1 {
-    const USER_DEFAULT_AUTH_REQUIRED: bool = false;
+fn do_add(global: &Global, add: AddArgs) -> Result<(), Error> {
+    let r = discover_registry(add.registry)?;
 
-    fn normalize(mut self) -> ConfigV1 {
-        ensure_last_segment_empty(&mut self.base_url);
+    let options = AddOptions {
+        source: metadata::Source {
+            url: add.source_url,
+            rev: add.source_rev,
+            ..Default::default()
+        },
+        deny_dirty: add.deny_dirty,
+        forbid_external_dependencies: add.forbid_external_deps,
+        rewrite_deps_to_self: add.rewrite_deps_to_self,
+        allow_any_name: add.allow_any_name,
+        channel: add.channel,
+    };
 
-        self
+    let lock = lock::exclusive(&r)?;
+    for i in add.path {
+        r.add_with_options(global, i, &options)?;
     }
+    drop(lock);
+
+    r.maybe_generate_html()?;
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
+
+    Ok(())
 }
 
-fn ensure_last_segment_empty(url: &mut Url) {
-    if let Ok(mut s) = url.path_segments_mut() {
-        s.pop_if_empty().push("");
+fn do_remove(_global: &Global, rm: RemoveArgs) -> Result<(), Error> {
+    use remove_error::*;
+
+    let r = discover_registry(rm.registry)?;
+
+    let tombstone = (rm.tombstone || rm.reason.is_some()).then(|| metadata::Tombstone {
+        reason: rm.reason,
+        removed_at: Timestamp::now(),
+    });
+    let tombstone = tombstone.as_ref();
+    let disposal = Disposal::permanent(rm.permanent);
+
+    let selector = VersionSelector {
+        version: rm.version,
+        req: rm.versions,
+        prereleases: rm.all_prereleases,
+        all: false,
+        channel: rm.channel,
+    };
+
+    let lock = lock::exclusive(&r)?;
+    match (rm.name, selector.is_empty(), rm.all_yanked) {
+        (Some(name), false, false) => {
+            r.remove_versions(name, &selector, tombstone, disposal, rm.dry_run)?
+        }
+        (Some(name), true, false) => r.remove_crate(name, tombstone, disposal, rm.dry_run)?,
+        (None, true, true) => r.remove_all_yanked(tombstone, disposal, rm.dry_run)?,
+        (None, _, false) => NameMissingSnafu.fail()?,
+        (_, _, true) => AllYankedConflictSnafu.fail()?,
     }
-}
+    drop(lock);
 
-#[derive(Debug, Default, Serialize, Deserialize)]
-struct ConfigV1Html {
-    #[serde(default)]
-    enabled: bool,
-    #[serde(default)]
-    suggested_registry_name: Option<String>,
+    if !rm.dry_run {
+        r.maybe_generate_html()?;
+        r.maybe_write_manifest()?;
+        r.maybe_precompress()?;
+    }
+
+    Ok(())
 }
 
-impl ConfigV1Html {
-    const USER_DEFAULT_ENABLED: bool = true;
-    const USER_DEFAULT_SUGGESTED_REGISTRY_NAME: &'static str = "my-awesome-registry";
+fn do_generate_html(_global: &Global, html: GenerateHtmlArgs) -> Result<(), Error> {
+    let r = discover_registry(html.registry)?;
+    r.generate_html_with(html.keep_old_assets)?;
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
+    if html.watch {
+        r.watch_html(html.keep_old_assets)?;
+    }
+    Ok(())
+}
 
-    fn suggested_registry_name(&self) -> &str {
-        self.suggested_registry_name
-            .as_deref()
-            .unwrap_or(Self::USER_DEFAULT_SUGGESTED_REGISTRY_NAME)
+fn do_generate_landing_page(
+    _global: &Global,
+    landing: GenerateLandingPageArgs,
+) -> Result<(), Error> {
+    let registries = open_namespaces(&landing.path)?;
+    for (name, _) in &registries {
+        println!("Found registry `{name}`");
     }
+    generate_landing_page(&landing.path, &registries)?;
+    Ok(())
 }
 
-mod config_json {
-    use serde::Serialize;
+fn do_yank(_global: &Global, yank: YankArgs) -> Result<(), Error> {
+    let r = discover_registry(yank.registry)?;
 
-    #[derive(Debug, Serialize)]
-    #[serde(rename_all = "kebab-case")]
-    pub struct Root {
-        // This field cannot be a `url::Url` because that type
-        // percent-escapes the `{` and `}` characters. Cargo performs
-        // string-replacement on this value based on those literal `{`
-        // and `}` characters.
-        pub dl: String,
+    let selector = VersionSelector {
+        version: yank.version,
+        req: yank.versions,
+        prereleases: yank.all_prereleases,
+        all: yank.all,
+        channel: yank.channel,
+    };
 
-        pub api: Option<String>, // Modified
+    if yank.dry_run {
+        let verb = if yank.undo { "unyank" } else { "yank" };
+        for version in r.select_versions(&yank.name, &selector)? {
+            println!("Would {verb} `{}` version {version}", yank.name);
+        }
+        return Ok(());
+    }
 
-        /// A private registry requires all operations to be authenticated.
-        ///
-        /// This includes API requests, crate downloads and sparse
-        /// index updates.
-        pub auth_required: bool,
+    let lock = lock::exclusive(&r)?;
+    let versions = r.yank(yank.name.clone(), &selector, !yank.undo)?;
+    drop(lock);
+
+    let verb = if yank.undo { "Unyanked" } else { "Yanked" };
+    for version in versions {
+        println!("{verb} `{}` version {version}", yank.name);
     }
+
+    r.maybe_generate_html()?;
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
+
+    Ok(())
 }
 
-mod index_entry {
-    use semver::{Version, VersionReq};
-    use serde::{Deserialize, Serialize};
-    use std::collections::BTreeMap;
-    use url::Url;
+fn do_plan(global: &Global, plan: PlanArgs) -> Result<(), Error> {
+    let r = discover_registry(plan.registry)?;
 
-    use crate::common::{CrateName, RustVersion};
+    let p = plan::plan(global, &r, &plan.tasks)?;
+    if p.steps.is_empty() {
+        println!("Nothing to do");
+    }
+    for step in &p.steps {
+        println!("Would {step}");
+    }
 
-    #[derive(Debug, Serialize, Deserialize)]
-    pub struct Root {
-        /// The name of the package.
-        pub name: CrateName,
+    if let Some(out) = plan.out {
+        plan::write(&p, &out)?;
+        println!("Wrote the plan to {}", out.display());
+    }
 
-        /// The version of the package this row is describing.
-        ///
-        /// This must be a valid version number according to the
-        /// Semantic Versioning 2.0.0 spec at https://semver.org/.
-        pub vers: Version,
+    Ok(())
+}
 
-        /// Direct dependencies of the package.
-        pub deps: Vec<Dependency>,
+fn do_apply(global: &Global, apply: ApplyArgs) -> Result<(), Error> {
+    let r = discover_registry(apply.registry)?;
 
-        /// A SHA256 checksum of the `.crate` file.
-        pub cksum: String,
+    let p = plan::read(&apply.plan)?;
 
-        /// Set of features defined for the package.
-        ///
-        /// Each feature maps to features or dependencies it enables.
-        pub features: BTreeMap<String, Vec<String>>,
+    let lock = lock::exclusive(&r)?;
+    plan::apply(global, &r, &p)?;
+    drop(lock);
 
-        /// Boolean of whether or not this version has been yanked.
-        pub yanked: bool,
+    println!("Applied {} step(s)", p.steps.len());
 
-        /// The `links` value from the package's manifest.
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub links: Option<String>,
+    r.maybe_generate_html()?;
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
 
-        /// The schema version of this entry.
-        //
-        /// If this not specified, it should be interpreted as the default of 1.
-        //
-        /// Cargo (starting with version 1.51) will ignore versions it does not
-        /// recognize. This provides a method to safely introduce changes to index
-        /// entries and allow older versions of cargo to ignore newer entries it
-        /// doesn't understand. Versions older than 1.51 ignore this field, and
-        /// thus may misinterpret the meaning of the index entry.
-        //
-        /// The current values are:
-        //
-        /// * 1: The schema as documented here, not including newer additions.
-        ///      This is honored in Rust version 1.51 and newer.
-        /// * 2: The addition of the `features2` field.
-        ///      This is honored in Rust version 1.60 and newer.
-        pub v: u32,
+    Ok(())
+}
 
-        /// Features with new, extended syntax, such as namespaced
-        /// features (`dep:`) and weak dependencies (`pkg?/feat`).
-        //
-        /// This is separated from `features` because versions older than 1.19
-        /// will fail to load due to not being able to parse the new syntax, even
-        /// with a `Cargo.lock` file.
-        //
-        /// Cargo will merge any values listed here with the "features" field.
-        //
-        /// If this field is included, the "v" field should be set to at least 2.
-        //
-        /// Registries are not required to use this field for extended feature
-        /// syntax, they are allowed to include those in the "features" field.
-        /// Using this is only necessary if the registry wants to support cargo
-        /// versions older than 1.19, which in practice is only crates.io since
-        /// those older versions do not support other registries.
-        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
-        pub features2: BTreeMap<String, Vec<String>>,
+fn do_reconcile(global: &Global, reconcile: ReconcileArgs) -> Result<(), Error> {
+    let r = discover_registry(reconcile.registry)?;
 
-        /// The minimal supported Rust version
-        ///
-        /// This must be a valid version requirement without an operator (e.g. no `=`)
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub rust_version: Option<RustVersion>,
+    if reconcile.dry_run {
+        let diff = reconcile::diff(global, &r, &reconcile.state)?;
+        for line in diff.describe() {
+            println!("Would {line}");
+        }
+        return Ok(());
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
-    pub struct Dependency {
-        /// Name of the dependency.
-        ///
-        /// If the dependency is renamed from the original package
-        /// name, this is the new name. The original package name is
-        /// stored in the `package` field.
-        pub name: String,
+    let lock = lock::exclusive(&r)?;
+    let diff = reconcile::diff(global, &r, &reconcile.state)?;
+    if diff.is_empty() {
+        println!("The registry already matches the state file");
+        return Ok(());
+    }
 
-        /// The SemVer requirement for this dependency.
-        ///
-        /// This must be a valid version requirement defined at
-        /// https://doc.rust-lang.org/cargo/reference/specifying-dependencies.html.
-        pub req: VersionReq,
+    let changes = diff.describe();
+    println!("Applying {} change(s):", changes.len());
+    for line in &changes {
+        println!("  {line}");
+    }
 
-        /// Features enabled for this dependency.
-        pub features: Vec<String>,
+    reconcile::apply(global, &r, &diff)?;
+    drop(lock);
 
-        /// Whether or not this is an optional dependency.
-        pub optional: bool,
+    r.maybe_generate_html()?;
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
 
-        /// Whether or not default features are enabled.
-        pub default_features: bool,
+    Ok(())
+}
 
-        /// The target platform for the dependency.
-        ///
-        /// A string such as `cfg(windows)`.
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub target: Option<String>,
+fn do_check_remote(global: &Global, check: CheckRemoteArgs) -> Result<(), Error> {
+    let r = discover_registry(check.registry)?;
 
-        /// The dependency kind.
-        ///
-        /// Note: this is a required field, but a small number of entries
-        /// exist in the crates.io index with either a missing or null
-        /// `kind` field due to implementation bugs.
-        pub kind: DependencyKind,
+    let client = http::Client::from_env(global, Some(&r.config.base_url))?;
 
-        /// The URL of the index of the registry where this dependency
-        /// is from.
-        ///
-        /// If not specified or null, it is assumed the dependency is
-        /// in the current registry.
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub registry: Option<Url>,
+    r.check_remote(check_remote::Options {
+        sample: check.sample,
+        jobs: check.jobs,
+        client: &client,
+    })?;
 
-        /// If the dependency is renamed, this is the actual package
-        /// name.
-        ///
-        /// If not specified or null, this dependency is not renamed.
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub package: Option<String>,
+    Ok(())
+}
+
+fn do_smoke_test(_global: &Global, smoke: SmokeTestArgs) -> Result<(), Error> {
+    let r = discover_registry(smoke.registry)?;
+
+    let options = smoke_test::Options {
+        local: smoke.local,
+        fetch_only: smoke.fetch_only,
+        keep: smoke.keep,
+    };
+    let report = smoke_test::run(&r, &smoke.krate, &options)?;
+
+    for outcome in &report.commands {
+        let status = if outcome.success { "ok" } else { "failed" };
+        println!("cargo {}: {status}", outcome.command);
+
+        for problem in &outcome.problems {
+            println!("  [{}] {}", problem.kind.label(), problem.message);
+        }
+        if !outcome.success && outcome.problems.is_empty() {
+            print!("{}", outcome.stderr);
+        }
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
-    #[serde(rename_all = "snake_case")]
-    pub enum DependencyKind {
-        #[allow(unused)]
-        // Stored in the index, but not actually used by Cargo
-        Dev,
-        Build,
-        Normal,
+    if smoke.keep {
+        println!("Kept the project at {}", report.project.display());
     }
+
+    report.ensure_succeeded(&smoke.krate)?;
+
+    Ok(())
 }
 
-mod common {
-    use ascii::{AsciiChar, AsciiStr, AsciiString};
-    use semver::Version;
-    use serde::{de::Error, Deserialize, Serialize};
-    use snafu::prelude::*;
-    use std::{
-        borrow::Cow,
-        fmt, ops,
-        path::{Path, PathBuf},
-        str::FromStr,
+fn do_apply_changes(global: &Global, apply: ApplyChangesArgs) -> Result<(), Error> {
+    let r = discover_registry(apply.registry)?;
+
+    let source = changes::Source::new(global, &apply.from)?;
+    let lock = lock::exclusive(&r)?;
+    let applied = changes::apply(&r, &source)?;
+    drop(lock);
+    println!("Applied {applied} change(s)");
+
+    r.maybe_generate_html()?;
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
+
+    Ok(())
+}
+
+fn do_import(global: &Global, import: ImportArgs) -> Result<(), Error> {
+    let r = discover_registry(import.registry)?;
+
+    let lock = lock::exclusive(&r)?;
+    let imported = import::import(global, &r, import.from, &import.index, import.crates)?;
+    drop(lock);
+    println!("Imported {imported} crate version(s)");
+
+    r.maybe_generate_html()?;
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
+
+    Ok(())
+}
+
+fn do_ingest_logs(_global: &Global, ingest: IngestLogsArgs) -> Result<(), Error> {
+    let r = discover_registry(ingest.registry)?;
+
+    let lock = lock::exclusive(&r)?;
+    let downloads = r.ingest_logs(ingest.format, &ingest.path)?;
+    drop(lock);
+    println!("Counted {downloads} download(s)");
+
+    r.maybe_generate_html()?;
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
+
+    Ok(())
+}
+
+fn do_stats(_global: &Global, stats: StatsArgs) -> Result<(), Error> {
+    use stats_error::*;
+
+    let r = discover_registry(stats.registry)?;
+
+    let crates = r.list_all().context(ListAllSnafu)?;
+    let metadata = r.list_all_metadata().context(MetadataSnafu)?;
+
+    let rows = crates
+        .iter()
+        .map(|(name, index)| {
+            let versions = index.len();
+            let yanked = index.values().filter(|e| e.yanked).count();
+            let downloads = metadata
+                .get(name)
+                .map_or(0, |m| m.downloads.values().sum::<u64>());
+            (name, versions, yanked, downloads)
+        })
+        .collect::<Vec<_>>();
+
+    let max_c = rows
+        .iter()
+        .map(|(name, ..)| name.len())
+        .chain(["crate".len()])
+        .max()
+        .unwrap_or(0);
+
+    println!(
+        "{:<max_c$} {:>8} {:>6} {:>9}",
+        "crate", "versions", "yanked", "downloads"
+    );
+    for (name, versions, yanked, downloads) in &rows {
+        println!("{name:<max_c$} {versions:>8} {yanked:>6} {downloads:>9}");
+    }
+
+    let total_versions = rows.iter().map(|r| r.1).sum::<usize>();
+    let total_yanked = rows.iter().map(|r| r.2).sum::<usize>();
+    let total_downloads = rows.iter().map(|r| r.3).sum::<u64>();
+    println!(
+        "{:<max_c$} {total_versions:>8} {total_yanked:>6} {total_downloads:>9}",
+        "total"
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum StatsError {
+    #[snafu(display("Could not list the crates"))]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not list the crate metadata"))]
+    Metadata { source: MetadataError },
+}
+
+fn do_latest(_global: &Global, latest: LatestArgs) -> Result<(), Error> {
+    use latest_error::*;
+
+    let r = discover_registry(latest.registry)?;
+    let name = latest.name;
+
+    let path = r.index_file_path_for(&name);
+    let index = Registry::parse_index_file(&path).context(IndexReadSnafu { path })?;
+    ensure!(!index.is_empty(), CrateNotFoundSnafu { name });
+
+    let version = match &latest.req {
+        Some(req) => latest_version_matching(&index, req),
+        None => latest_version(&index),
     };
+    let version = version.context(NoMatchSnafu { name })?;
 
-    /// Contains only alphanumeric, `-`, or `_` characters.
-    #[derive(Debug, Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
-    pub struct CrateName(AsciiString);
+    println!("{version}");
 
-    impl CrateName {
-        pub fn as_str(&self) -> &str {
-            self.0.as_str()
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum LatestError {
+    #[snafu(display("Could not read the crate's index file {}", path.display()))]
+    IndexRead {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("The crate `{name}` does not exist in the registry"))]
+    CrateNotFound { name: CrateName },
+
+    #[snafu(display("No available version of the crate `{name}` matches"))]
+    NoMatch { name: CrateName },
+}
+
+fn do_cat_index(_global: &Global, cat: CatIndexArgs) -> Result<(), Error> {
+    let r = discover_registry(cat.registry)?;
+
+    let output = cat_index(&r, &cat.name, cat.version.as_ref(), cat.pretty)?;
+    print!("{output}");
+
+    Ok(())
+}
+
+/// The crate's index file, or one version's line of it, exactly as
+/// stored unless `pretty` is set.
+fn cat_index(
+    r: &Registry,
+    name: &CrateName,
+    version: Option<&Version>,
+    pretty: bool,
+) -> Result<String, CatIndexError> {
+    use cat_index_error::*;
+
+    let path = r.index_file_path_for(name);
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return CrateNotFoundSnafu { name: name.clone() }.fail()
         }
+        Err(e) => return Err(e).context(ReadSnafu { path }),
+    };
 
-        pub fn len(&self) -> usize {
-            self.0.len()
+    let mut lines = data.lines().collect::<Vec<_>>();
+
+    if let Some(version) = version {
+        let mut found = None;
+        for (i, line) in lines.iter().enumerate() {
+            let entry = serde_json::from_str::<index_entry::Root>(line).context(ParseSnafu {
+                path: &path,
+                line: i,
+            })?;
+            if entry.vers == *version {
+                found = Some(*line);
+                break;
+            }
         }
+        let line = found.context(VersionNotFoundSnafu {
+            name: name.clone(),
+            version: version.clone(),
+        })?;
+        lines = vec![line];
+    } else if !pretty {
+        return Ok(data);
+    }
 
-        pub fn append_prefix_directories(&self, index_path: &mut PathBuf) {
-            match self.len() {
-                0 => unreachable!(),
-                1 => index_path.push("1"),
-                2 => index_path.push("2"),
-                3 => {
-                    let a = &self[0..1];
+    let mut output = String::new();
+    for (i, line) in lines.into_iter().enumerate() {
+        if pretty {
+            let entry = serde_json::from_str::<serde_json::Value>(line).context(ParseSnafu {
+                path: &path,
+                line: i,
+            })?;
+            let entry = serde_json::to_string_pretty(&entry).context(PrettySnafu)?;
+            output.push_str(&entry);
+        } else {
+            output.push_str(line);
+        }
+        output.push('\n');
+    }
 
-                    index_path.push("3");
-                    index_path.push(a.as_str());
-                }
-                _ => {
-                    let ab = &self[0..2];
-                    let cd = &self[2..4];
+    Ok(output)
+}
 
-                    index_path.push(ab.as_str());
-                    index_path.push(cd.as_str());
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum CatIndexError {
+    #[snafu(display("The crate `{name}` does not exist in the registry"))]
+    CrateNotFound { name: CrateName },
+
+    #[snafu(display("Could not read the crate's index file {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not parse line {line} of the index file {}", path.display()))]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+        line: usize,
+    },
+
+    #[snafu(display("Version {version} of the crate `{name}` does not exist in the registry"))]
+    VersionNotFound { name: CrateName, version: Version },
+
+    #[snafu(display("Could not format the index entry"))]
+    Pretty { source: serde_json::Error },
+}
+
+impl CatIndexError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::CrateNotFound { .. } | Self::VersionNotFound { .. } => Some(ErrorKind::NotFound),
+            _ => None,
+        }
+    }
+
+    fn code(&self) -> Option<diagnostics::Code> {
+        match self {
+            Self::CrateNotFound { .. } => Some(diagnostics::Code::CrateNotFound),
+            _ => None,
+        }
+    }
+}
+
+fn do_closure(global: &Global, closure: ClosureArgs) -> Result<(), Error> {
+    let r = discover_registry(closure.registry)?;
+
+    let requirements = closure::walk(global, &r, &closure.krate)?;
+
+    match closure.output {
+        ReportFormat::Json => print!("{}", closure::to_json(&requirements)?),
+        ReportFormat::Table => {
+            let rows = requirements
+                .iter()
+                .map(|req| {
+                    let mut notes = Vec::new();
+                    if req.kind != "normal" {
+                        notes.push(req.kind.to_owned());
+                    }
+                    if req.optional {
+                        notes.push("optional".to_owned());
+                    }
+                    if let Some(target) = &req.target {
+                        notes.push(target.clone());
+                    }
+                    let dependency = if notes.is_empty() {
+                        req.dependency.clone()
+                    } else {
+                        format!("{} ({})", req.dependency, notes.join(", "))
+                    };
+
+                    let dependent = format!("{}@{}", req.dependent, req.version);
+                    let status = req.resolution.to_string();
+                    [dependent, dependency, req.req.to_string(), status]
+                })
+                .collect::<Vec<_>>();
+
+            let header = ["crate", "dependency", "requirement", "status"].map(String::from);
+            let width = |i: usize| {
+                rows.iter()
+                    .chain([&header])
+                    .map(|row| row[i].len())
+                    .max()
+                    .unwrap_or(0)
+            };
+            let (w0, w1, w2) = (width(0), width(1), width(2));
+
+            // The root's dev-dependencies come last, set apart as
+            // only its own tests need them
+            let dev_start = requirements.iter().position(|req| req.kind == "dev");
+            for (i, [dependent, dependency, req, status]) in
+                [&header].into_iter().chain(&rows).enumerate()
+            {
+                if dev_start.is_some_and(|start| i == start + 1) {
+                    println!();
                 }
+                println!("{dependent:<w0$} {dependency:<w1$} {req:<w2$} {status}");
+            }
+        }
+    }
+
+    closure::ensure_complete(&requirements, closure.self_contained)?;
+
+    Ok(())
+}
+
+fn do_impact(_global: &Global, impact: ImpactArgs) -> Result<(), Error> {
+    let r = discover_registry(impact.registry)?;
+
+    let dependents = impact::dependents(&r, &impact.krate)?;
+
+    match impact.output {
+        ReportFormat::Json => print!("{}", impact::to_json(&dependents)?),
+        ReportFormat::Table if dependents.is_empty() => {
+            println!("No other version depends on `{}` alone", impact.krate);
+        }
+        ReportFormat::Table => {
+            let rows = dependents
+                .iter()
+                .map(|d| {
+                    let mut notes = Vec::new();
+                    if d.kind != "normal" {
+                        notes.push(d.kind);
+                    }
+                    if d.optional {
+                        notes.push("optional");
+                    }
+                    if d.yanked {
+                        notes.push("yanked");
+                    }
+                    [
+                        format!("{}@{}", d.name, d.version),
+                        d.req.to_string(),
+                        notes.join(", "),
+                    ]
+                })
+                .collect::<Vec<_>>();
+
+            let header = ["crate", "requirement", "notes"].map(String::from);
+            let width = |i: usize| {
+                rows.iter()
+                    .chain([&header])
+                    .map(|row| row[i].len())
+                    .max()
+                    .unwrap_or(0)
             };
+            let (w0, w1) = (width(0), width(1));
+
+            for [dependent, req, notes] in [&header].into_iter().chain(&rows) {
+                println!("{dependent:<w0$} {req:<w1$} {notes}");
+            }
         }
     }
 
-    impl fmt::Display for CrateName {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            self.0.fmt(f)
+    impact::ensure_unused(&dependents, &impact.krate)?;
+
+    Ok(())
+}
+
+fn do_compare(_global: &Global, compare: CompareArgs) -> Result<(), Error> {
+    use compare::Change;
+
+    let r = discover_registry(compare.registry)?;
+
+    let comparison =
+        compare::compare(&r, &compare.name, &compare.old, &compare.new, compare.files)?;
+
+    if let ReportFormat::Json = compare.output {
+        print!("{}", compare::to_json(&comparison)?);
+        return Ok(());
+    }
+
+    if comparison.is_empty() {
+        println!(
+            "`{}` versions {} and {} do not differ",
+            comparison.name, comparison.old, comparison.new,
+        );
+        return Ok(());
+    }
+
+    fn print_change<T>(label: &str, change: &Change<T>, show: impl Fn(&T) -> String) {
+        match change {
+            Change::Added { new } => println!("  + {label} {}", show(new)),
+            Change::Removed { old } => println!("  - {label} {}", show(old)),
+            Change::Changed { old, new } => println!("  ~ {label} {} -> {}", show(old), show(new)),
         }
     }
 
-    impl FromStr for CrateName {
-        type Err = CrateNameError;
+    if !comparison.dependencies.is_empty() {
+        println!("dependencies:");
+        for d in &comparison.dependencies {
+            let mut notes = Vec::new();
+            if d.kind != "normal" {
+                notes.push(d.kind.to_owned());
+            }
+            if let Some(target) = &d.target {
+                notes.push(target.clone());
+            }
+            let label = if notes.is_empty() {
+                d.name.clone()
+            } else {
+                format!("{} ({})", d.name, notes.join(", "))
+            };
+            print_change(&label, &d.change, ToString::to_string);
+        }
+    }
 
-        fn from_str(s: &str) -> Result<Self, Self::Err> {
-            s.try_into()
+    if !comparison.features.is_empty() {
+        println!("features:");
+        for f in &comparison.features {
+            print_change(&f.name, &f.change, |values| {
+                format!("[{}]", values.join(", "))
+            });
         }
     }
 
-    impl TryFrom<&str> for CrateName {
-        type Error = CrateNameError;
+    if let Some(change) = &comparison.rust_version {
+        println!("package:");
+        print_change("rust-version", change, Clone::clone);
+    }
 
-        fn try_from(value: &str) -> Result<Self, Self::Error> {
-            value.to_owned().try_into()
+    if let Some(files) = comparison.files.as_ref().filter(|f| !f.is_empty()) {
+        println!("files:");
+        for f in files {
+            print_change(&f.path, &f.change, |file| format!("({} bytes)", file.size));
         }
     }
 
-    impl TryFrom<String> for CrateName {
-        type Error = CrateNameError;
+    Ok(())
+}
 
-        fn try_from(value: String) -> Result<Self, Self::Error> {
-            AsciiString::from_ascii(value)
-                .map_err(|e| e.ascii_error())?
-                .try_into()
+fn do_next_version(_global: &Global, next: NextVersionArgs) -> Result<(), Error> {
+    use next_version_error::*;
+
+    let r = discover_registry(next.registry)?;
+    let name = next.name;
+
+    let path = r.index_file_path_for(&name);
+    let index = Registry::parse_index_file(&path).context(IndexReadSnafu { path })?;
+
+    // Yanked versions still can't be published again, so they count.
+    let current = index
+        .keys()
+        .next_back()
+        .context(CrateNotFoundSnafu { name })?;
+
+    let version =
+        bump_version(current, next.bump, next.pre.as_deref()).map_err(NextVersionError::from)?;
+    println!("{version}");
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum NextVersionError {
+    #[snafu(display("Could not read the crate's index file {}", path.display()))]
+    IndexRead {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("The crate `{name}` does not exist in the registry"))]
+    CrateNotFound { name: CrateName },
+
+    #[snafu(transparent)]
+    Bump { source: BumpError },
+}
+
+impl NextVersionError {
+    fn code(&self) -> Option<diagnostics::Code> {
+        match self {
+            Self::CrateNotFound { .. } => Some(diagnostics::Code::CrateNotFound),
+            _ => None,
         }
     }
+}
 
-    impl TryFrom<AsciiString> for CrateName {
-        type Error = CrateNameError;
+fn do_explain(_global: &Global, explain: ExplainArgs) -> Result<(), Error> {
+    print!("{}", explain.code.explanation());
+    Ok(())
+}
 
-        fn try_from(value: AsciiString) -> Result<Self, Self::Error> {
-            use crate_name_error::*;
+fn do_version(_global: &Global, version: VersionArgs) -> Result<(), Error> {
+    use version_error::*;
 
-            let first = value.first().context(EmptySnafu)?;
-            ensure!(first.is_alphabetic(), InitialAlphaSnafu);
+    println!("Margo {}", env!("CARGO_PKG_VERSION"));
 
-            if let Some(chr) = value.chars().find(|&chr| !valid_crate_name_char(chr)) {
-                return ContainsInvalidCharSnafu { chr }.fail();
-            }
+    // Unlike other commands, the current directory isn't assumed to
+    // be a registry.
+    let path = version.registry.or_else(|| {
+        env::var_os(ENV_REGISTRY)
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+    });
+    let Some(path) = path else { return Ok(()) };
+    let r = discover_registry(Some(path))?;
 
-            Ok(Self(value))
+    let describe = |g: Option<GeneratedBy>| match g {
+        Some(g) => g.to_string(),
+        None => "unknown".to_owned(),
+    };
+
+    let config_json = read_generated_by(&r.generated_path())?;
+    println!("config.json: {}", describe(config_json));
+
+    #[cfg(feature = "html")]
+    if r.config.html.enabled {
+        let html = read_generated_by(&r.path.join(html::HEALTH_JSON))?;
+        println!("HTML: {}", describe(html));
+    }
+
+    // Count the metadata files written by each version of Margo.
+    let mut metadata = BTreeMap::<Option<String>, (usize, Option<Timestamp>)>::new();
+    let all_metadata = r.list_all_metadata().context(MetadataSnafu)?;
+    for m in all_metadata.into_values() {
+        let (margo_version, at) = m
+            .generated_by
+            .map_or((None, None), |g| (Some(g.margo_version), Some(g.at)));
+        let (count, latest) = metadata.entry(margo_version).or_default();
+        *count += 1;
+        *latest = (*latest).max(at);
+    }
+
+    for (margo_version, (count, latest)) in metadata {
+        let files = if count == 1 { "file" } else { "files" };
+        match (margo_version, latest) {
+            (Some(v), Some(at)) => {
+                println!("Metadata: Margo {v} ({count} {files}, latest at {at})")
+            }
+            _ => println!("Metadata: unknown ({count} {files})"),
         }
     }
 
-    #[derive(Debug, Snafu)]
-    #[snafu(module)]
-    pub enum CrateNameError {
-        #[snafu(display("The crate name cannot be empty"))]
-        Empty,
+    Ok(())
+}
 
-        #[snafu(display("The crate name must start with an alphabetic character"))]
-        InitialAlpha,
+/// Reads a file recording which version of Margo generated another
+/// file. Files from before this was recorded don't exist.
+fn read_generated_by(path: &Path) -> Result<Option<GeneratedBy>, VersionError> {
+    use version_error::*;
+
+    let data = match fs::read(path) {
+        Ok(d) => d,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(ReadSnafu { path }),
+    };
+
+    serde_json::from_slice(&data)
+        .map(Some)
+        .context(ParseSnafu { path })
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum VersionError {
+    #[snafu(display("Could not read the generation details at {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not parse the generation details at {}", path.display()))]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not list the crate metadata"))]
+    Metadata { source: MetadataError },
+}
+
+fn do_config(global: &Global, config: ConfigArgs) -> Result<(), Error> {
+    match config.subcommand {
+        ConfigSubcommand::Validate(validate) => do_config_validate(global, validate),
+    }
+}
+
+fn do_snapshot(global: &Global, snapshot: SnapshotArgs) -> Result<(), Error> {
+    match snapshot.subcommand {
+        SnapshotSubcommand::Create(create) => do_snapshot_create(global, create),
+        SnapshotSubcommand::Restore(restore) => do_snapshot_restore(global, restore),
+        SnapshotSubcommand::Diff(diff) => do_snapshot_diff(global, diff),
+        SnapshotSubcommand::Export(export) => do_snapshot_export(global, export),
+    }
+}
+
+fn do_snapshot_create(_global: &Global, create: SnapshotCreateArgs) -> Result<(), Error> {
+    let r = discover_registry(create.registry)?;
+
+    let path = snapshot::create(&r, &create.name)?;
+    println!(
+        "Recorded snapshot `{}` in `{}`",
+        create.name,
+        path.display()
+    );
+
+    Ok(())
+}
+
+fn do_snapshot_restore(_global: &Global, restore: SnapshotRestoreArgs) -> Result<(), Error> {
+    let r = discover_registry(restore.registry)?;
+
+    let lock = lock::exclusive(&r)?;
+    let differences = snapshot::restore(&r, &restore.name, restore.dry_run)?;
+    drop(lock);
+    for d in &differences {
+        println!("{d}");
+    }
+
+    if restore.dry_run {
+        println!("Would change {} version(s)", differences.len());
+    } else {
+        println!("Changed {} version(s)", differences.len());
+        r.maybe_generate_html()?;
+        r.maybe_write_manifest()?;
+        r.maybe_precompress()?;
+    }
+
+    Ok(())
+}
+
+fn do_snapshot_diff(_global: &Global, diff: SnapshotDiffArgs) -> Result<(), Error> {
+    let r = discover_registry(diff.registry)?;
+
+    let old = snapshot::load(&r, &diff.name)?;
+    let new = match &diff.other {
+        Some(other) => snapshot::load(&r, other)?,
+        None => snapshot::Manifest::of(&r).map_err(snapshot::Error::from)?,
+    };
+
+    for d in snapshot::diff(&old, &new) {
+        println!("{d}");
+    }
+
+    Ok(())
+}
+
+fn do_snapshot_export(_global: &Global, export: SnapshotExportArgs) -> Result<(), Error> {
+    let r = discover_registry(export.registry)?;
+
+    let name = export
+        .name
+        .unwrap_or_else(|| r.config.html.suggested_registry_name().to_owned());
+    if !is_valid_registry_name(&name) {
+        return Err(snapshot::Error::RegistryName { name }.into());
+    }
+
+    let manifest = match &export.snapshot {
+        Some(snapshot) => snapshot::load(&r, snapshot)?,
+        None => snapshot::Manifest::of(&r).map_err(snapshot::Error::from)?,
+    };
+
+    print!("{}", snapshot::pin_set(&manifest, &r.config, &name));
+
+    Ok(())
+}
+
+fn do_config_validate(_global: &Global, validate: ConfigValidateArgs) -> Result<(), Error> {
+    use config_validate_error::*;
+
+    let registry = match validate.registry {
+        Some(p) => p,
+        None => match env_fallback(None, ENV_REGISTRY).map_err(ConfigValidateError::from)? {
+            Some(p) => p,
+            None => env::current_dir().context(CurrentDirSnafu)?,
+        },
+    };
+    let path = registry.join(CONFIG_FILE_NAME);
+    let config = fs::read_to_string(&path).context(ReadSnafu { path: &path })?;
+
+    let problems = config_check::validate(&config);
+    for problem in &problems {
+        println!("{problem}");
+    }
+
+    let errors = problems
+        .iter()
+        .filter(|p| p.severity == config_check::Severity::Error)
+        .count();
+    ensure!(errors == 0, InvalidSnafu { path, errors });
+
+    match problems.len() {
+        0 => println!("`{}` is valid", path.display()),
+        n => println!("`{}` is usable, with {n} warning(s)", path.display()),
+    }
+
+    Ok(())
+}
+
+fn do_config_stanza(_global: &Global, stanza: ConfigStanzaArgs) -> Result<(), Error> {
+    use config_stanza_error::*;
+
+    let r = discover_registry(stanza.registry)?;
+
+    let name = stanza
+        .name
+        .unwrap_or_else(|| r.config.html.suggested_registry_name().to_owned());
+    ensure!(is_valid_registry_name(&name), NameSnafu { name });
+
+    match stanza.write {
+        Some(project) => {
+            let path = project.join(".cargo").join("config.toml");
+            write_cargo_config(&r.config, &name, stanza.replace_crates_io, &path)?;
+            println!("Wrote the registry configuration to `{}`", path.display());
+        }
+        None => {
+            print!("{}", r.config.registries_stanza(&name));
+            if stanza.replace_crates_io {
+                println!();
+                print!("{}", replace_crates_io_stanza(&name));
+            }
+        }
+    }
+
+    if r.config.auth_required {
+        eprintln!("This registry requires authentication; provide a token with `cargo login --registry {name}`");
+    }
+
+    Ok(())
+}
+
+/// Whether Cargo accepts the name for a registry.
+fn is_valid_registry_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn replace_crates_io_stanza(name: &str) -> String {
+    format!("[source.crates-io]\nreplace-with = \"{name}\"\n")
+}
+
+/// Adds the registry to an existing Cargo configuration file by
+/// appending to it, so that comments and formatting are kept.
+/// Settings that are already present are left alone.
+fn write_cargo_config(
+    config: &ConfigV1,
+    name: &str,
+    replace_crates_io: bool,
+    path: &Path,
+) -> Result<(), ConfigStanzaError> {
+    use config_stanza_error::*;
+
+    let existing = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).context(ReadSnafu { path }),
+    };
+    let table = toml::from_str::<toml::Table>(&existing).context(ParseSnafu { path })?;
+
+    let lookup = |keys: &[&str]| {
+        let mut value = table.get(keys[0])?;
+        for key in &keys[1..] {
+            value = value.get(key)?;
+        }
+        value.as_str().map(str::to_owned)
+    };
+
+    let mut additions = Vec::new();
+
+    let index = format!("sparse+{}", config.base_url);
+    match lookup(&["registries", name, "index"]) {
+        Some(existing) if existing == index => {}
+        Some(_) => {
+            return ConflictSnafu {
+                path,
+                key: format!("registries.{name}.index"),
+            }
+            .fail()
+        }
+        None => additions.push(format!("[registries.{name}]\nindex = \"{index}\"\n")),
+    }
+
+    if replace_crates_io {
+        match lookup(&["source", "crates-io", "replace-with"]) {
+            Some(existing) if existing == name => {}
+            Some(_) => {
+                return ConflictSnafu {
+                    path,
+                    key: "source.crates-io.replace-with",
+                }
+                .fail()
+            }
+            None => additions.push(replace_crates_io_stanza(name)),
+        }
+    }
+
+    if additions.is_empty() {
+        return Ok(());
+    }
+
+    let mut contents = existing;
+    for addition in additions {
+        if !contents.is_empty() {
+            if !contents.ends_with('\n') {
+                contents.push('\n');
+            }
+            contents.push('\n');
+        }
+        contents.push_str(&addition);
+    }
+
+    // Tables written inline, such as `registries = { … }`, can't be
+    // extended by appending another table
+    ensure!(
+        toml::from_str::<toml::Table>(&contents).is_ok(),
+        AppendSnafu { path }
+    );
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(CreateDirSnafu { path: dir })?;
+    }
+    fs::write(path, contents).context(WriteSnafu { path })?;
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum ConfigStanzaError {
+    #[snafu(display("`{name}` is not a valid registry name; use letters, numbers, `-`, and `_`"))]
+    Name { name: String },
+
+    #[snafu(display("Could not read the Cargo configuration {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not parse the Cargo configuration {}", path.display()))]
+    Parse {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("The Cargo configuration {} already sets `{key}` to a different value", path.display()))]
+    Conflict { path: PathBuf, key: String },
+
+    #[snafu(display(
+        "Could not add to the Cargo configuration {} without rewriting it, such as when `registries` or `source` is an inline table; add the output of `margo config-stanza` by hand",
+        path.display(),
+    ))]
+    Append { path: PathBuf },
+
+    #[snafu(display("Could not create the directory {}", path.display()))]
+    CreateDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the Cargo configuration {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+}
+
+impl ConfigStanzaError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Name { .. } => Some(ErrorKind::Usage),
+            Self::Conflict { .. } | Self::Append { .. } => Some(ErrorKind::Conflict),
+            _ => None,
+        }
+    }
+}
+
+fn do_fix_case(_global: &Global, fix_case: FixCaseArgs) -> Result<(), Error> {
+    let r = discover_registry(fix_case.registry)?;
+
+    let lock = lock::exclusive(&r)?;
+    let moved = r.fix_case(fix_case.dry_run)?;
+    drop(lock);
+
+    if fix_case.dry_run {
+        println!("Would move {moved} file(s)");
+    } else {
+        println!("Moved {moved} file(s)");
+        r.maybe_generate_html()?;
+        r.maybe_write_manifest()?;
+        r.maybe_precompress()?;
+    }
+
+    Ok(())
+}
+
+fn do_gc(_global: &Global, gc: GcArgs) -> Result<(), Error> {
+    use do_gc_error::*;
+
+    let r = discover_registry(gc.registry)?;
+
+    ensure!(gc.orphans, NothingSnafu);
+
+    let lock = lock::exclusive(&r)?;
+    let orphans = gc::find(&r)?;
+    let verb = |dry_run, would, did| if dry_run { would } else { did };
+
+    let disposal = Disposal::permanent(gc.permanent);
+
+    for path in &orphans.files {
+        let verb = match disposal {
+            Disposal::Trash => verb(gc.dry_run, "Would trash", "Trashing"),
+            Disposal::Delete => verb(gc.dry_run, "Would remove", "Removing"),
+        };
+        println!(
+            "{verb} the orphaned file {}",
+            r.relative_path(path).display()
+        );
+    }
+
+    for entry in &orphans.entries {
+        let gc::Entry { name, version, .. } = entry;
+        if entry.restorable {
+            let verb = verb(gc.dry_run, "Would restore", "Restoring");
+            println!("{verb} the crate file for `{name}` version {version} from its content-addressed data");
+        } else {
+            let verb = verb(gc.dry_run, "Would remove", "Removing");
+            println!(
+                "{verb} `{name}` version {version} from the index, as its crate file is missing"
+            );
+        }
+    }
+
+    if orphans.is_empty() {
+        println!("Found no orphans");
+        return Ok(());
+    }
+
+    if !gc.dry_run {
+        gc::collect(&r, &orphans, disposal)?;
+        drop(lock);
+
+        r.maybe_generate_html()?;
+        r.maybe_write_manifest()?;
+        r.maybe_precompress()?;
+    }
+
+    Ok(())
+}
+
+fn do_trash(global: &Global, trash: TrashArgs) -> Result<(), Error> {
+    match trash.subcommand {
+        TrashSubcommand::List(list) => do_trash_list(global, list),
+        TrashSubcommand::Restore(restore) => do_trash_restore(global, restore),
+    }
+}
+
+fn do_trash_list(_global: &Global, list: TrashListArgs) -> Result<(), Error> {
+    let r = discover_registry(list.registry)?;
+
+    let items = trash::list(&r)?;
+    if items.is_empty() {
+        println!("The trash is empty");
+    }
+
+    for item in items {
+        let what = match &item.entry {
+            Some(e) => format!("`{}` version {}", e.name, e.vers),
+            None => item.path.display().to_string(),
+        };
+        println!("{}\t{}\t{what}", item.id, item.trashed_at);
+    }
+
+    Ok(())
+}
+
+fn do_trash_restore(_global: &Global, restore: TrashRestoreArgs) -> Result<(), Error> {
+    let r = discover_registry(restore.registry)?;
+
+    let lock = lock::exclusive(&r)?;
+    let restored = trash::restore(&r, &restore.id)?;
+    drop(lock);
+
+    match &restored.version {
+        Some((name, version)) => println!("Restored `{name}` version {version}"),
+        None => println!("Restored {}", restored.path.display()),
+    }
+
+    r.maybe_generate_html()?;
+    r.maybe_write_manifest()?;
+    r.maybe_precompress()?;
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum DoGcError {
+    #[snafu(display("Nothing to collect; pass `--orphans`"))]
+    Nothing,
+}
+
+fn do_repair(global: &Global, repair: RepairArgs) -> Result<(), Error> {
+    use do_repair_error::*;
+
+    let r = discover_registry(repair.registry)?;
+
+    ensure!(repair.rebuild_index, NothingSnafu);
+
+    let lock = lock::exclusive(&r)?;
+    let rebuilt = repair::rebuild_index(global, &r, repair.dry_run)?;
+    drop(lock);
+
+    for (path, reason) in &rebuilt.skipped {
+        eprintln!(
+            "Warning: skipped the crate file {}: {reason}",
+            r.relative_path(path).display(),
+        );
+    }
+
+    let (add, remove) = if repair.dry_run {
+        ("Would add", "Would remove")
+    } else {
+        ("Added", "Removed")
+    };
+    for (name, version) in &rebuilt.added {
+        println!("{add} `{name}` version {version}");
+    }
+    for (name, version) in &rebuilt.removed {
+        println!("{remove} `{name}` version {version}, as it has no crate file");
+    }
+
+    if !repair.dry_run {
+        println!("Rebuilt the index");
+        r.maybe_generate_html()?;
+        r.maybe_write_manifest()?;
+        r.maybe_precompress()?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum DoRepairError {
+    #[snafu(display("Nothing to repair; pass `--rebuild-index`"))]
+    Nothing,
+}
+
+fn do_verify(_global: &Global, verify: VerifyArgs) -> Result<(), Error> {
+    use verify_error::*;
+
+    let r = discover_registry(verify.registry)?;
+
+    ensure!(!(verify.fast && verify.deep), FastAndDeepSnafu);
+
+    let problems = if verify.fast {
+        merkle::verify(&r)?
+    } else {
+        r.verify(verify.deep)?
+    };
+    for problem in &problems {
+        println!("{problem}");
+    }
+
+    r.record_verification(&Verification {
+        at: Timestamp::now(),
+        deep: verify.deep,
+        problems: problems.len(),
+    })?;
+    r.maybe_generate_html()?;
+
+    ensure!(
+        problems.is_empty(),
+        ProblemsSnafu {
+            count: problems.len()
+        }
+    );
+
+    if verify.fast {
+        println!("The index matches the merkle tree and every crate file exists");
+    } else {
+        println!("Every crate file is intact");
+    }
+
+    Ok(())
+}
+
+fn do_inclusion_proof(_global: &Global, proof: InclusionProofArgs) -> Result<(), Error> {
+    use verify_inclusion_error::*;
+
+    let r = discover_registry(proof.registry)?;
+
+    let proof = merkle::proof(&r, &proof.name, &proof.version)?;
+    let proof = serde_json::to_string_pretty(&proof).context(SerializeSnafu)?;
+    println!("{proof}");
+
+    Ok(())
+}
+
+fn do_verify_inclusion(_global: &Global, verify: VerifyInclusionArgs) -> Result<(), Error> {
+    use verify_inclusion_error::*;
+
+    let path = &verify.proof;
+    let data = fs::read(path).context(ReadSnafu { path })?;
+    let proof = serde_json::from_slice::<merkle::Proof>(&data).context(MalformedSnafu { path })?;
+
+    let root = proof.root().map_err(VerifyInclusionError::from)?;
+    ensure!(
+        root.eq_ignore_ascii_case(&verify.root),
+        MismatchSnafu {
+            root,
+            expected: verify.root,
+        }
+    );
+
+    println!(
+        "`{}` version {} with checksum {} is included",
+        proof.name, proof.version, proof.cksum,
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum VerifyInclusionError {
+    #[snafu(display("Could not serialize the proof"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not read the proof {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The proof {} is malformed", path.display()))]
+    Malformed {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(transparent)]
+    Proof { source: merkle::ProofError },
+
+    #[snafu(display("The proof leads to the root {root}, not {expected}"))]
+    Mismatch { root: String, expected: String },
+}
+
+impl VerifyInclusionError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Malformed { .. } | Self::Proof { .. } => Some(ErrorKind::Malformed),
+            Self::Mismatch { .. } => Some(ErrorKind::Conflict),
+            _ => None,
+        }
+    }
+}
+
+fn do_vendor(_global: &Global, vendor: VendorArgs) -> Result<(), Error> {
+    let r = discover_registry(vendor.registry)?;
+
+    let locked = read_lockfile(&r, &vendor.lockfile, "vendored")?;
+
+    let unpacked = vendor::vendor(&r, &locked.crates, &vendor.out)?;
+    println!(
+        "Vendored {} crate(s), {unpacked} of them newly unpacked",
+        locked.crates.len(),
+    );
+
+    let name = r.config.html.suggested_registry_name();
+    println!();
+    println!("To use the vendored crates, add this to `.cargo/config.toml`:");
+    println!();
+    println!("[source.{name}]");
+    println!("registry = \"sparse+{}\"", r.config.base_url);
+    println!("replace-with = \"vendored-sources\"");
+    println!();
+    println!("[source.vendored-sources]");
+    println!("directory = \"{}\"", vendor.out.display());
+
+    Ok(())
+}
+
+fn do_export_local_registry(
+    _global: &Global,
+    export: ExportLocalRegistryArgs,
+) -> Result<(), Error> {
+    let r = discover_registry(export.registry)?;
+
+    let (crates, sources) = match &export.for_lockfile {
+        Some(path) => {
+            let locked = read_lockfile(&r, path, "exported")?;
+            (Some(locked.crates), locked.sources)
+        }
+        None => (None, [format!("sparse+{}", r.config.base_url)].into()),
+    };
+
+    let exported = local_registry::export(&r, crates.as_deref(), &export.out)?;
+    println!("Exported {exported} crate version(s)");
+
+    let dir = export.out.display().to_string();
+    println!();
+    println!("To use the local registry, add this to `.cargo/config.toml`:");
+    println!();
+    print!("{}", local_registry::cargo_config(&r, &sources, &dir));
+
+    Ok(())
+}
+
+fn do_docker_context(_global: &Global, docker: DockerContextArgs) -> Result<(), Error> {
+    let r = discover_registry(docker.registry)?;
+
+    let locked = read_lockfile(&r, &docker.lockfile, "included")?;
+    let exported = local_registry::docker_context(&r, &locked, &docker.out)?;
+    println!("Exported {exported} crate version(s)");
+
+    if !locked.skipped.is_empty() {
+        eprintln!(
+            "The build will fail offline unless the missing versions are vendored some other way"
+        );
+    }
+
+    println!();
+    println!("Copy the directory into the image next to the project's `Cargo.toml`.");
+
+    Ok(())
+}
+
+fn do_subset(_global: &Global, subset: SubsetArgs) -> Result<(), Error> {
+    let r = discover_registry(subset.registry)?;
+
+    let locked = read_lockfile(&r, &subset.lockfile, "included")?;
+    let created = subset::create(&r, &locked.crates, &subset.out, subset.base_url)?;
+    println!(
+        "Created a registry with {} crate version(s) in `{}`",
+        locked.crates.len(),
+        created.path.display(),
+    );
+
+    created.maybe_generate_html()?;
+    created.maybe_write_manifest()?;
+    created.maybe_precompress()?;
+
+    Ok(())
+}
+
+/// Reads the lockfile, warning about each version that the registry
+/// doesn't have.
+fn read_lockfile(r: &Registry, path: &Path, verb: &str) -> Result<lockfile::Locked, Error> {
+    let locked = lockfile::read(r, path)?;
+    for p in &locked.skipped {
+        eprintln!(
+            "`{}` version {} is not in the registry, so it was not {verb}",
+            p.name, p.version,
+        );
+    }
+    Ok(locked)
+}
+
+fn do_tui(_global: &Global, tui: TuiArgs) -> Result<(), Error> {
+    let r = discover_registry(tui.registry)?;
+    r.run_tui()?;
+    Ok(())
+}
+
+fn do_metrics(_global: &Global, metrics: MetricsArgs) -> Result<(), Error> {
+    use metrics_error::*;
+
+    let r = discover_registry(metrics.registry)?;
+
+    let m = r.metrics()?;
+    let output = match metrics.output {
+        MetricsFormat::Prom => m.to_prometheus(),
+        MetricsFormat::Json => {
+            let mut json = serde_json::to_string_pretty(&m).context(SerializeSnafu)?;
+            json.push('\n');
+            json
+        }
+    };
+
+    match metrics.out {
+        Some(path) => {
+            // Write to a temporary file and rename it so that
+            // collectors never observe a partially-written file.
+            let tmp = temp_path_beside(&path);
+
+            fs::write(&tmp, output).context(WriteSnafu { path: &tmp })?;
+            fs::rename(&tmp, &path).context(WriteSnafu { path })?;
+        }
+        None => print!("{output}"),
+    }
+
+    Ok(())
+}
+
+fn do_list(_global: &Global, list: ListArgs) -> Result<(), Error> {
+    let r = discover_registry(list.registry)?;
+
+    let crates = r.list_all().unwrap();
+
+    #[derive(Default)]
+    struct Max(usize, String);
+
+    impl Max {
+        fn push(&mut self, v: impl fmt::Display) {
+            use std::fmt::Write;
+
+            let Self(m, s) = self;
+
+            s.clear();
+            _ = write!(s, "{v}");
+            *m = usize::max(*m, s.len());
+        }
+
+        fn max(&self) -> usize {
+            self.0
+        }
+    }
+
+    let mut max_c = Max::default();
+    let mut max_v = Max::default();
+
+    for (crate_, versions) in &crates {
+        max_c.push(crate_);
+        for version in versions.keys() {
+            max_v.push(version);
+        }
+    }
+
+    let max_c = max_c.max();
+    let max_v = max_v.max();
+
+    for (crate_, versions) in crates {
+        for version in versions.keys() {
+            println!("{crate_:<max_c$} {version:<max_v$}");
+        }
+    }
+
+    Ok(())
+}
+
+fn do_ls_files(_global: &Global, ls_files: LsFilesArgs) -> Result<(), Error> {
+    let r = discover_registry(ls_files.registry)?;
+
+    for file in files::list(&r, ls_files.crate_.as_ref())? {
+        println!("{}\t{}\t{}", file.kind, file.sha256, file.path.display());
+    }
+
+    Ok(())
+}
+
+fn discover_registry(path: Option<PathBuf>) -> Result<Registry, DiscoverRegistryError> {
+    use discover_registry_error::*;
+
+    let path = path.or_else(|| {
+        env::var_os(ENV_REGISTRY)
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+    });
+
+    match path {
+        Some(p) => Registry::open(p).context(OpenSnafu),
+        None => {
+            let cwd = env::current_dir().context(CurrentDirSnafu)?;
+
+            match Registry::open(cwd) {
+                Ok(r) => Ok(r),
+                Err(e) if e.is_not_found() => FallbackNotFoundSnafu.fail(),
+                Err(e) => Err(e).context(FallbackOpenSnafu)?,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum DiscoverRegistryError {
+    #[snafu(display("Could not open the specified registry"))]
+    Open { source: OpenError },
+
+    #[snafu(display("Could not determine the current directory, {}", Self::TRY_THIS))]
+    CurrentDir { source: io::Error },
+
+    #[snafu(display(
+        "The current directory does not contain a registry, {}",
+        Self::TRY_THIS,
+    ))]
+    FallbackNotFound,
+
+    #[snafu(display("Could not open the registry in the current directory"))]
+    FallbackOpen { source: OpenError },
+}
+
+/// Opens the registries in the immediate subdirectories of `root`,
+/// ordered by directory name. Subdirectories that aren't registries
+/// are skipped.
+fn open_namespaces(root: &Path) -> Result<Vec<(String, Registry)>, NamespacesError> {
+    use namespaces_error::*;
+
+    let entries = fs::read_dir(root).context(ReadDirSnafu { path: root })?;
+
+    let mut registries = Vec::new();
+    for entry in entries {
+        let entry = entry.context(ReadDirSnafu { path: root })?;
+        let path = entry.path();
+
+        if !path.is_dir() {
+            continue;
+        }
+
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let name = name.to_owned();
+
+        match Registry::open(&path) {
+            Ok(r) => registries.push((name, r)),
+            Err(e) if e.is_not_found() => {}
+            Err(e) => return Err(e).context(OpenSnafu { path }),
+        }
+    }
+
+    ensure!(!registries.is_empty(), NoneFoundSnafu { path: root });
+
+    registries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(registries)
+}
+
+#[cfg(feature = "html")]
+fn generate_landing_page(root: &Path, registries: &[(String, Registry)]) -> Result<(), HtmlError> {
+    html::write_landing_page(root, registries, html::Options::default())
+}
+
+#[cfg(not(feature = "html"))]
+fn generate_landing_page(
+    _root: &Path,
+    _registries: &[(String, Registry)],
+) -> Result<(), HtmlError> {
+    Err(HtmlError)
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum NamespacesError {
+    #[snafu(display("Could not read the directory {}", path.display()))]
+    ReadDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not open the registry at {}", path.display()))]
+    Open {
+        #[snafu(source(from(OpenError, Box::new)))]
+        source: Box<OpenError>,
+        path: PathBuf,
+    },
+
+    #[snafu(display("No registries were found in {}", path.display()))]
+    NoneFound { path: PathBuf },
+}
+
+impl NamespacesError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::NoneFound { .. } => Some(ErrorKind::NotFound),
+            _ => None,
+        }
+    }
+}
+
+impl DiscoverRegistryError {
+    const TRY_THIS: &'static str =
+        "please use the `--registry` command line option or the `MARGO_REGISTRY` environment variable";
+
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::FallbackNotFound => Some(ErrorKind::NotFound),
+            _ => None,
+        }
+    }
+
+    fn code(&self) -> Option<diagnostics::Code> {
+        match self {
+            Self::FallbackNotFound => Some(diagnostics::Code::RegistryNotFound),
+            Self::Open { source } | Self::FallbackOpen { source } => source.code(),
+            Self::CurrentDir { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Registry {
+    path: PathBuf,
+    config: ConfigV1,
+    events: events::Sink,
+}
+
+/// Settings that apply to every crate added by one `add` command.
+#[derive(Debug, Default)]
+struct AddOptions {
+    source: metadata::Source,
+    deny_dirty: bool,
+    forbid_external_dependencies: bool,
+    rewrite_deps_to_self: bool,
+    allow_any_name: bool,
+    channel: Option<String>,
+}
+
+type Index = BTreeMap<Version, index_entry::Root>;
+type ListAll = BTreeMap<CrateName, Index>;
+
+/// Finds the version as stored in the index. When the requested
+/// version has no build metadata, it matches a stored version with
+/// any build metadata, as Cargo considers them to be the same.
+fn find_version<'a>(index: &'a Index, version: &Version) -> Option<&'a Version> {
+    if let Some((v, _)) = index.get_key_value(version) {
+        return Some(v);
+    }
+
+    if !version.build.is_empty() {
+        return None;
+    }
+
+    index.keys().find(|v| v.without_build() == *version)
+}
+
+/// The version Cargo would pick by default: the newest non-yanked
+/// release, falling back to the newest non-yanked pre-release.
+fn latest_version(index: &Index) -> Option<&Version> {
+    let available = || index.iter().filter(|(_, e)| !e.yanked).map(|(v, _)| v);
+
+    available()
+        .rfind(|v| v.pre.is_empty())
+        .or_else(|| available().next_back())
+}
+
+/// The newest non-yanked version matching the requirement.
+fn latest_version_matching<'a>(index: &'a Index, req: &VersionReq) -> Option<&'a Version> {
+    index
+        .iter()
+        .filter(|(v, e)| !e.yanked && req.matches(v))
+        .map(|(v, _)| v)
+        .next_back()
+}
+
+/// Computes the version following `current`.
+///
+/// Bumping a pre-release to the release it leads up to finalizes it
+/// (`2.0.0-rc.3` bumped as `major` is `2.0.0`). When a pre-release
+/// identifier is given, the result is a pre-release of the bumped
+/// version, continuing the current chain when the identifier matches
+/// (`2.0.0-rc.3` becomes `2.0.0-rc.4`) and starting at `.1`
+/// otherwise. `Pre` bumps only the pre-release, starting a new patch
+/// release when `current` is not a pre-release.
+fn bump_version(current: &Version, bump: Bump, pre: Option<&str>) -> Result<Version, BumpError> {
+    use bump_error::*;
+
+    let current = current.without_build();
+    let is_pre = !current.pre.is_empty();
+    let (major, minor, patch) = (current.major, current.minor, current.patch);
+
+    let mut next = match bump {
+        Bump::Major if is_pre && minor == 0 && patch == 0 => Version::new(major, 0, 0),
+        Bump::Major => Version::new(major + 1, 0, 0),
+        Bump::Minor if is_pre && patch == 0 => Version::new(major, minor, 0),
+        Bump::Minor => Version::new(major, minor + 1, 0),
+        Bump::Patch | Bump::Pre if is_pre => Version::new(major, minor, patch),
+        Bump::Patch | Bump::Pre => Version::new(major, minor, patch + 1),
+    };
+
+    let (chain, chain_number) = match current.pre.rsplit_once('.') {
+        Some((id, n)) => match n.parse::<u64>() {
+            Ok(n) => (id, n),
+            Err(_) => (current.pre.as_str(), 0),
+        },
+        None => (current.pre.as_str(), 0),
+    };
+
+    let pre = match (bump, pre) {
+        (_, Some(pre)) => Some(pre),
+        (Bump::Pre, None) if is_pre => Some(chain),
+        (Bump::Pre, None) => return PreMissingSnafu.fail(),
+        (_, None) => None,
+    };
+
+    if let Some(pre) = pre {
+        let same_release = (next.major, next.minor, next.patch) == (major, minor, patch);
+        let n = if same_release && pre == chain {
+            chain_number + 1
+        } else {
+            1
+        };
+
+        let pre = format!("{pre}.{n}");
+        next.pre = semver::Prerelease::new(&pre).context(PreInvalidSnafu { pre })?;
+    }
+
+    ensure!(next > current, NotNewerSnafu { current, next });
+
+    Ok(next)
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum BumpError {
+    #[snafu(display("`--pre` is required to start a pre-release from a release"))]
+    PreMissing,
+
+    #[snafu(display("`{pre}` is not a valid pre-release"))]
+    PreInvalid { source: semver::Error, pre: String },
+
+    #[snafu(display("The next version {next} would not be newer than {current}"))]
+    NotNewer { current: Version, next: Version },
+}
+
+trait VersionExt {
+    fn without_build(&self) -> Version;
+}
+
+impl VersionExt for Version {
+    fn without_build(&self) -> Version {
+        Version {
+            build: semver::BuildMetadata::EMPTY,
+            ..self.clone()
+        }
+    }
+}
+
+/// Chooses versions of a crate for `yank` and `rm`. A version is
+/// selected when it matches any of the selectors.
+///
+/// Like Cargo, a requirement only matches a pre-release when one of
+/// its comparators names a pre-release of the same version; use
+/// `prereleases` to select those.
+///
+/// A `channel` restricts the selection to the versions published to
+/// that channel, and selects all of them when nothing else is chosen.
+#[derive(Debug, Default)]
+struct VersionSelector {
+    version: Option<Version>,
+    req: Option<VersionReq>,
+    prereleases: bool,
+    all: bool,
+    channel: Option<String>,
+}
+
+impl VersionSelector {
+    fn is_empty(&self) -> bool {
+        self.version.is_none()
+            && self.req.is_none()
+            && !self.prereleases
+            && !self.all
+            && self.channel.is_none()
+    }
+
+    fn select<'a>(
+        &self,
+        index: &'a Index,
+        channels: &BTreeMap<Version, String>,
+    ) -> Vec<&'a Version> {
+        let exact = self.version.as_ref().and_then(|v| find_version(index, v));
+        let whole_channel = self.version.is_none()
+            && self.req.is_none()
+            && !self.prereleases
+            && self.channel.is_some();
+
+        index
+            .keys()
+            .filter(|&v| {
+                self.channel
+                    .as_ref()
+                    .map_or(true, |c| channels.get(v) == Some(c))
+            })
+            .filter(|&v| {
+                self.all
+                    || whole_channel
+                    || exact == Some(v)
+                    || (self.prereleases && !v.pre.is_empty())
+                    || self.req.as_ref().is_some_and(|r| r.matches(v))
+            })
+            .collect()
+    }
+}
+
+impl From<Version> for VersionSelector {
+    fn from(version: Version) -> Self {
+        Self {
+            version: Some(version),
+            ..Default::default()
+        }
+    }
+}
+
+impl Registry {
+    fn initialize(config: ConfigV1, path: impl Into<PathBuf>) -> Result<Self, InitializeError> {
+        use initialize_error::*;
+
+        let config = config.normalize();
+        let path = path.into();
+
+        ensure!(
+            matches!(config.base_url.scheme(), "http" | "https"),
+            BaseUrlSchemeSnafu {
+                base_url: config.base_url.clone(),
+            }
+        );
+
+        println!("Initializing registry in `{}`", path.display());
+
+        fs::create_dir_all(&path).context(RegistryCreateSnafu)?;
+
+        let config_toml_path = path.join(CONFIG_FILE_NAME);
+        let config = Config::V1(config);
+        let config_toml = toml::to_string(&config).context(ConfigTomlSerializeSnafu)?;
+        fs::write(&config_toml_path, config_toml).context(ConfigTomlWriteSnafu {
+            path: &config_toml_path,
+        })?;
+
+        let Config::V1(config) = config;
+
+        let dl = config.dl_template();
+        let auth_required = config.auth_required;
+
+        let this = Self {
+            path,
+            config,
+            events: events::Sink::from_env(),
+        };
+
+        let config_json_path = this.config_json_path();
+        let config_json = config_json::Root {
+            dl,
+            api: None,
+            auth_required,
+        };
+        let config_json = serde_json::to_string(&config_json).context(ConfigJsonSerializeSnafu)?;
+        fs::write(&config_json_path, config_json).context(ConfigJsonWriteSnafu {
+            path: &config_json_path,
+        })?;
+
+        // `config.json` is read by Cargo, so record who wrote it
+        // separately.
+        let generated_path = this.generated_path();
+        let generated =
+            serde_json::to_string_pretty(&GeneratedBy::now()).context(GeneratedSerializeSnafu)?;
+        fs::write(&generated_path, generated).context(GeneratedWriteSnafu {
+            path: &generated_path,
+        })?;
+
+        Ok(this)
+    }
+
+    fn open(path: impl Into<PathBuf>) -> Result<Self, OpenError> {
+        use open_error::*;
+
+        let path = path.into();
+
+        let config_path = path.join(CONFIG_FILE_NAME);
+        let config = fs::read_to_string(&config_path).context(ReadSnafu { path: &config_path })?;
+        let Config::V1(config) =
+            toml::from_str(&config).context(DeserializeSnafu { path: &config_path })?;
+        // Files written by hand or by older versions may lack the
+        // base URL's trailing slash.
+        let config = config.normalize();
+
+        Ok(Self {
+            path,
+            config,
+            events: events::Sink::from_env(),
+        })
+    }
+
+    fn add(&self, global: &Global, crate_path: impl AsRef<Path>) -> Result<(), AddError> {
+        self.add_with_options(global, crate_path, &AddOptions::default())
+    }
+
+    fn add_with_options(
+        &self,
+        global: &Global,
+        crate_path: impl AsRef<Path>,
+        options: &AddOptions,
+    ) -> Result<(), AddError> {
+        use add_error::*;
+
+        let crate_path = crate_path.as_ref();
+
+        println!("Adding crate `{}` to registry", crate_path.display());
+
+        let crate_file = fs::read(crate_path).context(ReadCrateSnafu)?;
+
+        use sha2::Digest;
+        let checksum = sha2::Sha256::digest(&crate_file);
+        let checksum_hex = hex::encode(checksum);
+
+        let root_files = extract_root_files(&crate_file, &self.config.package_limits)?;
+        let cargo_toml = root_files.cargo_toml.context(CargoTomlMissingSnafu)?;
+
+        let cargo_toml = String::from_utf8(cargo_toml).context(CargoTomlUtf8Snafu)?;
+        let cargo_toml =
+            toml::from_str::<cargo_toml::Root>(&cargo_toml).context(CargoTomlMalformedSnafu)?;
+
+        let unversioned = cargo_toml.unversioned_dependencies();
+        ensure!(
+            unversioned.is_empty(),
+            UnversionedDependenciesSnafu {
+                name: cargo_toml.package.name.clone(),
+                deps: unversioned.join(", "),
+            }
+        );
+
+        let vcs_info = root_files
+            .cargo_vcs_info
+            .map(|v| serde_json::from_slice::<cargo_vcs_info::Root>(&v))
+            .transpose()
+            .context(VcsInfoMalformedSnafu)?;
+
+        let name = &cargo_toml.package.name;
+        let version = &cargo_toml.package.version;
+
+        ensure!(
+            options.allow_any_name || self.config.name_allowed(name),
+            NameNotAllowedSnafu { name: name.clone() }
+        );
+
+        self.check_public_name(global, name, version)?;
+
+        self.events.emit(Event::CrateParsed {
+            name: name.clone(),
+            version: version.clone(),
+        });
+        self.events.emit(Event::ChecksumComputed {
+            name: name.clone(),
+            version: version.clone(),
+            checksum: checksum_hex.clone(),
+        });
+
+        let (git, path_in_vcs) = vcs_info.map_or((None, None), |v| (v.git, v.path_in_vcs));
+        let dirty = git.as_ref().is_some_and(|g| g.dirty);
+
+        if dirty {
+            ensure!(
+                !options.deny_dirty,
+                DirtySnafu {
+                    name: name.clone(),
+                    version: version.clone(),
+                }
+            );
+            eprintln!(
+                "Warning: `{name}` version {version} was packaged from a working directory with uncommitted changes"
+            );
+        }
+
+        // Explicitly provided values win over those recorded by Cargo.
+        let source = metadata::Source {
+            url: options
+                .source
+                .url
+                .clone()
+                .or_else(|| cargo_toml.package.repository.clone()),
+            rev: options.source.rev.clone().or_else(|| git.map(|g| g.sha1)),
+            path_in_vcs: path_in_vcs.filter(|p| !p.is_empty()),
+            dirty,
+        };
+        let description = cargo_toml.package.description.clone();
+
+        let mut index_entry =
+            adapt_cargo_toml_to_index_entry(global, &self.config, cargo_toml, checksum_hex);
+
+        if let Some(floor) = self.config.version_floors.get(&index_entry.name) {
+            ensure!(
+                index_entry.vers >= *floor,
+                BelowVersionFloorSnafu {
+                    name: index_entry.name.clone(),
+                    version: index_entry.vers.clone(),
+                    floor: floor.clone(),
+                }
+            );
+        }
+
+        if options.rewrite_deps_to_self {
+            self.rewrite_deps_to_self(&mut index_entry)?;
+        }
+
+        if options.forbid_external_dependencies || self.config.forbid_external_dependencies {
+            let external = self.config.external_dependencies(&index_entry);
+            ensure!(
+                external.is_empty(),
+                ExternalDependenciesSnafu {
+                    name: index_entry.name.clone(),
+                    deps: external.join(", "),
+                }
+            );
+        }
+
+        let index_path = self.index_file_path_for(&index_entry.name);
+        if let Some(path) = index_path.parent() {
+            fs::create_dir_all(path).context(IndexDirSnafu { path })?;
+        }
+
+        let index =
+            Self::parse_index_file(&index_path).context(IndexReadSnafu { path: &index_path })?;
+
+        // Crate names that differ only by case share an index file.
+        if let Some(existing) = index.values().next() {
+            ensure!(
+                existing.name == index_entry.name,
+                NameCaseConflictSnafu {
+                    name: index_entry.name.clone(),
+                    existing: existing.name.clone(),
+                }
+            );
+        }
+
+        // Versions that differ only by build metadata are the same
+        // version as far as Cargo is concerned.
+        if let Some(existing) = find_version(&index, &index_entry.vers.without_build()) {
+            ensure!(
+                existing.build == index_entry.vers.build,
+                BuildMetadataConflictSnafu {
+                    version: index_entry.vers.clone(),
+                    existing: existing.clone(),
+                }
+            );
+        }
+
+        // Lockfiles record each version's checksum, so a published
+        // version's contents may never change. Adding the same package
+        // again is harmless.
+        if let Some(existing) = index.get(&index_entry.vers) {
+            ensure!(
+                existing.cksum == index_entry.cksum,
+                VersionExistsSnafu {
+                    name: index_entry.name.clone(),
+                    version: index_entry.vers.clone(),
+                }
+            );
+        }
+
+        let crate_file_path =
+            self.crate_file_path_for(&index_entry.name, &index_entry.vers, &index_entry.cksum);
+        if let Some(path) = crate_file_path.parent() {
+            fs::create_dir_all(path).context(CrateDirSnafu { path })?;
+        }
+
+        // FUTURE: Stronger file system consistency (atomic file overwrites, rollbacks on error)
+        // FUTURE: "transactional" adding of multiple crates
+
+        self.modify_metadata(&index_entry.name, |m| {
+            m.tombstones.remove(&index_entry.vers);
+            m.published
+                .insert(index_entry.vers.clone(), Timestamp::now());
+            if source.is_empty() {
+                m.sources.remove(&index_entry.vers);
+            } else {
+                m.sources.insert(index_entry.vers.clone(), source);
+            }
+            match description {
+                Some(d) => m.descriptions.insert(index_entry.vers.clone(), d),
+                None => m.descriptions.remove(&index_entry.vers),
+            };
+            match &options.channel {
+                Some(c) => m.channels.insert(index_entry.vers.clone(), c.clone()),
+                None => m.channels.remove(&index_entry.vers),
+            };
+        })?;
+
+        let name = index_entry.name.clone();
+        let version = index_entry.vers.clone();
+        let cksum = index_entry.cksum.clone();
+
+        self.read_modify_write(&name, |index_file| {
+            index_file.insert(index_entry.vers.clone(), index_entry);
+            Ok::<_, AddError>(())
+        })?;
+
+        println!("Wrote crate index to `{}`", index_path.display());
+        self.events.emit(Event::IndexWritten {
+            name: name.clone(),
+            version: version.clone(),
+            path: index_path,
+        });
+
+        self.write_crate_file(&crate_file_path, &crate_file, &cksum)
+            .context(CrateWriteSnafu {
+                path: &crate_file_path,
+            })?;
+        println!("Wrote crate to `{}`", crate_file_path.display());
+
+        changes::record(self, &[Change::now(&name, &version, Action::Publish)])?;
+        self.events.emit(Event::CrateWritten {
+            name,
+            version,
+            path: crate_file_path,
+        });
+
+        Ok(())
+    }
+
+    /// Looks for a newer version of a crate with the same name in the
+    /// public index, which consumers using both registries could get
+    /// instead of the crate being added.
+    fn check_public_name(
+        &self,
+        global: &Global,
+        name: &CrateName,
+        version: &Version,
+    ) -> Result<(), AddError> {
+        use add_error::*;
+
+        let deny = match self.config.public_name_check {
+            PublicNameCheck::Off => return Ok(()),
+            PublicNameCheck::Warn => false,
+            PublicNameCheck::Deny => true,
+        };
+
+        let index = self.config.public_index();
+
+        let newest = match public_names::newest_version(global, &index, name) {
+            Ok(newest) => newest,
+            Err(e) if !deny => {
+                eprintln!(
+                    "Warning: Could not check {index} for `{name}`: {}",
+                    snafu::Report::from_error(e),
+                );
+                return Ok(());
+            }
+            Err(e) => Err(e).context(PublicNameQuerySnafu { name: name.clone() })?,
+        };
+
+        let Some(newest) = newest.filter(|n| n > version) else {
+            return Ok(());
+        };
+
+        ensure!(
+            !deny,
+            PublicNameConflictSnafu {
+                name: name.clone(),
+                index,
+                newest,
+            }
+        );
+        eprintln!(
+            "Warning: `{name}` also exists in {index} with the newer version {newest}, which Cargo may choose instead"
+        );
+
+        Ok(())
+    }
+
+    /// Points dependencies on other registries at this one when a
+    /// matching version of the dependency is already here.
+    fn rewrite_deps_to_self(&self, entry: &mut index_entry::Root) -> Result<(), AddError> {
+        use add_error::*;
+
+        for dep in &mut entry.deps {
+            if dep.registry.is_none() {
+                continue;
+            }
+
+            let package = dep.package.as_deref().unwrap_or(&dep.name);
+            let Ok(package) = package.parse::<CrateName>() else {
+                continue;
+            };
+
+            let path = self.index_file_path_for(&package);
+            let index = Self::parse_index_file(&path).context(IndexReadSnafu { path })?;
+
+            if index.keys().any(|v| dep.req.matches(v)) {
+                dep.registry = None;
+            } else {
+                eprintln!(
+                    "Warning: `{package}` was not rewritten to this registry as no version matching `{}` exists here",
+                    dep.req,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove(
+        &self,
+        name: CrateName,
+        version: Version,
+        tombstone: Option<&metadata::Tombstone>,
+        disposal: Disposal,
+    ) -> Result<(), RemoveError> {
+        let removed = self.read_modify_write(&name, |index| {
+            let version = find_version(index, &version).cloned();
+            Ok::<_, RemoveError>(version.and_then(|v| index.remove_entry(&v)))
+        })?;
+
+        let Some((version, removed)) = removed else {
+            return Ok(());
+        };
+
+        if let Some(tombstone) = tombstone {
+            self.bury(&name, &version, tombstone)?;
+        }
+
+        let crate_file = self.crate_file_path_for(&name, &version, &removed.cksum);
+        self.dispose_crate_file(&crate_file, &removed, disposal)?;
+
+        changes::record(self, &[Change::now(&name, &version, Action::Remove)])?;
+
+        Ok(())
+    }
+
+    /// Moves the crate file of a removed version to the trash, or
+    /// deletes it.
+    fn dispose_crate_file(
+        &self,
+        path: &Path,
+        entry: &index_entry::Root,
+        disposal: Disposal,
+    ) -> Result<(), RemoveError> {
+        if disposal == Disposal::Trash && path.exists() {
+            trash::put(self, path, Some(entry))?;
+        }
+        self.remove_crate_file(path, &entry.cksum)
+    }
+
+    /// Deletes the crate file along with any directories left empty,
+    /// and its content-addressed data once nothing links to it.
+    fn remove_crate_file(&self, path: &Path, cksum: &str) -> Result<(), RemoveError> {
+        self.remove_file_and_empty_dirs(path)?;
+
+        let blob = self.blob_path_for(cksum);
+        if is_unlinked(&blob) {
+            self.remove_file_and_empty_dirs(&blob)?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_file_and_empty_dirs(&self, path: &Path) -> Result<(), RemoveError> {
+        use remove_error::*;
+
+        match fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(DeleteSnafu { path }),
+        }
+
+        // Removal fails harmlessly for directories that still have
+        // contents.
+        let crate_dir = self.crate_dir();
+        for dir in path.ancestors().skip(1) {
+            if dir == crate_dir || fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_versions(
+        &self,
+        name: CrateName,
+        selector: &VersionSelector,
+        tombstone: Option<&metadata::Tombstone>,
+        disposal: Disposal,
+        dry_run: bool,
+    ) -> Result<(), RemoveError> {
+        use remove_error::*;
+
+        let index_path = self.index_file_path_for(&name);
+        let index =
+            Self::parse_index_file(&index_path).context(IndexReadSnafu { path: &index_path })?;
+        ensure!(!index.is_empty(), CrateNotFoundSnafu { name });
+
+        if let Some(version) = &selector.version {
+            ensure!(
+                find_version(&index, version).is_some(),
+                VersionNotFoundSnafu {
+                    name,
+                    version: version.clone()
+                },
+            );
+        }
+
+        let metadata = self.read_metadata(&name)?;
+        let versions = selector.select(&index, &metadata.channels);
+        ensure!(!versions.is_empty(), NoMatchingVersionsSnafu { name });
+
+        for version in versions {
+            if dry_run {
+                println!("Would remove `{name}` version {version}");
+            } else {
+                println!("Removing `{name}` version {version}");
+                self.remove(name.clone(), version.clone(), tombstone, disposal)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_crate(
+        &self,
+        name: CrateName,
+        tombstone: Option<&metadata::Tombstone>,
+        disposal: Disposal,
+        dry_run: bool,
+    ) -> Result<(), RemoveError> {
+        use remove_error::*;
+
+        let index_path = self.index_file_path_for(&name);
+        let index =
+            Self::parse_index_file(&index_path).context(IndexReadSnafu { path: &index_path })?;
+        ensure!(!index.is_empty(), CrateNotFoundSnafu { name });
+
+        if dry_run {
+            for version in index.keys() {
+                println!("Would remove `{name}` version {version}");
+            }
+            return Ok(());
+        }
+
+        if let Some(tombstone) = tombstone {
+            for version in index.keys() {
+                self.bury(&name, version, tombstone)?;
+            }
+        }
+
+        match fs::remove_file(&index_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(DeleteSnafu { path: index_path }),
+        }
+        println!("Removed crate index `{}`", index_path.display());
+
+        for (version, entry) in &index {
+            let crate_file = self.crate_file_path_for(&name, version, &entry.cksum);
+            self.dispose_crate_file(&crate_file, entry, disposal)?;
+            println!("Removed crate file `{}`", crate_file.display());
+        }
+
+        let removed = index
+            .keys()
+            .map(|v| Change::now(&name, v, Action::Remove))
+            .collect::<Vec<_>>();
+        changes::record(self, &removed)?;
+
+        Ok(())
+    }
+
+    fn remove_all_yanked(
+        &self,
+        tombstone: Option<&metadata::Tombstone>,
+        disposal: Disposal,
+        dry_run: bool,
+    ) -> Result<(), RemoveError> {
+        let crates = self.list_all()?;
+
+        for (name, index) in crates {
+            for (version, entry) in index {
+                if !entry.yanked {
+                    continue;
+                }
+
+                if dry_run {
+                    println!("Would remove `{name}` version {version}");
+                } else {
+                    println!("Removing `{name}` version {version}");
+                    self.remove(name.clone(), version, tombstone, disposal)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Describes each version whose crate file is missing or doesn't
+    /// match its checksum. When `deep`, every crate file is also
+    /// decompressed in full, which verifies the compressed data's own
+    /// checksum, and its `Cargo.toml` is compared to the index.
+    fn verify(&self, deep: bool) -> Result<Vec<String>, VerifyError> {
+        use verify_error::*;
+
+        let all = self.list_all().context(ListAllSnafu)?;
+        let mut problems = vec![];
+
+        let config_json_path = self.config_json_path();
+        let config_json = fs::read(&config_json_path).context(ReadSnafu {
+            path: &config_json_path,
+        })?;
+        let dl = serde_json::from_slice::<serde_json::Value>(&config_json)
+            .ok()
+            .and_then(|v| Some(v.get("dl")?.as_str()?.to_owned()));
+        let expected_dl = self.config.dl_template();
+        if dl.as_deref() != Some(&expected_dl) {
+            problems.push(format!(
+                "{} does not have the download URL `{expected_dl}`; run `margo init --reconfigure` to rewrite it",
+                config_json_path.display(),
+            ));
+        }
+
+        for (name, index) in &all {
+            for (version, entry) in index {
+                let path = self.crate_file_path_for(name, version, &entry.cksum);
+
+                let data = match fs::read(&path) {
+                    Ok(data) => data,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        problems.push(format!(
+                            "`{name}` version {version}: the crate file {} is missing",
+                            path.display(),
+                        ));
+                        continue;
+                    }
+                    Err(e) => return Err(e).context(ReadSnafu { path }),
+                };
+
+                use sha2::Digest;
+                let checksum = hex::encode(sha2::Sha256::digest(&data));
+                if checksum != entry.cksum {
+                    problems.push(format!(
+                        "`{name}` version {version}: the crate file {} has the checksum {checksum}, not {}",
+                        path.display(),
+                        entry.cksum,
+                    ));
+                    continue;
+                }
+
+                if deep {
+                    if let Err(e) = self.verify_package(&data, entry) {
+                        problems.push(format!(
+                            "`{name}` version {version}: {}",
+                            snafu::Report::from_error(e),
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    fn verify_package(
+        &self,
+        data: &[u8],
+        entry: &index_entry::Root,
+    ) -> Result<(), VerifyPackageError> {
+        use verify_package_error::*;
+
+        let limits = &self.config.package_limits;
+
+        // The decompressor only checks the CRC once it reaches the
+        // end of the data, which reading the entries doesn't do.
+        let decoder = flate2::read::GzDecoder::new(data);
+        let mut decoder = LimitedReader {
+            inner: decoder,
+            remaining: limits.max_total_bytes,
+        };
+        io::copy(&mut decoder, &mut io::sink()).context(DecompressSnafu)?;
+
+        let root_files = extract_root_files(data, limits)?;
+        let cargo_toml = root_files.cargo_toml.context(CargoTomlMissingSnafu)?;
+        let cargo_toml = String::from_utf8(cargo_toml).context(CargoTomlUtf8Snafu)?;
+        let cargo_toml =
+            toml::from_str::<cargo_toml::Root>(&cargo_toml).context(CargoTomlMalformedSnafu)?;
+
+        let package = cargo_toml.package;
+        ensure!(
+            package.name == entry.name && package.version == entry.vers,
+            MismatchSnafu {
+                name: package.name,
+                version: package.version,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Moves index, crate, and metadata files written before paths
+    /// were lowercased. Returns the number of files moved (or that
+    /// would be moved).
+    fn fix_case(&self, dry_run: bool) -> Result<usize, FixCaseError> {
+        use fix_case_error::*;
+
+        let mut moves = Vec::new();
+
+        let crate_dir = self.crate_dir();
+        let mut crate_files = BTreeMap::<_, Vec<_>>::new();
+
+        // Custom crate paths were introduced after paths were
+        // lowercased, so only the default layout needs fixing.
+        let crate_entries = self
+            .config
+            .crate_path
+            .is_default()
+            .then(|| Self::list_crate_files(&crate_dir));
+
+        for entry in crate_entries.into_iter().flatten() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e)
+                    if e.io_error()
+                        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound) =>
+                {
+                    break
+                }
+                Err(e) => return Err(e).context(WalkdirSnafu { path: &crate_dir }),
+            };
+
+            let path = entry.into_path();
+            if let Some(dir) = path.parent() {
+                crate_files.entry(dir.to_owned()).or_default().push(path);
+            }
+        }
+
+        for (dir, files) in crate_files {
+            let subdir = dir.strip_prefix(&crate_dir).context(PrefixSnafu {
+                path: &dir,
+                prefix: &crate_dir,
+            })?;
+            let name = dir
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<CrateName>().ok())
+                .context(NameSnafu { path: &dir })?;
+
+            // Before lowercasing, the index file mirrored the crate
+            // directory exactly.
+            let old_index = self.path.join(subdir);
+            let new_index = self.index_file_path_for(&name);
+            if old_index != new_index && old_index.exists() {
+                moves.push((old_index, new_index));
+            }
+
+            let new_dir = self.crate_dir_for(&name);
+            if dir != new_dir {
+                for file in files {
+                    if let Some(file_name) = file.file_name() {
+                        let new_file = new_dir.join(file_name);
+                        moves.push((file, new_file));
+                    }
+                }
+            }
+        }
+
+        let metadata_dir = self.metadata_dir();
+        for entry in walkdir::WalkDir::new(&metadata_dir) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e)
+                    if e.io_error()
+                        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound) =>
+                {
+                    break
+                }
+                Err(e) => {
+                    return Err(e).context(WalkdirSnafu {
+                        path: &metadata_dir,
+                    })
+                }
+            };
+
+            let path = entry.into_path();
+            let Some(name) = path
+                .file_stem()
+                .filter(|_| path.extension() == Some(OsStr::new("json")))
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.parse::<CrateName>().ok())
+            else {
+                continue;
+            };
+
+            let new_path = self.metadata_file_path_for(&name);
+            if path != new_path {
+                moves.push((path, new_path));
+            }
+        }
+
+        for (from, to) in &moves {
+            if dry_run {
+                println!("Would move `{}` to `{}`", from.display(), to.display());
+                continue;
+            }
+
+            println!("Moving `{}` to `{}`", from.display(), to.display());
+
+            if let Some(path) = to.parent() {
+                fs::create_dir_all(path).context(CreateDirSnafu { path })?;
+            }
+            fs::rename(from, to).context(MoveSnafu { from, to })?;
+
+            // Clean up the now-empty directories; removal fails
+            // harmlessly for directories that still have contents.
+            for dir in from.ancestors().skip(1) {
+                if dir == self.path || fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+        }
+
+        Ok(moves.len())
+    }
+
+    fn metrics(&self) -> Result<Metrics, MetricsError> {
+        use metrics_error::*;
+
+        let crates = self.list_all()?;
+        let metadata = self.list_all_metadata()?;
+
+        let mut m = Metrics {
+            crates: crates.len(),
+            ..Default::default()
+        };
+
+        for (name, index) in &crates {
+            m.versions += index.len();
+            m.yanked_versions += index.values().filter(|e| e.yanked).count();
+
+            for (version, entry) in index {
+                let path = self.crate_file_path_for(name, version, &entry.cksum);
+                let file_metadata = match fs::metadata(&path) {
+                    Ok(md) => md,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e).context(CrateFileSnafu { path }),
+                };
+                m.crate_bytes += file_metadata.len();
+            }
+        }
+
+        for (name, md) in &metadata {
+            if !crates.contains_key(name) {
+                continue;
+            }
+            m.downloads += md.downloads.values().sum::<u64>();
+
+            let last = md.published.values().map(Timestamp::as_secs).max();
+            m.last_publish_timestamp_seconds = m.last_publish_timestamp_seconds.max(last);
+        }
+
+        Ok(m)
+    }
+
+    /// Adds the successful crate downloads found in the access logs
+    /// to each version's download count.
+    fn ingest_logs(
+        &self,
+        format: access_log::Format,
+        paths: &[PathBuf],
+    ) -> Result<u64, IngestLogsError> {
+        use ingest_logs_error::*;
+
+        let crates = self.list_all()?;
+        let mut counts = BTreeMap::<CrateName, BTreeMap<Version, u64>>::new();
+
+        for path in paths {
+            println!("Reading access log `{}`", path.display());
+
+            let file = File::open(path).context(OpenSnafu { path })?;
+            let mut parser = access_log::Parser::new(format);
+
+            for line in BufReader::new(file).lines() {
+                let line = line.context(ReadSnafu { path })?;
+
+                let Some(download) = parser.parse_line(&line) else {
+                    continue;
+                };
+
+                let known = crates
+                    .get(&download.name)
+                    .is_some_and(|index| index.contains_key(&download.version));
+                if !known {
+                    continue;
+                }
+
+                *counts
+                    .entry(download.name)
+                    .or_default()
+                    .entry(download.version)
+                    .or_default() += 1;
+            }
+        }
+
+        let mut total = 0;
+        for (name, versions) in counts {
+            self.modify_metadata(&name, |m| {
+                for (version, count) in versions {
+                    *m.downloads.entry(version).or_default() += count;
+                    total += count;
+                }
+            })?;
+        }
+
+        Ok(total)
+    }
+
+    /// Records that a version used to exist in the registry.
+    fn bury(
+        &self,
+        name: &CrateName,
+        version: &Version,
+        tombstone: &metadata::Tombstone,
+    ) -> Result<(), MetadataError> {
+        self.modify_metadata(name, |m| {
+            m.tombstones.insert(version.clone(), tombstone.clone());
+        })?;
+        println!("Recorded a tombstone for `{name}` version {version}");
+
+        Ok(())
+    }
+
+    fn generate_html(&self) -> Result<(), HtmlError> {
+        self.generate_html_with(false)
+    }
+
+    #[cfg(feature = "html")]
+    fn generate_html_with(&self, keep_old_assets: bool) -> Result<(), HtmlError> {
+        html::write(self, html::Options { keep_old_assets })?;
+        self.events.emit(Event::HtmlRegenerated {
+            path: self.path.clone(),
+        });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "html"))]
+    fn generate_html_with(&self, _keep_old_assets: bool) -> Result<(), HtmlError> {
+        Err(HtmlError)
+    }
+
+    #[cfg(feature = "html")]
+    fn watch_html(&self, keep_old_assets: bool) -> Result<(), HtmlError> {
+        html::watch(self, html::Options { keep_old_assets })
+    }
+
+    #[cfg(not(feature = "html"))]
+    fn watch_html(&self, _keep_old_assets: bool) -> Result<(), HtmlError> {
+        Err(HtmlError)
+    }
+
+    #[cfg(feature = "tui")]
+    fn run_tui(&self) -> Result<(), TuiError> {
+        tui::run(self)
+    }
+
+    #[cfg(not(feature = "tui"))]
+    fn run_tui(&self) -> Result<(), TuiError> {
+        Err(TuiError)
+    }
+
+    fn check_remote(&self, options: check_remote::Options<'_>) -> Result<(), check_remote::Error> {
+        check_remote::check(self, options)
+    }
+
+    fn maybe_generate_html(&self) -> Result<(), HtmlError> {
+        if self.config.html.enabled {
+            self.generate_html()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Updates `manifest.json`, when the registry is configured to have
+    /// one.
+    fn maybe_write_manifest(&self) -> Result<(), manifest::Error> {
+        if self.config.manifest.enabled && manifest::write(self)? {
+            println!("Updated the registry manifest");
+        }
+        Ok(())
+    }
+
+    /// Updates the compressed copies of the index files and HTML
+    /// pages, when the registry is configured to have them.
+    fn maybe_precompress(&self) -> Result<(), precompress::Error> {
+        if !self.config.precompressed.is_empty() {
+            let written = precompress::write(self)?;
+            println!("Wrote {written} compressed file(s)");
+        }
+        Ok(())
+    }
+
+    /// Returns the versions that were changed.
+    fn yank(
+        &self,
+        name: CrateName,
+        selector: &VersionSelector,
+        yanked: bool,
+    ) -> Result<Vec<Version>, YankError> {
+        use yank_error::*;
+
+        ensure!(!selector.is_empty(), SelectorMissingSnafu);
+
+        let metadata = self.read_metadata(&name)?;
+
+        let versions = self.read_modify_write(&name, |index| {
+            let versions = selector
+                .select(index, &metadata.channels)
+                .into_iter()
+                .cloned()
+                .collect::<Vec<_>>();
+            ensure!(!versions.is_empty(), VersionSnafu);
+
+            for version in &versions {
+                let entry = index.get_mut(version).context(VersionSnafu)?;
+                entry.yanked = yanked;
+            }
+
+            Ok::<_, YankError>(versions)
+        })?;
+
+        let action = if yanked { Action::Yank } else { Action::Unyank };
+        let yanks = versions
+            .iter()
+            .map(|v| Change::now(&name, v, action))
+            .collect::<Vec<_>>();
+        changes::record(self, &yanks)?;
+
+        Ok(versions)
+    }
+
+    fn select_versions(
+        &self,
+        name: &CrateName,
+        selector: &VersionSelector,
+    ) -> Result<Vec<Version>, YankError> {
+        use yank_error::*;
+
+        ensure!(!selector.is_empty(), SelectorMissingSnafu);
+
+        let path = self.index_file_path_for(name);
+        let index = Self::parse_index_file(&path).context(IndexReadSnafu { path })?;
+        let metadata = self.read_metadata(name)?;
+        let versions = selector
+            .select(&index, &metadata.channels)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        ensure!(!versions.is_empty(), VersionSnafu);
+
+        Ok(versions)
+    }
+
+    fn read_modify_write<T, E>(
+        &self,
+        name: &CrateName,
+        modify: impl FnOnce(&mut Index) -> Result<T, E>,
+    ) -> Result<T, E>
+    where
+        E: From<ReadModifyWriteError>,
+    {
+        use read_modify_write_error::*;
+
+        let path = self.index_file_path_for(name);
+        let mut index = Self::parse_index_file(&path).context(IndexParseSnafu { path: &path })?;
+
+        let val = modify(&mut index)?;
+
+        Self::write_index_file(index, &path, self.config.durability)
+            .context(IndexWriteSnafu { path })?;
+
+        Ok(val)
+    }
+
+    fn list_crate_files(
+        crate_dir: &Path,
+    ) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> {
+        walkdir::WalkDir::new(crate_dir)
+            .into_iter()
+            .flat_map(|entry| {
+                let Ok(entry) = entry else { return Some(entry) };
+
+                let fname = entry.path().file_name()?;
+                let fname = Path::new(fname);
+
+                let extension = fname.extension()?;
+                if extension == "crate" {
+                    Some(Ok(entry))
+                } else {
+                    None
+                }
+            })
+    }
+
+    fn list_index_files(&self) -> Result<BTreeSet<PathBuf>, ListIndexFilesError> {
+        use list_index_files_error::*;
+
+        // Index files live in `1/`, `2/`, `3/`, or two-character
+        // prefix directories, so longer top-level directories (like
+        // the crate files) can be skipped entirely.
+        let walker = walkdir::WalkDir::new(&self.path)
+            .max_depth(3)
+            .into_iter()
+            .filter_entry(|e| e.depth() != 1 || e.file_name().len() <= 2);
+
+        let index_files = walker
+            .filter_map(|entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => return Some(Err(e).context(WalkdirSnafu { path: &self.path })),
+                };
+
+                if !entry.file_type().is_file() {
+                    return None;
+                }
+
+                let name = entry.file_name().to_str()?.parse::<CrateName>().ok()?;
+                let path = entry.into_path();
+
+                (self.index_file_path_for(&name) == path).then_some(Ok(path))
+            })
+            .collect::<Result<BTreeSet<_>, ListIndexFilesError>>();
+
+        match index_files {
+            Err(e) if e.is_not_found() => Ok(Default::default()),
+            r => r,
+        }
+    }
+
+    fn list_all(&self) -> Result<ListAll, ListAllError> {
+        use list_all_error::*;
+
+        let mut crates = BTreeMap::new();
+
+        for path in self.list_index_files()? {
+            let index = Self::parse_index_file(&path).context(ParseSnafu { path })?;
+
+            if let Some(entry) = index.values().next() {
+                crates.insert(entry.name.clone(), index);
+            }
+        }
+
+        Ok(crates)
+    }
+
+    fn parse_index_file(path: &Path) -> Result<Index, ParseIndexError> {
+        use parse_index_error::*;
+
+        let index_file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Default::default()),
+            Err(e) => Err(e).context(OpenSnafu)?,
+        };
+        let index_file = BufReader::new(index_file);
+
+        let mut index = BTreeMap::new();
+
+        for (i, line) in index_file.lines().enumerate() {
+            let line = line.context(ReadSnafu { line: i })?;
+            let entry =
+                serde_json::from_str::<index_entry::Root>(&line).context(ParseSnafu { line: i })?;
+
+            index.insert(entry.vers.clone(), entry);
+        }
+
+        Ok(index)
+    }
+
+    /// Writes to a temporary file and renames it into place, so that
+    /// readers see either the old or the new index.
+    fn write_index_file(
+        index_file: Index,
+        path: &Path,
+        durability: Durability,
+    ) -> Result<(), WriteIndexError> {
+        use write_index_error::*;
+
+        let tmp = temp_path_beside(path);
+
+        let file = File::create(&tmp).context(OpenSnafu)?;
+        let mut file = BufWriter::new(file);
+
+        for entry in index_file.values() {
+            serde_json::to_writer(&mut file, entry).context(EntrySerializeSnafu)?;
+            file.write_all(b"\n").context(EntryNewlineSnafu)?;
+        }
+
+        let file = file
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)
+            .context(FlushSnafu)?;
+        durability.sync_file(&file).context(FlushSnafu)?;
+        drop(file);
+
+        fs::rename(&tmp, path).context(RenameSnafu)?;
+        durability.sync_parent(path).context(FlushSnafu)?;
+
+        Ok(())
+    }
+
+    fn read_metadata(&self, name: &CrateName) -> Result<metadata::Root, MetadataError> {
+        Self::parse_metadata_file(&self.metadata_file_path_for(name))
+    }
+
+    fn modify_metadata<T>(
+        &self,
+        name: &CrateName,
+        modify: impl FnOnce(&mut metadata::Root) -> T,
+    ) -> Result<T, MetadataError> {
+        use metadata_error::*;
+
+        let path = self.metadata_file_path_for(name);
+        let mut metadata = Self::parse_metadata_file(&path)?;
+
+        let val = modify(&mut metadata);
+        metadata.generated_by = Some(GeneratedBy::now());
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(DirSnafu { path: dir })?;
+        }
+        let data = serde_json::to_vec_pretty(&metadata).context(SerializeSnafu)?;
+        fs::write(&path, data).context(WriteSnafu { path })?;
+
+        Ok(val)
+    }
+
+    fn list_all_metadata(&self) -> Result<BTreeMap<CrateName, metadata::Root>, MetadataError> {
+        use metadata_error::*;
+
+        let metadata_dir = self.metadata_dir();
+        let mut all = BTreeMap::new();
+
+        for entry in walkdir::WalkDir::new(&metadata_dir) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) if e.io_error().map(|e| e.kind()) == Some(io::ErrorKind::NotFound) => break,
+                Err(e) => {
+                    return Err(e).context(WalkdirSnafu {
+                        path: &metadata_dir,
+                    })
+                }
+            };
+
+            let path = entry.path();
+            if path.extension() != Some(OsStr::new("json")) {
+                continue;
+            }
+
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let Ok(name) = name.parse::<CrateName>() else {
+                continue;
+            };
+
+            all.insert(name, Self::parse_metadata_file(path)?);
+        }
+
+        Ok(all)
+    }
+
+    fn parse_metadata_file(path: &Path) -> Result<metadata::Root, MetadataError> {
+        use metadata_error::*;
+
+        let data = match fs::read(path) {
+            Ok(d) => d,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Default::default()),
+            Err(e) => return Err(e).context(ReadSnafu { path }),
+        };
+
+        serde_json::from_slice(&data).context(ParseSnafu { path })
+    }
+
+    fn crate_dir(&self) -> PathBuf {
+        self.path.join(CRATE_DIR_NAME)
+    }
+
+    /// The path within the registry, for display.
+    fn relative_path<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.path).unwrap_or(path)
+    }
+
+    /// Where the data for a checksum is stored when crates are
+    /// content-addressed.
+    fn blob_path_for(&self, cksum: &str) -> PathBuf {
+        let prefix = cksum.get(..2).unwrap_or(cksum);
+        self.crate_dir()
+            .join(BLOB_DIR_NAME)
+            .join(prefix)
+            .join(cksum)
+    }
+
+    /// Writes a crate file. When crates are content-addressed, the
+    /// data is stored once per checksum and the crate file is a hard
+    /// link to it.
+    fn write_crate_file(&self, path: &Path, data: &[u8], cksum: &str) -> io::Result<()> {
+        let durability = self.config.durability;
+
+        if !self.config.content_addressed_crates {
+            return durability.write(path, data);
+        }
+
+        let blob = self.blob_path_for(cksum);
+        if !blob.exists() {
+            if let Some(dir) = blob.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            durability.write(&blob, data)?;
+        }
+
+        match fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        fs::hard_link(&blob, path)?;
+        durability.sync_parent(path)
+    }
+
+    fn metadata_dir(&self) -> PathBuf {
+        self.path.join(METADATA_DIR_NAME)
+    }
+
+    fn metadata_file_path_for(&self, name: &CrateName) -> PathBuf {
+        let mut path = self.metadata_dir();
+        name.append_prefix_directories(&mut path);
+        path.push(format!("{name}.json"));
+        path
+    }
+
+    #[cfg(test)]
+    fn margo_config_toml_path(&self) -> PathBuf {
+        self.path.join(CONFIG_FILE_NAME)
+    }
+
+    fn config_json_path(&self) -> PathBuf {
+        self.path.join("config.json")
+    }
+
+    /// A path next to the registry's directory, named after it with
+    /// `suffix` appended, for files that must not be published with
+    /// it. `None` when the registry is the root of the filesystem.
+    fn path_beside(&self, suffix: &str) -> io::Result<Option<PathBuf>> {
+        let registry = fs::canonicalize(&self.path)?;
+        let (Some(parent), Some(name)) = (registry.parent(), registry.file_name()) else {
+            return Ok(None);
+        };
+
+        let mut name = name.to_owned();
+        name.push(suffix);
+        Ok(Some(parent.join(name)))
+    }
+
+    fn generated_path(&self) -> PathBuf {
+        self.path.join(GENERATED_FILE_NAME)
+    }
+
+    fn verified_path(&self) -> PathBuf {
+        self.path.join(VERIFIED_FILE_NAME)
+    }
+
+    fn record_verification(&self, verification: &Verification) -> Result<(), VerifyError> {
+        use verify_error::*;
+
+        let path = self.verified_path();
+        let data =
+            serde_json::to_string_pretty(verification).context(VerificationSerializeSnafu)?;
+        fs::write(&path, data).context(VerificationWriteSnafu { path })
+    }
+
+    /// The outcome of the last `margo verify`, if it has been run.
+    #[cfg(any(test, feature = "html"))]
+    fn last_verification(&self) -> Result<Option<Verification>, VerifyError> {
+        use verify_error::*;
+
+        let path = self.verified_path();
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(ReadSnafu { path }),
+        };
+        serde_json::from_slice(&data)
+            .map(Some)
+            .context(VerificationDeserializeSnafu { path })
+    }
+
+    fn index_file_path_for(&self, name: &CrateName) -> PathBuf {
+        let mut index_path = self.path.clone();
+        name.append_prefix_directories(&mut index_path);
+        index_path.push(name.as_str().to_ascii_lowercase());
+        index_path
+    }
+
+    fn crate_dir_for(&self, name: &CrateName) -> PathBuf {
+        let mut crate_dir = self.crate_dir();
+        name.append_prefix_directories(&mut crate_dir);
+        crate_dir.push(name);
+        crate_dir
+    }
+
+    fn crate_file_path_for(&self, name: &CrateName, version: &Version, cksum: &str) -> PathBuf {
+        self.path
+            .join(self.config.crate_path.expand(name, version, cksum))
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum InitializeError {
+    #[snafu(display("The base URL `{base_url}` must use HTTP or HTTPS"))]
+    BaseUrlScheme { base_url: Url },
+
+    #[snafu(display("Could not create the registry directory"))]
+    RegistryCreate { source: io::Error },
+
+    #[snafu(display("Could not serialize the registry's internal configuration"))]
+    ConfigTomlSerialize { source: toml::ser::Error },
+
+    #[snafu(display("Could not write the registry's internal configuration to {}", path.display()))]
+    ConfigTomlWrite { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not serialize the registry's public configuration"))]
+    ConfigJsonSerialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the registry's public configuration to {}", path.display()))]
+    ConfigJsonWrite { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not serialize the registry's generation details"))]
+    GeneratedSerialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the registry's generation details to {}", path.display()))]
+    GeneratedWrite { source: io::Error, path: PathBuf },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum OpenError {
+    #[snafu(display("Could not open the registry's internal configuration at {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display(
+        "Could not deserialize the registry's internal configuration at {}; run `margo config validate` for details",
+        path.display(),
+    ))]
+    Deserialize {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+}
+
+impl AddError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::RootFilesExtract { .. } | Self::CargoTomlMissing => Some(ErrorKind::Malformed),
+            Self::UnversionedDependencies { .. }
+            | Self::ExternalDependencies { .. }
+            | Self::NameNotAllowed { .. }
+            | Self::BelowVersionFloor { .. }
+            | Self::PublicNameConflict { .. }
+            | Self::Dirty { .. }
+            | Self::NameCaseConflict { .. }
+            | Self::BuildMetadataConflict { .. }
+            | Self::VersionExists { .. } => Some(ErrorKind::Conflict),
+            _ => None,
+        }
+    }
+
+    fn code(&self) -> Option<diagnostics::Code> {
+        use diagnostics::Code;
+
+        match self {
+            Self::RootFilesExtract { .. } | Self::CargoTomlMissing => Some(Code::NotAPackage),
+            Self::UnversionedDependencies { .. } => Some(Code::UnversionedDependencies),
+            Self::ExternalDependencies { .. } => Some(Code::ExternalDependencies),
+            Self::NameNotAllowed { .. } => Some(Code::NameNotAllowed),
+            Self::PublicNameConflict { .. } => Some(Code::PublicNameConflict),
+            Self::Dirty { .. } => Some(Code::DirtyPackage),
+            Self::NameCaseConflict { .. } => Some(Code::NameCaseConflict),
+            _ => None,
+        }
+    }
+}
+
+impl RemoveError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::NameMissing | Self::AllYankedConflict => Some(ErrorKind::Usage),
+            Self::CrateNotFound { .. }
+            | Self::VersionNotFound { .. }
+            | Self::NoMatchingVersions { .. } => Some(ErrorKind::NotFound),
+            _ => None,
+        }
+    }
+
+    fn code(&self) -> Option<diagnostics::Code> {
+        match self {
+            Self::CrateNotFound { .. } => Some(diagnostics::Code::CrateNotFound),
+            _ => None,
+        }
+    }
+}
+
+impl YankError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::SelectorMissing => Some(ErrorKind::Usage),
+            Self::Version => Some(ErrorKind::NotFound),
+            _ => None,
+        }
+    }
+}
+
+impl OpenError {
+    fn is_not_found(&self) -> bool {
+        match self {
+            Self::Read { source, .. } => source.kind() == io::ErrorKind::NotFound,
+            Self::Deserialize { .. } => false,
+        }
+    }
+
+    fn code(&self) -> Option<diagnostics::Code> {
+        match self {
+            Self::Read { .. } if self.is_not_found() => Some(diagnostics::Code::RegistryNotFound),
+            Self::Read { .. } => None,
+            Self::Deserialize { .. } => Some(diagnostics::Code::ConfigMalformed),
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum AddError {
+    #[snafu(display("Could not read the crate package"))]
+    ReadCrate { source: io::Error },
+
+    #[snafu(transparent)]
+    RootFilesExtract { source: ExtractRootFilesError },
+
+    #[snafu(display("The crate package does not contain a Cargo.toml file"))]
+    CargoTomlMissing,
+
+    #[snafu(display("The crate's Cargo.toml is not valid UTF-8"))]
+    CargoTomlUtf8 { source: std::string::FromUtf8Error },
+
+    #[snafu(display("The crate's Cargo.toml is malformed"))]
+    CargoTomlMalformed { source: toml::de::Error },
+
+    #[snafu(display(
+        "`{name}` version {version} is below the registry's version floor of {floor}"
+    ))]
+    BelowVersionFloor {
+        name: CrateName,
+        version: Version,
+        floor: Version,
+    },
+
+    #[snafu(display(
+        "`{name}` has dependencies without a version, which can't be represented in a registry: {deps}. Add a `version` alongside each `path` or `git` before packaging"
+    ))]
+    UnversionedDependencies { name: CrateName, deps: String },
+
+    #[snafu(display("`{name}` depends on crates from registries that are not allowed: {deps}"))]
+    ExternalDependencies { name: CrateName, deps: String },
+
+    #[snafu(display("`{name}` does not match any of the registry's `allow_names` patterns"))]
+    NameNotAllowed { name: CrateName },
+
+    #[snafu(display("Could not check the public index for `{name}`"))]
+    PublicNameQuery {
+        #[snafu(source(from(public_names::Error, Box::new)))]
+        source: Box<public_names::Error>,
+        name: CrateName,
+    },
+
+    #[snafu(display(
+        "`{name}` also exists in {index} with the newer version {newest}, which Cargo may choose instead"
+    ))]
+    PublicNameConflict {
+        name: CrateName,
+        index: Box<Url>,
+        newest: Version,
+    },
+
+    #[snafu(display("The crate's .cargo_vcs_info.json is malformed"))]
+    VcsInfoMalformed { source: serde_json::Error },
+
+    #[snafu(display(
+        "`{name}` version {version} was packaged from a working directory with uncommitted changes"
+    ))]
+    Dirty { name: CrateName, version: Version },
+
+    #[snafu(display("Could not create the crate's index directory {}", path.display()))]
+    IndexDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not read the crate's index file {}", path.display()))]
+    IndexRead {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display(
+        "The crate `{name}` conflicts with the existing crate `{existing}`; crate names are case-insensitive"
+    ))]
+    NameCaseConflict {
+        name: CrateName,
+        existing: CrateName,
+    },
+
+    #[snafu(display(
+        "Version {version} conflicts with the existing version {existing}; versions that differ only by build metadata are not distinct"
+    ))]
+    BuildMetadataConflict { version: Version, existing: Version },
+
+    #[snafu(display(
+        "`{name}` version {version} is already in the registry with different contents; publish a new version instead"
+    ))]
+    VersionExists { name: CrateName, version: Version },
+
+    #[snafu(transparent)]
+    IndexModify { source: ReadModifyWriteError },
+
+    #[snafu(display("Could not create the crate directory {}", path.display()))]
+    CrateDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the crate {}", path.display()))]
+    CrateWrite { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    Metadata { source: MetadataError },
+
+    #[snafu(transparent)]
+    Changes { source: changes::RecordError },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum RemoveError {
+    #[snafu(display("A crate name is required unless `--all-yanked` is used"))]
+    NameMissing,
+
+    #[snafu(display("`--all-yanked` cannot be combined with a crate name or version"))]
+    AllYankedConflict,
+
+    #[snafu(display("The crate `{name}` does not exist in the registry"))]
+    CrateNotFound { name: CrateName },
+
+    #[snafu(display("The crate `{name}` has no version {version}"))]
+    VersionNotFound { name: CrateName, version: Version },
+
+    #[snafu(display("No versions of the crate `{name}` match"))]
+    NoMatchingVersions { name: CrateName },
+
+    #[snafu(display("Could not read the crate's index file {}", path.display()))]
+    IndexRead {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not list the crates"))]
+    #[snafu(context(false))]
+    ListAll { source: ListAllError },
+
+    #[snafu(transparent)]
+    IndexModify { source: ReadModifyWriteError },
+
+    #[snafu(display("Could not delete {}", path.display()))]
+    Delete { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    Metadata { source: MetadataError },
+
+    #[snafu(transparent)]
+    Changes { source: changes::RecordError },
+
+    #[snafu(transparent)]
+    Trash { source: trash::Error },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum ConfigValidateError {
+    #[snafu(transparent)]
+    EnvFallback { source: EnvFallbackError },
+
+    #[snafu(display("Could not determine the current directory"))]
+    CurrentDir { source: io::Error },
+
+    #[snafu(display("Could not read the configuration file {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("{} has {errors} error(s)", path.display()))]
+    Invalid { path: PathBuf, errors: usize },
+}
+
+impl ConfigValidateError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Invalid { .. } => Some(ErrorKind::Validation),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum VerifyError {
+    #[snafu(display("Could not list the crates"))]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not read the crate file {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("{count} problem(s) were found"))]
+    Problems { count: usize },
+
+    #[snafu(display("`--fast` and `--deep` cannot be used together"))]
+    FastAndDeep,
+
+    #[snafu(display("Could not serialize the verification outcome"))]
+    VerificationSerialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the verification outcome to {}", path.display()))]
+    VerificationWrite { source: io::Error, path: PathBuf },
+
+    #[cfg(any(test, feature = "html"))]
+    #[snafu(display("Could not deserialize the verification outcome at {}", path.display()))]
+    VerificationDeserialize {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+}
+
+impl VerifyError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Problems { .. } => Some(ErrorKind::Validation),
+            Self::FastAndDeep => Some(ErrorKind::Usage),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum VerifyPackageError {
+    #[snafu(display("The crate file could not be decompressed"))]
+    Decompress { source: io::Error },
+
+    #[snafu(transparent)]
+    RootFilesExtract { source: ExtractRootFilesError },
+
+    #[snafu(display("The crate package does not contain a Cargo.toml file"))]
+    CargoTomlMissing,
+
+    #[snafu(display("The crate's Cargo.toml is not valid UTF-8"))]
+    CargoTomlUtf8 { source: std::string::FromUtf8Error },
+
+    #[snafu(display("The crate's Cargo.toml is malformed"))]
+    CargoTomlMalformed { source: toml::de::Error },
+
+    #[snafu(display("The crate's Cargo.toml is for `{name}` version {version}"))]
+    Mismatch { name: CrateName, version: Version },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum FixCaseError {
+    #[snafu(display("Could not enumerate the directory `{}`", path.display()))]
+    Walkdir {
+        source: walkdir::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display(
+        "Could not remove the path prefix `{prefix}` from `{path}`",
+        prefix = prefix.display(),
+        path = path.display(),
+    ))]
+    Prefix {
+        source: std::path::StripPrefixError,
+        path: PathBuf,
+        prefix: PathBuf,
+    },
+
+    #[snafu(display("The directory `{}` is not named after a crate", path.display()))]
+    Name { path: PathBuf },
+
+    #[snafu(display("Could not create the directory {}", path.display()))]
+    CreateDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not move `{}` to `{}`", from.display(), to.display()))]
+    Move {
+        source: io::Error,
+        from: PathBuf,
+        to: PathBuf,
+    },
+}
+
+/// The outcome of the last `margo verify`, shown on the health page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Verification {
+    #[serde(rename = "verified_timestamp_seconds")]
+    at: Timestamp,
+    deep: bool,
+    problems: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Metrics {
+    crates: usize,
+    versions: usize,
+    yanked_versions: usize,
+    crate_bytes: u64,
+    downloads: u64,
+    last_publish_timestamp_seconds: Option<u64>,
+}
+
+impl Metrics {
+    fn to_prometheus(&self) -> String {
+        use std::fmt::Write;
+
+        let mut out = String::new();
+
+        let mut gauge = |name: &str, help: &str, value: &dyn fmt::Display| {
+            _ = writeln!(out, "# HELP margo_{name} {help}");
+            _ = writeln!(out, "# TYPE margo_{name} gauge");
+            _ = writeln!(out, "margo_{name} {value}");
+        };
+
+        gauge("crates", "Number of crates in the registry.", &self.crates);
+        gauge(
+            "versions",
+            "Number of crate versions in the registry.",
+            &self.versions,
+        );
+        gauge(
+            "yanked_versions",
+            "Number of yanked crate versions in the registry.",
+            &self.yanked_versions,
+        );
+        gauge(
+            "crate_bytes",
+            "Total size of all crate files in bytes.",
+            &self.crate_bytes,
+        );
+        gauge(
+            "downloads",
+            "Number of crate downloads counted from ingested access logs.",
+            &self.downloads,
+        );
+        if let Some(t) = self.last_publish_timestamp_seconds {
+            gauge(
+                "last_publish_timestamp_seconds",
+                "Unix time of the most recent crate publish.",
+                &t,
+            );
+        }
+
+        out
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum MetricsError {
+    #[snafu(display("Could not list the crates"))]
+    #[snafu(context(false))]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not list the crate metadata"))]
+    #[snafu(context(false))]
+    Metadata { source: MetadataError },
+
+    #[snafu(display("Could not read the crate file {}", path.display()))]
+    CrateFile { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not serialize the metrics"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the metrics to {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum IngestLogsError {
+    #[snafu(display("Could not list the crates"))]
+    #[snafu(context(false))]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not open the access log {}", path.display()))]
+    Open { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not read the access log {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    Metadata { source: MetadataError },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum MetadataError {
+    #[snafu(display("Could not read the crate's metadata file {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not parse the crate's metadata file {}", path.display()))]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not create the metadata directory {}", path.display()))]
+    Dir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not serialize the crate's metadata"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the crate's metadata file {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not enumerate the metadata directory `{}`", path.display()))]
+    Walkdir {
+        source: walkdir::Error,
+        path: PathBuf,
+    },
+}
+
+#[cfg(feature = "html")]
+use html::Error as HtmlError;
+
+#[cfg(not(feature = "html"))]
+#[derive(Debug, Snafu)]
+#[snafu(display("Margo was not compiled with the HTML feature enabled. This binary will not be able to generate HTML files"))]
+struct HtmlError;
+
+#[cfg(feature = "tui")]
+use tui::Error as TuiError;
+
+#[cfg(not(feature = "tui"))]
+#[derive(Debug, Snafu)]
+#[snafu(display("Margo was not compiled with the TUI feature enabled. This binary will not be able to run the interactive interface"))]
+struct TuiError;
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum YankError {
+    #[snafu(display(
+        "One of `--version`, `--versions`, `--all-prereleases`, `--all`, or `--channel` is required"
+    ))]
+    SelectorMissing,
+
+    #[snafu(display("No matching version exists in the index"))]
+    Version,
+
+    #[snafu(display("Could not read the crate's index file {}", path.display()))]
+    IndexRead {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(transparent)]
+    Modify { source: ReadModifyWriteError },
+
+    #[snafu(transparent)]
+    Metadata { source: MetadataError },
+
+    #[snafu(transparent)]
+    Changes { source: changes::RecordError },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum ReadModifyWriteError {
+    #[snafu(display("Could not parse the crate's index file {}", path.display()))]
+    IndexParse {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not write the crate's index file {}", path.display()))]
+    IndexWrite {
+        source: WriteIndexError,
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum ListIndexFilesError {
+    #[snafu(display("Could not enumerate the index directory `{}`", path.display()))]
+    Walkdir {
+        source: walkdir::Error,
+        path: PathBuf,
+    },
+}
+
+impl ListIndexFilesError {
+    fn is_not_found(&self) -> bool {
+        let Self::Walkdir { source, .. } = self;
+        source
+            .io_error()
+            .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum ListAllError {
+    #[snafu(display("Unable to list the crate index files"))]
+    #[snafu(context(false))]
+    ListIndex { source: ListIndexFilesError },
+
+    #[snafu(display("Unable to parse the crate index file at `{}`", path.display()))]
+    Parse {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum ParseIndexError {
+    #[snafu(display("Could not open the file"))]
+    Open { source: io::Error },
+
+    #[snafu(display("Could not read line {line}"))]
+    Read { source: io::Error, line: usize },
+
+    #[snafu(display("Could not parse line {line}"))]
+    Parse {
+        source: serde_json::Error,
+        line: usize,
+    },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum WriteIndexError {
+    #[snafu(display("Could not open the file"))]
+    Open { source: io::Error },
+
+    #[snafu(display("Could not serialize the entry"))]
+    EntrySerialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the entry's newline"))]
+    EntryNewline { source: io::Error },
+
+    #[snafu(display("Could not flush the file to disk"))]
+    Flush { source: io::Error },
+
+    #[snafu(display("Could not replace the previous file"))]
+    Rename { source: io::Error },
+}
+
+/// Files at the root of a crate package that Margo reads.
+#[derive(Debug, Default)]
+struct RootFiles {
+    cargo_toml: Option<Vec<u8>>,
+    cargo_vcs_info: Option<Vec<u8>>,
+}
+
+fn extract_root_files(
+    crate_data: &[u8],
+    limits: &PackageLimits,
+) -> Result<RootFiles, ExtractRootFilesError> {
+    use extract_root_files_error::*;
+
+    let crate_data = flate2::read::GzDecoder::new(crate_data);
+    let crate_data = LimitedReader {
+        inner: crate_data,
+        remaining: limits.max_total_bytes,
+    };
+    let mut crate_data = tar::Archive::new(crate_data);
+
+    let entries = crate_data.entries().context(EntriesSnafu)?;
+
+    let mut dirname = None;
+    let mut files = RootFiles::default();
+    let mut total_bytes = 0u64;
+
+    for (i, entry) in entries.enumerate() {
+        ensure!(
+            i < limits.max_entries,
+            TooManyEntriesSnafu {
+                max: limits.max_entries
+            }
+        );
+
+        let mut entry = entry.context(EntrySnafu)?;
+        let path = entry.path().context(PathSnafu)?;
+
+        // Nothing outside the package may be touched if entries are
+        // ever extracted, so refuse such packages outright.
+        ensure!(is_contained_path(&path), UnsafePathSnafu { path });
+
+        let size = entry.size();
+        ensure!(
+            size <= limits.max_entry_bytes,
+            EntryTooLargeSnafu {
+                path,
+                size,
+                max: limits.max_entry_bytes,
+            }
+        );
+
+        total_bytes = total_bytes.saturating_add(size);
+        ensure!(
+            total_bytes <= limits.max_total_bytes,
+            TooLargeSnafu {
+                max: limits.max_total_bytes
+            }
+        );
+
+        let dirname = match &mut dirname {
+            Some(v) => v,
+            None => {
+                let Some(Component::Normal(first)) = path.components().next() else {
+                    return MalformedSnafu.fail();
+                };
+
+                dirname.insert(first.to_owned())
+            }
+        };
+
+        let fname = path.strip_prefix(dirname).context(PrefixSnafu)?;
+
+        let slot = if fname == Path::new("Cargo.toml") {
+            &mut files.cargo_toml
+        } else if fname == Path::new(".cargo_vcs_info.json") {
+            &mut files.cargo_vcs_info
+        } else {
+            continue;
+        };
+
+        let fname = fname.to_owned();
+        let mut data = vec![];
+        entry
+            .read_to_end(&mut data)
+            .context(ReadSnafu { path: fname })?;
+        *slot = Some(data);
+    }
+
+    Ok(files)
+}
+
+/// Whether the path of a package entry stays within the directory the
+/// package is extracted to.
+fn is_contained_path(path: &Path) -> bool {
+    path.components().all(|c| match c {
+        Component::Normal(c) => {
+            // Drive letters and separators that are only special on
+            // Windows are still dangerous when extracted there.
+            let c = c.as_encoded_bytes();
+            !c.contains(&b':') && !c.contains(&b'\\')
+        }
+        Component::CurDir => true,
+        Component::ParentDir | Component::RootDir | Component::Prefix(_) => false,
+    })
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum ExtractRootFilesError {
+    #[snafu(display("Could not get the entries of the crate package"))]
+    Entries { source: io::Error },
+
+    #[snafu(display("Could not get the next crate package entry"))]
+    Entry { source: io::Error },
+
+    #[snafu(display("Could not get the path of the crate package entry"))]
+    Path { source: io::Error },
+
+    #[snafu(display("The crate package was malformed"))]
+    Malformed,
+
+    #[snafu(display("Could not remove the path prefix from the crate package entry"))]
+    Prefix { source: std::path::StripPrefixError },
+
+    #[snafu(display("Could not read the crate package entry for {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The crate package contains more than {max} entries"))]
+    TooManyEntries { max: usize },
+
+    #[snafu(display(
+        "The crate package entry {} is {size} bytes, more than the limit of {max} bytes",
+        path.display(),
+    ))]
+    EntryTooLarge { path: PathBuf, size: u64, max: u64 },
+
+    #[snafu(display("The crate package decompresses to more than {max} bytes"))]
+    TooLarge { max: u64 },
+
+    #[snafu(display(
+        "The crate package entry {} would be extracted outside of the package",
+        path.display(),
+    ))]
+    UnsafePath { path: PathBuf },
+}
+
+/// Fails reads once more than the allowed number of bytes have been
+/// produced, so that a package can't decompress without bound before
+/// its entries are checked.
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            // Allow reaching the end of the data exactly at the limit
+            let mut probe = [0; 1];
+            return match self.inner.read(&mut probe)? {
+                0 => Ok(0),
+                _ => Err(io::Error::other(
+                    "the crate package decompresses to more data than allowed",
+                )),
+            };
+        }
+
+        let max = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..max])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+fn adapt_cargo_toml_to_index_entry(
+    global: &Global,
+    config: &ConfigV1,
+    mut cargo_toml: cargo_toml::Root,
+    checksum_hex: String,
+) -> index_entry::Root {
+    // Remove features that refer to dev-dependencies as we don't
+    // track those anyway.
+    {
+        // Ignore dependencies that also occur as a regular or build
+        // dependency, as we *do* track those.
+        let reg_dep_names = cargo_toml.dependencies.keys();
+        let build_dep_names = cargo_toml.build_dependencies.keys();
+        let mut only_dev_dep_names = cargo_toml.dev_dependencies.keys().collect::<BTreeSet<_>>();
+        for name in reg_dep_names.chain(build_dep_names) {
+            only_dev_dep_names.remove(name);
+        }
+
+        for name in only_dev_dep_names {
+            // We don't care about the official package name here as the
+            // feature syntax has to match the user-specified dependency
+            // name.
+            let prefix = format!("{name}/");
+
+            for enabled in cargo_toml.features.values_mut() {
+                enabled.retain(|enable| !enable.starts_with(&prefix));
+            }
+        }
+    }
+
+    let mut deps: Vec<_> = cargo_toml
+        .dependencies
+        .into_iter()
+        .map(|(name, dep)| adapt_dependency(global, config, dep, name))
+        .collect();
+
+    let build_deps = cargo_toml
+        .build_dependencies
+        .into_iter()
+        .map(|(name, dep)| {
+            let mut dep = adapt_dependency(global, config, dep, name);
+            dep.kind = index_entry::DependencyKind::Build;
+            dep
+        });
+    deps.extend(build_deps);
+
+    for (target, defn) in cargo_toml.target {
+        let target_deps = defn.dependencies.into_iter().map(|(name, dep)| {
+            let mut dep = adapt_dependency(global, config, dep, name);
+            dep.target = Some(target.clone());
+            dep
+        });
+        deps.extend(target_deps);
+    }
+
+    // FUTURE: Opt-in to checking that all dependencies already exist
+
+    index_entry::Root {
+        name: cargo_toml.package.name,
+        vers: cargo_toml.package.version,
+        deps,
+        cksum: checksum_hex,
+        features: cargo_toml.features,
+        yanked: false,
+        links: cargo_toml.package.links,
+        v: 2,
+        features2: Default::default(),
+        rust_version: cargo_toml.package.rust_version,
+        unknown: Default::default(),
+    }
+}
+
+fn adapt_dependency(
+    global: &Global,
+    config: &ConfigV1,
+    dep: cargo_toml::Dependency,
+    name: String,
+) -> index_entry::Dependency {
+    let cargo_toml::Dependency {
+        version,
+        features,
+        optional,
+        default_features,
+        registry_index,
+        package,
+        ..
+    } = dep;
+
+    index_entry::Dependency {
+        name,
+        // Unversioned dependencies have already been rejected
+        req: version.unwrap_or(VersionReq::STAR),
+        features,
+        optional,
+        default_features,
+        target: None,
+        kind: index_entry::DependencyKind::Normal,
+        registry: adapt_index(global, config, registry_index),
+        package,
+        unknown: Default::default(),
+    }
+}
+
+fn adapt_index(global: &Global, config: &ConfigV1, registry_index: Option<Url>) -> Option<Url> {
+    let registry_index = registry_index.map(|url| {
+        config
+            .dependency_registries
+            .iter()
+            .find(|(from, _)| same_index_url(from, &url))
+            .map_or(url, |(_, to)| to.clone())
+    });
+
+    // The dependency is in...
+    match registry_index {
+        // ...crates.io
+        None => Some(global.crates_io_index_url.clone()),
+
+        // ...this registry
+        Some(url) if same_index_url(&url, &config.base_url) => None,
+
+        // ...another registry
+        r => r,
+    }
+}
+
+/// Matches a crate name against a pattern in which `*` matches any
+/// run of characters. As on crates.io, names that differ only by case
+/// or by `-` and `_` are considered the same.
+fn name_matches_pattern(pattern: &str, name: &str) -> bool {
+    let normalize = |s: &str| s.to_ascii_lowercase().replace('_', "-");
+    let (pattern, name) = (normalize(pattern), normalize(name));
+
+    let mut parts = pattern.split('*').collect::<Vec<_>>();
+    let first = parts.remove(0);
+
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// Cargo writes sparse index URLs with a `sparse+` prefix, while the
+/// registry's base URL has none.
+fn same_index_url(a: &Url, b: &Url) -> bool {
+    let normalize = |u: &Url| {
+        let u = u.as_str();
+        let u = u.strip_prefix("sparse+").unwrap_or(u);
+        u.trim_end_matches('/').to_owned()
+    };
+
+    normalize(a) == normalize(b)
+}
+
+/// Replaces the markers in a `config.json` `dl` template with the
+/// values for a specific crate version.
+fn expand_dl_template(template: &str, name: &CrateName, version: &Version, cksum: &str) -> String {
+    const MARKERS: [&str; 5] = [
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowerprefix}",
+        "{sha256-checksum}",
+    ];
+
+    if !MARKERS.iter().any(|m| template.contains(m)) {
+        return format!("{template}/{name}/{version}/download");
+    }
+
+    let prefix = name.prefix();
+
+    template
+        .replace("{crate}", name.as_str())
+        .replace("{version}", &version.to_string())
+        .replace("{lowerprefix}", &prefix.to_lowercase())
+        .replace("{prefix}", &prefix)
+        .replace("{sha256-checksum}", cksum)
+}
+
+/// Only intended for the normalized Cargo.toml created for the
+/// packaged crate.
+mod cargo_toml {
+    use semver::{Version, VersionReq};
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+    use url::Url;
+
+    use crate::common::{CrateName, RustVersion};
+
+    pub type Dependencies = BTreeMap<String, Dependency>;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub struct Root {
+        pub package: Package,
+
+        #[serde(default)]
+        pub features: BTreeMap<String, Vec<String>>,
+
+        #[serde(default)]
+        pub dependencies: Dependencies,
+
+        #[serde(default)]
+        pub build_dependencies: Dependencies,
+
+        #[serde(default)]
+        pub dev_dependencies: Dependencies,
+
+        #[serde(default)]
+        pub target: BTreeMap<String, Target>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub struct Package {
+        pub name: CrateName,
+
+        pub version: Version,
+
+        #[serde(default)]
+        pub links: Option<String>,
+
+        #[serde(default)]
+        pub rust_version: Option<RustVersion>,
+
+        #[serde(default)]
+        pub repository: Option<String>,
+
+        #[serde(default)]
+        pub description: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub struct Dependency {
+        #[serde(default)]
+        pub version: Option<VersionReq>,
+
+        #[serde(default)]
+        pub path: Option<String>,
+
+        #[serde(default)]
+        pub git: Option<String>,
+
+        #[serde(default)]
+        pub features: Vec<String>,
+
+        #[serde(default)]
+        pub optional: bool,
+
+        #[serde(default = "true_def")]
+        pub default_features: bool,
+
+        #[serde(default)]
+        pub registry_index: Option<Url>,
+
+        #[serde(default)]
+        pub package: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Target {
+        #[serde(default)]
+        pub dependencies: Dependencies,
+    }
+
+    impl Root {
+        /// Describes the dependencies that only have a `path` or
+        /// `git` source. Dev-dependencies are skipped as they aren't
+        /// part of the index.
+        pub fn unversioned_dependencies(&self) -> Vec<String> {
+            let targets = self.target.values().map(|t| &t.dependencies);
+
+            [&self.dependencies, &self.build_dependencies]
+                .into_iter()
+                .chain(targets)
+                .flatten()
+                .filter(|(_, dep)| dep.version.is_none())
+                .map(|(name, dep)| match (&dep.path, &dep.git) {
+                    (Some(path), _) => format!("`{name}` (path `{path}`)"),
+                    (None, Some(git)) => format!("`{name}` (git `{git}`)"),
+                    (None, None) => format!("`{name}`"),
+                })
+                .collect()
+        }
+    }
+
+    fn true_def() -> bool {
+        true
+    }
+}
+
+/// The `.cargo_vcs_info.json` file that `cargo package` writes.
+mod cargo_vcs_info {
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    pub struct Root {
+        #[serde(default)]
+        pub git: Option<Git>,
+
+        #[serde(default)]
+        pub path_in_vcs: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct Git {
+        pub sha1: String,
+
+        #[serde(default)]
+        pub dirty: bool,
+    }
+}
+
+const CONFIG_FILE_NAME: &str = "margo-config.toml";
+const CRATE_DIR_NAME: &str = "crates";
+
+/// Holds the data of content-addressed crate files, inside the crate
+/// directory. The files don't have the `.crate` extension so they are
+/// not mistaken for crate files themselves.
+const BLOB_DIR_NAME: &str = ".blobs";
+
+/// Whether a content-addressed file exists with no crate files linked
+/// to it.
+#[cfg(unix)]
+fn is_unlinked(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path).is_ok_and(|m| m.nlink() == 1)
+}
+
+/// Link counts aren't available, so the data is kept.
+#[cfg(not(unix))]
+fn is_unlinked(_path: &Path) -> bool {
+    false
+}
+const METADATA_DIR_NAME: &str = "margo-metadata";
+
+/// Records which version of Margo wrote `config.json`.
+const GENERATED_FILE_NAME: &str = "margo-generated.json";
+
+const VERIFIED_FILE_NAME: &str = "margo-verified.json";
+
+const CRATES_IO_INDEX_URL: &str = "https://github.com/rust-lang/crates.io-index";
+
+#[derive(Debug)]
+struct Global {
+    crates_io_index_url: Url,
+
+    /// Networked operations must fail instead of making requests.
+    offline: bool,
+}
+
+impl Global {
+    fn new() -> Result<Self, GlobalError> {
+        use global_error::*;
+
+        Ok(Self {
+            crates_io_index_url: CRATES_IO_INDEX_URL.parse().context(CratesIoIndexUrlSnafu)?,
+            offline: false,
+        })
+    }
+
+    /// Applies the options shared by every subcommand.
+    fn with_args(mut self, args: &Args) -> Result<Self, GlobalError> {
+        self.offline = args.offline || env_fallback(None, ENV_OFFLINE)?.unwrap_or(false);
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum GlobalError {
+    #[snafu(display("Could not parse the crates.io index URL"))]
+    CratesIoIndexUrl { source: url::ParseError },
+
+    #[snafu(transparent)]
+    EnvFallback { source: EnvFallbackError },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "version")]
+enum Config {
+    #[serde(rename = "1")]
+    V1(ConfigV1),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ConfigV1 {
+    base_url: Url,
+
+    #[serde(default)]
+    auth_required: bool,
+
+    #[serde(default)]
+    html: ConfigV1Html,
+
+    /// The lowest version of each named crate that may be added, such
+    /// as when older version numbers were used elsewhere.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    version_floors: BTreeMap<CrateName, Version>,
+
+    #[serde(default, skip_serializing_if = "CratePath::is_default")]
+    crate_path: CratePath,
+
+    /// Index URLs found in packaged dependencies mapped to the URL
+    /// that should be recorded in the index, such as when publishers
+    /// refer to another internal registry by a mirror or old address.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    dependency_registries: BTreeMap<Url, Url>,
+
+    /// Refuse crates that depend on other registries, except those
+    /// in `allowed_dependency_registries`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    forbid_external_dependencies: bool,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowed_dependency_registries: Vec<Url>,
+
+    #[serde(default, skip_serializing_if = "PackageLimits::is_default")]
+    package_limits: PackageLimits,
+
+    /// Patterns, such as `mycorp-*`, that the names of added crates
+    /// must match. All names are allowed when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allow_names: Vec<String>,
+
+    /// Whether `add` looks for crates with the same name in a public
+    /// index, guarding against dependency confusion.
+    #[serde(default, skip_serializing_if = "PublicNameCheck::is_default")]
+    public_name_check: PublicNameCheck,
+
+    /// The sparse index used by `public_name_check`, when not
+    /// crates.io.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_index: Option<Url>,
+
+    /// Store the data of each crate file once per checksum, with the
+    /// crate files as hard links to it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    content_addressed_crates: bool,
+
+    /// Where the registry is served from, used to generate the files
+    /// that host needs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host: Option<hosting::Host>,
+
+    #[serde(default, skip_serializing_if = "hosting::ContentTypes::is_default")]
+    content_types: hosting::ContentTypes,
+
+    /// Write compressed copies of the index files and HTML pages
+    /// beside them, for hosts that serve precompressed files.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    precompressed: Vec<precompress::Encoding>,
+
+    /// Write `manifest.json`, summarizing the crates for consumers
+    /// checking whether anything changed.
+    #[serde(default, skip_serializing_if = "manifest::Settings::is_default")]
+    manifest: manifest::Settings,
+
+    #[serde(default, skip_serializing_if = "Durability::is_default")]
+    durability: Durability,
+
+    /// How long removed crate files are kept in the trash.
+    #[serde(default, skip_serializing_if = "trash::Settings::is_default")]
+    trash: trash::Settings,
+}
+
+impl ConfigV1 {
+    const USER_DEFAULT_AUTH_REQUIRED: bool = false;
+
+    fn new(base_url: Url) -> Self {
+        Self {
+            base_url,
+            auth_required: Self::USER_DEFAULT_AUTH_REQUIRED,
+            html: Default::default(),
+            version_floors: Default::default(),
+            crate_path: Default::default(),
+            dependency_registries: Default::default(),
+            forbid_external_dependencies: false,
+            allowed_dependency_registries: Default::default(),
+            package_limits: Default::default(),
+            allow_names: Default::default(),
+            public_name_check: Default::default(),
+            public_index: None,
+            content_addressed_crates: false,
+            host: None,
+            content_types: Default::default(),
+            precompressed: Default::default(),
+            manifest: Default::default(),
+            durability: Default::default(),
+            trash: Default::default(),
+        }
+    }
+
+    fn public_index(&self) -> Url {
+        self.public_index.clone().unwrap_or_else(|| {
+            public_names::CRATES_IO_INDEX
+                .parse()
+                .expect("The crates.io index URL must be valid")
+        })
+    }
+
+    fn name_allowed(&self, name: &CrateName) -> bool {
+        self.allow_names.is_empty()
+            || self
+                .allow_names
+                .iter()
+                .any(|p| name_matches_pattern(p, name.as_str()))
+    }
+
+    /// Describes the dependencies that come from registries other
+    /// than this one and those allowed.
+    fn external_dependencies(&self, entry: &index_entry::Root) -> Vec<String> {
+        entry
+            .deps
+            .iter()
+            .filter_map(|dep| {
+                let registry = dep.registry.as_ref()?;
+                let allowed = self
+                    .allowed_dependency_registries
+                    .iter()
+                    .any(|a| same_index_url(a, registry));
+                let name = dep.package.as_deref().unwrap_or(&dep.name);
+
+                (!allowed).then(|| format!("`{name}` ({registry})"))
+            })
+            .collect()
+    }
+
+    /// The `[registries]` table Cargo needs to use this registry.
+    fn registries_stanza(&self, name: &str) -> String {
+        format!(
+            "[registries]\n{name} = {{ index = \"sparse+{base_url}\" }}\n",
+            base_url = self.base_url,
+        )
+    }
+
+    fn dl_template(&self) -> String {
+        format!(
+            "{base_url}{crate_path}",
+            base_url = self.base_url,
+            crate_path = self.crate_path.as_str(),
+        )
+    }
+
+    fn normalize(mut self) -> ConfigV1 {
+        ensure_last_segment_empty(&mut self.base_url);
+
+        self
+    }
+}
+
+/// What `add` does when a crate's name is also used in a public index
+/// with a newer version.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PublicNameCheck {
+    /// Don't consult the public index.
+    #[default]
+    Off,
+    /// Print a warning.
+    Warn,
+    /// Refuse the crate.
+    Deny,
+}
+
+impl PublicNameCheck {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// How carefully index and crate files are written to disk.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Durability {
+    /// Leave flushing to the operating system; a power loss shortly
+    /// after a change can undo it.
+    #[default]
+    Fast,
+    /// Flush each file, and the directory it was added to, before
+    /// reporting success.
+    Full,
+}
+
+impl Durability {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn sync_file(self, file: &File) -> io::Result<()> {
+        match self {
+            Self::Fast => Ok(()),
+            Self::Full => file.sync_all(),
+        }
+    }
+
+    /// Flushes the directory's entries, so that a file created or
+    /// renamed in it survives a power loss.
+    #[cfg(unix)]
+    fn sync_dir(self, dir: &Path) -> io::Result<()> {
+        match self {
+            Self::Fast => Ok(()),
+            Self::Full => File::open(dir)?.sync_all(),
+        }
+    }
+
+    // Directories can't be opened for flushing on other platforms
+    #[cfg(not(unix))]
+    fn sync_dir(self, _dir: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sync_parent(self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => self.sync_dir(dir),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the file, then flushes it and its directory.
+    fn write(self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(data)?;
+        self.sync_file(&file)?;
+        self.sync_parent(path)
+    }
+}
+
+/// A path beside `path` to write its new contents to before renaming
+/// them into place. The name starts with a dot so that `watch` skips
+/// it, and is unique to this process and call so that concurrent
+/// writers never share one.
+fn temp_path_beside(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut tmp = OsString::from(".");
+    tmp.push(path.file_name().unwrap_or_default());
+    tmp.push(format!(".{}-{n}.tmp", process::id()));
+    path.with_file_name(tmp)
+}
+
+/// Limits on reading crate packages, protecting machines that add
+/// crates from malicious or corrupted packages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct PackageLimits {
+    /// The most entries a package may contain.
+    max_entries: usize,
+
+    /// The most bytes a single entry may decompress to.
+    max_entry_bytes: u64,
+
+    /// The most bytes a package may decompress to.
+    max_total_bytes: u64,
+}
+
+impl PackageLimits {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for PackageLimits {
+    fn default() -> Self {
+        // crates.io allows packages to decompress to 512 MiB
+        const MIB: u64 = 1024 * 1024;
+
+        Self {
+            max_entries: 100_000,
+            max_entry_bytes: 512 * MIB,
+            max_total_bytes: 512 * MIB,
+        }
+    }
+}
+
+/// Where crate files are stored, relative to the registry root. Uses
+/// the same markers as the `dl` template in `config.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+struct CratePath(String);
+
+impl CratePath {
+    const DEFAULT: &'static str = "crates/{lowerprefix}/{crate}/{version}.crate";
+
+    fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn is_default(&self) -> bool {
+        self.0 == Self::DEFAULT
+    }
+
+    fn expand(&self, name: &CrateName, version: &Version, cksum: &str) -> String {
+        expand_dl_template(&self.0, name, version, cksum)
+    }
+}
+
+impl Default for CratePath {
+    fn default() -> Self {
+        Self(Self::DEFAULT.into())
+    }
+}
+
+impl str::FromStr for CratePath {
+    type Err = CratePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.to_owned().try_into()
+    }
+}
+
+impl TryFrom<String> for CratePath {
+    type Error = CratePathError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        use crate_path_error::*;
+
+        let in_crate_dir = value
+            .strip_prefix(CRATE_DIR_NAME)
+            .is_some_and(|rest| rest.starts_with('/'));
+        ensure!(in_crate_dir, CrateDirSnafu { value });
+
+        ensure!(
+            !value
+                .split('/')
+                .any(|s| s.is_empty() || s == "." || s == ".."),
+            SegmentSnafu { value },
+        );
+
+        let unique = value.contains("{sha256-checksum}")
+            || (value.contains("{crate}") && value.contains("{version}"));
+        ensure!(unique, UniqueSnafu { value });
+
+        Ok(Self(value))
+    }
+}
+
+impl From<CratePath> for String {
+    fn from(value: CratePath) -> Self {
+        value.0
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum CratePathError {
+    #[snafu(display("The crate path `{value}` must be inside the `{CRATE_DIR_NAME}/` directory"))]
+    CrateDir { value: String },
+
+    #[snafu(display("The crate path `{value}` must not contain empty, `.`, or `..` segments"))]
+    Segment { value: String },
+
+    #[snafu(display(
+        "The crate path `{value}` must contain `{{sha256-checksum}}` or both `{{crate}}` and `{{version}}`"
+    ))]
+    Unique { value: String },
+}
+
+fn ensure_last_segment_empty(url: &mut Url) {
+    if let Ok(mut s) = url.path_segments_mut() {
+        s.pop_if_empty().push("");
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ConfigV1Html {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    suggested_registry_name: Option<String>,
+
+    /// The language of the generated pages.
+    #[serde(default, skip_serializing_if = "HtmlLocale::is_default")]
+    locale: HtmlLocale,
+
+    /// A TOML file, relative to the registry, replacing some or all
+    /// of the locale's text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    strings: Option<PathBuf>,
+
+    /// Split the crate list into pages of this many crates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    page_size: Option<NonZeroUsize>,
+
+    /// How many of the newest versions to show at the top of the
+    /// landing page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recently_published: Option<usize>,
+
+    /// Raw HTML added to the `head` of every page, such as a script
+    /// tag for an analytics service. It is not escaped or checked in
+    /// any way, so only use snippets from sources you trust.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    analytics_snippet: Option<String>,
+
+    /// Publish sourcemaps for the CSS and JS. Defaults to only doing
+    /// so in debug builds of Margo, as they double the size of the
+    /// assets and include paths from the machine that built them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    include_sourcemaps: Option<bool>,
+}
+
+impl ConfigV1Html {
+    const USER_DEFAULT_ENABLED: bool = true;
+    const USER_DEFAULT_SUGGESTED_REGISTRY_NAME: &'static str = "my-awesome-registry";
+    #[cfg(feature = "html")]
+    const DEFAULT_RECENTLY_PUBLISHED: usize = 10;
+
+    fn suggested_registry_name(&self) -> &str {
+        self.suggested_registry_name
+            .as_deref()
+            .unwrap_or(Self::USER_DEFAULT_SUGGESTED_REGISTRY_NAME)
+    }
+
+    #[cfg(feature = "html")]
+    fn recently_published(&self) -> usize {
+        self.recently_published
+            .unwrap_or(Self::DEFAULT_RECENTLY_PUBLISHED)
+    }
+
+    #[cfg(feature = "html")]
+    fn include_sourcemaps(&self) -> bool {
+        self.include_sourcemaps.unwrap_or(cfg!(debug_assertions))
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum HtmlLocale {
+    #[default]
+    En,
+    De,
+    Es,
+    Fr,
+}
+
+impl HtmlLocale {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+mod config_json {
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub struct Root {
+        // This field cannot be a `url::Url` because that type
+        // percent-escapes the `{` and `}` characters. Cargo performs
+        // string-replacement on this value based on those literal `{`
+        // and `}` characters.
+        pub dl: String,
+
+        pub api: Option<String>, // Modified
+
+        /// A private registry requires all operations to be authenticated.
+        ///
+        /// This includes API requests, crate downloads and sparse
+        /// index updates.
+        pub auth_required: bool,
+    }
+}
+
+/// Information that Margo tracks about a crate that is not part of
+/// the Cargo index.
+mod metadata {
+    use semver::Version;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    use crate::common::{GeneratedBy, Timestamp};
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    pub struct Root {
+        /// Versions that have been removed from the registry.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub tombstones: BTreeMap<Version, Tombstone>,
+
+        /// How many times each version has been downloaded, as
+        /// counted from web server access logs.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub downloads: BTreeMap<Version, u64>,
+
+        /// When each version was added to the registry.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub published: BTreeMap<Version, Timestamp>,
+
+        /// Where each version's source code came from.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub sources: BTreeMap<Version, Source>,
+
+        /// The `package.description` of each version.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub descriptions: BTreeMap<Version, String>,
+
+        /// The channel each version was published to, such as
+        /// `nightly`. Versions without a channel are ordinary
+        /// releases.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub channels: BTreeMap<Version, String>,
+
+        /// Which version of Margo last wrote this file.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub generated_by: Option<GeneratedBy>,
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Source {
+        /// The repository the package was built from, such as a git
+        /// URL.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub url: Option<String>,
+
+        /// The revision (e.g. commit SHA) the package was built from.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub rev: Option<String>,
+
+        /// The package's directory within the repository, when it is
+        /// not at the root.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub path_in_vcs: Option<String>,
+
+        /// If the package contained uncommitted changes, so it may
+        /// not match `rev`.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pub dirty: bool,
+    }
+
+    impl Source {
+        pub fn is_empty(&self) -> bool {
+            self.url.is_none() && self.rev.is_none()
+        }
+
+        /// A link to the exact revision, assuming the URL is for a
+        /// web-based forge such as GitHub or GitLab.
+        #[cfg(any(test, feature = "html"))]
+        pub fn rev_url(&self) -> Option<String> {
+            let url = self.url.as_deref()?;
+            let rev = self.rev.as_deref()?;
+
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return None;
+            }
+
+            let url = url.trim_end_matches('/');
+            let url = url.strip_suffix(".git").unwrap_or(url);
+
+            Some(match &self.path_in_vcs {
+                Some(path) => format!("{url}/tree/{rev}/{path}"),
+                None => format!("{url}/commit/{rev}"),
+            })
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct Tombstone {
+        /// Why the version was removed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub reason: Option<String>,
+
+        /// When the version was removed.
+        pub removed_at: Timestamp,
+    }
+}
+
+mod index_entry {
+    use semver::{Version, VersionReq};
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+    use url::Url;
+
+    use crate::common::{CrateName, RustVersion};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Root {
+        /// The name of the package.
+        pub name: CrateName,
+
+        /// The version of the package this row is describing.
+        ///
+        /// This must be a valid version number according to the
+        /// Semantic Versioning 2.0.0 spec at https://semver.org/.
+        pub vers: Version,
+
+        /// Direct dependencies of the package.
+        pub deps: Vec<Dependency>,
+
+        /// A SHA256 checksum of the `.crate` file.
+        pub cksum: String,
+
+        /// Set of features defined for the package.
+        ///
+        /// Each feature maps to features or dependencies it enables.
+        pub features: BTreeMap<String, Vec<String>>,
+
+        /// Boolean of whether or not this version has been yanked.
+        pub yanked: bool,
+
+        /// The `links` value from the package's manifest.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub links: Option<String>,
+
+        /// The schema version of this entry.
+        //
+        /// If this not specified, it should be interpreted as the default of 1.
+        //
+        /// Cargo (starting with version 1.51) will ignore versions it does not
+        /// recognize. This provides a method to safely introduce changes to index
+        /// entries and allow older versions of cargo to ignore newer entries it
+        /// doesn't understand. Versions older than 1.51 ignore this field, and
+        /// thus may misinterpret the meaning of the index entry.
+        //
+        /// The current values are:
+        //
+        /// * 1: The schema as documented here, not including newer additions.
+        ///   This is honored in Rust version 1.51 and newer.
+        /// * 2: The addition of the `features2` field.
+        ///   This is honored in Rust version 1.60 and newer.
+        pub v: u32,
+
+        /// Features with new, extended syntax, such as namespaced
+        /// features (`dep:`) and weak dependencies (`pkg?/feat`).
+        //
+        /// This is separated from `features` because versions older than 1.19
+        /// will fail to load due to not being able to parse the new syntax, even
+        /// with a `Cargo.lock` file.
+        //
+        /// Cargo will merge any values listed here with the "features" field.
+        //
+        /// If this field is included, the "v" field should be set to at least 2.
+        //
+        /// Registries are not required to use this field for extended feature
+        /// syntax, they are allowed to include those in the "features" field.
+        /// Using this is only necessary if the registry wants to support cargo
+        /// versions older than 1.19, which in practice is only crates.io since
+        /// those older versions do not support other registries.
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub features2: BTreeMap<String, Vec<String>>,
+
+        /// The minimal supported Rust version
+        ///
+        /// This must be a valid version requirement without an operator (e.g. no `=`)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub rust_version: Option<RustVersion>,
+
+        /// Fields added by newer schema versions or other tools.
+        ///
+        /// Preserved so that rewriting the index file doesn't
+        /// silently strip them.
+        #[serde(flatten)]
+        pub unknown: BTreeMap<String, serde_json::Value>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Dependency {
+        /// Name of the dependency.
+        ///
+        /// If the dependency is renamed from the original package
+        /// name, this is the new name. The original package name is
+        /// stored in the `package` field.
+        pub name: String,
+
+        /// The SemVer requirement for this dependency.
+        ///
+        /// This must be a valid version requirement defined at
+        /// https://doc.rust-lang.org/cargo/reference/specifying-dependencies.html.
+        pub req: VersionReq,
+
+        /// Features enabled for this dependency.
+        pub features: Vec<String>,
+
+        /// Whether or not this is an optional dependency.
+        pub optional: bool,
+
+        /// Whether or not default features are enabled.
+        pub default_features: bool,
+
+        /// The target platform for the dependency.
+        ///
+        /// A string such as `cfg(windows)`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub target: Option<String>,
+
+        /// The dependency kind.
+        ///
+        /// Note: this is a required field, but a small number of entries
+        /// exist in the crates.io index with either a missing or null
+        /// `kind` field due to implementation bugs.
+        pub kind: DependencyKind,
+
+        /// The URL of the index of the registry where this dependency
+        /// is from.
+        ///
+        /// If not specified or null, it is assumed the dependency is
+        /// in the current registry.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub registry: Option<Url>,
+
+        /// If the dependency is renamed, this is the actual package
+        /// name.
+        ///
+        /// If not specified or null, this dependency is not renamed.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub package: Option<String>,
+
+        /// Fields added by newer schema versions or other tools.
+        #[serde(flatten)]
+        pub unknown: BTreeMap<String, serde_json::Value>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum DependencyKind {
+        #[allow(unused)]
+        // Stored in the index, but not actually used by Cargo
+        Dev,
+        Build,
+        Normal,
+    }
+}
+
+mod common {
+    use ascii::{AsciiChar, AsciiStr, AsciiString};
+    use semver::Version;
+    use serde::{de::Error, Deserialize, Serialize};
+    use snafu::prelude::*;
+    use std::{
+        borrow::Cow,
+        fmt, ops,
+        path::{Path, PathBuf},
+        str::FromStr,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    /// Contains only alphanumeric, `-`, or `_` characters.
+    #[derive(Debug, Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct CrateName(AsciiString);
+
+    impl CrateName {
+        pub fn as_str(&self) -> &str {
+            self.0.as_str()
+        }
+
+        pub fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        /// The prefix directories as used by the `{prefix}` marker;
+        /// the case of the crate name is preserved.
+        pub fn prefix(&self) -> String {
+            match self.len() {
+                0 => unreachable!(),
+                1 => "1".into(),
+                2 => "2".into(),
+                3 => format!("3/{}", &self[0..1]),
+                _ => format!("{}/{}", &self[0..2], &self[2..4]),
+            }
+        }
+
+        /// Appends the lowercased prefix directories, as used by the
+        /// `{lowerprefix}` marker. Cargo lowercases the entire index
+        /// path it requests, so case-sensitive hosts only work when
+        /// the files are stored at lowercase paths.
+        pub fn append_prefix_directories(&self, index_path: &mut PathBuf) {
+            let lower = self.0.to_ascii_lowercase();
+
+            match lower.len() {
+                0 => unreachable!(),
+                1 => index_path.push("1"),
+                2 => index_path.push("2"),
+                3 => {
+                    let a = &lower[0..1];
+
+                    index_path.push("3");
+                    index_path.push(a.as_str());
+                }
+                _ => {
+                    let ab = &lower[0..2];
+                    let cd = &lower[2..4];
+
+                    index_path.push(ab.as_str());
+                    index_path.push(cd.as_str());
+                }
+            };
+        }
+    }
+
+    impl fmt::Display for CrateName {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl FromStr for CrateName {
+        type Err = CrateNameError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            s.try_into()
+        }
+    }
+
+    impl TryFrom<&str> for CrateName {
+        type Error = CrateNameError;
+
+        fn try_from(value: &str) -> Result<Self, Self::Error> {
+            value.to_owned().try_into()
+        }
+    }
+
+    impl TryFrom<String> for CrateName {
+        type Error = CrateNameError;
+
+        fn try_from(value: String) -> Result<Self, Self::Error> {
+            AsciiString::from_ascii(value)
+                .map_err(|e| e.ascii_error())?
+                .try_into()
+        }
+    }
+
+    impl TryFrom<AsciiString> for CrateName {
+        type Error = CrateNameError;
+
+        fn try_from(value: AsciiString) -> Result<Self, Self::Error> {
+            use crate_name_error::*;
+
+            let first = value.first().context(EmptySnafu)?;
+            ensure!(first.is_alphabetic(), InitialAlphaSnafu);
+
+            if let Some(chr) = value.chars().find(|&chr| !valid_crate_name_char(chr)) {
+                return ContainsInvalidCharSnafu { chr }.fail();
+            }
+
+            Ok(Self(value))
+        }
+    }
+
+    #[derive(Debug, Snafu)]
+    #[snafu(module)]
+    pub enum CrateNameError {
+        #[snafu(display("The crate name cannot be empty"))]
+        Empty,
+
+        #[snafu(display("The crate name must start with an alphabetic character"))]
+        InitialAlpha,
 
         #[snafu(display("The crate name must only contain alphanumeric characters, hyphen (-) or underscore (_), not {chr}"))]
         ContainsInvalidChar { chr: char },
 
-        #[snafu(transparent)]
-        NotAscii { source: ascii::AsAsciiStrError },
-    }
+        #[snafu(transparent)]
+        NotAscii { source: ascii::AsAsciiStrError },
+    }
+
+    impl<'de> Deserialize<'de> for CrateName {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            let ascii: AsciiString = Deserialize::deserialize(deserializer)?;
+            Self::try_from(ascii).map_err(D::Error::custom)
+        }
+    }
+
+    impl ops::Index<ops::Range<usize>> for CrateName {
+        type Output = AsciiStr;
+
+        fn index(&self, index: ops::Range<usize>) -> &Self::Output {
+            self.0.index(index)
+        }
+    }
+
+    impl AsRef<Path> for CrateName {
+        fn as_ref(&self) -> &Path {
+            self.0.as_str().as_ref()
+        }
+    }
+
+    fn valid_crate_name_char(chr: AsciiChar) -> bool {
+        chr.is_alphanumeric() || chr == AsciiChar::UnderScore || chr == AsciiChar::Minus
+    }
+
+    /// A crate and one of its versions, written `name@version` as
+    /// Cargo does.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct CrateVersion {
+        pub name: CrateName,
+        pub version: Version,
+    }
+
+    impl FromStr for CrateVersion {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (name, version) = s
+                .split_once('@')
+                .ok_or_else(|| format!("expected `name@version`, not `{s}`"))?;
+            let name = name.parse::<CrateName>().map_err(|e| e.to_string())?;
+            let version = version.parse::<Version>().map_err(|e| e.to_string())?;
+            Ok(Self { name, version })
+        }
+    }
+
+    impl fmt::Display for CrateVersion {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}@{}", self.name, self.version)
+        }
+    }
+
+    /// Seconds since the Unix epoch, displayed in UTC.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    #[serde(transparent)]
+    pub struct Timestamp(u64);
+
+    impl Timestamp {
+        pub fn now() -> Self {
+            let since_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            Self(since_epoch.as_secs())
+        }
+
+        #[cfg(any(test, feature = "html"))]
+        pub fn from_secs(secs: u64) -> Self {
+            Self(secs)
+        }
+
+        pub fn as_secs(&self) -> u64 {
+            self.0
+        }
+    }
+
+    /// Which version of Margo wrote a file, and when.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct GeneratedBy {
+        pub margo_version: String,
+        #[serde(rename = "generated_timestamp_seconds")]
+        pub at: Timestamp,
+    }
+
+    impl GeneratedBy {
+        pub fn now() -> Self {
+            Self {
+                margo_version: env!("CARGO_PKG_VERSION").into(),
+                at: Timestamp::now(),
+            }
+        }
+    }
+
+    impl fmt::Display for GeneratedBy {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Margo {} at {}", self.margo_version, self.at)
+        }
+    }
+
+    impl fmt::Display for Timestamp {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            // Converts days since the epoch to a civil date. See
+            // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+            let secs = self.0;
+            let days = (secs / 86_400) as i64;
+            let secs_of_day = secs % 86_400;
+
+            let z = days + 719_468;
+            let era = z.div_euclid(146_097);
+            let doe = z.rem_euclid(146_097);
+            let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+            let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+            let mp = (5 * doy + 2) / 153;
+            let day = doy - (153 * mp + 2) / 5 + 1;
+            let month = if mp < 10 { mp + 3 } else { mp - 9 };
+            let year = yoe + era * 400 + i64::from(month <= 2);
+
+            let hour = secs_of_day / 3600;
+            let minute = secs_of_day % 3600 / 60;
+            let second = secs_of_day % 60;
+
+            write!(
+                f,
+                "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z"
+            )
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct RustVersion(Version);
+
+    impl FromStr for RustVersion {
+        type Err = RustVersionError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            use rust_version_error::*;
+
+            let v: Version = match s.parse() {
+                Ok(v) => v,
+                Err(e) => {
+                    let version = [s, ".0"].concat();
+                    match version.parse() {
+                        Ok(v) => v,
+                        Err(_) => return Err(e)?,
+                    }
+                }
+            };
+
+            ensure!(v.pre.is_empty(), PrereleaseSnafu);
+            ensure!(v.build.is_empty(), BuildSnafu);
+
+            Ok(Self(v))
+        }
+    }
+
+    #[derive(Debug, Snafu)]
+    #[snafu(module)]
+    pub enum RustVersionError {
+        #[snafu(transparent)]
+        Semver { source: semver::Error },
+
+        #[snafu(display("May not specify a prerelease version"))]
+        Prerelease,
+
+        #[snafu(display("May not specify a version with build metadata"))]
+        Build,
+    }
+
+    impl From<RustVersion> for Version {
+        fn from(value: RustVersion) -> Self {
+            value.0
+        }
+    }
+
+    impl fmt::Display for RustVersion {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl<'de> serde::Deserialize<'de> for RustVersion {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            let version = Cow::<str>::deserialize(deserializer)?;
+            version.parse().map_err(D::Error::custom)
+        }
+    }
+
+    impl serde::Serialize for RustVersion {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use registry_conformance::{Crate, ScratchSpace};
+
+    fn default_config() -> ConfigV1 {
+        ConfigV1 {
+            base_url: "http://example.com".parse().unwrap(),
+            auth_required: false,
+            html: ConfigV1Html {
+                enabled: false,
+                suggested_registry_name: None,
+                locale: Default::default(),
+                strings: None,
+                page_size: None,
+                recently_published: None,
+                analytics_snippet: None,
+                include_sourcemaps: None,
+            },
+            version_floors: Default::default(),
+            crate_path: Default::default(),
+            dependency_registries: Default::default(),
+            forbid_external_dependencies: false,
+            allowed_dependency_registries: Default::default(),
+            package_limits: Default::default(),
+            allow_names: Default::default(),
+            public_name_check: Default::default(),
+            public_index: None,
+            content_addressed_crates: false,
+            host: None,
+            content_types: Default::default(),
+            precompressed: Default::default(),
+            manifest: Default::default(),
+            durability: Default::default(),
+            trash: Default::default(),
+        }
+    }
+
+    /// The path of a crate file that has been added to the registry.
+    fn added_crate_file_path(r: &Registry, name: &CrateName, version: &Version) -> PathBuf {
+        let index = Registry::parse_index_file(&r.index_file_path_for(name)).unwrap();
+        r.crate_file_path_for(name, version, &index[version].cksum)
+    }
+
+    /// Writes an index entry directly, for versions whose
+    /// dependencies can't be expressed by a packaged crate.
+    fn insert_entry(r: &Registry, json: &str) {
+        let entry = serde_json::from_str::<index_entry::Root>(json).unwrap();
+        let name = entry.name.clone();
+        let path = r.index_file_path_for(&name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        r.read_modify_write(&name, |index| {
+            index.insert(entry.vers.clone(), entry);
+            Ok::<_, ReadModifyWriteError>(())
+        })
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn adding_duplicate_crate() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let config = default_config();
+
+        let r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        let c = Crate::new("duplicated", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+
+        r.add(&global, &p).unwrap();
+        r.add(&global, &p).unwrap();
+
+        let name = CrateName::try_from(c.name()).unwrap();
+        let index_file_path = r.index_file_path_for(&name);
+        let index_contents = fs::read_to_string(index_file_path).unwrap();
+
+        assert_eq!(1, index_contents.lines().count());
+
+        // As if different contents had been published as this version
+        r.read_modify_write(&name, |index| {
+            index.values_mut().for_each(|e| e.cksum = "0".repeat(64));
+            Ok::<_, ReadModifyWriteError>(())
+        })
+        .unwrap();
+        let e = r.add(&global, &p).unwrap_err();
+        assert!(matches!(e, AddError::VersionExists { .. }), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn versions_below_the_floor_are_rejected() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let config = ConfigV1 {
+            version_floors: [("floored".parse().unwrap(), "2.0.0".parse().unwrap())].into(),
+            ..default_config()
+        };
+        let r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        let old = Crate::new("floored", "1.9.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let old = old.package().await.unwrap();
+
+        let e = r.add(&global, &old).unwrap_err();
+        assert!(matches!(e, AddError::BelowVersionFloor { .. }), "{e:?}");
+
+        let new = Crate::new("floored", "2.0.0")
+            .lib_rs(r#"pub const ID: u8 = 2;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let new = new.package().await.unwrap();
+
+        r.add(&global, &new).unwrap();
+    }
+
+    #[test]
+    fn error_kinds_come_from_the_underlying_cause() {
+        let missing = Error::from(DiscoverRegistryError::Open {
+            source: OpenError::Read {
+                source: io::ErrorKind::NotFound.into(),
+                path: "margo-config.toml".into(),
+            },
+        });
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+
+        let denied = Error::from(DiscoverRegistryError::Open {
+            source: OpenError::Read {
+                source: io::ErrorKind::PermissionDenied.into(),
+                path: "margo-config.toml".into(),
+            },
+        });
+        assert_eq!(denied.kind(), ErrorKind::Io);
+
+        let conflict = Error::from(AddError::Dirty {
+            name: "dirty".parse().unwrap(),
+            version: Version::new(1, 0, 0),
+        });
+        assert_eq!(conflict.kind(), ErrorKind::Conflict);
+    }
+
+    #[cfg(feature = "html")]
+    #[tokio::test]
+    async fn landing_page_links_to_each_registry() {
+        let scratch = ScratchSpace::new().await.unwrap();
+        let root = scratch.registry();
+
+        for name in ["team-b", "team-a"] {
+            Registry::initialize(default_config(), root.join(name)).unwrap();
+        }
+        fs::create_dir_all(root.join("not-a-registry")).unwrap();
+
+        let registries = open_namespaces(&root).unwrap();
+        let names = registries
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["team-a", "team-b"]);
+
+        generate_landing_page(&root, &registries).unwrap();
+
+        let page = fs::read_to_string(root.join("index.html")).unwrap();
+        assert!(page.contains(r#"href="team-a/""#), "{page}");
+        assert!(page.contains(r#"href="team-b/""#), "{page}");
+        assert!(!page.contains("not-a-registry"), "{page}");
+    }
+
+    #[cfg(feature = "html")]
+    #[tokio::test]
+    async fn html_generation_waits_for_changes_to_finish() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let mut packages = Vec::new();
+        for version in ["1.0.0", "1.1.0", "1.2.0", "1.3.0", "1.4.0", "1.5.0"] {
+            let c = Crate::new("contended", version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            packages.push(c.package().await.unwrap());
+        }
+
+        let index_html = r.path.join("index.html");
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for p in &packages {
+                    let lock = lock::exclusive(&r).unwrap();
+                    r.add(&global, p).unwrap();
+                    drop(lock);
+                }
+                done.store(true, Ordering::SeqCst);
+            });
+
+            s.spawn(|| {
+                while !done.load(Ordering::SeqCst) {
+                    r.generate_html().unwrap();
+                }
+            });
+
+            s.spawn(|| {
+                while !done.load(Ordering::SeqCst) {
+                    if let Ok(page) = fs::read_to_string(&index_html) {
+                        assert!(page.trim_end().ends_with("</html>"), "{page}");
+                    }
+                }
+            });
+        });
+
+        r.generate_html().unwrap();
+        let page = fs::read_to_string(r.path.join("crate/contended/index.html")).unwrap();
+        assert!(page.contains("1.5.0"), "{page}");
+    }
+
+    #[tokio::test]
+    async fn verifying_finds_damaged_crate_files() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let c = Crate::new("verified", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+
+        r.add(&global, &p).unwrap();
+
+        assert!(r.verify(true).unwrap().is_empty());
+
+        let name = CrateName::try_from(c.name()).unwrap();
+        let crate_path = added_crate_file_path(&r, &name, &Version::new(1, 0, 0));
+        let mut data = fs::read(&crate_path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        fs::write(&crate_path, data).unwrap();
+
+        let problems = r.verify(false).unwrap();
+        assert_eq!(problems.len(), 1, "{problems:?}");
+        assert!(problems[0].contains("checksum"), "{problems:?}");
+
+        fs::remove_file(&crate_path).unwrap();
+
+        let problems = r.verify(false).unwrap();
+        assert!(problems[0].contains("missing"), "{problems:?}");
+
+        assert_eq!(r.last_verification().unwrap(), None);
+        let verification = Verification {
+            at: Timestamp::now(),
+            deep: false,
+            problems: problems.len(),
+        };
+        r.record_verification(&verification).unwrap();
+        assert_eq!(r.last_verification().unwrap(), Some(verification));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn content_addressed_crates_share_data() {
+        use std::os::unix::fs::MetadataExt;
+
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let config = ConfigV1 {
+            content_addressed_crates: true,
+            ..default_config()
+        };
+        let r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        let c = Crate::new("addressed", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+
+        r.add(&global, &p).unwrap();
+
+        let name = CrateName::try_from(c.name()).unwrap();
+        let version = Version::new(1, 0, 0);
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        let blob = r.blob_path_for(&index[&version].cksum);
+
+        let crate_path = added_crate_file_path(&r, &name, &version);
+        assert_eq!(fs::metadata(&crate_path).unwrap().nlink(), 2);
+        assert_eq!(fs::read(&crate_path).unwrap(), fs::read(&blob).unwrap());
+
+        r.remove(name, version, None, Disposal::Delete).unwrap();
+
+        assert!(!crate_path.exists());
+        assert!(!blob.exists());
+    }
+
+    #[tokio::test]
+    async fn base_url_requires_trailing_slash() {
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let config = ConfigV1 {
+            base_url: "http://example.com/path/to/index".parse().unwrap(),
+            ..default_config()
+        };
+
+        let r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        let paths = [r.config_json_path(), r.margo_config_toml_path()];
+
+        for path in paths {
+            let contents = fs::read_to_string(&path).unwrap();
+
+            assert!(
+                contents.contains("/path/to/index/"),
+                "{path} does not have the trailing slash:\n{contents}",
+                path = path.display(),
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_base_url_slashes_are_repaired() {
+        let scratch = ScratchSpace::new().await.unwrap();
+        let path = scratch.registry();
+
+        let r = Registry::initialize(default_config(), &path).unwrap();
+
+        // As written by older versions of Margo
+        let config_toml = fs::read_to_string(r.margo_config_toml_path()).unwrap();
+        let config_toml = config_toml.replace("http://example.com/", "http://example.com/registry");
+        fs::write(r.margo_config_toml_path(), config_toml).unwrap();
+        fs::write(
+            r.config_json_path(),
+            r#"{"dl":"http://example.com/registrycrates/{lowerprefix}/{crate}/{version}.crate","api":null}"#,
+        )
+        .unwrap();
+
+        let r = Registry::open(&path).unwrap();
+        assert_eq!(r.config.base_url.as_str(), "http://example.com/registry/");
+        assert_eq!(r.verify(false).unwrap().len(), 1);
+
+        let r = Registry::initialize(r.config, &path).unwrap();
+        assert!(r.verify(false).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn removing_all_yanked_versions() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let name = "yanked-away";
+        for version in ["1.0.0", "1.1.0"] {
+            let c = Crate::new(name, version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            r.add(&global, c.package().await.unwrap()).unwrap();
+        }
+
+        let name: CrateName = name.parse().unwrap();
+        let yanked: Version = "1.0.0".parse().unwrap();
+        let kept: Version = "1.1.0".parse().unwrap();
+        r.yank(name.clone(), &yanked.clone().into(), true).unwrap();
+
+        let yanked_path = added_crate_file_path(&r, &name, &yanked);
+        let kept_path = added_crate_file_path(&r, &name, &kept);
+
+        r.remove_all_yanked(None, Disposal::Delete, true).unwrap();
+        assert!(yanked_path.exists());
+
+        r.remove_all_yanked(None, Disposal::Delete, false).unwrap();
+        assert!(!yanked_path.exists());
+        assert!(kept_path.exists());
+
+        r.remove_crate(name.clone(), None, Disposal::Delete, false)
+            .unwrap();
+        assert!(!r.crate_dir_for(&name).exists());
+        assert!(!r.index_file_path_for(&name).exists());
+    }
+
+    #[tokio::test]
+    async fn init_refuses_to_overwrite_unless_reconfiguring() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let args = |reconfigure, base_url: Option<&str>| InitArgs {
+            base_url: base_url.map(|u| u.parse().unwrap()),
+            defaults: true,
+            non_interactive: false,
+            auth_required: None,
+            html: Some(false),
+            html_suggested_registry_name: None,
+            crate_path: None,
+            reconfigure,
+            host: None,
+            path: scratch.registry(),
+        };
+
+        do_init(&global, args(false, Some("http://example.com/first/"))).unwrap();
+
+        let e = do_init(&global, args(false, Some("http://example.com/second/"))).unwrap_err();
+        assert!(
+            matches!(e, DoInitializeError::AlreadyInitialized { .. }),
+            "{e:?}"
+        );
+
+        do_init(&global, args(true, None)).unwrap();
+        let r = Registry::open(scratch.registry()).unwrap();
+        assert_eq!(r.config.base_url.as_str(), "http://example.com/first/");
+
+        do_init(&global, args(true, Some("http://example.com/second/"))).unwrap();
+        let r = Registry::open(scratch.registry()).unwrap();
+        assert_eq!(r.config.base_url.as_str(), "http://example.com/second/");
+    }
+
+    #[tokio::test]
+    async fn precompressed_copies_follow_the_index() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let mut config = default_config();
+        config.precompressed = vec![precompress::Encoding::Gzip, precompress::Encoding::Brotli];
+        let mut r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        let c = Crate::new("squeezed", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+        r.add(&global, &p).unwrap();
+        r.maybe_precompress().unwrap();
+
+        let name: CrateName = "squeezed".parse().unwrap();
+        let index_path = r.index_file_path_for(&name);
+        let gz_path = precompress_path(&index_path, "gz");
+        let br_path = precompress_path(&index_path, "br");
+
+        let mut unzipped = String::new();
+        flate2::read::GzDecoder::new(File::open(&gz_path).unwrap())
+            .read_to_string(&mut unzipped)
+            .unwrap();
+        assert_eq!(unzipped, fs::read_to_string(&index_path).unwrap());
+        assert!(br_path.exists());
+        assert!(precompress_path(&r.config_json_path(), "gz").exists());
+
+        r.config.precompressed = vec![precompress::Encoding::Gzip];
+        r.remove_crate(name, None, Disposal::Delete, false).unwrap();
+        r.maybe_precompress().unwrap();
+
+        assert!(!gz_path.exists());
+        assert!(!br_path.exists());
+        assert!(precompress_path(&r.config_json_path(), "gz").exists());
+        assert!(!precompress_path(&r.config_json_path(), "br").exists());
+    }
+
+    #[tokio::test]
+    async fn manifest_changes_with_the_crates() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let mut config = default_config();
+        config.manifest.enabled = true;
+        let r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        let c = Crate::new("summarized", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+        r.add(&global, &p).unwrap();
+        r.maybe_write_manifest().unwrap();
+
+        let path = r.path.join(manifest::MANIFEST_FILE_NAME);
+        let read = || serde_json::from_slice::<manifest::Manifest>(&fs::read(&path).unwrap());
+        let first = read().unwrap();
+        let name: CrateName = "summarized".parse().unwrap();
+        assert_eq!(first.serial, 1);
+        assert_eq!(first.crates[&name].latest, Some(Version::new(1, 0, 0)));
+
+        r.maybe_write_manifest().unwrap();
+        assert_eq!(read().unwrap(), first);
+
+        r.yank(name.clone(), &Version::new(1, 0, 0).into(), true)
+            .unwrap();
+        r.maybe_write_manifest().unwrap();
+
+        let yanked = read().unwrap();
+        assert_eq!(yanked.serial, 2);
+        assert_ne!(yanked.checksum, first.checksum);
+        assert_eq!(yanked.crates[&name].latest, None);
+    }
+
+    #[tokio::test]
+    async fn merkle_proofs_lead_to_the_manifest_root() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let mut config = default_config();
+        config.manifest.enabled = true;
+        config.manifest.merkle_tree = true;
+        let r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        for (name, version) in [("rooted", "1.0.0"), ("rooted", "1.1.0"), ("leafy", "0.1.0")] {
+            let c = Crate::new(name, version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            let p = c.package().await.unwrap();
+            r.add(&global, &p).unwrap();
+        }
+        r.maybe_write_manifest().unwrap();
+
+        let manifest = fs::read(r.path.join(manifest::MANIFEST_FILE_NAME)).unwrap();
+        let manifest = serde_json::from_slice::<manifest::Manifest>(&manifest).unwrap();
+        let root = manifest.merkle_root.unwrap();
+
+        let name: CrateName = "rooted".parse().unwrap();
+        let proof = merkle::proof(&r, &name, &Version::new(1, 0, 0)).unwrap();
+        assert_eq!(proof.root().unwrap(), root);
+
+        let mut forged = proof.clone();
+        forged.version = Version::new(1, 1, 0);
+        assert_ne!(forged.root().unwrap(), root);
+
+        assert_eq!(merkle::verify(&r).unwrap(), Vec::<String>::new());
+
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        let entry = &index[&Version::new(1, 1, 0)];
+        fs::remove_file(r.crate_file_path_for(&name, &entry.vers, &entry.cksum)).unwrap();
+        assert_eq!(merkle::verify(&r).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn full_durability_replaces_index_files() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let mut config = default_config();
+        config.durability = Durability::Full;
+        config.content_addressed_crates = true;
+        let r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        let r = Registry::open(&r.path).unwrap();
+        assert_eq!(r.config.durability, Durability::Full);
+
+        for version in ["1.0.0", "1.1.0"] {
+            let c = Crate::new("durable", version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            let p = c.package().await.unwrap();
+            r.add(&global, &p).unwrap();
+        }
+
+        let name: CrateName = "durable".parse().unwrap();
+        let index_path = r.index_file_path_for(&name);
+        let index = Registry::parse_index_file(&index_path).unwrap();
+        assert_eq!(index.len(), 2);
+        for (version, entry) in &index {
+            assert!(r
+                .crate_file_path_for(&name, version, &entry.cksum)
+                .is_file());
+        }
+
+        let leftovers = fs::read_dir(index_path.parent().unwrap())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .filter(|n| n != "durable")
+            .collect::<Vec<_>>();
+        assert!(leftovers.is_empty(), "{leftovers:?}");
+    }
+
+    #[tokio::test]
+    async fn removed_versions_can_be_restored_from_the_trash() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let c = Crate::new("trashed", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+        r.add(&global, &p).unwrap();
+
+        let name: CrateName = "trashed".parse().unwrap();
+        let version = Version::new(1, 0, 0);
+        let crate_file = added_crate_file_path(&r, &name, &version);
+
+        let tombstone = metadata::Tombstone {
+            reason: None,
+            removed_at: Timestamp::now(),
+        };
+        r.remove(
+            name.clone(),
+            version.clone(),
+            Some(&tombstone),
+            Disposal::Trash,
+        )
+        .unwrap();
+        assert!(!crate_file.exists());
+
+        let items = trash::list(&r).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(r.path.join(&items[0].path), crate_file);
+
+        let restored = trash::restore(&r, &items[0].id).unwrap();
+        assert_eq!(restored.version, Some((name.clone(), version.clone())));
+        assert!(crate_file.is_file());
+        assert!(r.verify(true).unwrap().is_empty());
+        assert!(r.read_metadata(&name).unwrap().tombstones.is_empty());
+        assert!(trash::list(&r).unwrap().is_empty());
+
+        let e = trash::restore(&r, &items[0].id).unwrap_err();
+        assert!(matches!(e, trash::Error::ItemNotFound { .. }), "{e:?}");
+        let e = trash::restore(&r, "../trashed").unwrap_err();
+        assert!(matches!(e, trash::Error::ItemNotFound { .. }), "{e:?}");
+
+        r.remove(name, version, None, Disposal::Delete).unwrap();
+        assert!(!crate_file.exists());
+        assert!(trash::list(&r).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn plans_are_applied_together_or_not_at_all() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let mut packages = Vec::new();
+        for (name, version) in [
+            ("planned", "1.0.0"),
+            ("planned", "1.1.0"),
+            ("extra", "1.0.0"),
+        ] {
+            let c = Crate::new(name, version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            packages.push(c.package().await.unwrap());
+        }
+        r.add(&global, &packages[0]).unwrap();
+
+        let name: CrateName = "planned".parse().unwrap();
+        let package = |i: usize| fs::canonicalize(&packages[i]).unwrap();
+        let dir = r.path.parent().unwrap();
+        let write_tasks = |file_name: &str, operations: serde_json::Value| {
+            let path = dir.join(file_name);
+            let tasks = serde_json::json!({ "operations": operations });
+            fs::write(&path, tasks.to_string()).unwrap();
+            path
+        };
+
+        let tasks = write_tasks(
+            "tasks.json",
+            serde_json::json!([
+                { "add": package(1) },
+                { "yank": { "crate": "PLANNED", "version": "1.0.0" } },
+            ]),
+        );
+
+        let p = plan::plan(&global, &r, &tasks).unwrap();
+        let steps = p.steps.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(steps.len(), 2);
+        assert!(steps[0].starts_with("add `planned` version 1.1.0 from "));
+        assert_eq!(steps[1], "yank `planned` version 1.0.0");
+
+        // Planning changes nothing
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        assert_eq!(index.len(), 1);
+
+        plan::apply(&global, &r, &p).unwrap();
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        assert_eq!(index.len(), 2);
+        assert!(index[&Version::new(1, 0, 0)].yanked);
+
+        let e = plan::apply(&global, &r, &p).unwrap_err();
+        assert!(matches!(e, plan::Error::Stale), "{e:?}");
+
+        let e = plan::plan(&global, &r, &tasks).unwrap_err();
+        assert!(matches!(e, plan::Error::AlreadyYanked { .. }), "{e:?}");
+
+        // A step that fails undoes the steps before it
+        let tasks = write_tasks(
+            "failing.json",
+            serde_json::json!([
+                { "add": package(2) },
+                { "remove": { "crate": "planned", "version": "1.1.0" } },
+            ]),
+        );
+        let mut p = plan::plan(&global, &r, &tasks).unwrap();
+        p.steps.push(plan::Step::Yank(plan::Target {
+            name: name.clone(),
+            version: Version::new(9, 9, 9),
+        }));
+
+        let e = plan::apply(&global, &r, &p).unwrap_err();
+        assert!(matches!(e, plan::Error::Step { number: 3, .. }), "{e:?}");
+
+        assert_eq!(manifest::checksum(&r.list_all().unwrap()), p.base);
+        assert!(added_crate_file_path(&r, &name, &Version::new(1, 1, 0)).is_file());
+        assert!(!r.index_file_path_for(&"extra".parse().unwrap()).exists());
+        assert!(trash::list(&r).unwrap().is_empty());
+        assert!(r.verify(true).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reconcile_matches_the_state_file() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let root = scratch.registry();
+        let upstream = Registry::initialize(default_config(), root.join("upstream")).unwrap();
+        let r = Registry::initialize(default_config(), root.join("managed")).unwrap();
+
+        for (name, version) in [
+            ("declared", "1.0.0"),
+            ("declared", "1.1.0"),
+            ("stray", "1.0.0"),
+        ] {
+            let c = Crate::new(name, version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            let p = c.package().await.unwrap();
+            if version == "1.0.0" {
+                r.add(&global, &p).unwrap();
+            }
+            if name == "declared" {
+                upstream.add(&global, &p).unwrap();
+            }
+        }
+
+        let state = root.join("state.toml");
+        fs::write(
+            &state,
+            r#"
+                sources = ["upstream"]
+                prune = true
+
+                [crates.declared]
+                "1.0.0" = { yanked = true }
+                "1.1.0" = {}
+            "#,
+        )
+        .unwrap();
+
+        let diff = reconcile::diff(&global, &r, &state).unwrap();
+        assert_eq!(
+            diff.describe(),
+            [
+                "add `declared` version 1.1.0 from upstream",
+                "yank `declared` version 1.0.0",
+                "remove `stray` version 1.0.0",
+            ],
+        );
+
+        reconcile::apply(&global, &r, &diff).unwrap();
+        let crates = r.list_all().unwrap();
+        let names = crates.keys().map(CrateName::as_str).collect::<Vec<_>>();
+        assert_eq!(names, ["declared"]);
+        let index = &crates[&"declared".parse::<CrateName>().unwrap()];
+        assert_eq!(index.len(), 2);
+        assert!(index[&Version::new(1, 0, 0)].yanked);
+        assert!(!r.path.join(".margo-reconcile").exists());
+        assert!(r.verify(true).unwrap().is_empty());
+
+        assert!(reconcile::diff(&global, &r, &state).unwrap().is_empty());
+
+        fs::write(&state, "[crates.declared]\n\"2.0.0\" = {}\n").unwrap();
+        let e = reconcile::diff(&global, &r, &state).unwrap_err();
+        assert!(matches!(e, reconcile::Error::Missing { .. }), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn closure_follows_requirements_through_the_registry() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let dep = |name: &str, req: &str, registry: Option<&str>| {
+            let registry = registry.map_or(String::new(), |r| format!(r#", "registry": "{r}""#));
+            format!(
+                r#"{{"name": "{name}", "req": "{req}", "features": [], "optional": false, "default_features": true, "kind": "normal"{registry}}}"#
+            )
+        };
+        let dev_dep = |name: &str, req: &str| {
+            dep(name, req, None).replace(r#""kind": "normal""#, r#""kind": "dev""#)
+        };
+        let entry = |name: &str, vers: &str, yanked: bool, deps: &[String]| {
+            let deps = deps.join(", ");
+            insert_entry(
+                &r,
+                &format!(
+                    r#"{{"name": "{name}", "vers": "{vers}", "deps": [{deps}], "cksum": "", "features": {{}}, "yanked": {yanked}, "v": 2}}"#
+                ),
+            );
+        };
+
+        entry(
+            "app",
+            "1.0.0",
+            false,
+            &[
+                dep("core", "^1", None),
+                dep("retired", "^1", None),
+                dep("absent", "^1", None),
+                dep("serde", "^1", Some(CRATES_IO_INDEX_URL)),
+                dev_dep("core", "^2"),
+            ],
+        );
+        entry("core", "1.0.0", false, &[dep("leaf", "^0.2", None)]);
+        entry(
+            "core",
+            "1.4.0",
+            false,
+            &[dep("leaf", "^0.3", None), dev_dep("harness", "^1")],
+        );
+        entry("core", "2.0.0", false, &[]);
+        entry("retired", "1.0.0", true, &[]);
+        entry("leaf", "0.3.1", false, &[dep("core", "^1", None)]);
+
+        let root = "app@1.0.0".parse().unwrap();
+        let requirements = closure::walk(&global, &r, &root).unwrap();
+        let statuses = requirements
+            .iter()
+            .map(|req| {
+                let from = format!("{}@{}", req.dependent, req.version);
+                (from, req.dependency.as_str(), req.resolution.to_string())
+            })
+            .collect::<Vec<_>>();
+        let expected = [
+            ("app@1.0.0", "core", "1.4.0"),
+            ("app@1.0.0", "retired", "only yanked 1.0.0"),
+            ("app@1.0.0", "absent", "missing"),
+            ("app@1.0.0", "serde", "crates.io"),
+            ("core@1.4.0", "leaf", "0.3.1"),
+            ("leaf@0.3.1", "core", "1.4.0"),
+            ("app@1.0.0", "core", "2.0.0"),
+        ]
+        .map(|(from, dep, status)| (from.to_owned(), dep, status.to_owned()));
+        assert_eq!(statuses, expected);
+
+        let e = closure::ensure_complete(&requirements, false).unwrap_err();
+        assert!(
+            matches!(e, closure::Error::Incomplete { unmet: 2 }),
+            "{e:?}"
+        );
+
+        let leaf = "leaf@0.3.1".parse().unwrap();
+        let requirements = closure::walk(&global, &r, &leaf).unwrap();
+        closure::ensure_complete(&requirements, true).unwrap();
+
+        let e = closure::walk(&global, &r, &"leaf@9.0.0".parse().unwrap()).unwrap_err();
+        assert!(matches!(e, closure::Error::VersionNotFound { .. }), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn impact_lists_requirements_only_the_version_satisfies() {
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let dep = |req: &str, registry: Option<&str>| {
+            let registry = registry.map_or(String::new(), |r| format!(r#", "registry": "{r}""#));
+            format!(
+                r#"{{"name": "core", "req": "{req}", "features": [], "optional": false, "default_features": true, "kind": "normal"{registry}}}"#
+            )
+        };
+        let entry = |name: &str, vers: &str, yanked: bool, deps: &[String]| {
+            let deps = deps.join(", ");
+            insert_entry(
+                &r,
+                &format!(
+                    r#"{{"name": "{name}", "vers": "{vers}", "deps": [{deps}], "cksum": "", "features": {{}}, "yanked": {yanked}, "v": 2}}"#
+                ),
+            );
+        };
+
+        entry("core", "1.2.0", false, &[]);
+        entry("core", "1.2.3", false, &[]);
+        entry("core", "1.3.0", true, &[]);
+        entry("pinned", "1.0.0", false, &[dep("=1.2.3", None)]);
+        entry("narrow", "1.0.0", true, &[dep(">=1.2.3, <1.3", None)]);
+        entry("loose", "1.0.0", false, &[dep("^1.2", None)]);
+        entry("older", "1.0.0", false, &[dep("=1.2.0", None)]);
+        entry(
+            "upstream",
+            "1.0.0",
+            false,
+            &[dep("=1.2.3", Some(CRATES_IO_INDEX_URL))],
+        );
+
+        let target = "core@1.2.3".parse().unwrap();
+        let dependents = impact::dependents(&r, &target).unwrap();
+        let found = dependents
+            .iter()
+            .map(|d| (d.name.as_str(), d.req.to_string(), d.yanked))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("narrow", ">=1.2.3, <1.3".to_owned(), true),
+                ("pinned", "=1.2.3".to_owned(), false),
+            ],
+        );
+
+        let e = impact::ensure_unused(&dependents, &target).unwrap_err();
+        assert!(
+            matches!(e, impact::Error::Impacted { count: 2, .. }),
+            "{e:?}"
+        );
+
+        let target = "core@1.3.0".parse().unwrap();
+        let dependents = impact::dependents(&r, &target).unwrap();
+        impact::ensure_unused(&dependents, &target).unwrap();
+
+        let e = impact::dependents(&r, &"core@9.0.0".parse().unwrap()).unwrap_err();
+        assert!(matches!(e, impact::Error::VersionNotFound { .. }), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn compare_reports_what_changed_between_versions() {
+        use compare::Change;
+
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        for (version, lib_rs) in [
+            ("1.2.0", "pub const ID: u8 = 1;"),
+            ("1.3.0", "pub const ID: u8 = 2;"),
+        ] {
+            let p = Crate::new("compared", version)
+                .lib_rs(lib_rs)
+                .create_in(&scratch)
+                .await
+                .unwrap()
+                .package()
+                .await
+                .unwrap();
+            r.add(&global, &p).unwrap();
+        }
+
+        let name = "compared".parse::<CrateName>().unwrap();
+        let (old, new) = (Version::new(1, 2, 0), Version::new(1, 3, 0));
+
+        let dep = |name: &str, req: &str| {
+            format!(
+                r#"{{"name": "{name}", "req": "{req}", "features": [], "optional": false, "default_features": true, "kind": "normal"}}"#
+            )
+        };
+        let edit = |version: &Version, deps: &[String], features: &str, rust_version: &str| {
+            let deps = serde_json::from_str(&format!("[{}]", deps.join(", "))).unwrap();
+            let features = serde_json::from_str(features).unwrap();
+            r.read_modify_write(&name, |index| {
+                let entry = index.get_mut(version).unwrap();
+                entry.deps = deps;
+                entry.features = features;
+                entry.rust_version = Some(rust_version.parse().unwrap());
+                Ok::<_, ReadModifyWriteError>(())
+            })
+            .unwrap();
+        };
+        edit(
+            &old,
+            &[dep("kept", "^1"), dep("bumped", "^1"), dep("dropped", "^1")],
+            r#"{"std": [], "alloc": []}"#,
+            "1.60",
+        );
+        edit(
+            &new,
+            &[dep("kept", "^1"), dep("bumped", "^2"), dep("fresh", "^0.1")],
+            r#"{"std": ["alloc"], "alloc": []}"#,
+            "1.70",
+        );
+
+        let comparison = compare::compare(&r, &name, &old, &new, true).unwrap();
+
+        let dependencies = comparison
+            .dependencies
+            .iter()
+            .map(|d| {
+                let change = match &d.change {
+                    Change::Added { new } => format!("+{new}"),
+                    Change::Removed { old } => format!("-{old}"),
+                    Change::Changed { old, new } => format!("{old} -> {new}"),
+                };
+                (d.name.as_str(), change)
+            })
+            .collect::<Vec<_>>();
+        let expected = [
+            ("bumped", "^1 -> ^2"),
+            ("dropped", "-^1"),
+            ("fresh", "+^0.1"),
+        ]
+        .map(|(name, change)| (name, change.to_owned()));
+        assert_eq!(dependencies, expected);
+
+        let features = comparison
+            .features
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(features, ["std"]);
+
+        assert_eq!(
+            comparison.rust_version,
+            Some(Change::Changed {
+                old: "1.60.0".to_owned(),
+                new: "1.70.0".to_owned(),
+            }),
+        );
+
+        let files = comparison.files.unwrap();
+        let lib_rs = files.iter().find(|f| f.path == "src/lib.rs").unwrap();
+        assert!(
+            matches!(lib_rs.change, Change::Changed { .. }),
+            "{lib_rs:?}"
+        );
+
+        let same = compare::compare(&r, &name, &new, &new, false).unwrap();
+        assert!(same.is_empty());
+
+        let e = compare::compare(&r, &name, &old, &Version::new(9, 0, 0), false).unwrap_err();
+        assert!(matches!(e, compare::Error::VersionNotFound { .. }), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn gc_collects_orphans_in_both_directions() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let mut config = default_config();
+        config.content_addressed_crates = true;
+        let r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        for version in ["1.0.0", "1.1.0", "1.2.0"] {
+            let c = Crate::new("stranded", version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            let p = c.package().await.unwrap();
+            r.add(&global, &p).unwrap();
+        }
+
+        let name: CrateName = "stranded".parse().unwrap();
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        let crate_file = |v: &str| {
+            let entry = &index[&v.parse::<Version>().unwrap()];
+            r.crate_file_path_for(&name, &entry.vers, &entry.cksum)
+        };
+
+        // The data is still stored, so the crate file can be restored
+        fs::remove_file(crate_file("1.0.0")).unwrap();
+
+        let lost = &index[&Version::new(1, 1, 0)];
+        fs::remove_file(crate_file("1.1.0")).unwrap();
+        fs::remove_file(r.blob_path_for(&lost.cksum)).unwrap();
+
+        let stray = r.crate_dir().join("stray.crate");
+        fs::write(&stray, "stray").unwrap();
+
+        let orphans = gc::find(&r).unwrap();
+        assert_eq!(orphans.files, std::slice::from_ref(&stray));
+        let entries = orphans
+            .entries
+            .iter()
+            .map(|e| (e.version.to_string(), e.restorable))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, [("1.0.0".into(), true), ("1.1.0".into(), false)]);
+
+        gc::collect(&r, &orphans, Disposal::Delete).unwrap();
+
+        assert!(!stray.exists());
+        assert!(crate_file("1.0.0").exists());
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        assert_eq!(
+            index.keys().map(ToString::to_string).collect::<Vec<_>>(),
+            ["1.0.0", "1.2.0"],
+        );
+        assert!(gc::find(&r).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rebuilt_index_keeps_yanked_versions() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        for version in ["1.0.0", "1.1.0", "1.2.0"] {
+            let c = Crate::new("mended", version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            let p = c.package().await.unwrap();
+            r.add(&global, &p).unwrap();
+        }
+
+        let name: CrateName = "mended".parse().unwrap();
+        r.yank(name.clone(), &Version::new(1, 0, 0).into(), true)
+            .unwrap();
+
+        let index_path = r.index_file_path_for(&name);
+        let original = fs::read_to_string(&index_path).unwrap();
+        let index = Registry::parse_index_file(&index_path).unwrap();
+        let removed = &index[&Version::new(1, 2, 0)];
+        fs::remove_file(r.crate_file_path_for(&name, &removed.vers, &removed.cksum)).unwrap();
+
+        // A merge conflict that lost the line for 1.1.0
+        let mut lines = original.lines();
+        let damaged = format!(
+            "<<<<<<< HEAD\n{}\n=======\n{}\n>>>>>>> branch\n",
+            lines.next().unwrap(),
+            lines.nth(1).unwrap(),
+        );
+        fs::write(&index_path, damaged).unwrap();
+
+        let rebuilt = repair::rebuild_index(&global, &r, false).unwrap();
+        assert_eq!(rebuilt.added, [(name.clone(), Version::new(1, 1, 0))]);
+        assert_eq!(rebuilt.removed, [(name.clone(), Version::new(1, 2, 0))]);
+        assert!(rebuilt.skipped.is_empty());
+
+        let index = Registry::parse_index_file(&index_path).unwrap();
+        let yanked = index
+            .values()
+            .map(|e| (e.vers.to_string(), e.yanked))
+            .collect::<Vec<_>>();
+        assert_eq!(yanked, [("1.0.0".into(), true), ("1.1.0".into(), false)]);
+    }
+
+    #[test]
+    fn failure_classes_have_stable_exit_codes() {
+        use ErrorKind::*;
+
+        let codes =
+            [Other, Usage, NotFound, Conflict, Validation, Io, Malformed].map(ErrorKind::exit_code);
+        assert_eq!(codes, [1, 2, 3, 4, 5, 6, 7]);
+
+        let e = Error::from(VerifyError::Problems { count: 1 });
+        assert_eq!(e.kind(), Validation);
+
+        let e = Error::from(RemoveError::NameMissing);
+        assert_eq!(e.kind(), Usage);
+    }
+
+    #[test]
+    fn common_errors_have_codes() {
+        use diagnostics::Code;
+
+        let e = Error::from(AddError::CargoTomlMissing);
+        assert_eq!(e.code(), Some(Code::NotAPackage));
+
+        let e = Error::from(DiscoverRegistryError::FallbackNotFound);
+        assert_eq!(e.code(), Some(Code::RegistryNotFound));
+
+        let e = Error::from(RemoveError::NameMissing);
+        assert_eq!(e.code(), None);
+    }
+
+    fn precompress_path(path: &Path, extension: &str) -> PathBuf {
+        format!("{}.{extension}", path.display()).into()
+    }
+
+    #[tokio::test]
+    async fn mirrors_apply_changes() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let root = scratch.registry();
+        let upstream = Registry::initialize(default_config(), root.join("upstream")).unwrap();
+        let mirror = Registry::initialize(default_config(), root.join("mirror")).unwrap();
+
+        for version in ["1.0.0", "1.1.0"] {
+            let c = Crate::new("mirrored", version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            let p = c.package().await.unwrap();
+            upstream.add(&global, &p).unwrap();
+        }
+
+        let name: CrateName = "mirrored".parse().unwrap();
+        let old: Version = "1.0.0".parse().unwrap();
+        let new: Version = "1.1.0".parse().unwrap();
+        upstream
+            .yank(name.clone(), &old.clone().into(), true)
+            .unwrap();
+
+        let log = fs::read_to_string(upstream.path.join(changes::CHANGES_FILE_NAME)).unwrap();
+        let actions = log
+            .lines()
+            .map(|l| serde_json::from_str::<Change>(l).unwrap().action)
+            .collect::<Vec<_>>();
+        assert_eq!(actions, [Action::Publish, Action::Publish, Action::Yank]);
+
+        let source = changes::Source::Local(Box::new(Registry::open(&upstream.path).unwrap()));
+        assert_eq!(changes::apply(&mirror, &source).unwrap(), 3);
+
+        assert_eq!(
+            fs::read(mirror.index_file_path_for(&name)).unwrap(),
+            fs::read(upstream.index_file_path_for(&name)).unwrap(),
+        );
+        let index = Registry::parse_index_file(&mirror.index_file_path_for(&name)).unwrap();
+        for (version, entry) in &index {
+            assert!(mirror
+                .crate_file_path_for(&name, version, &entry.cksum)
+                .exists());
+        }
+
+        // Nothing new to apply
+        assert_eq!(changes::apply(&mirror, &source).unwrap(), 0);
+
+        let new_path = added_crate_file_path(&mirror, &name, &new);
+        upstream
+            .remove(name.clone(), new, None, Disposal::Delete)
+            .unwrap();
+        assert_eq!(changes::apply(&mirror, &source).unwrap(), 1);
+
+        let index = Registry::parse_index_file(&mirror.index_file_path_for(&name)).unwrap();
+        assert_eq!(index.keys().collect::<Vec<_>>(), [&old]);
+        assert!(!new_path.exists());
+
+        let log = fs::read_to_string(mirror.path.join(changes::CHANGES_FILE_NAME)).unwrap();
+        let actions = log
+            .lines()
+            .map(|l| serde_json::from_str::<Change>(l).unwrap().action)
+            .collect::<Vec<_>>();
+        assert_eq!(actions, [Action::Publish, Action::Publish, Action::Remove]);
+    }
+
+    #[tokio::test]
+    async fn imports_keep_yank_state() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+        let root = scratch.registry();
+
+        // A Margo registry's index has the same format as the other
+        // implementations', so it stands in for theirs.
+        let upstream = Registry::initialize(default_config(), root.join("upstream")).unwrap();
+        for version in ["1.0.0", "1.1.0"] {
+            let c = Crate::new("imported", version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            upstream.add(&global, c.package().await.unwrap()).unwrap();
+        }
+        let name = "imported".parse::<CrateName>().unwrap();
+        let old = "1.0.0".parse::<Version>().unwrap();
+        upstream
+            .yank(name.clone(), &old.clone().into(), true)
+            .unwrap();
+
+        let store = root.join("store");
+        fs::create_dir_all(&store).unwrap();
+        let index = Registry::parse_index_file(&upstream.index_file_path_for(&name)).unwrap();
+        for (version, entry) in &index {
+            let path = upstream.crate_file_path_for(&name, version, &entry.cksum);
+            fs::copy(path, store.join(format!("imported-{version}.crate"))).unwrap();
+        }
+
+        let r = Registry::initialize(default_config(), root.join("imported")).unwrap();
+        let from = upstream.path.to_str().unwrap();
+        let kellnr = import::Implementation::Kellnr;
+
+        let imported = import::import(&global, &r, kellnr, from, Some(store.clone())).unwrap();
+        assert_eq!(imported, 2);
+
+        assert_eq!(
+            fs::read(r.index_file_path_for(&name)).unwrap(),
+            fs::read(upstream.index_file_path_for(&name)).unwrap(),
+        );
+        let copied = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        assert!(copied[&old].yanked);
+        for (version, entry) in &copied {
+            assert!(r.crate_file_path_for(&name, version, &entry.cksum).exists());
+        }
+
+        upstream
+            .yank(name.clone(), &old.clone().into(), false)
+            .unwrap();
+        let imported = import::import(&global, &r, kellnr, from, Some(store)).unwrap();
+        assert_eq!(imported, 0);
+
+        let copied = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        assert!(!copied[&old].yanked);
+    }
+
+    #[tokio::test]
+    async fn lockfile_versions_are_vendored() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let root = scratch.registry();
+        let r = Registry::initialize(default_config(), root.join("registry")).unwrap();
+
+        let c = Crate::new("vendored", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        r.add(&global, c.package().await.unwrap()).unwrap();
+
+        let name = "vendored".parse::<CrateName>().unwrap();
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        let cksum = &index.values().next().unwrap().cksum;
+
+        let lockfile = |cksum: &str| {
+            format!(
+                r#"
+                version = 3
+
+                [[package]]
+                name = "app"
+                version = "0.1.0"
+
+                [[package]]
+                name = "vendored"
+                version = "1.0.0"
+                source = "sparse+{base_url}"
+                checksum = "{cksum}"
+
+                [[package]]
+                name = "elsewhere"
+                version = "2.0.0"
+                source = "registry+https://github.com/rust-lang/crates.io-index"
+                checksum = "0000"
+                "#,
+                base_url = r.config.base_url,
+            )
+        };
+        let lockfile_path = root.join("Cargo.lock");
+
+        fs::write(&lockfile_path, lockfile("0000")).unwrap();
+        let e = lockfile::read(&r, &lockfile_path).unwrap_err();
+        assert!(matches!(e, lockfile::Error::Missing { .. }), "{e:?}");
+
+        fs::write(&lockfile_path, lockfile(cksum)).unwrap();
+        let locked = lockfile::read(&r, &lockfile_path).unwrap();
+        assert_eq!(locked.crates.len(), 1);
+        assert_eq!(locked.skipped.len(), 1);
+        assert_eq!(locked.skipped[0].name.as_str(), "elsewhere");
+
+        let out = root.join("vendor");
+        assert_eq!(vendor::vendor(&r, &locked.crates, &out).unwrap(), 1);
+
+        let dir = out.join("vendored-1.0.0");
+        let manifest = fs::read(dir.join("Cargo.toml")).unwrap();
+        let checksums = fs::read(dir.join(".cargo-checksum.json")).unwrap();
+        let checksums = serde_json::from_slice::<serde_json::Value>(&checksums).unwrap();
+        assert_eq!(checksums["package"], cksum.as_str());
+        let manifest_cksum = {
+            use sha2::Digest;
+            hex::encode(sha2::Sha256::digest(manifest))
+        };
+        assert_eq!(checksums["files"]["Cargo.toml"], manifest_cksum.as_str());
+        assert!(checksums["files"]["src/lib.rs"].is_string());
+
+        // Already unpacked
+        assert_eq!(vendor::vendor(&r, &locked.crates, &out).unwrap(), 0);
+
+        // No longer used
+        assert_eq!(vendor::vendor(&r, &[], &out).unwrap(), 0);
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn subsets_hold_only_locked_versions() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let root = scratch.registry();
+        let r = Registry::initialize(default_config(), root.join("registry")).unwrap();
+
+        for (name, version) in [("kept", "1.0.0"), ("kept", "1.1.0"), ("dropped", "1.0.0")] {
+            let c = Crate::new(name, version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            r.add(&global, c.package().await.unwrap()).unwrap();
+        }
+
+        let name = "kept".parse::<CrateName>().unwrap();
+        let version = "1.1.0".parse::<Version>().unwrap();
+        r.yank(name.clone(), &version.clone().into(), true).unwrap();
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+
+        let only = [lockfile::Crate {
+            name: name.clone(),
+            version: version.clone(),
+            cksum: index[&version].cksum.clone(),
+        }];
+        let base_url = "https://small.example.com/".parse::<Url>().unwrap();
+        let out = root.join("subset");
+        let subset = subset::create(&r, &only, &out, Some(base_url.clone())).unwrap();
+
+        let opened = Registry::open(&out).unwrap();
+        assert_eq!(opened.config.base_url, base_url);
+
+        let all = subset.list_all().unwrap();
+        assert_eq!(all.keys().collect::<Vec<_>>(), [&name]);
+        assert_eq!(all[&name].keys().collect::<Vec<_>>(), [&version]);
+        assert!(all[&name][&version].yanked);
+        assert!(subset
+            .crate_file_path_for(&name, &version, &index[&version].cksum)
+            .exists());
+
+        let e = subset::create(&r, &only, &out, None).unwrap_err();
+        assert!(matches!(e, subset::Error::NotEmpty { .. }), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn local_registries_are_exported() {
+        use sha2::Digest;
+
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let root = scratch.registry();
+        let r = Registry::initialize(default_config(), root.join("registry")).unwrap();
+
+        for version in ["1.0.0", "1.1.0"] {
+            let c = Crate::new("exported", version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            r.add(&global, c.package().await.unwrap()).unwrap();
+        }
+
+        let name = "exported".parse::<CrateName>().unwrap();
+        let old = "1.0.0".parse::<Version>().unwrap();
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+
+        let out = root.join("local-registry");
+        assert_eq!(local_registry::export(&r, None, &out).unwrap(), 2);
+        assert!(out.join("exported-1.0.0.crate").exists());
+        assert!(out.join("exported-1.1.0.crate").exists());
+        assert_eq!(
+            fs::read(out.join("index/ex/po/exported")).unwrap(),
+            fs::read(r.index_file_path_for(&name)).unwrap(),
+        );
+
+        let only = [lockfile::Crate {
+            name: name.clone(),
+            version: old.clone(),
+            cksum: index[&old].cksum.clone(),
+        }];
+        let stale = out.join("exported-1.0.0.crate");
+        fs::write(&stale, "not the exported package").unwrap();
+
+        assert_eq!(local_registry::export(&r, Some(&only), &out).unwrap(), 1);
+        assert_eq!(
+            hex::encode(sha2::Sha256::digest(fs::read(&stale).unwrap())),
+            index[&old].cksum,
+        );
+        assert!(!out.join("exported-1.1.0.crate").exists());
+
+        let exported = Registry::parse_index_file(&out.join("index/ex/po/exported")).unwrap();
+        assert_eq!(exported.keys().collect::<Vec<_>>(), [&old]);
+
+        let lockfile_path = root.join("Cargo.lock");
+        let source = format!("sparse+{}", r.config.base_url);
+        fs::write(
+            &lockfile_path,
+            format!(
+                "[[package]]\nname = \"exported\"\nversion = \"1.1.0\"\nsource = \"{source}\"\n"
+            ),
+        )
+        .unwrap();
+        let locked = lockfile::read(&r, &lockfile_path).unwrap();
+
+        let context = root.join("context");
+        assert_eq!(
+            local_registry::docker_context(&r, &locked, &context).unwrap(),
+            1
+        );
+        assert!(context.join("local-registry/exported-1.1.0.crate").exists());
+
+        let config = fs::read_to_string(context.join(".cargo/config.toml")).unwrap();
+        let config = toml::from_str::<toml::Table>(&config).unwrap();
+        let name = r.config.html.suggested_registry_name();
+        assert_eq!(config["source"][name]["registry"].as_str(), Some(&*source));
+        assert_eq!(
+            config["source"][name]["replace-with"].as_str(),
+            Some("local-registry"),
+        );
+        assert_eq!(
+            config["source"]["local-registry"]["local-registry"].as_str(),
+            Some("local-registry"),
+        );
+        assert_eq!(config["net"]["offline"].as_bool(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn cat_index_prints_stored_entries() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        for version in ["1.0.0", "1.1.0"] {
+            let c = Crate::new("catted", version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            r.add(&global, c.package().await.unwrap()).unwrap();
+        }
+
+        let name = "catted".parse().unwrap();
+        let stored = fs::read_to_string(r.index_file_path_for(&name)).unwrap();
+
+        let raw = cat_index(&r, &name, None, false).unwrap();
+        assert_eq!(raw, stored);
+
+        let version = "1.1.0".parse().unwrap();
+        let line = cat_index(&r, &name, Some(&version), false).unwrap();
+        assert_eq!(line, format!("{}\n", stored.lines().nth(1).unwrap()));
+
+        let pretty = cat_index(&r, &name, Some(&version), true).unwrap();
+        assert!(pretty.contains("\n  \"vers\": \"1.1.0\""), "{pretty}");
+
+        let missing = "2.0.0".parse().unwrap();
+        let e = cat_index(&r, &name, Some(&missing), false).unwrap_err();
+        assert!(matches!(e, CatIndexError::VersionNotFound { .. }), "{e:?}");
+
+        let e = cat_index(&r, &"absent".parse().unwrap(), None, false).unwrap_err();
+        assert!(matches!(e, CatIndexError::CrateNotFound { .. }), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn ls_files_lists_registry_files() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        for name in ["listed", "other"] {
+            let c = Crate::new(name, "1.0.0")
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            r.add(&global, c.package().await.unwrap()).unwrap();
+        }
+        fs::create_dir_all(r.path.join(".git")).unwrap();
+        fs::write(r.path.join(".git").join("HEAD"), "").unwrap();
+
+        let listed = "listed".parse().unwrap();
+        let index = Registry::parse_index_file(&r.index_file_path_for(&listed)).unwrap();
+        let entry = index.values().next().unwrap();
+        let crate_path = r.crate_file_path_for(&listed, &entry.vers, &entry.cksum);
+        let crate_path = crate_path.strip_prefix(&r.path).unwrap();
+
+        let all = files::list(&r, None).unwrap();
+        let kind_of = |path: &Path| all.iter().find(|f| f.path == path).map(|f| f.kind);
+        assert_eq!(kind_of(Path::new("config.json")), Some(files::Kind::Config));
+        assert_eq!(
+            kind_of(Path::new(CONFIG_FILE_NAME)),
+            Some(files::Kind::Margo)
+        );
+        assert_eq!(kind_of(Path::new("li/st/listed")), Some(files::Kind::Index));
+        assert_eq!(kind_of(crate_path), Some(files::Kind::Crate));
+        assert!(all.iter().all(|f| !f.path.starts_with(".git")));
+
+        let crate_file = all.iter().find(|f| f.path == crate_path).unwrap();
+        assert_eq!(crate_file.sha256, entry.cksum);
+
+        let only = files::list(&r, Some(&listed)).unwrap();
+        let kinds = only.iter().map(|f| f.kind).collect::<Vec<_>>();
+        assert!(kinds.contains(&files::Kind::Index));
+        assert!(kinds.contains(&files::Kind::Crate));
+        assert!(only
+            .iter()
+            .all(|f| f.path.to_string_lossy().contains("listed")));
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_restored() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let mut packages = vec![];
+        for version in ["1.0.0", "1.1.0", "1.2.0"] {
+            let c = Crate::new("pinned", version)
+                .lib_rs(r#"pub const ID: u8 = 1;"#)
+                .create_in(&scratch)
+                .await
+                .unwrap();
+            packages.push(c.package().await.unwrap());
+        }
+        r.add(&global, &packages[0]).unwrap();
+        r.add(&global, &packages[1]).unwrap();
+        r.yank(
+            "pinned".parse().unwrap(),
+            &"1.1.0".parse::<Version>().unwrap().into(),
+            true,
+        )
+        .unwrap();
+        snapshot::create(&r, "2024.06").unwrap();
+
+        let e = snapshot::create(&r, "2024.06").unwrap_err();
+        assert!(matches!(e, snapshot::Error::Exists { .. }), "{e:?}");
+
+        r.add(&global, &packages[2]).unwrap();
+        r.yank(
+            "pinned".parse().unwrap(),
+            &"1.1.0".parse::<Version>().unwrap().into(),
+            false,
+        )
+        .unwrap();
+
+        let changed = snapshot::diff(
+            &snapshot::load(&r, "2024.06").unwrap(),
+            &snapshot::Manifest::of(&r).unwrap(),
+        );
+        let changed = changed.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(changed, ["~ pinned 1.1.0 (unyanked)", "+ pinned 1.2.0"]);
+
+        let before = snapshot::Manifest::of(&r).unwrap().crates;
+        let planned = snapshot::restore(&r, "2024.06", true).unwrap();
+        assert_eq!(planned.len(), 2);
+        assert_eq!(snapshot::Manifest::of(&r).unwrap().crates, before);
+
+        snapshot::restore(&r, "2024.06", false).unwrap();
+        let restored = snapshot::Manifest::of(&r).unwrap();
+        assert_eq!(
+            snapshot::diff(&snapshot::load(&r, "2024.06").unwrap(), &restored),
+            [],
+        );
+
+        r.remove(
+            "pinned".parse().unwrap(),
+            "1.0.0".parse().unwrap(),
+            None,
+            Disposal::Delete,
+        )
+        .unwrap();
+        let e = snapshot::restore(&r, "2024.06", false).unwrap_err();
+        assert!(matches!(e, snapshot::Error::Missing { .. }), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn init_writes_hosting_files() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let args = |reconfigure, host| InitArgs {
+            base_url: Some("https://example.com/registry/".parse().unwrap()),
+            defaults: true,
+            non_interactive: true,
+            auth_required: None,
+            html: Some(false),
+            html_suggested_registry_name: None,
+            crate_path: None,
+            reconfigure,
+            host,
+            path: scratch.registry(),
+        };
+        do_init(&global, args(false, Some(hosting::Host::Netlify))).unwrap();
+
+        let headers = fs::read_to_string(scratch.registry().join("_headers")).unwrap();
+        assert!(
+            headers.contains("/registry/config.json\n  Content-Type: application/json\n"),
+            "{headers}",
+        );
+        assert!(
+            headers.contains("/registry/crates/*\n  Content-Type: application/gzip\n"),
+            "{headers}",
+        );
+        assert!(scratch.registry().join("netlify.toml").exists());
+        assert!(!scratch.registry().join(".nojekyll").exists());
+
+        // The host is remembered
+        do_init(&global, args(true, None)).unwrap();
+        let r = Registry::open(scratch.registry()).unwrap();
+        assert_eq!(r.config.host, Some(hosting::Host::Netlify));
+
+        do_init(&global, args(true, Some(hosting::Host::S3))).unwrap();
+        let headers = fs::read_to_string(scratch.registry().join("headers.json")).unwrap();
+        let headers = serde_json::from_str::<serde_json::Value>(&headers).unwrap();
+        assert_eq!(headers[1]["path"], "/registry/1/*");
+        assert_eq!(headers[1]["headers"]["Content-Type"], "text/plain");
+    }
+
+    #[tokio::test]
+    async fn tombstones_are_recorded_and_cleared() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let c = Crate::new("buried", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+        r.add(&global, &p).unwrap();
+
+        let name: CrateName = "buried".parse().unwrap();
+        let version: Version = "1.0.0".parse().unwrap();
+        let tombstone = metadata::Tombstone {
+            reason: Some("security issue".into()),
+            removed_at: Timestamp::now(),
+        };
+
+        r.remove(
+            name.clone(),
+            version.clone(),
+            Some(&tombstone),
+            Disposal::Delete,
+        )
+        .unwrap();
+
+        let m = r.read_metadata(&name).unwrap();
+        let t = &m.tombstones[&version];
+        assert_eq!(t.reason.as_deref(), Some("security issue"));
+
+        r.add(&global, &p).unwrap();
+        let m = r.read_metadata(&name).unwrap();
+        assert!(m.tombstones.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sources_are_recorded() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let c = Crate::new("traced", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+
+        let source = metadata::Source {
+            url: Some("https://git.example.com/traced.git".into()),
+            rev: Some("0123456789abcdef0123456789abcdef01234567".into()),
+            ..Default::default()
+        };
+        let options = AddOptions {
+            source: source.clone(),
+            ..Default::default()
+        };
+        r.add_with_options(&global, &p, &options).unwrap();
+
+        let name: CrateName = "traced".parse().unwrap();
+        let version: Version = "1.0.0".parse().unwrap();
+
+        let m = r.read_metadata(&name).unwrap();
+        assert_eq!(m.sources.get(&version), Some(&source));
+
+        // Re-adding without provenance doesn't leave stale details
+        r.add(&global, &p).unwrap();
+        let m = r.read_metadata(&name).unwrap();
+        assert!(!m.sources.contains_key(&version));
+    }
+
+    #[tokio::test]
+    async fn generated_files_are_stamped() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
+
+        let generated = read_generated_by(&r.generated_path()).unwrap().unwrap();
+        assert_eq!(generated.margo_version, env!("CARGO_PKG_VERSION"));
+
+        let c = Crate::new("stamped", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+        r.add(&global, &p).unwrap();
+
+        let name: CrateName = "stamped".parse().unwrap();
+        let m = r.read_metadata(&name).unwrap();
+        let generated = m.generated_by.unwrap();
+        assert_eq!(generated.margo_version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn dependency_registries_are_mapped() {
+        let global = Global::new().unwrap();
+        let url = |s: &str| s.parse::<Url>().unwrap();
+
+        let config = ConfigV1 {
+            base_url: url("https://registry.example.com/"),
+            dependency_registries: [(
+                url("sparse+https://mirror.example.com/other/"),
+                url("sparse+https://other.example.com/"),
+            )]
+            .into(),
+            ..default_config()
+        };
+
+        let adapt = |index: Option<&str>| adapt_index(&global, &config, index.map(url));
+
+        assert_eq!(adapt(None), Some(global.crates_io_index_url.clone()));
+        assert_eq!(adapt(Some("sparse+https://registry.example.com/")), None);
+        assert_eq!(adapt(Some("https://registry.example.com")), None);
+        assert_eq!(
+            adapt(Some("sparse+https://mirror.example.com/other")),
+            Some(url("sparse+https://other.example.com/")),
+        );
+        assert_eq!(
+            adapt(Some("sparse+https://unknown.example.com/")),
+            Some(url("sparse+https://unknown.example.com/")),
+        );
+    }
+
+    #[test]
+    fn external_dependencies_are_detected() {
+        let config = ConfigV1 {
+            allowed_dependency_registries: vec!["https://allowed.example.com/".parse().unwrap()],
+            ..default_config()
+        };
+
+        let entry = r#"{
+            "name": "closed", "vers": "1.0.0", "cksum": "", "features": {}, "yanked": false, "v": 2,
+            "deps": [
+                {"name": "local", "req": "^1", "features": [], "optional": false, "default_features": true, "kind": "normal"},
+                {"name": "allowed", "req": "^1", "features": [], "optional": false, "default_features": true, "kind": "normal", "registry": "sparse+https://allowed.example.com/"},
+                {"name": "renamed", "package": "serde", "req": "^1", "features": [], "optional": false, "default_features": true, "kind": "build", "registry": "https://github.com/rust-lang/crates.io-index"}
+            ]
+        }"#;
+        let entry = serde_json::from_str::<index_entry::Root>(entry).unwrap();
+
+        assert_eq!(
+            config.external_dependencies(&entry),
+            ["`serde` (https://github.com/rust-lang/crates.io-index)"],
+        );
+    }
+
+    #[test]
+    fn crate_names_are_matched_against_patterns() {
+        let config = ConfigV1 {
+            allow_names: vec!["mycorp-*".into(), "tool".into(), "*-internal-*-sys".into()],
+            ..default_config()
+        };
+        let allowed = |name: &str| config.name_allowed(&name.parse().unwrap());
+
+        assert!(allowed("mycorp-core"));
+        assert!(allowed("MyCorp_Core"));
+        assert!(allowed("mycorp-"));
+        assert!(allowed("tool"));
+        assert!(allowed("zlib-internal-v2-sys"));
+
+        assert!(!allowed("mycorp"));
+        assert!(!allowed("serde"));
+        assert!(!allowed("tools"));
+        assert!(!allowed("internal-sys"));
+
+        assert!(default_config().name_allowed(&"serde".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn names_must_be_allowed() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
+
+        let config = ConfigV1 {
+            allow_names: vec!["mycorp-*".into()],
+            ..default_config()
+        };
+        let r = Registry::initialize(config, scratch.registry()).unwrap();
+
+        let c = Crate::new("serde", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        let p = c.package().await.unwrap();
+
+        let e = r.add(&global, &p).unwrap_err();
+        assert!(matches!(e, AddError::NameNotAllowed { .. }), "{e:?}");
 
-    impl<'de> Deserialize<'de> for CrateName {
-        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-        where
-            D: serde::Deserializer<'de>,
-        {
-            let ascii: AsciiString = Deserialize::deserialize(deserializer)?;
-            Self::try_from(ascii).map_err(D::Error::custom)
-        }
+        let options = AddOptions {
+            allow_any_name: true,
+            ..Default::default()
+        };
+        r.add_with_options(&global, &p, &options).unwrap();
     }
 
-    impl ops::Index<ops::Range<usize>> for CrateName {
-        type Output = AsciiStr;
+    #[tokio::test]
+    async fn cargo_config_is_written() {
+        let scratch = ScratchSpace::new().await.unwrap();
+        let path = scratch
+            .registry()
+            .with_file_name("project")
+            .join(".cargo/config.toml");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "# keep me\n[net]\nretry = 3\n").unwrap();
 
-        fn index(&self, index: ops::Range<usize>) -> &Self::Output {
-            self.0.index(index)
-        }
-    }
+        let config = default_config();
+        write_cargo_config(&config, "mine", true, &path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+
+        assert!(written.starts_with("# keep me\n"), "{written}");
+        let table = toml::from_str::<toml::Table>(&written).unwrap();
+        assert_eq!(
+            table["registries"]["mine"]["index"].as_str(),
+            Some("sparse+http://example.com/"),
+        );
+        assert_eq!(
+            table["source"]["crates-io"]["replace-with"].as_str(),
+            Some("mine"),
+        );
 
-    impl AsRef<Path> for CrateName {
-        fn as_ref(&self) -> &Path {
-            self.0.as_str().as_ref()
-        }
+        // Writing again is a no-op
+        write_cargo_config(&config, "mine", true, &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+
+        let moved = ConfigV1 {
+            base_url: "http://example.com/moved/".parse().unwrap(),
+            ..default_config()
+        };
+        assert!(matches!(
+            write_cargo_config(&moved, "mine", false, &path),
+            Err(ConfigStanzaError::Conflict { .. }),
+        ));
+
+        let inline = "registries = { other = { index = \"sparse+http://example.com/other/\" } }\n";
+        fs::write(&path, inline).unwrap();
+        assert!(matches!(
+            write_cargo_config(&config, "mine", false, &path),
+            Err(ConfigStanzaError::Append { .. }),
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), inline);
     }
 
-    fn valid_crate_name_char(chr: AsciiChar) -> bool {
-        chr.is_alphanumeric() || chr == AsciiChar::UnderScore || chr == AsciiChar::Minus
+    #[test]
+    fn unversioned_dependencies_are_described() {
+        let cargo_toml = r#"
+            [package]
+            name = "leaky"
+            version = "1.0.0"
+
+            [dependencies]
+            fine = { version = "1.0" }
+            local = { path = "../local" }
+            remote = { git = "https://git.example.com/remote.git" }
+
+            [dev-dependencies]
+            test-helper = { path = "../test-helper" }
+
+            [target.'cfg(unix)'.dependencies]
+            unix-only = { path = "../unix-only", version = "0.1" }
+        "#;
+        let cargo_toml = toml::from_str::<cargo_toml::Root>(cargo_toml).unwrap();
+
+        assert_eq!(
+            cargo_toml.unversioned_dependencies(),
+            [
+                "`local` (path `../local`)",
+                "`remote` (git `https://git.example.com/remote.git`)",
+            ],
+        );
     }
 
-    #[derive(Debug)]
-    pub struct RustVersion(Version);
+    #[test]
+    fn source_rev_urls() {
+        let source = |url: &str, path_in_vcs: Option<&str>| metadata::Source {
+            url: Some(url.into()),
+            rev: Some("abc123".into()),
+            path_in_vcs: path_in_vcs.map(Into::into),
+            dirty: false,
+        };
 
-    impl FromStr for RustVersion {
-        type Err = RustVersionError;
+        assert_eq!(
+            source("https://github.com/o/r.git", None)
+                .rev_url()
+                .as_deref(),
+            Some("https://github.com/o/r/commit/abc123"),
+        );
+        assert_eq!(
+            source("https://github.com/o/r/", Some("crates/a"))
+                .rev_url()
+                .as_deref(),
+            Some("https://github.com/o/r/tree/abc123/crates/a"),
+        );
+        assert_eq!(source("git@github.com:o/r.git", None).rev_url(), None);
+    }
 
-        fn from_str(s: &str) -> Result<Self, Self::Err> {
-            use rust_version_error::*;
+    #[test]
+    fn dl_template_expansion() {
+        let name: CrateName = "MyCrate".parse().unwrap();
+        let version: Version = "1.2.3".parse().unwrap();
 
-            let v: Version = match s.parse() {
-                Ok(v) => v,
-                Err(e) => {
-                    let version = [s, ".0"].concat();
-                    match version.parse() {
-                        Ok(v) => v,
-                        Err(_) => return Err(e)?,
-                    }
-                }
-            };
+        let url = expand_dl_template(
+            "https://example.com/{prefix}/{lowerprefix}/{crate}/{version}/{sha256-checksum}",
+            &name,
+            &version,
+            "abcd",
+        );
+        assert_eq!(url, "https://example.com/My/Cr/my/cr/MyCrate/1.2.3/abcd");
 
-            ensure!(v.pre.is_empty(), PrereleaseSnafu);
-            ensure!(v.build.is_empty(), BuildSnafu);
+        let url = expand_dl_template("https://example.com/api", &name, &version, "abcd");
+        assert_eq!(url, "https://example.com/api/MyCrate/1.2.3/download");
+    }
 
-            Ok(Self(v))
+    #[test]
+    fn timestamp_display() {
+        let cases = [
+            ("0", "1970-01-01T00:00:00Z"),
+            ("951782400", "2000-02-29T00:00:00Z"),
+            ("1718454896", "2024-06-15T12:34:56Z"),
+        ];
+
+        for (secs, expected) in cases {
+            let t: Timestamp = serde_json::from_str(secs).unwrap();
+            assert_eq!(t.to_string(), expected);
         }
     }
 
-    #[derive(Debug, Snafu)]
-    #[snafu(module)]
-    pub enum RustVersionError {
-        #[snafu(transparent)]
-        Semver { source: semver::Error },
+    #[test]
+    fn env_fallback_precedence() {
+        const KEY: &str = "MARGO_TEST_ENV_FALLBACK_PRECEDENCE";
 
-        #[snafu(display("May not specify a prerelease version"))]
-        Prerelease,
+        env::set_var(KEY, "from-env");
 
-        #[snafu(display("May not specify a version with build metadata"))]
-        Build,
-    }
+        let v = env_fallback(Some(String::from("from-cli")), KEY).unwrap();
+        assert_eq!(v.as_deref(), Some("from-cli"));
 
-    impl From<RustVersion> for Version {
-        fn from(value: RustVersion) -> Self {
-            value.0
-        }
-    }
+        let v = env_fallback::<String>(None, KEY).unwrap();
+        assert_eq!(v.as_deref(), Some("from-env"));
 
-    impl<'de> serde::Deserialize<'de> for RustVersion {
-        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-        where
-            D: serde::Deserializer<'de>,
-        {
-            let version = Cow::<str>::deserialize(deserializer)?;
-            version.parse().map_err(D::Error::custom)
-        }
-    }
+        env::set_var(KEY, "");
+        let v = env_fallback::<String>(None, KEY).unwrap();
+        assert_eq!(v, None);
 
-    impl serde::Serialize for RustVersion {
-        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-        where
-            S: serde::Serializer,
-        {
-            self.0.serialize(serializer)
-        }
+        env::set_var(KEY, "not-a-bool");
+        assert!(env_fallback::<bool>(None, KEY).is_err());
+
+        env::remove_var(KEY);
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use registry_conformance::{Crate, ScratchSpace};
+    #[test]
+    fn metrics_prometheus_output() {
+        let m = Metrics {
+            crates: 2,
+            versions: 5,
+            yanked_versions: 1,
+            crate_bytes: 10240,
+            downloads: 42,
+            last_publish_timestamp_seconds: None,
+        };
 
-    fn default_config() -> ConfigV1 {
-        ConfigV1 {
-            base_url: "http://example.com".parse().unwrap(),
-            auth_required: false,
-            html: ConfigV1Html {
-                enabled: false,
-                suggested_registry_name: None,
-            },
-        }
+        let out = m.to_prometheus();
+        assert!(out.contains("# TYPE margo_crates gauge\nmargo_crates 2\n"));
+        assert!(out.contains("margo_yanked_versions 1\n"));
+        assert!(out.contains("margo_crate_bytes 10240\n"));
+        assert!(!out.contains("last_publish"));
+
+        let m = Metrics {
+            last_publish_timestamp_seconds: Some(1718027736),
+            ..m
+        };
+        assert!(m
+            .to_prometheus()
+            .contains("margo_last_publish_timestamp_seconds 1718027736\n"));
     }
 
     #[tokio::test]
-    async fn adding_duplicate_crate() {
+    async fn fixing_case_moves_files_to_lowercase_paths() {
         let global = Global::new().unwrap();
         let scratch = ScratchSpace::new().await.unwrap();
 
-        let config = default_config();
-
-        let r = Registry::initialize(config, scratch.registry()).unwrap();
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
 
-        let c = Crate::new("duplicated", "1.0.0")
+        let c = Crate::new("MyCrate", "1.0.0")
             .lib_rs(r#"pub const ID: u8 = 1;"#)
             .create_in(&scratch)
             .await
             .unwrap();
-        let p = c.package().await.unwrap();
+        r.add(&global, c.package().await.unwrap()).unwrap();
+
+        let name = "MyCrate".parse::<CrateName>().unwrap();
+        let index_path = r.index_file_path_for(&name);
+        let crate_dir = r.crate_dir_for(&name);
+        assert!(index_path.ends_with("my/cr/mycrate"));
+        assert!(crate_dir.ends_with("crates/my/cr/MyCrate"));
+
+        // Recreate the layout from before paths were lowercased
+        let old_index_path = r.path.join("My/Cr/MyCrate");
+        let old_crate_dir = r.path.join("crates/My/Cr/MyCrate");
+        fs::create_dir_all(old_index_path.parent().unwrap()).unwrap();
+        fs::create_dir_all(old_crate_dir.parent().unwrap()).unwrap();
+        fs::rename(&index_path, &old_index_path).unwrap();
+        fs::rename(&crate_dir, &old_crate_dir).unwrap();
+
+        assert!(r.list_all().unwrap().is_empty());
+
+        assert_eq!(r.fix_case(true).unwrap(), 2);
+        assert!(old_index_path.exists());
+
+        assert_eq!(r.fix_case(false).unwrap(), 2);
+        assert!(index_path.exists());
+        assert!(crate_dir.join("1.0.0.crate").exists());
+        assert!(!r.path.join("My").exists());
+        assert!(!r.path.join("crates/My").exists());
+        assert!(r.list_all().unwrap().contains_key(&name));
+
+        assert_eq!(r.fix_case(false).unwrap(), 0);
+    }
 
-        r.add(&global, &p).unwrap();
-        r.add(&global, &p).unwrap();
+    #[tokio::test]
+    async fn dependencies_are_rewritten_to_self() {
+        let global = Global::new().unwrap();
+        let scratch = ScratchSpace::new().await.unwrap();
 
-        let name = CrateName::try_from(c.name()).unwrap();
-        let index_file_path = r.index_file_path_for(&name);
-        let index_contents = fs::read_to_string(index_file_path).unwrap();
+        let r = Registry::initialize(default_config(), scratch.registry()).unwrap();
 
-        assert_eq!(1, index_contents.lines().count());
+        let c = Crate::new("mirrored", "1.2.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        r.add(&global, c.package().await.unwrap()).unwrap();
+
+        let entry = r#"{
+            "name": "mirroring", "vers": "1.0.0", "cksum": "", "features": {}, "yanked": false, "v": 2,
+            "deps": [
+                {"name": "mirrored", "req": "^1", "features": [], "optional": false, "default_features": true, "kind": "normal", "registry": "https://github.com/rust-lang/crates.io-index"},
+                {"name": "too-new", "package": "mirrored", "req": "^2", "features": [], "optional": false, "default_features": true, "kind": "normal", "registry": "https://github.com/rust-lang/crates.io-index"},
+                {"name": "absent", "req": "^1", "features": [], "optional": false, "default_features": true, "kind": "normal", "registry": "https://github.com/rust-lang/crates.io-index"}
+            ]
+        }"#;
+        let mut entry = serde_json::from_str::<index_entry::Root>(entry).unwrap();
+
+        r.rewrite_deps_to_self(&mut entry).unwrap();
+
+        let registries = entry
+            .deps
+            .iter()
+            .map(|d| (d.name.as_str(), d.registry.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            registries,
+            [("mirrored", false), ("too-new", true), ("absent", true)],
+        );
     }
 
     #[tokio::test]
-    async fn base_url_requires_trailing_slash() {
+    async fn custom_crate_paths_are_honored() {
+        let global = Global::new().unwrap();
         let scratch = ScratchSpace::new().await.unwrap();
 
         let config = ConfigV1 {
-            base_url: "http://example.com/path/to/index".parse().unwrap(),
+            crate_path: "crates/{sha256-checksum}.crate".parse().unwrap(),
             ..default_config()
         };
-
         let r = Registry::initialize(config, scratch.registry()).unwrap();
 
-        let paths = [r.config_json_path(), r.margo_config_toml_path()];
+        let c = Crate::new("addressed", "1.0.0")
+            .lib_rs(r#"pub const ID: u8 = 1;"#)
+            .create_in(&scratch)
+            .await
+            .unwrap();
+        r.add(&global, c.package().await.unwrap()).unwrap();
 
-        for path in paths {
-            let contents = fs::read_to_string(&path).unwrap();
+        let name = "addressed".parse::<CrateName>().unwrap();
+        let version = "1.0.0".parse::<Version>().unwrap();
+        let index = Registry::parse_index_file(&r.index_file_path_for(&name)).unwrap();
+        let cksum = &index[&version].cksum;
 
-            assert!(
-                contents.contains("/path/to/index/"),
-                "{path} does not have the trailing slash:\n{contents}",
-                path = path.display(),
+        let crate_path = r.path.join(format!("crates/{cksum}.crate"));
+        assert!(crate_path.exists());
+        assert!(r.list_all().unwrap().contains_key(&name));
+
+        let config_json = fs::read_to_string(r.config_json_path()).unwrap();
+        assert!(
+            config_json.contains("http://example.com/crates/{sha256-checksum}.crate"),
+            "{config_json}",
+        );
+
+        r.remove_crate(name, None, Disposal::Delete, false).unwrap();
+        assert!(!crate_path.exists());
+    }
+
+    #[test]
+    fn unknown_index_fields_are_preserved() {
+        let line = r#"{"name":"future","vers":"1.0.0","deps":[{"name":"dep","req":"^1","features":[],"optional":false,"default_features":true,"kind":"normal","artifact":["bin"]}],"cksum":"abcd","features":{},"yanked":false,"v":3,"pubtime":"2024-06-10T13:55:36Z"}"#;
+
+        let mut entry = serde_json::from_str::<index_entry::Root>(line).unwrap();
+        entry.yanked = true;
+
+        let rewritten = serde_json::to_value(&entry).unwrap();
+        assert_eq!(rewritten["pubtime"], "2024-06-10T13:55:36Z");
+        assert_eq!(rewritten["v"], 3);
+        assert_eq!(rewritten["yanked"], true);
+        assert_eq!(rewritten["deps"][0]["artifact"][0], "bin");
+    }
+
+    fn index_of(versions: &[(&str, bool)]) -> Index {
+        versions
+            .iter()
+            .map(|&(vers, yanked)| {
+                let line = format!(
+                    r#"{{"name":"pre","vers":"{vers}","deps":[],"cksum":"","features":{{}},"yanked":{yanked},"v":2}}"#
+                );
+                let entry = serde_json::from_str::<index_entry::Root>(&line).unwrap();
+                (entry.vers.clone(), entry)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prerelease_and_build_metadata_versions() {
+        let v = |s: &str| s.parse::<Version>().unwrap();
+
+        let index = index_of(&[
+            ("1.0.0-alpha.1+build5", false),
+            ("1.0.0-alpha.2", false),
+            ("0.9.0", false),
+        ]);
+
+        assert_eq!(
+            index.keys().map(ToString::to_string).collect::<Vec<_>>(),
+            ["0.9.0", "1.0.0-alpha.1+build5", "1.0.0-alpha.2"],
+        );
+
+        // Stable releases are preferred over newer pre-releases
+        assert_eq!(latest_version(&index), Some(&v("0.9.0")));
+
+        let only_pre = index_of(&[("1.0.0-alpha.1", false), ("1.0.0-beta.1", true)]);
+        assert_eq!(latest_version(&only_pre), Some(&v("1.0.0-alpha.1")));
+
+        assert_eq!(
+            find_version(&index, &v("1.0.0-alpha.1")),
+            Some(&v("1.0.0-alpha.1+build5")),
+        );
+        assert_eq!(
+            find_version(&index, &v("1.0.0-alpha.1+build5")),
+            Some(&v("1.0.0-alpha.1+build5")),
+        );
+        assert_eq!(find_version(&index, &v("1.0.0-alpha.1+build6")), None);
+        assert_eq!(find_version(&index, &v("1.0.0")), None);
+
+        let req = |s: &str| s.parse::<VersionReq>().unwrap();
+        let index = index_of(&[
+            ("1.2.0", false),
+            ("1.3.0", true),
+            ("2.0.0-rc.1", false),
+            ("2.0.0", false),
+        ]);
+        assert_eq!(
+            latest_version_matching(&index, &req("^1")),
+            Some(&v("1.2.0")),
+        );
+        assert_eq!(
+            latest_version_matching(&index, &req("=2.0.0-rc.1")),
+            Some(&v("2.0.0-rc.1")),
+        );
+        assert_eq!(latest_version_matching(&index, &req("^3")), None);
+    }
+
+    #[test]
+    fn bumping_versions() {
+        use Bump::*;
+
+        let bump = |current: &str, bump: Bump, pre: Option<&str>| {
+            let current = current.parse::<Version>().unwrap();
+            bump_version(&current, bump, pre).map(|v| v.to_string())
+        };
+
+        let cases = [
+            ("1.2.3", Major, None, "2.0.0"),
+            ("1.2.3", Minor, None, "1.3.0"),
+            ("1.2.3", Patch, None, "1.2.4"),
+            ("1.2.3+build", Patch, None, "1.2.4"),
+            ("1.2.3", Major, Some("rc"), "2.0.0-rc.1"),
+            ("1.2.3", Pre, Some("alpha"), "1.2.4-alpha.1"),
+            ("2.0.0-rc.3", Major, None, "2.0.0"),
+            ("2.0.0-rc.3", Minor, None, "2.0.0"),
+            ("2.0.0-rc.3", Patch, None, "2.0.0"),
+            ("2.0.0-rc.3", Pre, None, "2.0.0-rc.4"),
+            ("2.0.0-rc.3", Major, Some("rc"), "2.0.0-rc.4"),
+            ("2.0.0-beta.2", Pre, Some("rc"), "2.0.0-rc.1"),
+            ("2.0.0-rc", Pre, None, "2.0.0-rc.1"),
+            ("1.3.0-rc.1", Major, None, "2.0.0"),
+            ("1.2.4-rc.1", Minor, Some("rc"), "1.3.0-rc.1"),
+        ];
+
+        for (current, b, pre, expected) in cases {
+            assert_eq!(
+                bump(current, b, pre).unwrap(),
+                expected,
+                "{current} {b:?} {pre:?}",
             );
         }
+
+        assert!(matches!(
+            bump("1.2.3", Pre, None),
+            Err(BumpError::PreMissing)
+        ));
+        assert!(matches!(
+            bump("2.0.0-rc.1", Pre, Some("alpha")),
+            Err(BumpError::NotNewer { .. })
+        ));
+        assert!(matches!(
+            bump("1.2.3", Patch, Some("not valid")),
+            Err(BumpError::PreInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn version_selectors() {
+        let index = index_of(&[
+            ("1.0.0", false),
+            ("1.1.0", true),
+            ("2.0.0-rc.1", false),
+            ("2.0.0", false),
+            ("2.1.0-beta.1", false),
+        ]);
+
+        let channels = BTreeMap::from([
+            ("2.0.0-rc.1".parse().unwrap(), "beta".to_owned()),
+            ("2.1.0-beta.1".parse().unwrap(), "beta".to_owned()),
+        ]);
+
+        let selected = |selector: VersionSelector| {
+            selector
+                .select(&index, &channels)
+                .into_iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+        let req = |s: &str| VersionSelector {
+            req: Some(s.parse().unwrap()),
+            ..Default::default()
+        };
+
+        assert!(VersionSelector::default().is_empty());
+        assert!(selected(VersionSelector::default()).is_empty());
+
+        assert_eq!(selected(req("<2.0.0")), ["1.0.0", "1.1.0"]);
+        assert_eq!(selected(req(">=2.0.0-rc.1, <2.0.0")), ["2.0.0-rc.1"]);
+
+        let prereleases = VersionSelector {
+            prereleases: true,
+            ..Default::default()
+        };
+        assert_eq!(selected(prereleases), ["2.0.0-rc.1", "2.1.0-beta.1"]);
+
+        let combined = VersionSelector {
+            version: Some("2.0.0".parse().unwrap()),
+            req: Some("~1.1".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(selected(combined), ["1.1.0", "2.0.0"]);
+
+        let all = VersionSelector {
+            all: true,
+            ..Default::default()
+        };
+        assert_eq!(selected(all).len(), index.len());
+
+        let beta = || VersionSelector {
+            channel: Some("beta".into()),
+            ..Default::default()
+        };
+        assert_eq!(selected(beta()), ["2.0.0-rc.1", "2.1.0-beta.1"]);
+
+        let beta_2_1 = VersionSelector {
+            req: Some("^2.1.0-beta".parse().unwrap()),
+            ..beta()
+        };
+        assert_eq!(selected(beta_2_1), ["2.1.0-beta.1"]);
+    }
+
+    #[test]
+    fn crate_path_validation() {
+        for valid in [
+            CratePath::DEFAULT,
+            "crates/{sha256-checksum}",
+            "crates/{prefix}/{crate}-{version}.crate",
+        ] {
+            assert!(valid.parse::<CratePath>().is_ok(), "{valid}");
+        }
+
+        for invalid in [
+            "{crate}/{version}.crate",
+            "crates2/{crate}/{version}.crate",
+            "crates/../{crate}/{version}.crate",
+            "crates//{crate}/{version}.crate",
+            "crates/{crate}.crate",
+        ] {
+            assert!(invalid.parse::<CratePath>().is_err(), "{invalid}");
+        }
     }
 
     #[tokio::test]
@@ -1745,17 +9958,24 @@ mod test {
 
         let name = name.parse().unwrap();
         let version = version.parse().unwrap();
-        let crate_path = r.crate_file_path_for(&name, &version);
 
         r.add(&global, p).unwrap();
 
+        let crate_path = added_crate_file_path(&r, &name, &version);
+
         assert!(
             crate_path.exists(),
             "The crate file should be in the registry at {}",
             crate_path.display(),
         );
 
-        r.remove(name, version).unwrap();
+        let missing = VersionSelector::from("2.0.0".parse::<Version>().unwrap());
+        let e = r
+            .remove_versions(name.clone(), &missing, None, Disposal::Delete, true)
+            .unwrap_err();
+        assert!(matches!(e, RemoveError::VersionNotFound { .. }), "{e:?}");
+
+        r.remove(name, version, None, Disposal::Delete).unwrap();
 
         assert!(
             !crate_path.exists(),
@@ -1763,4 +9983,253 @@ mod test {
             crate_path.display(),
         );
     }
+
+    mod crate_name {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Characters that look like ASCII but aren't.
+        const LOOKALIKES: &[char] = &[
+            '\u{0430}', // Cyrillic a
+            '\u{0435}', // Cyrillic e
+            '\u{043E}', // Cyrillic o
+            '\u{0131}', // dotless i
+            '\u{FF45}', // fullwidth e
+            '\u{2010}', // hyphen
+            '\u{2011}', // non-breaking hyphen
+        ];
+
+        fn valid_name() -> impl Strategy<Value = String> {
+            "[a-zA-Z][a-zA-Z0-9_-]{0,63}"
+        }
+
+        /// The path Cargo's sparse index client requests, following
+        /// `make_dep_path` in Cargo.
+        fn cargo_index_path(name: &str) -> String {
+            let name = name.to_lowercase();
+            match name.len() {
+                1 => format!("1/{name}"),
+                2 => format!("2/{name}"),
+                3 => format!("3/{}/{name}", &name[..1]),
+                _ => format!("{}/{}/{name}", &name[0..2], &name[2..4]),
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn valid_names_round_trip(name in valid_name()) {
+                let parsed = name.parse::<CrateName>().unwrap();
+                prop_assert_eq!(parsed.to_string(), name.as_str());
+
+                let json = serde_json::to_string(&parsed).unwrap();
+                let deserialized = serde_json::from_str::<CrateName>(&json).unwrap();
+                prop_assert_eq!(deserialized, parsed);
+            }
+
+            #[test]
+            fn lookalikes_are_rejected(
+                name in valid_name(),
+                lookalike in proptest::sample::select(LOOKALIKES),
+                position in any::<prop::sample::Index>(),
+            ) {
+                let mut chars = name.chars().collect::<Vec<_>>();
+                let i = position.index(chars.len() + 1);
+                chars.insert(i, lookalike);
+                let name = chars.into_iter().collect::<String>();
+
+                prop_assert!(name.parse::<CrateName>().is_err());
+
+                let json = serde_json::to_string(&name).unwrap();
+                prop_assert!(serde_json::from_str::<CrateName>(&json).is_err());
+            }
+
+            #[test]
+            fn invalid_characters_are_rejected(
+                name in valid_name(),
+                invalid in "[^a-zA-Z0-9_-]",
+            ) {
+                let name = format!("{name}{invalid}");
+                prop_assert!(name.parse::<CrateName>().is_err());
+            }
+
+            #[test]
+            fn names_must_start_alphabetic(name in "[0-9_-][a-zA-Z0-9_-]{0,63}") {
+                prop_assert!(name.parse::<CrateName>().is_err());
+            }
+
+            #[test]
+            fn prefix_directories_match_cargo(name in valid_name()) {
+                let parsed = name.parse::<CrateName>().unwrap();
+
+                let root = PathBuf::from("/registry");
+                let r = Registry {
+                    path: root.clone(),
+                    config: default_config(),
+                    events: Default::default(),
+                };
+
+                let path = r.index_file_path_for(&parsed);
+                let path = path
+                    .strip_prefix(&root)
+                    .unwrap()
+                    .components()
+                    .map(|c| c.as_os_str().to_str().unwrap())
+                    .collect::<Vec<_>>()
+                    .join("/");
+
+                prop_assert_eq!(path, cargo_index_path(&name));
+                prop_assert_eq!(
+                    format!("{}/{}", parsed.prefix(), parsed).to_lowercase(),
+                    cargo_index_path(&name),
+                );
+            }
+        }
+    }
+
+    /// Crate packages and index files can come from anywhere, so
+    /// parsing them must fail cleanly instead of panicking.
+    mod untrusted_input {
+        use super::*;
+        use proptest::{collection::vec, prelude::*};
+
+        fn gzip(data: &[u8]) -> Vec<u8> {
+            let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+            gz.write_all(data).unwrap();
+            gz.finish().unwrap()
+        }
+
+        fn package(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+            let mut tar = tar::Builder::new(Vec::new());
+            for (path, data) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                tar.append_data(&mut header, path, &data[..]).unwrap();
+            }
+            gzip(&tar.into_inner().unwrap())
+        }
+
+        fn entry_path() -> impl Strategy<Value = String> {
+            prop_oneof![
+                Just("pkg/Cargo.toml".to_owned()),
+                Just("pkg/.cargo_vcs_info.json".to_owned()),
+                "[a-z]{1,8}(/[a-z]{1,8}){0,3}",
+            ]
+        }
+
+        fn cargo_toml() -> impl Strategy<Value = String> {
+            let dep = (
+                "[a-z][a-z0-9_-]{0,8}",
+                prop::option::of("[\\^~=<>]?[0-9]{1,2}(\\.[0-9]{1,2}){0,2}"),
+                any::<bool>(),
+                prop::option::of("[a-z]{1,8}"),
+            );
+
+            (
+                "[a-z][a-z0-9_-]{0,8}",
+                "[0-9]{1,2}\\.[0-9]{1,2}\\.[0-9]{1,2}",
+                vec(dep, 0..5),
+                vec(("[a-z]{1,8}", vec("[a-z:/?]{1,12}", 0..3)), 0..3),
+            )
+                .prop_map(|(name, version, deps, features)| {
+                    let mut toml = format!("[package]\nname = {name:?}\nversion = {version:?}\n");
+
+                    toml.push_str("[dependencies]\n");
+                    for (dep, req, optional, package) in deps {
+                        toml.push_str(&format!("{dep:?} = {{ optional = {optional}"));
+                        if let Some(req) = req {
+                            toml.push_str(&format!(", version = {req:?}"));
+                        }
+                        if let Some(package) = package {
+                            toml.push_str(&format!(", package = {package:?}"));
+                        }
+                        toml.push_str(" }\n");
+                    }
+
+                    toml.push_str("[features]\n");
+                    for (feature, enables) in features {
+                        toml.push_str(&format!("{feature:?} = {enables:?}\n"));
+                    }
+
+                    toml
+                })
+        }
+
+        #[test]
+        fn entries_may_not_escape_the_package() {
+            for path in [
+                "pkg/../../etc/passwd",
+                "/etc/passwd",
+                "C:/Windows/win.ini",
+                "pkg/..\\..\\evil",
+            ] {
+                assert!(!is_contained_path(Path::new(path)), "{path} escapes");
+            }
+
+            for path in ["pkg/Cargo.toml", "pkg/./src/lib.rs", "pkg/src/..rs"] {
+                assert!(is_contained_path(Path::new(path)), "{path} is contained");
+            }
+        }
+
+        #[test]
+        fn package_limits_are_enforced() {
+            let data = package(&[
+                ("pkg/Cargo.toml".to_owned(), vec![b'#'; 100]),
+                ("pkg/src/lib.rs".to_owned(), vec![b'/'; 100]),
+            ]);
+
+            let limits = |max_entries, max_entry_bytes, max_total_bytes| PackageLimits {
+                max_entries,
+                max_entry_bytes,
+                max_total_bytes,
+            };
+
+            assert!(matches!(
+                extract_root_files(&data, &limits(1, 1000, 1_000_000)),
+                Err(ExtractRootFilesError::TooManyEntries { max: 1 }),
+            ));
+            assert!(matches!(
+                extract_root_files(&data, &limits(10, 50, 1_000_000)),
+                Err(ExtractRootFilesError::EntryTooLarge { size: 100, .. }),
+            ));
+            assert!(extract_root_files(&data, &limits(10, 1000, 150)).is_err());
+            assert!(extract_root_files(&data, &PackageLimits::default()).is_ok());
+        }
+
+        proptest! {
+            #[test]
+            fn arbitrary_bytes_are_not_a_package(data in vec(any::<u8>(), 0..1024)) {
+                let _ = extract_root_files(&data, &PackageLimits::default());
+            }
+
+            #[test]
+            fn arbitrary_compressed_bytes_are_not_a_package(data in vec(any::<u8>(), 0..4096)) {
+                let _ = extract_root_files(&gzip(&data), &PackageLimits::default());
+            }
+
+            #[test]
+            fn arbitrary_package_entries_are_handled(
+                entries in vec((entry_path(), vec(any::<u8>(), 0..256)), 0..8),
+            ) {
+                let _ = extract_root_files(&package(&entries), &PackageLimits::default());
+            }
+
+            #[test]
+            fn arbitrary_index_lines_are_handled(line in ".*") {
+                let _ = serde_json::from_str::<index_entry::Root>(&line);
+            }
+
+            #[test]
+            fn arbitrary_manifests_are_adapted(toml in cargo_toml()) {
+                let global = Global::new().unwrap();
+
+                if let Ok(cargo_toml) = toml::from_str::<cargo_toml::Root>(&toml) {
+                    let _ = cargo_toml.unversioned_dependencies();
+                    let entry =
+                        adapt_cargo_toml_to_index_entry(&global, &default_config(), cargo_toml, String::new());
+                    prop_assert!(serde_json::to_string(&entry).is_ok());
+                }
+            }
+        }
+    }
 }
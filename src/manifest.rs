@@ -0,0 +1,266 @@
+//! A small summary of the registry's crates that downstream tools can
+//! fetch cheaply to tell whether anything changed before syncing.
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::prelude::*;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    common::{CrateName, GeneratedBy},
+    latest_version, lock, merkle, ListAll, ListAllError, Registry,
+};
+
+/// Written at the root of the registry, beside `config.json`.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// The signature written by `ssh-keygen -Y sign`.
+pub const SIGNATURE_FILE_NAME: &str = "manifest.json.sig";
+
+/// Keeps signatures of the manifest from being valid for anything
+/// else signed with the same key.
+pub const SIGNATURE_NAMESPACE: &str = "margo-manifest";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub enabled: bool,
+
+    /// Also write `merkle.json`, recording the merkle tree whose root
+    /// is in the manifest.
+    pub merkle_tree: bool,
+
+    /// An SSH private key to sign the manifest with. It must be outside
+    /// the registry, which is published; a relative path is resolved
+    /// from the registry's directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<PathBuf>,
+}
+
+impl Settings {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Increases by one each time the crates change.
+    pub serial: u64,
+    pub generated_by: GeneratedBy,
+    /// Changes whenever any version is added, removed, replaced, or
+    /// yanked.
+    pub checksum: String,
+    /// The root of the tree in `merkle.json`, when it is written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merkle_root: Option<String>,
+    pub crates: BTreeMap<CrateName, Summary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Summary {
+    /// The newest version that isn't yanked.
+    pub latest: Option<Version>,
+    pub versions: usize,
+}
+
+/// The SHA-256 of one line per version, in order, holding the name,
+/// version, crate file checksum, and whether it is yanked.
+pub fn checksum(crates: &ListAll) -> String {
+    let mut hasher = Sha256::new();
+    for (name, index) in crates {
+        for (version, entry) in index {
+            let line = format!("{name} {version} {} {}\n", entry.cksum, entry.yanked);
+            hasher.update(line);
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// The manifest of the crates, continuing from the previous one.
+fn build(crates: &ListAll, merkle_root: Option<String>, previous: Option<Manifest>) -> Manifest {
+    let checksum = checksum(crates);
+
+    let serial = match previous {
+        Some(p) if p.checksum == checksum && p.merkle_root == merkle_root => return p,
+        Some(p) => p.serial + 1,
+        None => 1,
+    };
+
+    let summaries = crates
+        .iter()
+        .map(|(name, index)| {
+            let summary = Summary {
+                latest: latest_version(index).cloned(),
+                versions: index.len(),
+            };
+            (name.clone(), summary)
+        })
+        .collect();
+
+    Manifest {
+        serial,
+        generated_by: GeneratedBy::now(),
+        checksum,
+        merkle_root,
+        crates: summaries,
+    }
+}
+
+/// Writes the manifest, returning whether it changed. The manifest is
+/// left alone when the crates haven't changed, so its generation time
+/// and serial number only move when there is something to sync.
+pub fn write(r: &Registry) -> Result<bool, Error> {
+    use error::*;
+
+    // The index must not change while it is being summarized
+    let _lock = lock::shared(r)?;
+
+    let path = r.path.join(MANIFEST_FILE_NAME);
+    let signature_path = r.path.join(SIGNATURE_FILE_NAME);
+
+    // A manifest that can't be read is replaced
+    let previous = fs::read(&path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<Manifest>(&data).ok());
+
+    let crates = r.list_all()?;
+
+    let merkle_root = if r.config.manifest.merkle_tree {
+        Some(merkle::write(r, &crates)?)
+    } else {
+        merkle::remove(r)?;
+        None
+    };
+
+    let manifest = build(&crates, merkle_root, previous.clone());
+
+    let signing_key = r.config.manifest.signing_key.as_ref();
+    if let Some(key) = signing_key {
+        ensure_unpublished(r, &r.path.join(key))?;
+    }
+    let signature_current = signing_key.is_some() == signature_path.exists();
+    if previous.as_ref() == Some(&manifest) && signature_current {
+        return Ok(false);
+    }
+
+    let mut data = serde_json::to_vec_pretty(&manifest).context(SerializeSnafu)?;
+    data.push(b'\n');
+    fs::write(&path, data).context(WriteSnafu { path: &path })?;
+
+    match fs::remove_file(&signature_path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).context(RemoveSignatureSnafu {
+                path: signature_path,
+            })
+        }
+    }
+
+    if let Some(key) = signing_key {
+        sign(&r.path.join(key), &path)?;
+    }
+
+    Ok(true)
+}
+
+/// Refuses a private key that the web server would serve alongside
+/// the manifest it signs.
+fn ensure_unpublished(r: &Registry, key: &Path) -> Result<(), Error> {
+    use error::*;
+
+    let (Ok(key), Ok(registry)) = (key.canonicalize(), r.path.canonicalize()) else {
+        // `ssh-keygen` reports a missing key
+        return Ok(());
+    };
+    ensure!(!key.starts_with(registry), KeyPublishedSnafu { path: key });
+
+    Ok(())
+}
+
+/// Signs with `ssh-keygen`, which also verifies the signature for
+/// consumers.
+fn sign(key: &Path, path: &Path) -> Result<(), Error> {
+    use error::*;
+
+    let output = Command::new("ssh-keygen")
+        .args(["-Y", "sign", "-n", SIGNATURE_NAMESPACE, "-f"])
+        .arg(key)
+        .arg(path)
+        .output()
+        .context(SignStartSnafu)?;
+
+    ensure!(
+        output.status.success(),
+        SignSnafu {
+            key,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        }
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not lock the registry"))]
+    #[snafu(context(false))]
+    Lock { source: lock::Error },
+
+    #[snafu(transparent)]
+    ListAll { source: ListAllError },
+
+    #[snafu(transparent)]
+    Merkle { source: merkle::Error },
+
+    #[snafu(display("Could not serialize the manifest"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the manifest {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not remove the outdated signature {}", path.display()))]
+    RemoveSignature { source: io::Error, path: PathBuf },
+
+    #[snafu(display(
+        "The signing key {} is inside the registry, where it would be published",
+        path.display(),
+    ))]
+    KeyPublished { path: PathBuf },
+
+    #[snafu(display("Could not run `ssh-keygen` to sign the manifest"))]
+    SignStart { source: io::Error },
+
+    #[snafu(display("Could not sign the manifest with {}: {stderr}", key.display()))]
+    Sign { key: PathBuf, stderr: String },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serials_only_increase_when_crates_change() {
+        let crates = ListAll::new();
+
+        let first = build(&crates, None, None);
+        assert_eq!(first.serial, 1);
+
+        let again = build(&crates, None, Some(first.clone()));
+        assert_eq!(again, first);
+
+        let mut previous = first.clone();
+        previous.checksum = "outdated".into();
+        let changed = build(&crates, None, Some(previous));
+        assert_eq!(changed.serial, 2);
+        assert_eq!(changed.checksum, first.checksum);
+    }
+}
@@ -0,0 +1,391 @@
+//! A merkle tree over the checksums of every crate file, so that the
+//! index can be checked one crate at a time and consumers can confirm
+//! that a single version is part of the registry from a short proof.
+//!
+//! Each crate has its own tree over its versions, and the registry's
+//! tree is over the roots of those. Leaves and interior nodes are
+//! hashed with distinct prefixes, as in RFC 6962, and a node without
+//! a sibling is carried up to the next level unchanged.
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::prelude::*;
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+
+use crate::{
+    common::CrateName, ErrorKind, Index, ListAll, ListAllError, ParseIndexError, Registry,
+};
+
+/// Written at the root of the registry, so consumers can fetch it.
+pub const TREE_FILE_NAME: &str = "merkle.json";
+
+type Hash = [u8; 32];
+
+/// The persisted tree: the root of each crate's tree, and the root
+/// over all of them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tree {
+    pub root: String,
+    pub crates: BTreeMap<CrateName, String>,
+}
+
+impl Tree {
+    pub fn of(crates: &ListAll) -> Self {
+        let crates = crates
+            .iter()
+            .map(|(name, index)| (name.clone(), hex::encode(crate_root(index))))
+            .collect::<BTreeMap<_, _>>();
+
+        let leaves = crates.iter().map(|(n, r)| crate_leaf(n, r)).collect();
+        let (root, _) = root_and_path(leaves, None);
+
+        Self {
+            root: hex::encode(root),
+            crates,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// A sibling to combine with on the way up to the root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Step {
+    pub side: Side,
+    pub hash: String,
+}
+
+/// Shows that a version with the given checksum is included in the
+/// tree with some root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proof {
+    pub name: CrateName,
+    pub version: Version,
+    pub cksum: String,
+    /// From the version up to the root of the crate's tree.
+    pub crate_path: Vec<Step>,
+    /// From the crate up to the root of the registry's tree.
+    pub registry_path: Vec<Step>,
+}
+
+impl Proof {
+    /// The root that the proof leads to.
+    pub fn root(&self) -> Result<String, ProofError> {
+        let leaf = version_leaf(&self.name, &self.version, &self.cksum);
+        let crate_root = climb(leaf, &self.crate_path)?;
+        let leaf = crate_leaf(&self.name, &hex::encode(crate_root));
+        let root = climb(leaf, &self.registry_path)?;
+        Ok(hex::encode(root))
+    }
+}
+
+fn climb(mut hash: Hash, path: &[Step]) -> Result<Hash, ProofError> {
+    use proof_error::*;
+
+    for step in path {
+        let sibling = hex::decode(&step.hash)
+            .ok()
+            .and_then(|h| Hash::try_from(h).ok())
+            .context(HashSnafu { hash: &step.hash })?;
+
+        hash = match step.side {
+            Side::Left => node(&sibling, &hash),
+            Side::Right => node(&hash, &sibling),
+        };
+    }
+
+    Ok(hash)
+}
+
+fn leaf(data: &[u8]) -> Hash {
+    Sha256::new()
+        .chain_update([0])
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+fn node(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([1])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+fn version_leaf(name: &CrateName, version: &Version, cksum: &str) -> Hash {
+    leaf(format!("{name} {version} {cksum}").as_bytes())
+}
+
+fn crate_leaf(name: &CrateName, root: &str) -> Hash {
+    leaf(format!("{name} {root}").as_bytes())
+}
+
+fn crate_root(index: &Index) -> Hash {
+    root_and_path(crate_leaves(index), None).0
+}
+
+fn crate_leaves(index: &Index) -> Vec<Hash> {
+    index
+        .values()
+        .map(|e| version_leaf(&e.name, &e.vers, &e.cksum))
+        .collect()
+}
+
+/// The root over the leaves, and the path from the leaf at `index`
+/// when one is given.
+fn root_and_path(mut level: Vec<Hash>, mut index: Option<usize>) -> (Hash, Vec<Step>) {
+    let mut path = Vec::new();
+
+    if level.is_empty() {
+        return (Sha256::digest([]).into(), path);
+    }
+
+    while level.len() > 1 {
+        if let Some(i) = index {
+            let sibling = i ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                let side = if sibling < i { Side::Left } else { Side::Right };
+                path.push(Step {
+                    side,
+                    hash: hex::encode(hash),
+                });
+            }
+            index = Some(i / 2);
+        }
+
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node(left, right),
+                [single] => *single,
+                _ => unreachable!("chunks are never empty"),
+            })
+            .collect();
+    }
+
+    (level[0], path)
+}
+
+/// Writes the tree when it changed, returning its root.
+pub fn write(r: &Registry, crates: &ListAll) -> Result<String, Error> {
+    use error::*;
+
+    let tree = Tree::of(crates);
+    let path = r.path.join(TREE_FILE_NAME);
+    let mut data = serde_json::to_vec_pretty(&tree).context(SerializeSnafu)?;
+    data.push(b'\n');
+
+    if fs::read(&path).ok().as_ref() != Some(&data) {
+        fs::write(&path, data).context(WriteSnafu { path })?;
+    }
+
+    Ok(tree.root)
+}
+
+/// Removes a tree left from when it was enabled.
+pub fn remove(r: &Registry) -> Result<(), Error> {
+    use error::*;
+
+    let path = r.path.join(TREE_FILE_NAME);
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).context(RemoveSnafu { path }),
+    }
+}
+
+fn read(r: &Registry) -> Result<Tree, Error> {
+    use error::*;
+
+    let path = r.path.join(TREE_FILE_NAME);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return TreeMissingSnafu.fail(),
+        Err(e) => return Err(e).context(ReadSnafu { path }),
+    };
+    serde_json::from_slice(&data).context(MalformedSnafu { path })
+}
+
+/// Proves that the version is included in the persisted tree.
+pub fn proof(r: &Registry, name: &CrateName, version: &Version) -> Result<Proof, Error> {
+    use error::*;
+
+    let tree = read(r)?;
+
+    let path = r.index_file_path_for(name);
+    let index = Registry::parse_index_file(&path).context(IndexSnafu { path })?;
+
+    let position = index
+        .keys()
+        .position(|v| v == version)
+        .context(VersionNotFoundSnafu {
+            name: name.clone(),
+            version: version.clone(),
+        })?;
+    let entry = &index[version];
+
+    // The tree names crates as they were published
+    let (position_in_tree, recorded) = tree
+        .crates
+        .iter()
+        .enumerate()
+        .find(|(_, (n, _))| n.as_str().eq_ignore_ascii_case(name.as_str()))
+        .map(|(i, (_, root))| (i, root))
+        .context(CrateNotFoundSnafu { name: name.clone() })?;
+
+    let (crate_root, crate_path) = root_and_path(crate_leaves(&index), Some(position));
+    ensure!(
+        *recorded == hex::encode(crate_root),
+        StaleSnafu { name: name.clone() }
+    );
+
+    let leaves = tree.crates.iter().map(|(n, r)| crate_leaf(n, r)).collect();
+    let (_, registry_path) = root_and_path(leaves, Some(position_in_tree));
+
+    Ok(Proof {
+        name: entry.name.clone(),
+        version: entry.vers.clone(),
+        cksum: entry.cksum.clone(),
+        crate_path,
+        registry_path,
+    })
+}
+
+/// Compares each crate's index with its root in the persisted tree
+/// and checks that every crate file exists, without reading the crate
+/// files. Describes each problem found.
+pub fn verify(r: &Registry) -> Result<Vec<String>, Error> {
+    let tree = read(r)?;
+    let crates = r.list_all()?;
+    let mut problems = Vec::new();
+
+    let current = Tree::of(&crates);
+
+    let leaves = tree.crates.iter().map(|(n, r)| crate_leaf(n, r)).collect();
+    let (root, _) = root_and_path(leaves, None);
+    if hex::encode(root) != tree.root {
+        problems.push(format!(
+            "{TREE_FILE_NAME} has the root {}, but its crates lead to {}",
+            tree.root,
+            hex::encode(root),
+        ));
+    }
+
+    for name in tree.crates.keys() {
+        if !current.crates.contains_key(name) {
+            problems.push(format!("`{name}` is in {TREE_FILE_NAME} but not the index"));
+        }
+    }
+
+    for (name, root) in &current.crates {
+        match tree.crates.get(name) {
+            None => problems.push(format!("`{name}` is in the index but not {TREE_FILE_NAME}")),
+            Some(recorded) if recorded != root => problems.push(format!(
+                "`{name}` does not match its root in {TREE_FILE_NAME}"
+            )),
+            Some(_) => {}
+        }
+
+        for (version, entry) in &crates[name] {
+            let path = r.crate_file_path_for(name, version, &entry.cksum);
+            if !path.is_file() {
+                problems.push(format!(
+                    "`{name}` version {version}: the crate file {} is missing",
+                    path.display(),
+                ));
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(transparent)]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not serialize the merkle tree"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the merkle tree {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not remove the outdated merkle tree {}", path.display()))]
+    Remove { source: io::Error, path: PathBuf },
+
+    #[snafu(display(
+        "The registry has no merkle tree; enable `merkle_tree` in the `[manifest]` configuration"
+    ))]
+    TreeMissing,
+
+    #[snafu(display("Could not read the merkle tree {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The merkle tree {} is malformed", path.display()))]
+    Malformed {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not read the index file {}", path.display()))]
+    Index {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("`{name}` has no version {version}"))]
+    VersionNotFound { name: CrateName, version: Version },
+
+    #[snafu(display("`{name}` is not in the merkle tree"))]
+    CrateNotFound { name: CrateName },
+
+    #[snafu(display("The merkle tree is out of date for `{name}`"))]
+    Stale { name: CrateName },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::TreeMissing | Self::VersionNotFound { .. } | Self::CrateNotFound { .. } => {
+                Some(ErrorKind::NotFound)
+            }
+            Self::Stale { .. } => Some(ErrorKind::Conflict),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum ProofError {
+    #[snafu(display("The proof contains the malformed hash `{hash}`"))]
+    Hash { hash: String },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paths_lead_to_the_root() {
+        for count in 1..=9 {
+            let leaves = (0..count).map(|i: u8| leaf(&[i])).collect::<Vec<_>>();
+            let (root, _) = root_and_path(leaves.clone(), None);
+
+            for (i, &hash) in leaves.iter().enumerate() {
+                let (_, path) = root_and_path(leaves.clone(), Some(i));
+                assert_eq!(climb(hash, &path).unwrap(), root, "leaf {i} of {count}");
+            }
+        }
+    }
+}
@@ -0,0 +1,544 @@
+//! Batches of adds, yanks, and removals that are checked against the
+//! registry before anything is written. `plan` works out what a task
+//! file would do; `apply` carries out the resulting plan, putting the
+//! registry back as it was if any step fails.
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    changes,
+    common::{CrateName, GeneratedBy},
+    manifest, repair,
+    trash::{self, Disposal},
+    AddError, ErrorKind, Global, ListAll, ListAllError, Registry, RemoveError, VersionSelector,
+    YankError,
+};
+
+/// Starts with a dot so that it is skipped when listing the
+/// registry's files and watching it for changes.
+const JOURNAL_DIR_NAME: &str = ".margo-apply";
+
+/// The operations a task file asks for, in order.
+#[derive(Debug, Deserialize)]
+struct Tasks {
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Operation {
+    /// The path of a `.crate` file, relative to the task file.
+    Add(PathBuf),
+    Yank(Target),
+    Unyank(Target),
+    Remove(Target),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Target {
+    #[serde(rename = "crate")]
+    pub name: CrateName,
+    pub version: Version,
+}
+
+/// Everything `apply` will do, worked out against the registry as it
+/// was when the plan was made.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub generated_by: GeneratedBy,
+    /// The checksum of the registry's versions, as recorded in the
+    /// manifest, when the plan was made.
+    pub base: String,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Add {
+        path: PathBuf,
+        #[serde(rename = "crate")]
+        name: CrateName,
+        version: Version,
+        cksum: String,
+        /// The same package is already in the registry.
+        #[serde(default)]
+        replaces: bool,
+    },
+    Yank(Target),
+    Unyank(Target),
+    Remove(Target),
+}
+
+impl Step {
+    fn name(&self) -> &CrateName {
+        match self {
+            Self::Add { name, .. } => name,
+            Self::Yank(t) | Self::Unyank(t) | Self::Remove(t) => &t.name,
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Add {
+                path,
+                name,
+                version,
+                replaces,
+                ..
+            } => {
+                let verb = if *replaces { "add again" } else { "add" };
+                write!(
+                    f,
+                    "{verb} `{name}` version {version} from {}",
+                    path.display()
+                )
+            }
+            Self::Yank(t) => write!(f, "yank `{}` version {}", t.name, t.version),
+            Self::Unyank(t) => write!(f, "unyank `{}` version {}", t.name, t.version),
+            Self::Remove(t) => write!(f, "remove `{}` version {}", t.name, t.version),
+        }
+    }
+}
+
+/// The versions of each crate and whether they are yanked, by
+/// lowercased name since names that differ by case share an index
+/// file.
+type State = BTreeMap<String, (CrateName, BTreeMap<Version, bool>)>;
+
+/// Works out the steps of the task file against the registry, without
+/// changing it.
+pub fn plan(global: &Global, r: &Registry, tasks_path: &Path) -> Result<Plan, Error> {
+    use error::*;
+
+    let data = fs::read(tasks_path).context(ReadSnafu { path: tasks_path })?;
+    let tasks =
+        serde_json::from_slice::<Tasks>(&data).context(MalformedSnafu { path: tasks_path })?;
+    let base_dir = tasks_path.parent().unwrap_or(Path::new(""));
+
+    let crates = r.list_all()?;
+    let base = manifest::checksum(&crates);
+
+    let mut state = State::new();
+    for (name, index) in &crates {
+        let versions = index.iter().map(|(v, e)| (v.clone(), e.yanked)).collect();
+        state.insert(name.as_str().to_ascii_lowercase(), (name.clone(), versions));
+    }
+
+    let mut steps = Vec::new();
+    for operation in tasks.operations {
+        let step = match operation {
+            Operation::Add(path) => {
+                let path = base_dir.join(path);
+                let path = fs::canonicalize(&path).context(ReadSnafu { path: &path })?;
+                let data = fs::read(&path).context(ReadSnafu { path: &path })?;
+                let entry =
+                    repair::entry_for(global, r, &data).context(PackageSnafu { path: &path })?;
+
+                let (name, version) = (entry.name, entry.vers);
+                ensure!(r.config.name_allowed(&name), NameNotAllowedSnafu { name });
+
+                let key = name.as_str().to_ascii_lowercase();
+                let (existing, versions) = state
+                    .entry(key)
+                    .or_insert_with(|| (name.clone(), BTreeMap::new()));
+                ensure!(
+                    *existing == name,
+                    NameCaseConflictSnafu {
+                        name,
+                        existing: existing.clone(),
+                    }
+                );
+                let replaces = versions.insert(version.clone(), false).is_some();
+
+                // Only the same package may be added again
+                let published = crates
+                    .iter()
+                    .find(|(n, _)| n.as_str().eq_ignore_ascii_case(name.as_str()))
+                    .and_then(|(_, index)| index.get(&version));
+                ensure!(
+                    published.map_or(true, |e| e.cksum == entry.cksum),
+                    VersionExistsSnafu { name, version }
+                );
+
+                Step::Add {
+                    path,
+                    name,
+                    version,
+                    cksum: entry.cksum,
+                    replaces,
+                }
+            }
+
+            Operation::Yank(mut target) => {
+                let versions = find(&mut state, &mut target)?;
+                let was_yanked = versions.insert(target.version.clone(), true);
+                ensure!(
+                    was_yanked == Some(false),
+                    AlreadyYankedSnafu {
+                        name: target.name,
+                        version: target.version,
+                    }
+                );
+                Step::Yank(target)
+            }
+
+            Operation::Unyank(mut target) => {
+                let versions = find(&mut state, &mut target)?;
+                let was_yanked = versions.insert(target.version.clone(), false);
+                ensure!(
+                    was_yanked == Some(true),
+                    NotYankedSnafu {
+                        name: target.name,
+                        version: target.version,
+                    }
+                );
+                Step::Unyank(target)
+            }
+
+            Operation::Remove(mut target) => {
+                let versions = find(&mut state, &mut target)?;
+                versions.remove(&target.version);
+                Step::Remove(target)
+            }
+        };
+        steps.push(step);
+    }
+
+    Ok(Plan {
+        generated_by: GeneratedBy::now(),
+        base,
+        steps,
+    })
+}
+
+/// The versions of the target's crate, updating the target to use the
+/// crate's name as stored.
+fn find<'a>(
+    state: &'a mut State,
+    target: &mut Target,
+) -> Result<&'a mut BTreeMap<Version, bool>, Error> {
+    use error::*;
+
+    let key = target.name.as_str().to_ascii_lowercase();
+    let found = state
+        .get_mut(&key)
+        .filter(|(_, versions)| versions.contains_key(&target.version));
+    let (name, versions) = found.context(VersionNotFoundSnafu {
+        name: target.name.clone(),
+        version: target.version.clone(),
+    })?;
+
+    target.name = name.clone();
+    Ok(versions)
+}
+
+pub fn write(plan: &Plan, path: &Path) -> Result<(), Error> {
+    use error::*;
+
+    let data = serde_json::to_vec_pretty(plan).context(SerializeSnafu)?;
+    fs::write(path, data).context(WriteSnafu { path })
+}
+
+pub fn read(path: &Path) -> Result<Plan, Error> {
+    use error::*;
+
+    let data = fs::read(path).context(ReadSnafu { path })?;
+    serde_json::from_slice(&data).context(MalformedSnafu { path })
+}
+
+/// Carries out the plan, which must have been made against the
+/// registry as it is now. If a step fails, every file the plan could
+/// have touched is put back as it was. The caller holds the
+/// registry's lock.
+pub fn apply(global: &Global, r: &Registry, plan: &Plan) -> Result<(), Error> {
+    use error::*;
+    use sha2::Digest;
+
+    let crates = r.list_all()?;
+    ensure!(manifest::checksum(&crates) == plan.base, StaleSnafu);
+
+    for step in &plan.steps {
+        if let Step::Add { path, cksum, .. } = step {
+            let data = fs::read(path).context(ReadSnafu { path })?;
+            let actual = hex::encode(sha2::Sha256::digest(&data));
+            ensure!(actual == *cksum, CrateChangedSnafu { path });
+        }
+    }
+
+    let journal = Journal::start(r, &crates, plan)?;
+
+    for (i, step) in plan.steps.iter().enumerate() {
+        if let Err(source) = run(global, r, step) {
+            let number = i + 1;
+            if let Err(e) = journal.roll_back(r) {
+                eprintln!("Error: step {number} ({step}) failed: {source}");
+                return Err(e);
+            }
+            return Err(source).context(StepSnafu {
+                number,
+                step: step.to_string(),
+            });
+        }
+    }
+
+    journal.finish()
+}
+
+fn run(global: &Global, r: &Registry, step: &Step) -> Result<(), StepError> {
+    match step {
+        Step::Add { path, .. } => r.add(global, path)?,
+        Step::Yank(t) | Step::Unyank(t) => {
+            let selector = VersionSelector {
+                version: Some(t.version.clone()),
+                ..Default::default()
+            };
+            r.yank(t.name.clone(), &selector, matches!(step, Step::Yank(_)))?;
+        }
+        Step::Remove(t) => r.remove(t.name.clone(), t.version.clone(), None, Disposal::Trash)?,
+    }
+    Ok(())
+}
+
+/// Copies of the files a plan could change, so that they can be put
+/// back if a step fails.
+struct Journal {
+    dir: PathBuf,
+    /// Each file, with where its copy is kept, or `None` when it
+    /// didn't exist.
+    files: Vec<(PathBuf, Option<PathBuf>)>,
+    /// The IDs of the items in the trash before the plan started.
+    trash: BTreeSet<String>,
+}
+
+impl Journal {
+    fn start(r: &Registry, crates: &ListAll, plan: &Plan) -> Result<Self, Error> {
+        use error::*;
+
+        let dir = r.path.join(JOURNAL_DIR_NAME);
+        // Only left behind when restoring the registry failed
+        ensure!(!dir.exists(), JournalExistsSnafu { path: &dir });
+        fs::create_dir(&dir).context(JournalSnafu { path: &dir })?;
+
+        let mut paths = BTreeSet::new();
+        paths.insert(r.path.join(changes::CHANGES_FILE_NAME));
+
+        for step in &plan.steps {
+            let name = step.name();
+            paths.insert(r.index_file_path_for(name));
+            paths.insert(r.metadata_file_path_for(name));
+
+            match step {
+                Step::Add {
+                    name,
+                    version,
+                    cksum,
+                    ..
+                } => {
+                    paths.insert(r.crate_file_path_for(name, version, cksum));
+                    if r.config.content_addressed_crates {
+                        paths.insert(r.blob_path_for(cksum));
+                    }
+                }
+                Step::Remove(t) => {
+                    let entry = crates
+                        .iter()
+                        .find(|(n, _)| n.as_str().eq_ignore_ascii_case(t.name.as_str()))
+                        .and_then(|(_, index)| index.get(&t.version));
+                    if let Some(entry) = entry {
+                        paths.insert(r.crate_file_path_for(&t.name, &t.version, &entry.cksum));
+                    }
+                }
+                Step::Yank(_) | Step::Unyank(_) => {}
+            }
+        }
+
+        let mut files = Vec::new();
+        for (i, path) in paths.into_iter().enumerate() {
+            let copy = dir.join(i.to_string());
+            match fs::copy(&path, &copy) {
+                Ok(_) => files.push((path, Some(copy))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => files.push((path, None)),
+                Err(e) => return Err(e).context(JournalSnafu { path }),
+            }
+        }
+
+        let trash = trash::list(r)?.into_iter().map(|i| i.id).collect();
+
+        Ok(Self { dir, files, trash })
+    }
+
+    fn roll_back(self, r: &Registry) -> Result<(), Error> {
+        use error::*;
+
+        let journal = &self.dir;
+
+        for item in trash::list(r)? {
+            if !self.trash.contains(&item.id) {
+                let path = trash::dir(r)?.join(&item.id);
+                fs::remove_dir_all(&path).context(RestoreSnafu { path, journal })?;
+            }
+        }
+
+        for (path, copy) in &self.files {
+            let restored = match copy {
+                Some(copy) => path
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|()| fs::rename(copy, path)),
+                None => match fs::remove_file(path) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                    res => res,
+                },
+            };
+            restored.context(RestoreSnafu { path, journal })?;
+        }
+
+        self.finish()
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        use error::*;
+
+        fs::remove_dir_all(&self.dir).context(JournalSnafu { path: &self.dir })
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not read {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("{} is malformed", path.display()))]
+    Malformed {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not serialize the plan"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not read the crate package {}", path.display()))]
+    Package {
+        source: repair::EntryError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("`{name}` does not match any of the registry's `allow_names` patterns"))]
+    NameNotAllowed { name: CrateName },
+
+    #[snafu(display("`{name}` differs only by case from the existing crate `{existing}`"))]
+    NameCaseConflict {
+        name: CrateName,
+        existing: CrateName,
+    },
+
+    #[snafu(display("`{name}` version {version} is not in the registry"))]
+    VersionNotFound { name: CrateName, version: Version },
+
+    #[snafu(display(
+        "`{name}` version {version} is already in the registry with different contents"
+    ))]
+    VersionExists { name: CrateName, version: Version },
+
+    #[snafu(display("`{name}` version {version} is already yanked"))]
+    AlreadyYanked { name: CrateName, version: Version },
+
+    #[snafu(display("`{name}` version {version} is not yanked"))]
+    NotYanked { name: CrateName, version: Version },
+
+    #[snafu(display("The registry has changed since the plan was made; run `margo plan` again"))]
+    Stale,
+
+    #[snafu(display("{} has changed since the plan was made", path.display()))]
+    CrateChanged { path: PathBuf },
+
+    #[snafu(display(
+        "{} was left by an earlier `apply` that could not be undone; restore the files it holds, then delete it",
+        path.display(),
+    ))]
+    JournalExists { path: PathBuf },
+
+    #[snafu(display("Could not keep a copy of {}", path.display()))]
+    Journal { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    Trash { source: trash::Error },
+
+    #[snafu(display("Step {number} ({step}) failed; the registry was put back as it was"))]
+    Step {
+        #[snafu(source(from(StepError, Box::new)))]
+        source: Box<StepError>,
+        number: usize,
+        step: String,
+    },
+
+    #[snafu(display(
+        "Could not restore {} after a failed step; the original files are in {}",
+        path.display(),
+        journal.display(),
+    ))]
+    Restore {
+        source: io::Error,
+        path: PathBuf,
+        journal: PathBuf,
+    },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::VersionNotFound { .. } => Some(ErrorKind::NotFound),
+            Self::NameNotAllowed { .. }
+            | Self::NameCaseConflict { .. }
+            | Self::VersionExists { .. }
+            | Self::AlreadyYanked { .. }
+            | Self::NotYanked { .. }
+            | Self::Stale
+            | Self::CrateChanged { .. }
+            | Self::JournalExists { .. } => Some(ErrorKind::Conflict),
+            Self::Trash { source } => source.kind(),
+            Self::Step { source, .. } => source.kind(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum StepError {
+    #[snafu(transparent)]
+    Add { source: AddError },
+
+    #[snafu(transparent)]
+    Yank { source: YankError },
+
+    #[snafu(transparent)]
+    Remove { source: RemoveError },
+}
+
+impl StepError {
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Add { source } => source.kind(),
+            Self::Yank { source } => source.kind(),
+            Self::Remove { source } => source.kind(),
+        }
+    }
+}
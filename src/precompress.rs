@@ -0,0 +1,208 @@
+//! Compressed copies of the index files and HTML pages, for hosts
+//! that serve `file.gz` or `file.br` in place of `file` to clients
+//! that accept them.
+
+use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    lock, manifest, merkle, ListIndexFilesError, Registry, CRATE_DIR_NAME, METADATA_DIR_NAME,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    const ALL: [Self; 2] = [Self::Gzip, Self::Brotli];
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Gzip => "gz",
+            Self::Brotli => "br",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut w = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                w.write_all(data)?;
+                w.finish()
+            }
+            Self::Brotli => {
+                let mut w = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+                w.write_all(data)?;
+                w.flush()?;
+                Ok(w.into_inner())
+            }
+        }
+    }
+}
+
+/// Brings the compressed copies up to date, returning how many were
+/// written. Copies that are newer than their file are kept, and
+/// copies of removed files or of encodings no longer configured are
+/// deleted.
+pub fn write(r: &Registry) -> Result<usize, Error> {
+    use error::*;
+
+    // The index must not change while it is being compressed
+    let _lock = lock::shared(r)?;
+
+    let encodings = &r.config.precompressed;
+
+    let mut sources = r.list_index_files()?;
+    sources.insert(r.config_json_path());
+    for name in [manifest::MANIFEST_FILE_NAME, merkle::TREE_FILE_NAME] {
+        let path = r.path.join(name);
+        if path.exists() {
+            sources.insert(path);
+        }
+    }
+    sources.extend(html_files(&r.path)?);
+
+    let mut written = 0;
+
+    for source in &sources {
+        let modified = modified(source)?.context(SourceMissingSnafu { path: source })?;
+
+        for &encoding in encodings {
+            let path = variant_path(source, encoding);
+
+            if modified_after(&path, modified)? {
+                continue;
+            }
+
+            let data = fs::read(source).context(ReadSnafu { path: source })?;
+            let compressed = encoding
+                .compress(&data)
+                .context(CompressSnafu { path: source })?;
+            fs::write(&path, compressed).context(WriteSnafu { path: &path })?;
+            written += 1;
+        }
+    }
+
+    for path in walk(&r.path)? {
+        let Some(encoding) = Encoding::ALL
+            .into_iter()
+            .find(|e| path.extension().is_some_and(|ext| ext == e.extension()))
+        else {
+            continue;
+        };
+
+        // Only remove files that look like our own copies
+        let source = path.with_extension("");
+        if !could_be_source(r, &source) {
+            continue;
+        }
+
+        let wanted = encodings.contains(&encoding) && sources.contains(&source);
+        if !wanted {
+            fs::remove_file(&path).context(RemoveSnafu { path: &path })?;
+        }
+    }
+
+    Ok(written)
+}
+
+fn could_be_source(r: &Registry, path: &Path) -> bool {
+    if path.extension().is_some_and(|ext| ext == "html") || path == r.config_json_path() {
+        return true;
+    }
+
+    path.file_name()
+        .and_then(|n| n.to_str()?.parse().ok())
+        .is_some_and(|name| r.index_file_path_for(&name) == path)
+}
+
+fn variant_path(source: &Path, encoding: Encoding) -> PathBuf {
+    let mut path = source.as_os_str().to_owned();
+    path.push(".");
+    path.push(encoding.extension());
+    path.into()
+}
+
+fn modified(path: &Path) -> Result<Option<std::time::SystemTime>, Error> {
+    use error::*;
+
+    match fs::metadata(path) {
+        Ok(m) => m.modified().map(Some).context(MetadataSnafu { path }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(MetadataSnafu { path }),
+    }
+}
+
+/// Timestamps may be coarse, so a copy written in the same instant as
+/// its file is treated as out of date.
+fn modified_after(path: &Path, time: std::time::SystemTime) -> Result<bool, Error> {
+    Ok(modified(path)?.is_some_and(|m| m > time))
+}
+
+fn html_files(root: &Path) -> Result<Vec<PathBuf>, Error> {
+    Ok(walk(root)?
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|ext| ext == "html"))
+        .collect())
+}
+
+/// Every file in the registry, other than the crate files and
+/// Margo's metadata.
+fn walk(root: &Path) -> Result<Vec<PathBuf>, Error> {
+    use error::*;
+
+    walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() != 1
+                || (e.file_name() != CRATE_DIR_NAME && e.file_name() != METADATA_DIR_NAME)
+        })
+        .filter_map(|entry| match entry {
+            Ok(entry) => entry.file_type().is_file().then(|| Ok(entry.into_path())),
+            Err(e) => Some(Err(e).context(WalkdirSnafu { path: root })),
+        })
+        .collect()
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not lock the registry"))]
+    #[snafu(context(false))]
+    Lock { source: lock::Error },
+
+    #[snafu(transparent)]
+    ListIndexFiles { source: ListIndexFilesError },
+
+    #[snafu(display("Could not list the files in `{}`", path.display()))]
+    Walkdir {
+        source: walkdir::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("The file `{}` was removed while compressing", path.display()))]
+    SourceMissing { path: PathBuf },
+
+    #[snafu(display("Could not get the modification time of `{}`", path.display()))]
+    Metadata { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not read `{}`", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not compress `{}`", path.display()))]
+    Compress { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write `{}`", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not remove the outdated `{}`", path.display()))]
+    Remove { source: io::Error, path: PathBuf },
+}
@@ -0,0 +1,86 @@
+use semver::Version;
+use serde::Deserialize;
+use snafu::prelude::*;
+use url::Url;
+
+use crate::{common::CrateName, http, Global};
+
+/// The crates.io sparse index, consulted unless the registry names
+/// another public index.
+pub const CRATES_IO_INDEX: &str = "https://index.crates.io/";
+
+/// The newest version of the crate in the public sparse index at
+/// `index_url`, if the crate exists there.
+pub fn newest_version(
+    global: &Global,
+    index_url: &Url,
+    name: &CrateName,
+) -> Result<Option<Version>, Error> {
+    use error::*;
+
+    let client = http::Client::from_env(global, None)?;
+
+    // Cargo always requests the lowercased path
+    let path = format!("{}/{}", name.prefix(), name).to_lowercase();
+    let url = index_url.join(&path).context(UrlSnafu)?;
+
+    let fetched = client.get(&url, false)?;
+    match fetched.status {
+        200 => Ok(newest_version_in(&fetched.body)),
+        404 | 410 | 451 => Ok(None),
+        status => StatusSnafu { url, status }.fail(),
+    }
+}
+
+/// The newest version listed in a sparse index file. Lines that can't
+/// be understood are skipped.
+fn newest_version_in(index_file: &[u8]) -> Option<Version> {
+    #[derive(Deserialize)]
+    struct Entry {
+        vers: Version,
+    }
+
+    index_file
+        .split(|&b| b == b'\n')
+        .filter_map(|line| serde_json::from_slice::<Entry>(line).ok())
+        .map(|e| e.vers)
+        .max()
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(transparent)]
+    Client { source: http::Error },
+
+    #[snafu(display("Could not construct the public index URL"))]
+    Url { source: url::ParseError },
+
+    #[snafu(transparent)]
+    Request { source: http::RequestError },
+
+    #[snafu(display("{url} responded with HTTP status {status}"))]
+    Status { url: Url, status: u16 },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn newest_version_is_found() {
+        let index_file = [
+            r#"{"name":"shared","vers":"1.0.0","deps":[],"cksum":"","features":{},"yanked":false}"#,
+            r#"{"name":"shared","vers":"1.10.0","deps":[],"cksum":"","features":{},"yanked":true}"#,
+            "not json",
+            r#"{"name":"shared","vers":"1.9.0","deps":[],"cksum":"","features":{},"yanked":false}"#,
+        ]
+        .join("\n");
+
+        assert_eq!(
+            newest_version_in(index_file.as_bytes()),
+            Some("1.10.0".parse().unwrap()),
+        );
+        assert_eq!(newest_version_in(b""), None);
+    }
+}
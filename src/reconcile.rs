@@ -0,0 +1,370 @@
+//! Bringing the registry in line with a state file that declares
+//! which versions should exist and which of them are yanked. The
+//! differences are carried out as a plan, so they are applied
+//! together or not at all.
+
+use semver::Version;
+use serde::Deserialize;
+use snafu::prelude::*;
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+use crate::{
+    changes,
+    common::{CrateName, GeneratedBy},
+    manifest,
+    plan::{self, Plan, Step, Target},
+    repair, ErrorKind, Global, ListAllError, Registry,
+};
+
+/// Starts with a dot so that it is skipped when listing the
+/// registry's files and watching it for changes.
+const STAGING_DIR_NAME: &str = ".margo-reconcile";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct State {
+    /// Registries to copy missing versions from, by URL or path,
+    /// tried in order.
+    #[serde(default)]
+    sources: Vec<String>,
+
+    /// Remove the crates that aren't listed.
+    #[serde(default)]
+    prune: bool,
+
+    #[serde(default)]
+    crates: BTreeMap<CrateName, BTreeMap<Version, Desired>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Desired {
+    #[serde(default)]
+    yanked: bool,
+
+    /// The `.crate` file to add the version from, relative to the
+    /// state file.
+    file: Option<PathBuf>,
+}
+
+/// What has to change for the registry to match the state file.
+pub struct Diff {
+    base: String,
+    sources: Vec<(String, changes::Source)>,
+    pub additions: Vec<Addition>,
+    pub yanks: Vec<Target>,
+    pub unyanks: Vec<Target>,
+    pub removals: Vec<Target>,
+}
+
+pub struct Addition {
+    pub name: CrateName,
+    pub version: Version,
+    cksum: String,
+    origin: Origin,
+}
+
+enum Origin {
+    File(PathBuf),
+    /// The index into the diff's sources.
+    Source(usize),
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.additions.is_empty()
+            && self.yanks.is_empty()
+            && self.unyanks.is_empty()
+            && self.removals.is_empty()
+    }
+
+    /// One line per change, in the order they are applied.
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for a in &self.additions {
+            let from = match &a.origin {
+                Origin::File(path) => path.display().to_string(),
+                Origin::Source(i) => self.sources[*i].0.clone(),
+            };
+            lines.push(format!(
+                "add `{}` version {} from {from}",
+                a.name, a.version
+            ));
+        }
+        for t in &self.yanks {
+            lines.push(Step::Yank(t.clone()).to_string());
+        }
+        for t in &self.unyanks {
+            lines.push(Step::Unyank(t.clone()).to_string());
+        }
+        for t in &self.removals {
+            lines.push(Step::Remove(t.clone()).to_string());
+        }
+        lines
+    }
+}
+
+impl fmt::Debug for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.describe()).finish()
+    }
+}
+
+/// Compares the registry with the state file. Versions of listed
+/// crates that the state file doesn't list are removed; unlisted
+/// crates are only removed when the state file sets `prune`.
+pub fn diff(global: &Global, r: &Registry, state_path: &Path) -> Result<Diff, Error> {
+    use error::*;
+
+    let data = fs::read_to_string(state_path).context(ReadSnafu { path: state_path })?;
+    let state = toml::from_str::<State>(&data).context(MalformedSnafu { path: state_path })?;
+    let base_dir = state_path.parent().unwrap_or(Path::new(""));
+
+    let mut sources = Vec::new();
+    for from in state.sources {
+        // Paths are relative to the state file, like `file`
+        let location = match from.parse::<Url>() {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => from.clone(),
+            _ => base_dir.join(&from).display().to_string(),
+        };
+        let source =
+            changes::Source::new(global, &location).context(SourceSnafu { from: &from })?;
+        sources.push((from, source));
+    }
+
+    let crates = r.list_all()?;
+    let base = manifest::checksum(&crates);
+
+    // Crate names that differ only by case share an index file.
+    let desired = state
+        .crates
+        .into_iter()
+        .map(|(name, versions)| (name.as_str().to_ascii_lowercase(), (name, versions)))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut diff = Diff {
+        base,
+        sources: Vec::new(),
+        additions: Vec::new(),
+        yanks: Vec::new(),
+        unyanks: Vec::new(),
+        removals: Vec::new(),
+    };
+
+    for (name, index) in &crates {
+        let key = name.as_str().to_ascii_lowercase();
+        let wanted = desired.get(&key).map(|(_, versions)| versions);
+        if wanted.is_none() && !state.prune {
+            continue;
+        }
+
+        for (version, entry) in index {
+            let target = Target {
+                name: name.clone(),
+                version: version.clone(),
+            };
+            match wanted.and_then(|w| w.get(version)) {
+                None => diff.removals.push(target),
+                Some(d) if d.yanked && !entry.yanked => diff.yanks.push(target),
+                Some(d) if !d.yanked && entry.yanked => diff.unyanks.push(target),
+                Some(_) => {}
+            }
+        }
+    }
+
+    for (key, (name, versions)) in desired {
+        let existing = crates
+            .iter()
+            .find(|(n, _)| n.as_str().eq_ignore_ascii_case(&key));
+        // Changes use the name as stored
+        let name = existing.map_or(name, |(n, _)| n.clone());
+        let index = existing.map(|(_, index)| index);
+
+        for (version, d) in versions {
+            if index.is_some_and(|i| i.contains_key(&version)) {
+                continue;
+            }
+
+            let (cksum, origin) = match d.file {
+                Some(file) => {
+                    let path = base_dir.join(file);
+                    let data = fs::read(&path).context(ReadSnafu { path: &path })?;
+                    let entry = repair::entry_for(global, r, &data)
+                        .context(PackageSnafu { path: &path })?;
+                    ensure!(
+                        entry.name.as_str().eq_ignore_ascii_case(name.as_str())
+                            && entry.vers == version,
+                        FileMismatchSnafu {
+                            path,
+                            name,
+                            version,
+                        }
+                    );
+                    (entry.cksum, Origin::File(path))
+                }
+                None => find_in_sources(&sources, &name, &version)?,
+            };
+
+            if d.yanked {
+                diff.yanks.push(Target {
+                    name: name.clone(),
+                    version: version.clone(),
+                });
+            }
+            diff.additions.push(Addition {
+                name: name.clone(),
+                version,
+                cksum,
+                origin,
+            });
+        }
+    }
+
+    diff.sources = sources;
+    Ok(diff)
+}
+
+fn find_in_sources(
+    sources: &[(String, changes::Source)],
+    name: &CrateName,
+    version: &Version,
+) -> Result<(String, Origin), Error> {
+    use error::*;
+
+    for (i, (from, source)) in sources.iter().enumerate() {
+        let index = source.index_file(name).context(SourceSnafu { from })?;
+        if let Some(entry) = index.get(version) {
+            return Ok((entry.cksum.clone(), Origin::Source(i)));
+        }
+    }
+
+    MissingSnafu {
+        name: name.clone(),
+        version: version.clone(),
+    }
+    .fail()
+}
+
+/// Copies the missing versions from their sources, then applies the
+/// changes as one plan. The caller holds the registry's lock.
+pub fn apply(global: &Global, r: &Registry, diff: &Diff) -> Result<(), Error> {
+    use error::*;
+
+    let staging = r.path.join(STAGING_DIR_NAME);
+    fs::create_dir_all(&staging).context(StageSnafu { path: &staging })?;
+
+    let applied = stage_and_apply(global, r, diff, &staging);
+
+    let cleaned = fs::remove_dir_all(&staging).context(StageSnafu { path: &staging });
+    applied.and(cleaned)
+}
+
+fn stage_and_apply(
+    global: &Global,
+    r: &Registry,
+    diff: &Diff,
+    staging: &Path,
+) -> Result<(), Error> {
+    use error::*;
+
+    let mut steps = Vec::new();
+
+    for a in &diff.additions {
+        let path = match &a.origin {
+            Origin::File(path) => path.clone(),
+            Origin::Source(i) => {
+                let (from, source) = &diff.sources[*i];
+                let data = source
+                    .crate_file(&a.name, &a.version, &a.cksum)
+                    .context(SourceSnafu { from })?;
+                let path = staging.join(format!("{}-{}.crate", a.name, a.version));
+                fs::write(&path, data).context(StageSnafu { path: &path })?;
+                path
+            }
+        };
+
+        steps.push(Step::Add {
+            path,
+            name: a.name.clone(),
+            version: a.version.clone(),
+            cksum: a.cksum.clone(),
+            replaces: false,
+        });
+    }
+
+    steps.extend(diff.yanks.iter().cloned().map(Step::Yank));
+    steps.extend(diff.unyanks.iter().cloned().map(Step::Unyank));
+    steps.extend(diff.removals.iter().cloned().map(Step::Remove));
+
+    let plan = Plan {
+        generated_by: GeneratedBy::now(),
+        base: diff.base.clone(),
+        steps,
+    };
+    plan::apply(global, r, &plan)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not read {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The state file {} is malformed", path.display()))]
+    Malformed {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not read from the source `{from}`"))]
+    Source {
+        #[snafu(source(from(changes::ApplyError, Box::new)))]
+        source: Box<changes::ApplyError>,
+        from: String,
+    },
+
+    #[snafu(transparent)]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not read the crate package {}", path.display()))]
+    Package {
+        source: repair::EntryError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("{} is not `{name}` version {version}", path.display()))]
+    FileMismatch {
+        path: PathBuf,
+        name: CrateName,
+        version: Version,
+    },
+
+    #[snafu(display(
+        "`{name}` version {version} is not in the registry, and neither a `file` nor a source provides it"
+    ))]
+    Missing { name: CrateName, version: Version },
+
+    #[snafu(display("Could not stage the crate files in {}", path.display()))]
+    Stage { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    Plan { source: plan::Error },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Missing { .. } => Some(ErrorKind::NotFound),
+            Self::FileMismatch { .. } => Some(ErrorKind::Conflict),
+            Self::Plan { source } => source.kind(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Rebuilding the index from the crate files alone, for when the
+//! index files were damaged, such as by a botched merge.
+
+use semver::Version;
+use snafu::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::PathBuf,
+};
+
+use crate::{
+    adapt_cargo_toml_to_index_entry, cargo_toml,
+    changes::{self, Action, Change},
+    common::CrateName,
+    extract_root_files, index_entry, ExtractRootFilesError, Global, ListAll, ListIndexFilesError,
+    Registry, WriteIndexError, BLOB_DIR_NAME,
+};
+
+/// What rebuilding the index changed, or would change.
+#[derive(Debug, Default)]
+pub struct Rebuilt {
+    /// Versions with a crate file that weren't in the index.
+    pub added: Vec<(CrateName, Version)>,
+    /// Versions in the index without a crate file.
+    pub removed: Vec<(CrateName, Version)>,
+    /// Crate files that couldn't be used, and why.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// Writes every index file again from the crate files, keeping
+/// whether each version was yanked when the old index says so. Lines
+/// of the old index that can't be read are ignored, so this works
+/// even when the index files are damaged.
+pub fn rebuild_index(global: &Global, r: &Registry, dry_run: bool) -> Result<Rebuilt, Error> {
+    use error::*;
+
+    let old_files = r.list_index_files()?;
+    let old = read_leniently(&old_files)?;
+
+    let mut rebuilt = Rebuilt::default();
+    let mut new = ListAll::new();
+
+    let crate_dir = r.crate_dir();
+    let blob_dir = crate_dir.join(BLOB_DIR_NAME);
+
+    for entry in walkdir::WalkDir::new(&crate_dir) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if e.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound) => break,
+            Err(e) => return Err(e).context(WalkSnafu { path: &crate_dir }),
+        };
+        if !entry.file_type().is_file() || entry.path().starts_with(&blob_dir) {
+            continue;
+        }
+        let path = entry.into_path();
+
+        let data = fs::read(&path).context(ReadSnafu { path: &path })?;
+        let mut index_entry = match entry_for(global, r, &data) {
+            Ok(e) => e,
+            Err(e) => {
+                let reason = snafu::Report::from_error(e).to_string();
+                rebuilt.skipped.push((path, reason));
+                continue;
+            }
+        };
+
+        let expected =
+            r.crate_file_path_for(&index_entry.name, &index_entry.vers, &index_entry.cksum);
+        if expected != path {
+            let reason = format!(
+                "the crate path places `{}` version {} at {}",
+                index_entry.name,
+                index_entry.vers,
+                expected.display(),
+            );
+            rebuilt.skipped.push((path, reason));
+            continue;
+        }
+
+        let key = index_entry.name.as_str().to_ascii_lowercase();
+        let previous = old
+            .get(&key)
+            .and_then(|versions| versions.get(&index_entry.vers))
+            .filter(|e| e.cksum == index_entry.cksum);
+        index_entry.yanked = previous.is_some_and(|e| e.yanked);
+
+        let index = new.entry(index_entry.name.clone()).or_default();
+        if index.contains_key(&index_entry.vers) {
+            let reason = format!(
+                "another crate file is also `{}` version {}",
+                index_entry.name, index_entry.vers,
+            );
+            rebuilt.skipped.push((path, reason));
+            continue;
+        }
+        index.insert(index_entry.vers.clone(), index_entry);
+    }
+
+    let new_keys = new
+        .iter()
+        .map(|(name, index)| (name.as_str().to_ascii_lowercase(), (name, index)))
+        .collect::<BTreeMap<_, _>>();
+
+    for (key, (name, index)) in &new_keys {
+        let old_versions = old.get(key);
+        for version in index.keys() {
+            if !old_versions.is_some_and(|o| o.contains_key(version)) {
+                rebuilt.added.push(((*name).clone(), version.clone()));
+            }
+        }
+    }
+
+    for (key, versions) in &old {
+        let new_index = new_keys.get(key).map(|(_, i)| *i);
+        for (version, entry) in versions {
+            if !new_index.is_some_and(|i| i.contains_key(version)) {
+                rebuilt.removed.push((entry.name.clone(), version.clone()));
+            }
+        }
+    }
+
+    if dry_run {
+        return Ok(rebuilt);
+    }
+
+    for path in &old_files {
+        fs::remove_file(path).context(RemoveSnafu { path })?;
+    }
+
+    for (name, index) in new {
+        let path = r.index_file_path_for(&name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(IndexDirSnafu { path: dir })?;
+        }
+        Registry::write_index_file(index, &path, r.config.durability)
+            .context(IndexWriteSnafu { path })?;
+    }
+
+    let mut changes = Vec::new();
+    for (name, version) in &rebuilt.added {
+        changes.push(Change::now(name, version, Action::Publish));
+    }
+    for (name, version) in &rebuilt.removed {
+        changes.push(Change::now(name, version, Action::Remove));
+    }
+    changes::record(r, &changes)?;
+
+    Ok(rebuilt)
+}
+
+/// Every entry that can still be parsed, by lowercased crate name
+/// since names that differ by case share an index file.
+fn read_leniently(
+    paths: &BTreeSet<PathBuf>,
+) -> Result<BTreeMap<String, BTreeMap<Version, index_entry::Root>>, Error> {
+    use error::*;
+
+    let mut old = BTreeMap::<_, BTreeMap<_, _>>::new();
+
+    for path in paths {
+        let data = fs::read_to_string(path).context(ReadSnafu { path })?;
+        for line in data.lines() {
+            let Ok(entry) = serde_json::from_str::<index_entry::Root>(line) else {
+                continue;
+            };
+            old.entry(entry.name.as_str().to_ascii_lowercase())
+                .or_default()
+                .insert(entry.vers.clone(), entry);
+        }
+    }
+
+    Ok(old)
+}
+
+/// The index entry that `add` would write for the crate file.
+pub fn entry_for(
+    global: &Global,
+    r: &Registry,
+    data: &[u8],
+) -> Result<index_entry::Root, EntryError> {
+    use entry_error::*;
+    use sha2::Digest;
+
+    let cksum = hex::encode(sha2::Sha256::digest(data));
+
+    let root_files = extract_root_files(data, &r.config.package_limits)?;
+    let cargo_toml = root_files.cargo_toml.context(CargoTomlMissingSnafu)?;
+    let cargo_toml = String::from_utf8(cargo_toml).context(CargoTomlUtf8Snafu)?;
+    let cargo_toml =
+        toml::from_str::<cargo_toml::Root>(&cargo_toml).context(CargoTomlMalformedSnafu)?;
+
+    Ok(adapt_cargo_toml_to_index_entry(
+        global, &r.config, cargo_toml, cksum,
+    ))
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(transparent)]
+    ListIndexFiles { source: ListIndexFilesError },
+
+    #[snafu(display("Could not list the crate files in {}", path.display()))]
+    Walk {
+        source: walkdir::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not read {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not remove the old index file {}", path.display()))]
+    Remove { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not create the index directory {}", path.display()))]
+    IndexDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the index file {}", path.display()))]
+    IndexWrite {
+        source: WriteIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(transparent)]
+    Changes { source: changes::RecordError },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum EntryError {
+    #[snafu(transparent)]
+    RootFilesExtract { source: ExtractRootFilesError },
+
+    #[snafu(display("The crate package does not contain a Cargo.toml file"))]
+    CargoTomlMissing,
+
+    #[snafu(display("The crate's Cargo.toml is not valid UTF-8"))]
+    CargoTomlUtf8 { source: std::string::FromUtf8Error },
+
+    #[snafu(display("The crate's Cargo.toml is malformed"))]
+    CargoTomlMalformed { source: toml::de::Error },
+}
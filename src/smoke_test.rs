@@ -0,0 +1,323 @@
+//! Checking that Cargo can use a published version, by building a
+//! throwaway project that depends on it the way a consumer would.
+
+use snafu::prelude::*;
+use std::{
+    collections::BTreeSet,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    common::{CrateVersion, Timestamp},
+    find_version, local_registry, ErrorKind, ParseIndexError, Registry,
+};
+
+/// What the project calls the registry in its Cargo configuration.
+const REGISTRY_NAME: &str = "under-test";
+
+pub struct Options {
+    /// Use a copy of the registry as a `local-registry` source
+    /// instead of its URL, to check a registry before deploying it.
+    pub local: bool,
+    /// Stop after `cargo fetch`.
+    pub fetch_only: bool,
+    /// Leave the project in place for investigation.
+    pub keep: bool,
+}
+
+/// How each Cargo command went.
+#[derive(Debug)]
+pub struct Report {
+    pub project: PathBuf,
+    pub commands: Vec<Outcome>,
+}
+
+impl Report {
+    /// Fails with the first command that failed.
+    pub fn ensure_succeeded(&self, target: &CrateVersion) -> Result<(), Error> {
+        use error::*;
+
+        match self.commands.iter().find(|c| !c.success) {
+            Some(c) => FailedSnafu {
+                target: target.clone(),
+                command: c.command,
+            }
+            .fail(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Outcome {
+    pub command: &'static str,
+    pub success: bool,
+    pub problems: Vec<Problem>,
+    pub stderr: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    /// A dependency requirement can't be met.
+    Resolution,
+    /// The crate or a dependency needs a newer Rust.
+    Msrv,
+    /// A file couldn't be downloaded.
+    Download,
+    Other,
+}
+
+impl ProblemKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Resolution => "resolution",
+            Self::Msrv => "MSRV",
+            Self::Download => "download",
+            Self::Other => "error",
+        }
+    }
+}
+
+pub fn run(r: &Registry, target: &CrateVersion, options: &Options) -> Result<Report, Error> {
+    use error::*;
+
+    let index_path = r.index_file_path_for(&target.name);
+    let index = Registry::parse_index_file(&index_path).context(IndexSnafu { path: index_path })?;
+    let entry = find_version(&index, &target.version)
+        .and_then(|v| index.get(v))
+        .context(VersionNotFoundSnafu {
+            target: target.clone(),
+        })?;
+    ensure!(
+        !entry.yanked,
+        YankedSnafu {
+            target: target.clone()
+        }
+    );
+
+    let project = env::temp_dir().join(format!(
+        "margo-smoke-test-{}-{}",
+        std::process::id(),
+        Timestamp::now().as_secs(),
+    ));
+    write_project(r, target, options, &project)?;
+
+    let mut commands = Vec::new();
+    let mut names = vec!["fetch"];
+    if !options.fetch_only {
+        names.push("check");
+    }
+    for command in names {
+        let outcome = cargo(&project, command)?;
+        let success = outcome.success;
+        commands.push(outcome);
+        if !success {
+            break;
+        }
+    }
+
+    if !options.keep {
+        fs::remove_dir_all(&project).context(CleanUpSnafu { path: &project })?;
+    }
+
+    Ok(Report { project, commands })
+}
+
+fn write_project(
+    r: &Registry,
+    target: &CrateVersion,
+    options: &Options,
+    project: &Path,
+) -> Result<(), Error> {
+    use error::*;
+
+    let write = |relative: &str, contents: &str| -> Result<(), Error> {
+        let path = project.join(relative);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(WriteSnafu { path: dir })?;
+        }
+        fs::write(&path, contents).context(WriteSnafu { path })
+    };
+
+    // The empty `workspace` keeps Cargo from looking for one in the
+    // parent directories.
+    let manifest = format!(
+        "[package]\n\
+         name = \"margo-smoke-test\"\n\
+         version = \"0.0.0\"\n\
+         edition = \"2021\"\n\
+         publish = false\n\
+         \n\
+         [dependencies]\n\
+         {name} = {{ version = \"={version}\", registry = \"{REGISTRY_NAME}\" }}\n\
+         \n\
+         [workspace]\n",
+        name = target.name,
+        version = target.version,
+    );
+    write("Cargo.toml", &manifest)?;
+    write("src/lib.rs", "")?;
+
+    let mut config = r.config.registries_stanza(REGISTRY_NAME);
+    if options.local {
+        let dir = project.join("local-registry");
+        local_registry::export(r, None, &dir)?;
+
+        let sources = BTreeSet::from([format!("sparse+{}", r.config.base_url)]);
+        config.push('\n');
+        config.push_str(&local_registry::cargo_config(
+            r,
+            &sources,
+            &dir.display().to_string(),
+        ));
+    }
+    write(".cargo/config.toml", &config)
+}
+
+fn cargo(project: &Path, command: &'static str) -> Result<Outcome, Error> {
+    use error::*;
+
+    // Use the same Cargo as whoever is running us
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+
+    let output = Command::new(cargo)
+        .arg(command)
+        .current_dir(project)
+        .env("CARGO_TARGET_DIR", project.join("target"))
+        .output()
+        .context(StartSnafu { command })?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    Ok(Outcome {
+        command,
+        success: output.status.success(),
+        problems: diagnose(&stderr),
+        stderr,
+    })
+}
+
+/// The errors Cargo printed, sorted by what usually causes them.
+fn diagnose(stderr: &str) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut lines = stderr.lines().map(str::trim);
+
+    while let Some(line) = lines.next() {
+        let Some(message) = line.strip_prefix("error: ") else {
+            continue;
+        };
+
+        // Cargo explains resolution failures on the following lines
+        let mut message = message.to_owned();
+        if message.starts_with("failed to select a version") {
+            if let Some(detail) = lines.find(|l| !l.is_empty()) {
+                message.push_str(": ");
+                message.push_str(detail);
+            }
+        }
+
+        let kind = if message.contains("requires rustc") {
+            ProblemKind::Msrv
+        } else if message.starts_with("failed to select a version")
+            || message.starts_with("no matching package")
+        {
+            ProblemKind::Resolution
+        } else if message.starts_with("failed to download") || message.contains("failed to get") {
+            ProblemKind::Download
+        } else {
+            ProblemKind::Other
+        };
+
+        problems.push(Problem { kind, message });
+    }
+
+    problems
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not read the index file {}", path.display()))]
+    Index {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("`{target}` is not in the registry"))]
+    VersionNotFound { target: CrateVersion },
+
+    #[snafu(display("`{target}` is yanked, so Cargo will not select it"))]
+    Yanked { target: CrateVersion },
+
+    #[snafu(display("Could not write the project file {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    LocalRegistry { source: local_registry::Error },
+
+    #[snafu(display("Could not run `cargo {command}`"))]
+    Start {
+        source: io::Error,
+        command: &'static str,
+    },
+
+    #[snafu(display("Could not delete the project {}", path.display()))]
+    CleanUp { source: io::Error, path: PathBuf },
+
+    #[snafu(display("`cargo {command}` failed for a project depending on `{target}`"))]
+    Failed {
+        target: CrateVersion,
+        command: &'static str,
+    },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::VersionNotFound { .. } => Some(ErrorKind::NotFound),
+            Self::Yanked { .. } => Some(ErrorKind::Conflict),
+            Self::Failed { .. } => Some(ErrorKind::Validation),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cargo_errors_are_diagnosed() {
+        let stderr = "\
+    Updating `under-test` index
+error: failed to select a version for the requirement `dep = \"^2\"`
+candidate versions found which didn't match: 1.0.0
+location searched: `under-test` index
+error: package `widget v1.2.3` cannot be built because it requires rustc 1.99 or newer
+error: could not compile `widget`
+";
+        let kinds = diagnose(stderr)
+            .into_iter()
+            .map(|p| p.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                ProblemKind::Resolution,
+                ProblemKind::Msrv,
+                ProblemKind::Other
+            ],
+        );
+
+        let target = "widget@1.2.3".parse::<CrateVersion>().unwrap();
+        assert_eq!(target.to_string(), "widget@1.2.3");
+        assert!("widget".parse::<CrateVersion>().is_err());
+    }
+}
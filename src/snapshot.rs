@@ -0,0 +1,397 @@
+//! Named records of exactly which crate versions the registry held at
+//! a point in time, such as the versions a release was built with.
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::PathBuf,
+};
+
+use crate::{
+    common::{CrateName, GeneratedBy},
+    trash::Disposal,
+    ConfigV1, ErrorKind, ListAllError, Registry, RemoveError, YankError,
+};
+
+/// Kept out of the index directories and Margo's per-crate metadata.
+pub const SNAPSHOT_DIR_NAME: &str = "margo-snapshots";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub generated_by: GeneratedBy,
+    pub crates: BTreeMap<CrateName, BTreeMap<Version, Entry>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub cksum: String,
+    pub yanked: bool,
+}
+
+impl Manifest {
+    /// The registry's current contents.
+    pub fn of(r: &Registry) -> Result<Self, ListAllError> {
+        let crates = r
+            .list_all()?
+            .into_iter()
+            .map(|(name, index)| {
+                let versions = index
+                    .into_iter()
+                    .map(|(version, entry)| {
+                        let entry = Entry {
+                            cksum: entry.cksum,
+                            yanked: entry.yanked,
+                        };
+                        (version, entry)
+                    })
+                    .collect();
+                (name, versions)
+            })
+            .collect();
+
+        Ok(Self {
+            generated_by: GeneratedBy::now(),
+            crates,
+        })
+    }
+
+    fn versions(&self) -> BTreeSet<(&CrateName, &Version)> {
+        self.crates
+            .iter()
+            .flat_map(|(name, versions)| versions.keys().map(move |v| (name, v)))
+            .collect()
+    }
+
+    fn get(&self, name: &CrateName, version: &Version) -> Option<&Entry> {
+        self.crates.get(name)?.get(version)
+    }
+}
+
+/// How one crate version differs between two states of the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Added(CrateName, Version),
+    Removed(CrateName, Version),
+    /// The version was removed and published again with different
+    /// contents.
+    Replaced(CrateName, Version),
+    Yanked(CrateName, Version),
+    Unyanked(CrateName, Version),
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (marker, name, version, note) = match self {
+            Self::Added(n, v) => ('+', n, v, ""),
+            Self::Removed(n, v) => ('-', n, v, ""),
+            Self::Replaced(n, v) => ('~', n, v, " (checksum changed)"),
+            Self::Yanked(n, v) => ('~', n, v, " (yanked)"),
+            Self::Unyanked(n, v) => ('~', n, v, " (unyanked)"),
+        };
+        write!(f, "{marker} {name} {version}{note}")
+    }
+}
+
+/// What changed going from `old` to `new`, ordered by crate and
+/// version.
+pub fn diff(old: &Manifest, new: &Manifest) -> Vec<Difference> {
+    let all = old.versions().into_iter().chain(new.versions());
+    let all = all.collect::<BTreeSet<_>>();
+
+    all.into_iter()
+        .filter_map(|(name, version)| {
+            let (name, version) = (name.clone(), version.clone());
+            let d = match (old.get(&name, &version), new.get(&name, &version)) {
+                (None, Some(_)) => Difference::Added(name, version),
+                (Some(_), None) => Difference::Removed(name, version),
+                (Some(o), Some(n)) if o.cksum != n.cksum => Difference::Replaced(name, version),
+                (Some(o), Some(n)) if !o.yanked && n.yanked => Difference::Yanked(name, version),
+                (Some(o), Some(n)) if o.yanked && !n.yanked => Difference::Unyanked(name, version),
+                _ => return None,
+            };
+            Some(d)
+        })
+        .collect()
+}
+
+fn path_for(r: &Registry, name: &str) -> Result<PathBuf, Error> {
+    use error::*;
+
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    ensure!(valid, NameSnafu { name });
+
+    Ok(r.path.join(SNAPSHOT_DIR_NAME).join(format!("{name}.json")))
+}
+
+/// Records the registry's current contents under `name`.
+pub fn create(r: &Registry, name: &str) -> Result<PathBuf, Error> {
+    use error::*;
+
+    let path = path_for(r, name)?;
+    ensure!(!path.exists(), ExistsSnafu { name });
+
+    let manifest = Manifest::of(r)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(WriteSnafu { path: dir })?;
+    }
+    let mut data = serde_json::to_string_pretty(&manifest).context(SerializeSnafu)?;
+    data.push('\n');
+    fs::write(&path, data).context(WriteSnafu { path: &path })?;
+
+    Ok(path)
+}
+
+pub fn load(r: &Registry, name: &str) -> Result<Manifest, Error> {
+    use error::*;
+
+    let path = path_for(r, name)?;
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return NotFoundSnafu { name }.fail(),
+        Err(e) => return Err(e).context(ReadSnafu { path }),
+    };
+
+    serde_json::from_slice(&data).context(ParseSnafu { path })
+}
+
+/// Returns the registry to the contents recorded in the snapshot by
+/// removing versions published since and restoring yank states,
+/// returning what was changed.
+///
+/// Versions that have been removed since can't be restored, as their
+/// crate files are gone; nothing is changed if there are any.
+pub fn restore(r: &Registry, name: &str, dry_run: bool) -> Result<Vec<Difference>, Error> {
+    use error::*;
+
+    let snapshot = load(r, name)?;
+    let current = Manifest::of(r)?;
+
+    // Going from the current state back to the snapshot
+    let differences = diff(&current, &snapshot);
+
+    let missing = differences
+        .iter()
+        .filter_map(|d| match d {
+            Difference::Added(n, v) | Difference::Replaced(n, v) => Some(format!("`{n}` {v}")),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    ensure!(
+        missing.is_empty(),
+        MissingSnafu {
+            name,
+            versions: missing.join(", "),
+        }
+    );
+
+    if dry_run {
+        return Ok(differences);
+    }
+
+    for d in &differences {
+        match d {
+            Difference::Removed(n, v) => r.remove(n.clone(), v.clone(), None, Disposal::Trash)?,
+            Difference::Yanked(n, v) => {
+                r.yank(n.clone(), &v.clone().into(), true)?;
+            }
+            Difference::Unyanked(n, v) => {
+                r.yank(n.clone(), &v.clone().into(), false)?;
+            }
+            Difference::Added(..) | Difference::Replaced(..) => {
+                unreachable!("Missing versions were refused above")
+            }
+        }
+    }
+
+    Ok(differences)
+}
+
+/// The Cargo configuration and `Cargo.lock` entries that make a
+/// consumer use exactly the versions in the manifest.
+pub fn pin_set(manifest: &Manifest, config: &ConfigV1, registry_name: &str) -> String {
+    use std::fmt::Write;
+
+    let source = format!("sparse+{}", config.base_url);
+
+    let mut out = String::new();
+    out.push_str("# Add to `.cargo/config.toml`:\n\n");
+    out.push_str(&config.registries_stanza(registry_name));
+    out.push_str("\n# Merge into `Cargo.lock`:\n");
+
+    for (name, versions) in &manifest.crates {
+        for (version, entry) in versions {
+            let _ = write!(
+                out,
+                "\n[[package]]\n\
+                 name = \"{name}\"\n\
+                 version = \"{version}\"\n\
+                 source = \"{source}\"\n\
+                 checksum = \"{cksum}\"\n",
+                cksum = entry.cksum,
+            );
+        }
+    }
+
+    out
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display(
+        "`{name}` is not a valid snapshot name; use letters, numbers, `.`, `-`, and `_`"
+    ))]
+    Name { name: String },
+
+    #[snafu(display("The snapshot `{name}` already exists"))]
+    Exists { name: String },
+
+    #[snafu(display("The snapshot `{name}` does not exist"))]
+    NotFound { name: String },
+
+    #[snafu(display("`{name}` is not a valid registry name; use letters, numbers, `-`, and `_`"))]
+    RegistryName { name: String },
+
+    #[snafu(display("Could not list the crates"))]
+    #[snafu(context(false))]
+    ListAll { source: ListAllError },
+
+    #[snafu(display("Could not serialize the snapshot"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write the snapshot {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not read the snapshot {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The snapshot {} is malformed", path.display()))]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display(
+        "The snapshot `{name}` can't be restored because these versions are no longer in the registry: {versions}"
+    ))]
+    Missing { name: String, versions: String },
+
+    #[snafu(transparent)]
+    Remove {
+        #[snafu(source(from(RemoveError, Box::new)))]
+        source: Box<RemoveError>,
+    },
+
+    #[snafu(transparent)]
+    Yank {
+        #[snafu(source(from(YankError, Box::new)))]
+        source: Box<YankError>,
+    },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::NotFound { .. } => Some(ErrorKind::NotFound),
+            Self::Exists { .. } | Self::Missing { .. } => Some(ErrorKind::Conflict),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::Timestamp;
+
+    fn manifest(entries: &[(&str, &str, &str, bool)]) -> Manifest {
+        let mut crates = BTreeMap::<_, BTreeMap<_, _>>::new();
+        for &(name, version, cksum, yanked) in entries {
+            crates.entry(name.parse().unwrap()).or_default().insert(
+                version.parse().unwrap(),
+                Entry {
+                    cksum: cksum.into(),
+                    yanked,
+                },
+            );
+        }
+
+        Manifest {
+            generated_by: GeneratedBy {
+                margo_version: "0.0.0".into(),
+                at: Timestamp::from_secs(0),
+            },
+            crates,
+        }
+    }
+
+    #[test]
+    fn differences_are_described() {
+        let old = manifest(&[
+            ("kept", "1.0.0", "a", false),
+            ("gone", "1.0.0", "b", false),
+            ("replaced", "1.0.0", "c", false),
+            ("yanked", "1.0.0", "d", false),
+        ]);
+        let new = manifest(&[
+            ("kept", "1.0.0", "a", false),
+            ("kept", "1.1.0", "e", false),
+            ("replaced", "1.0.0", "f", false),
+            ("yanked", "1.0.0", "d", true),
+        ]);
+
+        let described = diff(&old, &new)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            described,
+            [
+                "- gone 1.0.0",
+                "+ kept 1.1.0",
+                "~ replaced 1.0.0 (checksum changed)",
+                "~ yanked 1.0.0 (yanked)",
+            ],
+        );
+
+        assert_eq!(diff(&new, &new), []);
+    }
+
+    #[test]
+    fn pin_sets_lock_each_version() {
+        let config = ConfigV1::new("https://example.com/registry/".parse().unwrap());
+        let pins = pin_set(
+            &manifest(&[("pinned", "1.0.0", "abc", false)]),
+            &config,
+            "mine",
+        );
+
+        assert_eq!(
+            pins,
+            [
+                "# Add to `.cargo/config.toml`:",
+                "",
+                "[registries]",
+                r#"mine = { index = "sparse+https://example.com/registry/" }"#,
+                "",
+                "# Merge into `Cargo.lock`:",
+                "",
+                "[[package]]",
+                r#"name = "pinned""#,
+                r#"version = "1.0.0""#,
+                r#"source = "sparse+https://example.com/registry/""#,
+                r#"checksum = "abc""#,
+                "",
+            ]
+            .join("\n"),
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! New registries holding only some of another registry's versions,
+//! such as the versions one project needs from a large mirror.
+
+use snafu::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+use crate::{
+    changes::{self, Action, Change},
+    common::CrateName,
+    hosting, lockfile, ErrorKind, InitializeError, OpenError, ParseIndexError, Registry,
+    WriteIndexError,
+};
+
+/// Creates a registry at `out` with the same configuration as `r`,
+/// except for the base URL when one is given, holding just the given
+/// versions. The per-crate metadata is copied as well.
+pub fn create(
+    r: &Registry,
+    crates: &[lockfile::Crate],
+    out: &Path,
+    base_url: Option<Url>,
+) -> Result<Registry, Error> {
+    use error::*;
+
+    let is_empty = match fs::read_dir(out) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e).context(ReadDirSnafu { path: out }),
+    };
+    ensure!(is_empty, NotEmptySnafu { path: out });
+
+    // Reading the configuration again gives a copy to change
+    let mut config = Registry::open(&r.path).context(OpenSnafu)?.config;
+    if let Some(base_url) = base_url {
+        config.base_url = base_url;
+    }
+
+    let subset = Registry::initialize(config, out).context(InitializeSnafu)?;
+    if let Some(host) = subset.config.host {
+        hosting::write_files(&subset, host)?;
+    }
+
+    let mut wanted = BTreeMap::<&CrateName, BTreeSet<_>>::new();
+    for c in crates {
+        wanted.entry(&c.name).or_default().insert(&c.version);
+    }
+
+    for (name, versions) in wanted {
+        let path = r.index_file_path_for(name);
+        let mut index = Registry::parse_index_file(&path).context(IndexReadSnafu { path })?;
+        index.retain(|v, _| versions.contains(v));
+
+        let mut changes = Vec::new();
+
+        for (version, entry) in &index {
+            let from = r.crate_file_path_for(&entry.name, version, &entry.cksum);
+            let data = fs::read(&from).context(CrateReadSnafu { path: from })?;
+
+            let path = subset.crate_file_path_for(&entry.name, version, &entry.cksum);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).context(CrateWriteSnafu { path: dir })?;
+            }
+            subset
+                .write_crate_file(&path, &data, &entry.cksum)
+                .context(CrateWriteSnafu { path: &path })?;
+
+            changes.push(Change::now(&entry.name, version, Action::Publish));
+            if entry.yanked {
+                changes.push(Change::now(&entry.name, version, Action::Yank));
+            }
+        }
+
+        let path = subset.index_file_path_for(name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).context(IndexDirSnafu { path: dir })?;
+        }
+        Registry::write_index_file(index, &path, subset.config.durability)
+            .context(IndexWriteSnafu { path: &path })?;
+
+        let from = r.metadata_file_path_for(name);
+        if from.exists() {
+            let path = subset.metadata_file_path_for(name);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).context(MetadataCopySnafu { path: dir })?;
+            }
+            fs::copy(&from, &path).context(MetadataCopySnafu { path: &path })?;
+        }
+
+        changes::record(&subset, &changes)?;
+    }
+
+    Ok(subset)
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not read the directory {}", path.display()))]
+    ReadDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The directory {} is not empty", path.display()))]
+    NotEmpty { path: PathBuf },
+
+    #[snafu(display("Could not read the registry's configuration"))]
+    Open {
+        #[snafu(source(from(OpenError, Box::new)))]
+        source: Box<OpenError>,
+    },
+
+    #[snafu(display("Could not initialize the new registry"))]
+    Initialize { source: InitializeError },
+
+    #[snafu(transparent)]
+    Hosting { source: hosting::Error },
+
+    #[snafu(display("Could not read the index file {}", path.display()))]
+    IndexRead {
+        source: ParseIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not read the crate file {}", path.display()))]
+    CrateRead { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the crate file {}", path.display()))]
+    CrateWrite { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not create the index directory {}", path.display()))]
+    IndexDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write the index file {}", path.display()))]
+    IndexWrite {
+        source: WriteIndexError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("Could not copy the crate's metadata to {}", path.display()))]
+    MetadataCopy { source: io::Error, path: PathBuf },
+
+    #[snafu(transparent)]
+    Changes { source: changes::RecordError },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::NotEmpty { .. } => Some(ErrorKind::Conflict),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Removed crate files are moved into the registry's trash instead of
+//! being deleted, so that an accidental removal can be undone. Items
+//! older than the retention window are deleted whenever something
+//! else is moved to the trash.
+//!
+//! The trash is kept next to the registry rather than inside it, as
+//! everything inside is published, including files that were removed
+//! because they never should have been.
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    changes::{self, Action, Change},
+    common::{CrateName, Timestamp},
+    index_entry, ErrorKind, MetadataError, ReadModifyWriteError, Registry,
+};
+
+/// Appended to the registry directory's name to name the trash.
+const TRASH_DIR_SUFFIX: &str = ".margo-trash";
+
+const ITEM_FILE_NAME: &str = "item.json";
+const DATA_FILE_NAME: &str = "data";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// How many days removed files are kept before being deleted for
+    /// good.
+    pub retention_days: u64,
+
+    /// Where to keep the trash, relative to the registry. It must be
+    /// on the same filesystem, so files can be moved there.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+}
+
+impl Settings {
+    const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            retention_days: Self::DEFAULT_RETENTION_DAYS,
+            dir: None,
+        }
+    }
+}
+
+/// What happens to the files that a removal leaves behind.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Disposal {
+    /// Move them to the trash.
+    #[default]
+    Trash,
+    /// Delete them immediately.
+    Delete,
+}
+
+impl Disposal {
+    pub fn permanent(permanent: bool) -> Self {
+        if permanent {
+            Self::Delete
+        } else {
+            Self::Trash
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Item {
+    /// The name of the item's directory in the trash.
+    #[serde(skip)]
+    pub id: String,
+    pub trashed_at: Timestamp,
+    /// Where the file was, relative to the registry root.
+    pub path: PathBuf,
+    /// The index entry of the version the file belonged to, when the
+    /// version was removed along with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<index_entry::Root>,
+}
+
+#[derive(Serialize)]
+struct NewItem<'a> {
+    trashed_at: Timestamp,
+    path: &'a Path,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entry: Option<&'a index_entry::Root>,
+}
+
+/// Where the trash is kept: the configured directory, or one next to
+/// the registry named after it.
+pub fn dir(r: &Registry) -> Result<PathBuf, Error> {
+    use error::*;
+
+    if let Some(dir) = &r.config.trash.dir {
+        return Ok(r.path.join(dir));
+    }
+
+    r.path_beside(TRASH_DIR_SUFFIX)
+        .context(LocateSnafu { path: &r.path })?
+        .context(NoParentSnafu { path: &r.path })
+}
+
+/// Moves the file into the trash, returning the item's ID, and
+/// deletes the items that are past the retention window.
+pub fn put(r: &Registry, path: &Path, entry: Option<&index_entry::Root>) -> Result<String, Error> {
+    use error::*;
+
+    purge_expired(r)?;
+
+    let trash_dir = dir(r)?;
+    fs::create_dir_all(&trash_dir).context(CreateSnafu { path: &trash_dir })?;
+
+    let trashed_at = Timestamp::now();
+    let label = match entry {
+        Some(e) => format!("{}-{}", e.name, e.vers),
+        None => path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    // IDs sort by when the item was trashed
+    let mut attempt = 0;
+    let (id, item_dir) = loop {
+        let id = match attempt {
+            0 => format!("{}-{label}", trashed_at.as_secs()),
+            n => format!("{}-{label}-{n}", trashed_at.as_secs()),
+        };
+        let item_dir = trash_dir.join(&id);
+        match fs::create_dir(&item_dir) {
+            Ok(()) => break (id, item_dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e).context(CreateSnafu { path: item_dir }),
+        }
+    };
+
+    let item = NewItem {
+        trashed_at,
+        path: r.relative_path(path),
+        entry,
+    };
+    let data = serde_json::to_vec_pretty(&item).context(SerializeSnafu)?;
+    let item_path = item_dir.join(ITEM_FILE_NAME);
+    fs::write(&item_path, data).context(WriteSnafu { path: item_path })?;
+
+    let data_path = item_dir.join(DATA_FILE_NAME);
+    fs::rename(path, &data_path).context(MoveSnafu {
+        from: path,
+        to: data_path,
+    })?;
+
+    Ok(id)
+}
+
+/// The items in the trash, oldest first.
+pub fn list(r: &Registry) -> Result<Vec<Item>, Error> {
+    use error::*;
+
+    let trash_dir = dir(r)?;
+    let entries = match fs::read_dir(&trash_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(ListSnafu { path: trash_dir }),
+    };
+
+    let mut items = Vec::new();
+    for entry in entries {
+        let entry = entry.context(ListSnafu { path: &trash_dir })?;
+        if !entry.path().is_dir() {
+            continue;
+        }
+        let id = entry.file_name().to_string_lossy().into_owned();
+        items.push(read(r, &id)?);
+    }
+
+    items.sort_by(|a, b| (a.trashed_at, &a.id).cmp(&(b.trashed_at, &b.id)));
+    Ok(items)
+}
+
+fn read(r: &Registry, id: &str) -> Result<Item, Error> {
+    use error::*;
+
+    // IDs are the names of directories in the trash
+    ensure!(
+        Path::new(id).file_name() == Some(OsStr::new(id)),
+        ItemNotFoundSnafu { id }
+    );
+
+    let path = dir(r)?.join(id).join(ITEM_FILE_NAME);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return ItemNotFoundSnafu { id }.fail();
+        }
+        Err(e) => return Err(e).context(ReadSnafu { path }),
+    };
+
+    let mut item = serde_json::from_slice::<Item>(&data).context(MalformedSnafu { path })?;
+    item.id = id.to_owned();
+    Ok(item)
+}
+
+/// Deletes the items that are past the retention window, returning
+/// how many were deleted.
+pub fn purge_expired(r: &Registry) -> Result<usize, Error> {
+    use error::*;
+
+    let retention = r.config.trash.retention_days.saturating_mul(24 * 60 * 60);
+    let cutoff = Timestamp::now().as_secs().saturating_sub(retention);
+
+    let mut purged = 0;
+    for item in list(r)? {
+        if item.trashed_at.as_secs() >= cutoff {
+            continue;
+        }
+
+        let path = dir(r)?.join(&item.id);
+        fs::remove_dir_all(&path).context(PurgeSnafu { path })?;
+        purged += 1;
+    }
+
+    Ok(purged)
+}
+
+/// What `restore` put back.
+#[derive(Debug)]
+pub struct Restored {
+    /// Relative to the registry root.
+    pub path: PathBuf,
+    /// The version added back to the index, if any.
+    pub version: Option<(CrateName, Version)>,
+}
+
+/// Moves the file back to where it was and, when it was removed along
+/// with its version, adds the version back to the index.
+pub fn restore(r: &Registry, id: &str) -> Result<Restored, Error> {
+    use error::*;
+
+    let item = read(r, id)?;
+    let item_dir = dir(r)?.join(id);
+    let path = r.path.join(&item.path);
+
+    ensure!(!path.exists(), OccupiedSnafu { path });
+
+    let mut version = None;
+    if let Some(entry) = item.entry {
+        let (name, vers) = (entry.name.clone(), entry.vers.clone());
+
+        let index_path = r.index_file_path_for(&name);
+        if let Some(dir) = index_path.parent() {
+            fs::create_dir_all(dir).context(CreateSnafu { path: dir })?;
+        }
+
+        r.read_modify_write(&name, |index| {
+            ensure!(
+                !index.contains_key(&vers),
+                VersionExistsSnafu {
+                    name: name.clone(),
+                    version: vers.clone(),
+                }
+            );
+            index.insert(vers.clone(), entry);
+            Ok::<_, Error>(())
+        })?;
+
+        r.modify_metadata(&name, |m| m.tombstones.remove(&vers))?;
+        version = Some((name, vers));
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(CreateSnafu { path: dir })?;
+    }
+    let data_path = item_dir.join(DATA_FILE_NAME);
+    fs::rename(&data_path, &path).context(MoveSnafu {
+        from: data_path,
+        to: &path,
+    })?;
+
+    fs::remove_dir_all(&item_dir).context(PurgeSnafu { path: &item_dir })?;
+
+    if let Some((name, vers)) = &version {
+        changes::record(r, &[Change::now(name, vers, Action::Publish)])?;
+    }
+
+    Ok(Restored {
+        path: item.path,
+        version,
+    })
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not find the registry {} to keep the trash next to", path.display()))]
+    Locate { source: io::Error, path: PathBuf },
+
+    #[snafu(display(
+        "The registry {} has no parent directory to keep the trash in; set `dir` in the `[trash]` table",
+        path.display()
+    ))]
+    NoParent { path: PathBuf },
+
+    #[snafu(display("Could not create {}", path.display()))]
+    Create { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not serialize the trash item"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not move {} to {}", from.display(), to.display()))]
+    Move {
+        source: io::Error,
+        from: PathBuf,
+        to: PathBuf,
+    },
+
+    #[snafu(display("Could not list the trash at {}", path.display()))]
+    List { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not read {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("The trash item {} is malformed", path.display()))]
+    Malformed {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[snafu(display("The trash has no item `{id}`; run `margo trash list` to see them"))]
+    ItemNotFound { id: String },
+
+    #[snafu(display("Could not delete the trash item {}", path.display()))]
+    Purge { source: io::Error, path: PathBuf },
+
+    #[snafu(display("{} already exists", path.display()))]
+    Occupied { path: PathBuf },
+
+    #[snafu(display("`{name}` version {version} is already in the index"))]
+    VersionExists { name: CrateName, version: Version },
+
+    #[snafu(transparent)]
+    Index { source: ReadModifyWriteError },
+
+    #[snafu(transparent)]
+    Metadata { source: MetadataError },
+
+    #[snafu(transparent)]
+    Changes { source: changes::RecordError },
+}
+
+impl Error {
+    pub fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::ItemNotFound { .. } => Some(ErrorKind::NotFound),
+            Self::Occupied { .. } | Self::VersionExists { .. } => Some(ErrorKind::Conflict),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    style::{Modifier, Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
+};
+use semver::Version;
+use snafu::prelude::*;
+use std::io::{self, Stdout};
+
+use crate::{common::CrateName, index_entry, lock, trash::Disposal, ListAll, Registry};
+
+const HELP: &str = "↑/↓ move · ←/→ switch pane · y yank/unyank · d delete · q quit";
+
+/// Browse and modify the registry interactively. Returns once the
+/// user quits; the HTML is regenerated if anything changed.
+pub fn run(registry: &Registry) -> Result<(), Error> {
+    use error::*;
+
+    let crates = registry.list_all()?;
+
+    let mut app = App {
+        registry,
+        crates,
+        crate_state: ListState::default().with_selected(Some(0)),
+        version_state: ListState::default().with_selected(Some(0)),
+        focus: Focus::Crates,
+        pending_delete: false,
+        status: HELP.into(),
+        changed: false,
+    };
+
+    {
+        let mut terminal = TerminalGuard::new().context(TerminalSnafu)?;
+
+        loop {
+            terminal
+                .0
+                .draw(|frame| app.draw(frame))
+                .context(TerminalSnafu)?;
+
+            let Event::Key(key) = event::read().context(TerminalSnafu)? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            if !app.handle_key(key.code)? {
+                break;
+            }
+        }
+    }
+
+    if app.changed {
+        registry.maybe_generate_html()?;
+        registry.maybe_write_manifest()?;
+        registry.maybe_precompress()?;
+    }
+
+    Ok(())
+}
+
+/// Restores the terminal even when drawing fails part way through.
+struct TerminalGuard(Terminal<CrosstermBackend<Stdout>>);
+
+impl TerminalGuard {
+    fn new() -> io::Result<Self> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Terminal::new(CrosstermBackend::new(io::stdout())).map(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.0.backend_mut(), LeaveAlternateScreen);
+        let _ = self.0.show_cursor();
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Focus {
+    Crates,
+    Versions,
+}
+
+struct App<'a> {
+    registry: &'a Registry,
+    crates: ListAll,
+    crate_state: ListState,
+    version_state: ListState,
+    focus: Focus,
+    pending_delete: bool,
+    status: String,
+    changed: bool,
+}
+
+impl App<'_> {
+    /// Returns `false` when the user wants to quit.
+    fn handle_key(&mut self, code: KeyCode) -> Result<bool, Error> {
+        if self.pending_delete {
+            self.pending_delete = false;
+            match code {
+                KeyCode::Char('y') => self.delete()?,
+                _ => self.status = "Delete cancelled".into(),
+            }
+            return Ok(true);
+        }
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Left | KeyCode::Char('h') => self.focus = Focus::Crates,
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Enter => {
+                if self.selected_crate().is_some() {
+                    self.focus = Focus::Versions;
+                }
+            }
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Crates => Focus::Versions,
+                    Focus::Versions => Focus::Crates,
+                }
+            }
+            KeyCode::Char('y') => self.toggle_yank()?,
+            KeyCode::Char('d') => {
+                if let Some((name, version, _)) = self.selected_version() {
+                    self.status = format!("Delete `{name}` version {version}? (y/n)");
+                    self.pending_delete = true;
+                }
+            }
+            _ => self.status = HELP.into(),
+        }
+
+        Ok(true)
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let (state, len) = match self.focus {
+            Focus::Crates => (&mut self.crate_state, self.crates.len()),
+            Focus::Versions => {
+                let len = self
+                    .crate_state
+                    .selected()
+                    .and_then(|i| self.crates.values().nth(i))
+                    .map_or(0, |index| index.len());
+                (&mut self.version_state, len)
+            }
+        };
+
+        if len == 0 {
+            return;
+        }
+
+        let current = state.selected().unwrap_or(0);
+        let next = current.saturating_add_signed(delta).min(len - 1);
+        state.select(Some(next));
+
+        if self.focus == Focus::Crates {
+            self.version_state.select(Some(0));
+        }
+    }
+
+    fn selected_crate(&self) -> Option<&CrateName> {
+        self.crate_state
+            .selected()
+            .and_then(|i| self.crates.keys().nth(i))
+    }
+
+    /// Versions are listed newest first.
+    fn selected_version(&self) -> Option<(&CrateName, &Version, &index_entry::Root)> {
+        let (name, index) = self
+            .crate_state
+            .selected()
+            .and_then(|i| self.crates.iter().nth(i))?;
+        let (version, entry) = self
+            .version_state
+            .selected()
+            .and_then(|i| index.iter().rev().nth(i))?;
+        Some((name, version, entry))
+    }
+
+    fn toggle_yank(&mut self) -> Result<(), Error> {
+        let Some((name, version, entry)) = self.selected_version() else {
+            return Ok(());
+        };
+        let (name, version, yanked) = (name.clone(), version.clone(), !entry.yanked);
+
+        let verb = if yanked { "Yanked" } else { "Unyanked" };
+        let lock = lock::exclusive(self.registry)?;
+        let yanked = self
+            .registry
+            .yank(name.clone(), &version.clone().into(), yanked);
+        drop(lock);
+
+        self.status = match yanked {
+            Ok(_) => {
+                self.changed = true;
+                format!("{verb} `{name}` version {version}")
+            }
+            Err(e) => format!("Could not change `{name}` version {version}: {e}"),
+        };
+
+        self.reload()
+    }
+
+    fn delete(&mut self) -> Result<(), Error> {
+        let Some((name, version, _)) = self.selected_version() else {
+            return Ok(());
+        };
+        let (name, version) = (name.clone(), version.clone());
+
+        let lock = lock::exclusive(self.registry)?;
+        let removed = self
+            .registry
+            .remove(name.clone(), version.clone(), None, Disposal::Trash);
+        drop(lock);
+
+        self.status = match removed {
+            Ok(()) => {
+                self.changed = true;
+                format!("Removed `{name}` version {version}")
+            }
+            Err(e) => format!("Could not remove `{name}` version {version}: {e}"),
+        };
+
+        self.reload()
+    }
+
+    fn reload(&mut self) -> Result<(), Error> {
+        self.crates = self.registry.list_all()?;
+
+        let clamp = |state: &mut ListState, len: usize| {
+            let selected = state.selected().unwrap_or(0).min(len.saturating_sub(1));
+            state.select(Some(selected));
+        };
+
+        clamp(&mut self.crate_state, self.crates.len());
+        let versions = self
+            .crate_state
+            .selected()
+            .and_then(|i| self.crates.values().nth(i))
+            .map_or(0, |index| index.len());
+        clamp(&mut self.version_state, versions);
+
+        if versions == 0 {
+            self.focus = Focus::Crates;
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame<'_>) {
+        let [main, detail, status] = Layout::vertical([
+            Constraint::Min(5),
+            Constraint::Length(12),
+            Constraint::Length(1),
+        ])
+        .areas(frame.size());
+        let [crates_area, versions_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+        let block = |title: &'static str, focused: bool| {
+            let block = Block::bordered().title(title);
+            if focused {
+                block.bold()
+            } else {
+                block
+            }
+        };
+
+        let crate_items = self
+            .crates
+            .iter()
+            .map(|(name, index)| ListItem::new(format!("{name} ({})", index.len())));
+        let crate_list = List::new(crate_items)
+            .block(block("Crates", self.focus == Focus::Crates))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(crate_list, crates_area, &mut self.crate_state);
+
+        let index = self
+            .crate_state
+            .selected()
+            .and_then(|i| self.crates.values().nth(i));
+        let version_items = index.into_iter().flat_map(|index| {
+            index.iter().rev().map(|(version, entry)| {
+                if entry.yanked {
+                    ListItem::new(Line::from(format!("{version} (yanked)")).dim())
+                } else {
+                    ListItem::new(version.to_string())
+                }
+            })
+        });
+        let version_list = List::new(version_items)
+            .block(block("Versions", self.focus == Focus::Versions))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(version_list, versions_area, &mut self.version_state);
+
+        let entry = match (self.focus, self.selected_version()) {
+            (Focus::Versions, Some((_, _, entry))) => {
+                serde_json::to_string_pretty(entry).unwrap_or_else(|e| e.to_string())
+            }
+            _ => String::new(),
+        };
+        let entry = Paragraph::new(entry)
+            .block(Block::bordered().title("Index entry"))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(entry, detail);
+
+        frame.render_widget(Paragraph::new(self.status.as_str()), status);
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not list the crates"))]
+    #[snafu(context(false))]
+    ListAll { source: crate::ListAllError },
+
+    #[snafu(display("Could not update the terminal"))]
+    Terminal { source: io::Error },
+
+    #[snafu(display("Could not lock the registry"))]
+    #[snafu(context(false))]
+    Lock { source: lock::Error },
+
+    #[snafu(display("Could not regenerate the HTML"))]
+    #[snafu(context(false))]
+    Html {
+        #[snafu(source(from(crate::HtmlError, Box::new)))]
+        source: Box<crate::HtmlError>,
+    },
+
+    #[snafu(display("Could not update the registry manifest"))]
+    #[snafu(context(false))]
+    Manifest { source: crate::manifest::Error },
+
+    #[snafu(display("Could not update the compressed files"))]
+    #[snafu(context(false))]
+    Precompress { source: crate::precompress::Error },
+}
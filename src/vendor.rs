@@ -0,0 +1,196 @@
+//! Directories of unpacked crates laid out like the output of `cargo
+//! vendor`, made from the crate files already in the registry.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use snafu::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{is_contained_path, lockfile, Registry};
+
+/// Cargo refuses to use a vendored crate unless every file matches
+/// the checksums recorded here.
+const CHECKSUM_FILE_NAME: &str = ".cargo-checksum.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Checksums {
+    files: BTreeMap<String, String>,
+    package: Option<String>,
+}
+
+/// Unpacks each crate into `out`, returning how many were unpacked.
+/// Crates that were already unpacked are kept, and crates that
+/// previously were vendored but are no longer wanted are removed.
+pub fn vendor(r: &Registry, crates: &[lockfile::Crate], out: &Path) -> Result<usize, Error> {
+    use error::*;
+
+    fs::create_dir_all(out).context(CreateSnafu { path: out })?;
+
+    let mut wanted = BTreeSet::new();
+    let mut unpacked = 0;
+
+    for c in crates {
+        let dir_name = format!("{}-{}", c.name, c.version);
+        let dir = out.join(&dir_name);
+        wanted.insert(dir_name);
+
+        if vendored_checksum(&dir)?.as_deref() == Some(&c.cksum) {
+            continue;
+        }
+
+        remove_dir(&dir)?;
+
+        let path = r.crate_file_path_for(&c.name, &c.version, &c.cksum);
+        let data = fs::read(&path).context(ReadSnafu { path: &path })?;
+        let files = unpack(&data, &dir).context(UnpackSnafu { path: &path })?;
+
+        let checksums = Checksums {
+            files,
+            package: Some(c.cksum.clone()),
+        };
+        let checksums = serde_json::to_vec(&checksums).context(SerializeSnafu)?;
+        let checksum_path = dir.join(CHECKSUM_FILE_NAME);
+        fs::write(&checksum_path, checksums).context(WriteSnafu {
+            path: &checksum_path,
+        })?;
+
+        unpacked += 1;
+    }
+
+    // Only directories that look vendored are removed, in case the
+    // output directory holds anything else.
+    for entry in fs::read_dir(out).context(ListSnafu { path: out })? {
+        let entry = entry.context(ListSnafu { path: out })?;
+        let stale = !wanted.contains(&*entry.file_name().to_string_lossy());
+        if stale && entry.path().join(CHECKSUM_FILE_NAME).is_file() {
+            remove_dir(&entry.path())?;
+        }
+    }
+
+    Ok(unpacked)
+}
+
+/// The package checksum of a previously vendored crate.
+fn vendored_checksum(dir: &Path) -> Result<Option<String>, Error> {
+    use error::*;
+
+    let path = dir.join(CHECKSUM_FILE_NAME);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(ReadSnafu { path }),
+    };
+
+    // A damaged file is replaced by unpacking again
+    let checksums = serde_json::from_slice::<Checksums>(&data).ok();
+    Ok(checksums.and_then(|c| c.package))
+}
+
+fn remove_dir(dir: &Path) -> Result<(), Error> {
+    use error::*;
+
+    match fs::remove_dir_all(dir) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).context(RemoveSnafu { path: dir }),
+    }
+}
+
+/// Writes the files of the package into `dir`, without the
+/// `name-version` directory they are archived in, returning the
+/// checksum of each by its `/`-separated path.
+fn unpack(crate_data: &[u8], dir: &Path) -> Result<BTreeMap<String, String>, UnpackError> {
+    use unpack_error::*;
+
+    let crate_data = flate2::read::GzDecoder::new(crate_data);
+    let mut crate_data = tar::Archive::new(crate_data);
+
+    let mut files = BTreeMap::new();
+
+    for entry in crate_data.entries().context(EntriesSnafu)? {
+        let mut entry = entry.context(EntrySnafu)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path().context(PathSnafu)?.into_owned();
+        ensure!(is_contained_path(&path), UnsafePathSnafu { path });
+
+        let relative = path.components().skip(1).collect::<PathBuf>();
+        if relative.as_os_str().is_empty() {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .context(ReadSnafu { path: &relative })?;
+
+        let target = dir.join(&relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).context(WriteSnafu { path: parent })?;
+        }
+        fs::write(&target, &data).context(WriteSnafu { path: &target })?;
+
+        let key = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.insert(key, hex::encode(Sha256::digest(&data)));
+    }
+
+    Ok(files)
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum Error {
+    #[snafu(display("Could not create the directory {}", path.display()))]
+    Create { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not list the directory {}", path.display()))]
+    List { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not read {}", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not unpack the crate file {}", path.display()))]
+    Unpack { source: UnpackError, path: PathBuf },
+
+    #[snafu(display("Could not serialize the checksums of the vendored crate"))]
+    Serialize { source: serde_json::Error },
+
+    #[snafu(display("Could not write {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not remove the vendored crate {}", path.display()))]
+    Remove { source: io::Error, path: PathBuf },
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+pub enum UnpackError {
+    #[snafu(display("Could not read the entries of the archive"))]
+    Entries { source: io::Error },
+
+    #[snafu(display("Could not read an entry of the archive"))]
+    Entry { source: io::Error },
+
+    #[snafu(display("Could not read the path of an entry"))]
+    Path { source: io::Error },
+
+    #[snafu(display("The entry `{}` would be written outside of the crate", path.display()))]
+    UnsafePath { path: PathBuf },
+
+    #[snafu(display("Could not read the entry `{}`", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write {}", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+}
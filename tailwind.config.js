@@ -14,7 +14,7 @@ module.exports = {
             light: "#d6a1a8",
             DEFAULT: "#c06c84",
           },
-          purple: "#6c5b7c",
+          purple: "#5a4a6a",
         },
       },
     },
@@ -33,3 +33,63 @@ class Copy extends HTMLElement {
 }
 
 window.customElements.define("mg-copy", Copy);
+
+class Versions extends HTMLElement {
+  connectedCallback() {
+    const select = this.querySelector("select");
+    const panels = this.querySelectorAll<HTMLElement>("[data-version]");
+
+    if (!select) {
+      return;
+    }
+
+    const show = () => {
+      for (let panel of panels) {
+        panel.hidden = panel.dataset.version !== select.value;
+      }
+    };
+
+    select.addEventListener("change", show);
+    show();
+  }
+}
+
+window.customElements.define("mg-versions", Versions);
+
+const RELATIVE_TIME_UNITS: [Intl.RelativeTimeFormatUnit, number][] = [
+  ["year", 60 * 60 * 24 * 365],
+  ["month", 60 * 60 * 24 * 30],
+  ["week", 60 * 60 * 24 * 7],
+  ["day", 60 * 60 * 24],
+  ["hour", 60 * 60],
+  ["minute", 60],
+  ["second", 1],
+];
+
+class RelativeTime extends HTMLElement {
+  connectedCallback() {
+    const time = this.querySelector("time");
+    if (!time) {
+      return;
+    }
+
+    const then = Date.parse(time.dateTime);
+    if (Number.isNaN(then)) {
+      return;
+    }
+
+    const seconds = (then - Date.now()) / 1000;
+    const format = new Intl.RelativeTimeFormat(document.documentElement.lang, {
+      numeric: "auto",
+    });
+
+    for (const [unit, size] of RELATIVE_TIME_UNITS) {
+      if (Math.abs(seconds) >= size || unit === "second") {
+        time.textContent = format.format(Math.round(seconds / size), unit);
+        return;
+      }
+    }
+  }
+}
+
+window.customElements.define("mg-relative-time", RelativeTime);
@@ -1,6 +1,7 @@
 use notify::{RecursiveMode, Watcher};
 use quote::quote;
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use snafu::prelude::*;
 use std::{
     env, fs, io,
@@ -10,7 +11,7 @@ use std::{
     thread,
     time::Duration,
 };
-use toml_edit::{DocumentMut, Item};
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
 
 /// Build tools for Margo
 #[derive(Debug, argh::FromArgs)]
@@ -24,6 +25,7 @@ struct Args {
 enum Subcommand {
     Assets(AssetsArgs),
     PrepareRelease(PrepareReleaseArgs),
+    BootstrapRegistry(BootstrapRegistryArgs),
 }
 
 /// Manage assets
@@ -41,10 +43,33 @@ struct AssetsArgs {
 #[argh(subcommand)]
 #[argh(name = "prepare-release")]
 struct PrepareReleaseArgs {
+    /// build, strip, and archive release binaries
+    #[argh(switch)]
+    binaries: bool,
+
+    /// a target to build release binaries for; may be repeated.
+    /// Defaults to every release target
+    #[argh(option)]
+    target: Vec<String>,
+
     #[argh(positional)]
     tag: String,
 }
 
+/// Add the current version of Margo to a registry that hosts Margo
+#[derive(Debug, argh::FromArgs)]
+#[argh(subcommand)]
+#[argh(name = "bootstrap-registry")]
+struct BootstrapRegistryArgs {
+    /// the URL that the registry is hosted at; only used when the
+    /// registry needs to be created
+    #[argh(option)]
+    base_url: Option<String>,
+
+    #[argh(positional)]
+    registry: PathBuf,
+}
+
 #[snafu::report]
 fn main() -> Result<(), Error> {
     let args: Args = argh::from_env();
@@ -52,6 +77,7 @@ fn main() -> Result<(), Error> {
     match args.subcommand {
         Subcommand::Assets(args) => do_assets(args)?,
         Subcommand::PrepareRelease(args) => do_prepare_release(args)?,
+        Subcommand::BootstrapRegistry(args) => do_bootstrap_registry(args)?,
     }
 
     Ok(())
@@ -64,6 +90,9 @@ enum Error {
 
     #[snafu(transparent)]
     PrepareRelease { source: PrepareReleaseError },
+
+    #[snafu(transparent)]
+    BootstrapRegistry { source: BootstrapRegistryError },
 }
 
 fn do_assets(args: AssetsArgs) -> Result<(), AssetsError> {
@@ -111,15 +140,28 @@ fn do_assets_watch(
 ) -> Result<(), AssetsWatchError> {
     use assets_watch_error::*;
 
-    // The directory needs to exist before we can watch it.
+    // The directory needs to exist before the bundler starts writing
+    // to it, but we watch its parent so that it may be removed and
+    // recreated without losing events.
     std::fs::create_dir_all(&asset_root)
         .context(AssetDirectoryCreateSnafu { path: &asset_root })?;
+    let watch_root = asset_root.parent().unwrap_or(&asset_root).to_owned();
 
     let (tx, rx) = mpsc::channel();
 
+    // Pick up assets from a previous build, if there are any.
+    if asset_index.exists() {
+        let _ = tx.send(());
+    }
+
+    let watched_root = asset_root.clone();
     let mut watcher = notify::recommended_watcher(move |evt: notify::Result<notify::Event>| {
         if let Ok(evt) = evt {
-            if evt.paths.iter().any(|p| is_asset_file(p).unwrap_or(false)) {
+            let is_relevant = |p: &PathBuf| {
+                p.parent() == Some(&watched_root) && is_asset_file(p).unwrap_or(false)
+            };
+
+            if evt.paths.iter().any(is_relevant) {
                 let _ = tx.send(());
             }
         }
@@ -127,14 +169,19 @@ fn do_assets_watch(
     .context(WatcherCreateSnafu)?;
 
     watcher
-        .watch(&asset_root, RecursiveMode::NonRecursive)
+        .watch(&watch_root, RecursiveMode::Recursive)
         .context(WatcherWatchSnafu)?;
 
     // Debounce notifications
     thread::spawn(move || -> Result<(), AssetsWatchError> {
         loop {
             recv_debounced(&rx)?;
-            rebuild_asset_file(&root, &asset_root, &asset_index)?;
+
+            // The bundler may not have written every file yet; the
+            // next event will try again.
+            if let Err(e) = rebuild_asset_file(&root, &asset_root, &asset_index) {
+                eprintln!("{}", snafu::Report::from_error(e));
+            }
         }
     });
 
@@ -159,9 +206,6 @@ enum AssetsWatchError {
     #[snafu(context(false))]
     RxClosed { source: mpsc::RecvError },
 
-    #[snafu(transparent)]
-    Rebuild { source: RebuildAssetFileError },
-
     #[snafu(display("Could not watch assets"))]
     #[snafu(context(false))]
     PnpmWatch { source: PnpmError },
@@ -230,19 +274,22 @@ fn rebuild_asset_file(
 ) -> Result<(), RebuildAssetFileError> {
     use rebuild_asset_file_error::*;
 
-    let entry =
+    let mut entry =
         fs::read_to_string(asset_index).context(ReadEntrypointSnafu { path: asset_index })?;
 
-    let (css_name, css, css_map) = extract_asset(&entry, asset_root, {
+    let (css_name, mut css, css_map) = extract_asset(&entry, asset_root, {
         r#"href="assets/(ui.[a-zA-Z0-9]+.css)""#
     })
     .context(ExtractCssSnafu)?;
 
-    let (js_name, js, js_map) = extract_asset(&entry, asset_root, {
+    let (js_name, mut js, js_map) = extract_asset(&entry, asset_root, {
         r#"src="assets/(ui.[a-zA-Z0-9]+.js)""#
     })
     .context(ExtractJsSnafu)?;
 
+    let css_name = rename_by_content(&mut entry, &css_name, &mut css);
+    let js_name = rename_by_content(&mut entry, &js_name, &mut js);
+
     let html_dir = join!(root, "src", "html");
     fs::create_dir_all(&html_dir).context(CreateHtmlDirSnafu { path: &html_dir })?;
 
@@ -283,11 +330,11 @@ enum RebuildAssetFileError {
     WriteAssetFile { source: io::Error, path: PathBuf },
 }
 
-fn extract_asset<'a>(
-    entry: &'a str,
+fn extract_asset(
+    entry: &str,
     asset_root: &Path,
     re: &str,
-) -> Result<(&'a str, String, String), ExtractAssetError> {
+) -> Result<(String, String, String), ExtractAssetError> {
     use extract_asset_error::*;
 
     let find_asset = Regex::new(re)?;
@@ -307,7 +354,24 @@ fn extract_asset<'a>(
     let asset_map =
         fs::read_to_string(&asset_map).context(ReadAssetMapSnafu { path: asset_map })?;
 
-    Ok((asset_name, asset, asset_map))
+    Ok((asset_name.to_owned(), asset, asset_map))
+}
+
+/// Names an asset after a hash of its content, replacing the name the
+/// bundler chose everywhere it is referenced. The name only changes
+/// when the content does, keeping diffs of a registry small.
+fn rename_by_content(entry: &mut String, name: &str, asset: &mut String) -> String {
+    let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+
+    // The asset refers to its own sourcemap by name
+    let content = asset.replace(name, "");
+    let hash = hex::encode(Sha256::digest(content));
+    let new_name = format!("ui.{}.{ext}", &hash[..16]);
+
+    *entry = entry.replace(name, &new_name);
+    *asset = asset.replace(name, &new_name);
+
+    new_name
 }
 
 #[derive(Debug, Snafu)]
@@ -341,7 +405,11 @@ use join;
 fn do_prepare_release(args: PrepareReleaseArgs) -> Result<(), PrepareReleaseError> {
     use prepare_release_error::*;
 
-    let PrepareReleaseArgs { tag } = args;
+    let PrepareReleaseArgs {
+        binaries,
+        target,
+        tag,
+    } = args;
 
     do_assets(AssetsArgs { watch: false })?;
 
@@ -360,7 +428,17 @@ fn do_prepare_release(args: PrepareReleaseArgs) -> Result<(), PrepareReleaseErro
     cargo!("update", "margo").context(VersionLockUpdateSnafu)?;
     git!("add", CARGO_TOML_FILE, CARGO_LOCK_FILE).context(VersionAddSnafu)?;
     git!("commit", "--message", update_msg).context(VersionCommitSnafu)?;
-    git!("tag", tag).context(VersionTagSnafu)?;
+    git!("tag", &tag).context(VersionTagSnafu)?;
+
+    if binaries {
+        let targets = if target.is_empty() {
+            RELEASE_TARGETS.iter().map(|&t| t.to_owned()).collect()
+        } else {
+            target
+        };
+
+        build_binaries(&tag, &targets)?;
+    }
 
     git!("rm", ASSET_FILE).context(AssetRmSnafu)?;
     git!("commit", "--message", rm_msg).context(AssetRmCommitSnafu)?;
@@ -395,6 +473,9 @@ enum PrepareReleaseError {
     #[snafu(display("Could not tag the release commit in git"))]
     VersionTag { source: GitError },
 
+    #[snafu(transparent)]
+    Binaries { source: BuildBinariesError },
+
     #[snafu(display("Could not remove the asset file from git"))]
     AssetRm { source: GitError },
 
@@ -402,6 +483,227 @@ enum PrepareReleaseError {
     AssetRmCommit { source: GitError },
 }
 
+const RELEASE_TARGETS: &[&str] = &[
+    "x86_64-unknown-linux-gnu",
+    "x86_64-unknown-linux-musl",
+    "aarch64-unknown-linux-musl",
+    "x86_64-apple-darwin",
+    "aarch64-apple-darwin",
+    "x86_64-pc-windows-msvc",
+];
+
+const RELEASE_ARTIFACTS_DIR: &str = "target/release-artifacts";
+const RELEASE_SUMS_FILE: &str = "SHA256SUMS";
+const RELEASE_MANIFEST_FILE: &str = "manifest.toml";
+
+/// Builds a stripped binary for each target and archives it,
+/// alongside a checksum file and a manifest describing every archive,
+/// ready to be attached to a GitHub release.
+fn build_binaries(tag: &str, targets: &[String]) -> Result<(), BuildBinariesError> {
+    use build_binaries_error::*;
+
+    let out_dir = join!(RELEASE_ARTIFACTS_DIR, tag);
+    fs::create_dir_all(&out_dir).context(OutDirSnafu { path: &out_dir })?;
+
+    let mut sums = String::new();
+    let mut artifacts = ArrayOfTables::new();
+
+    for target in targets {
+        cargo!(
+            "build",
+            "--package",
+            "margo",
+            "--release",
+            "--locked",
+            "--target",
+            target,
+            "--config",
+            "profile.release.strip=true",
+        )
+        .context(BuildSnafu { target })?;
+
+        let bin_dir = join!("target", target, "release");
+
+        let (binary, archive_name) = if target.contains("windows") {
+            ("margo.exe", format!("margo-{tag}-{target}.zip"))
+        } else {
+            ("margo", format!("margo-{tag}-{target}.tar.gz"))
+        };
+        let archive = join!(&out_dir, &archive_name);
+
+        if target.contains("windows") {
+            command!("zip", "-j", &archive, join!(&bin_dir, binary))
+        } else {
+            command!("tar", "-czf", &archive, "-C", &bin_dir, binary)
+        }
+        .context(ArchiveSnafu { target })?;
+
+        let contents = fs::read(&archive).context(ReadArchiveSnafu { path: &archive })?;
+        let sha256 = hex::encode(Sha256::digest(contents));
+
+        sums.push_str(&format!("{sha256}  {archive_name}\n"));
+
+        let mut artifact = Table::new();
+        artifact["target"] = value(target.as_str());
+        artifact["file"] = value(archive_name);
+        artifact["sha256"] = value(sha256);
+        artifacts.push(artifact);
+    }
+
+    let sums_path = join!(&out_dir, RELEASE_SUMS_FILE);
+    fs::write(&sums_path, sums).context(WriteSnafu { path: sums_path })?;
+
+    let mut manifest = DocumentMut::new();
+    manifest["version"] = value(tag);
+    manifest["artifacts"] = Item::ArrayOfTables(artifacts);
+
+    let manifest_path = join!(&out_dir, RELEASE_MANIFEST_FILE);
+    fs::write(&manifest_path, manifest.to_string()).context(WriteSnafu {
+        path: manifest_path,
+    })?;
+
+    println!("Release binaries were written to {}", out_dir.display());
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum BuildBinariesError {
+    #[snafu(display("Could not create the release directory `{}`", path.display()))]
+    OutDir { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not build Margo for `{target}`"))]
+    Build { source: CargoError, target: String },
+
+    #[snafu(display("Could not archive the binary for `{target}`"))]
+    Archive {
+        source: ProcessError,
+        target: String,
+    },
+
+    #[snafu(display("Could not read the archive `{}`", path.display()))]
+    ReadArchive { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not write `{}`", path.display()))]
+    Write { source: io::Error, path: PathBuf },
+}
+
+fn do_bootstrap_registry(args: BootstrapRegistryArgs) -> Result<(), BootstrapRegistryError> {
+    use bootstrap_registry_error::*;
+
+    let BootstrapRegistryArgs { base_url, registry } = args;
+
+    const CARGO_TOML_FILE: &str = "Cargo.toml";
+    const MARGO_CONFIG_FILE: &str = "margo-config.toml";
+
+    let version = read_string(CARGO_TOML_FILE, &["package", "version"])?;
+    let config_path = join!(&registry, MARGO_CONFIG_FILE);
+
+    if !config_path.exists() {
+        let base_url = base_url.context(BaseUrlSnafu)?;
+        cargo!(
+            "run",
+            "--package",
+            "margo",
+            "--",
+            "init",
+            &registry,
+            "--base-url",
+            base_url,
+            "--non-interactive",
+        )
+        .context(InitSnafu)?;
+    }
+
+    cargo!("package", "--package", "margo").context(PackageSnafu)?;
+
+    let package = join!("target", "package", format!("margo-{version}.crate"));
+    cargo!(
+        "run",
+        "--package",
+        "margo",
+        "--",
+        "add",
+        "--registry",
+        &registry,
+        package
+    )
+    .context(AddSnafu)?;
+
+    let base_url = read_string(&config_path, &["base_url"])?;
+
+    println!("Margo {version} was added to the registry. Install it with:");
+    println!();
+    println!("    cargo install margo --index sparse+{base_url}");
+
+    Ok(())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum BootstrapRegistryError {
+    #[snafu(display("The registry does not exist yet, so `--base-url` must be provided"))]
+    BaseUrl,
+
+    #[snafu(display("Could not initialize the registry"))]
+    Init { source: CargoError },
+
+    #[snafu(display("Could not package Margo"))]
+    Package { source: CargoError },
+
+    #[snafu(display("Could not add Margo to the registry"))]
+    Add { source: CargoError },
+
+    #[snafu(transparent)]
+    ReadString { source: ReadStringError },
+}
+
+/// Reads a string value from a TOML file, such as the version from
+/// `Cargo.toml`.
+fn read_string(fname: impl AsRef<Path>, keys: &[&str]) -> Result<String, ReadStringError> {
+    use read_string_error::*;
+
+    let fname = fname.as_ref();
+
+    let toml = fs::read_to_string(fname).context(ReadSnafu { path: fname })?;
+    let toml: DocumentMut = toml.parse().context(ParseSnafu { path: fname })?;
+
+    let mut item = toml.as_item();
+    for key in keys {
+        item = item.get(key).context(MissingSnafu {
+            path: fname,
+            key: *key,
+        })?;
+    }
+
+    let value = item.as_str().context(NotStringSnafu {
+        path: fname,
+        key: keys.join("."),
+    })?;
+
+    Ok(value.to_owned())
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(module)]
+enum ReadStringError {
+    #[snafu(display("Could not read `{}`", path.display()))]
+    Read { source: io::Error, path: PathBuf },
+
+    #[snafu(display("Could not parse `{}`", path.display()))]
+    Parse {
+        source: toml_edit::TomlError,
+        path: PathBuf,
+    },
+
+    #[snafu(display("`{}` did not contain `{key}`", path.display()))]
+    Missing { path: PathBuf, key: String },
+
+    #[snafu(display("`{key}` in `{}` was not a string", path.display()))]
+    NotString { path: PathBuf, key: String },
+}
+
 fn set_version(fname: impl AsRef<Path>, version: &str) -> Result<(), SetVersionError> {
     use set_version_error::*;
 